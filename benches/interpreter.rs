@@ -0,0 +1,133 @@
+//! Measures the interpreter on a few workloads, with and without the predecode cache, and
+//! the basic-block translator on the same workloads.
+//!
+//! Run with `cargo bench`. Every workload is an endless loop, so each run executes the same
+//! number of instructions.
+
+use std::hint::black_box;
+use std::time::{Duration, Instant};
+
+use lc_3_vm::{assembler::assemble, translation::BlockTranslator, vm::Vm};
+
+const STEPS: usize = 5_000_000;
+
+/// Arithmetic and branches on registers only.
+const ARITHMETIC: &str = "
+        .ORIG x3000
+LOOP    AND R0, R0, #0
+        ADD R1, R1, #1
+        ADD R2, R1, R1
+        NOT R3, R2
+        AND R4, R3, #15
+        ADD R4, R4, #-8
+        BRn SKIP
+        ADD R5, R5, #1
+SKIP    BRnzp LOOP
+        .END
+";
+
+/// Sums and rewrites an array, like a game updating its board.
+const MEMORY: &str = "
+        .ORIG x3000
+START   LEA R1, BOARD
+        AND R2, R2, #0
+        ADD R2, R2, #15
+        ADD R2, R2, #1
+        AND R0, R0, #0
+CELL    LDR R3, R1, #0
+        ADD R0, R0, R3
+        ADD R3, R3, #1
+        STR R3, R1, #0
+        ADD R1, R1, #1
+        ADD R2, R2, #-1
+        BRp CELL
+        ST R0, TOTAL
+        BRnzp START
+TOTAL   .FILL 0
+BOARD   .BLKW 16
+        .END
+";
+
+/// Subroutine calls that push and pop their arguments on a stack.
+const CALLS: &str = "
+        .ORIG x3000
+        LD R6, STACK
+LOOP    ADD R6, R6, #-1
+        STR R1, R6, #0
+        JSR SQUARE
+        LDR R1, R6, #0
+        ADD R6, R6, #1
+        ADD R1, R1, #1
+        AND R1, R1, #7
+        BRnzp LOOP
+SQUARE  AND R0, R0, #0
+        ADD R2, R1, #0
+        BRz DONE
+MUL     ADD R0, R0, R1
+        ADD R2, R2, #-1
+        BRp MUL
+DONE    RET
+STACK   .FILL xFE00
+        .END
+";
+
+/// How a workload is executed.
+#[derive(Clone, Copy)]
+enum Mode {
+    Decode,
+    Cached,
+    Blocks,
+}
+
+/// Runs `STEPS` instructions of a workload and returns how long it took.
+fn measure(source: &str, mode: Mode) -> Duration {
+    let assembly = assemble("bench.asm", source).expect("workload assembles");
+    let mut vm = Vm::new();
+    vm.memory.predecode = !matches!(mode, Mode::Decode);
+    vm.load_sections(&assembly.sections);
+
+    let mut running = true;
+    let start = Instant::now();
+    if let Mode::Blocks = mode {
+        let mut translator = BlockTranslator::new();
+        while translator.executed < STEPS as u64 {
+            translator
+                .run_block(&mut vm, black_box(&mut running))
+                .expect("workload runs");
+        }
+    } else {
+        for _ in 0..STEPS {
+            vm.step(black_box(&mut running)).expect("workload runs");
+        }
+    }
+    let elapsed = start.elapsed();
+    black_box(&vm.registers);
+    elapsed
+}
+
+fn main() {
+    println!(
+        "{:<12} {:>14} {:>14} {:>14} {:>8} {:>8}",
+        "workload", "decode ns/op", "cached ns/op", "blocks ns/op", "cached", "blocks"
+    );
+    for (name, source) in [
+        ("arithmetic", ARITHMETIC),
+        ("memory", MEMORY),
+        ("calls", CALLS),
+    ] {
+        let decode = measure(source, Mode::Decode);
+        let cached = measure(source, Mode::Cached);
+        let blocks = measure(source, Mode::Blocks);
+        let per_op = |d: Duration| d.as_nanos() as f64 / STEPS as f64;
+        let speedup = |d: Duration| decode.as_secs_f64() / d.as_secs_f64();
+        println!(
+            "{:<12} {:>14.2} {:>14.2} {:>14.2} {:>7.2}x {:>7.2}x",
+            name,
+            per_op(decode),
+            per_op(cached),
+            per_op(blocks),
+            speedup(cached),
+            speedup(blocks)
+        );
+    }
+}
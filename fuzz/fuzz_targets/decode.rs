@@ -0,0 +1,16 @@
+//! Decodes arbitrary words and checks that every instruction encodes back to the same word.
+
+#![no_main]
+
+use lc_3_vm::instruction::Instruction;
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    for (i, pair) in data.chunks_exact(2).enumerate() {
+        let word = u16::from_be_bytes([pair[0], pair[1]]);
+        let instruction = Instruction::decode(word);
+        assert_eq!(instruction.encode(), word);
+        let _ = instruction.to_string();
+        let _ = instruction.target(i as u16);
+    }
+});
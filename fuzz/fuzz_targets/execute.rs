@@ -0,0 +1,75 @@
+//! Executes an arbitrary `.obj` image on the interpreter and on the block backend in lockstep.
+//!
+//! The machines must never panic or disagree, and PC-relative instructions must wrap around
+//! the address space as the specification says. Both machines get a console without input,
+//! so a program that reads the keyboard stops there.
+
+#![no_main]
+
+use lc_3_vm::{
+    console::ScriptedConsole,
+    constants::DEVICE_PAGE_START,
+    differential::Lockstep,
+    image_format::{parse_image, ImageFormat},
+    instruction::Instruction,
+    vm::{Backend, Vm},
+};
+use libfuzzer_sys::fuzz_target;
+
+const MAX_STEPS: u64 = 10_000;
+
+fn machine(sections: &[lc_3_vm::image_format::Section], backend: Backend) -> Vm {
+    let mut vm = Vm::new();
+    vm.load_sections(sections);
+    vm.registers.pc = sections[0].origin;
+    vm.backend = backend;
+    vm.memory.console = Box::new(ScriptedConsole::new(&[]));
+    vm
+}
+
+/// The address `offset` words after the incremented PC, modulo the size of memory.
+fn target(address: u16, offset: i16) -> u16 {
+    (address as i32 + 1 + offset as i32).rem_euclid(0x10000) as u16
+}
+
+fuzz_target!(|data: &[u8]| {
+    let sections = match parse_image(ImageFormat::Obj, data) {
+        Ok(sections) if !sections.is_empty() => sections,
+        _ => return,
+    };
+    let mut reference = machine(&sections, Backend::Interpreter);
+    reference.memory.predecode = false;
+    let candidate = machine(&sections, Backend::Blocks);
+    let mut lockstep = Lockstep::new(reference, candidate);
+
+    while lockstep.steps < MAX_STEPS && lockstep.reference.running {
+        let vm = &lockstep.reference.vm;
+        let address = vm.registers.pc;
+        let instruction = Instruction::decode(vm.memory.memory[address as usize]);
+        let taken = match instruction {
+            Instruction::Br { conditions, .. } => conditions & vm.registers.cond != 0,
+            _ => true,
+        };
+
+        match lockstep.step() {
+            Err(divergence) => panic!("{}", divergence),
+            Ok((Err(_), _)) => return,
+            Ok(_) => {}
+        }
+
+        if address >= DEVICE_PAGE_START {
+            continue;
+        }
+        let vm = &lockstep.reference.vm;
+        match instruction {
+            Instruction::Lea { dr, offset } => {
+                assert_eq!(vm.registers[dr], target(address, offset))
+            }
+            Instruction::Jsr { offset } => assert_eq!(vm.registers.pc, target(address, offset)),
+            Instruction::Br { offset, .. } if taken => {
+                assert_eq!(vm.registers.pc, target(address, offset))
+            }
+            _ => {}
+        }
+    }
+});
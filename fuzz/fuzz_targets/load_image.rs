@@ -0,0 +1,28 @@
+//! Parses arbitrary bytes as an image in every format, then loads and checks what was parsed.
+
+#![no_main]
+
+use lc_3_vm::{
+    image_format::{parse_image, ImageFormat},
+    validation::check_image,
+    vm::Vm,
+};
+use libfuzzer_sys::fuzz_target;
+
+const FORMATS: [ImageFormat; 4] = [
+    ImageFormat::Obj,
+    ImageFormat::Hex,
+    ImageFormat::Bin,
+    ImageFormat::Lc3Tools,
+];
+
+fuzz_target!(|data: &[u8]| {
+    let detected = ImageFormat::detect("fuzz.img", data);
+    for format in std::iter::once(detected).chain(FORMATS) {
+        if let Ok(sections) = parse_image(format, data) {
+            let mut vm = Vm::new();
+            vm.load_sections(&sections);
+            let _ = check_image("fuzz.img", &sections, &sections);
+        }
+    }
+});
@@ -0,0 +1,738 @@
+pub mod preprocessor;
+
+use std::collections::HashMap;
+use std::fs;
+
+use crate::{
+    constants::{FL_NEG, FL_POS, FL_ZRO},
+    image_format::Section,
+    instruction::{Instruction, Operand},
+    symbols::SymbolTable,
+    utils::{parse_number, parse_word},
+    vm_error::VmError,
+};
+
+use preprocessor::{preprocess, unquote, Line};
+
+/// The output of the assembler: the sections to load, and the labels they define along
+/// with the source line of every word. Words produced by a macro are mapped to the line
+/// that called it, so stepping by line treats a macro call like a single statement.
+///
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Assembly {
+    pub sections: Vec<Section>,
+    pub symbols: SymbolTable,
+}
+
+/// A line split into its parts.
+struct Statement<'a> {
+    label: Option<&'a str>,
+    mnemonic: Option<String>,
+    operands: &'a [String],
+}
+
+/// Reads and assembles an LC-3 source file.
+///
+/// # Returns
+///
+/// The assembled program, or a `VmError` if the file cannot be read or has errors.
+///
+pub fn assemble_file(path: &str) -> Result<Assembly, VmError> {
+    let text = fs::read_to_string(path).map_err(|e| VmError::FailedToOpenFile(e.to_string()))?;
+    assemble(path, &text)
+}
+
+/// Assembles LC-3 source code.
+///
+/// Besides the instructions and the classic directives (`.ORIG`, `.END`, `.FILL`, `.BLKW`
+/// and `.STRINGZ`), the source can use `.INCLUDE` and macros, see the `preprocessor` module.
+/// Several `.ORIG` sections produce several sections in the output.
+///
+/// # Arguments
+///
+/// * `path` - The path of the source, used for error messages and to resolve includes.
+/// * `text` - The source code.
+///
+/// # Returns
+///
+/// The assembled program, or a `VmError::AssemblyError` listing every error found.
+///
+pub fn assemble(path: &str, text: &str) -> Result<Assembly, VmError> {
+    let lines = preprocess(path, text)?;
+    let mut errors = Vec::new();
+
+    // First pass: find the address of every line and define the labels.
+    let mut statements = Vec::new();
+    let mut labels: HashMap<String, (u16, &Line)> = HashMap::new();
+    let mut symbols = SymbolTable::new();
+    let mut pc: Option<u32> = None;
+    for line in &lines {
+        let statement = match parse_statement(line) {
+            Ok(statement) => statement,
+            Err(message) => {
+                errors.push(line.error(&message));
+                continue;
+            }
+        };
+        let mnemonic = statement.mnemonic.as_deref();
+        if mnemonic == Some(".ORIG") {
+            match statement.operands {
+                [origin] => match parse_word(origin) {
+                    Some(origin) => pc = Some(origin as u32),
+                    None => errors.push(line.error(&format!("invalid origin {}", origin))),
+                },
+                _ => errors.push(line.error(".ORIG expects an address")),
+            }
+        }
+
+        let Some(address) = pc else {
+            if statement.label.is_some() || !matches!(mnemonic, None | Some(".ORIG" | ".END")) {
+                errors.push(line.error("code before .ORIG"));
+            }
+            continue;
+        };
+        if address > u16::MAX as u32 {
+            // A section may fill memory up to xFFFF, as long as nothing follows it.
+            if statement.label.is_none() && mnemonic == Some(".END") {
+                pc = None;
+                continue;
+            }
+            errors.push(line.error("the program runs past the end of memory"));
+            pc = None;
+            continue;
+        }
+        let address = address as u16;
+        if let Some(label) = statement.label {
+            match labels.get(&label.to_ascii_uppercase()) {
+                Some((_, other)) => errors.push(line.error(&format!(
+                    "label {} is already defined at {}",
+                    label, other.location
+                ))),
+                None => {
+                    labels.insert(label.to_ascii_uppercase(), (address, line));
+                    symbols.insert(label, address);
+                }
+            }
+        }
+        match size(&statement) {
+            Ok(words) => pc = Some(address as u32 + words),
+            Err(message) => errors.push(line.error(&message)),
+        }
+        if mnemonic == Some(".END") {
+            pc = None;
+        }
+        statements.push((line, statement, address));
+    }
+    if !errors.is_empty() {
+        return Err(VmError::AssemblyError(errors.join("\n")));
+    }
+
+    // Second pass: encode every line now that all the labels are known.
+    let labels: HashMap<String, u16> = labels
+        .into_iter()
+        .map(|(name, (address, _))| (name, address))
+        .collect();
+    let mut sections: Vec<Section> = Vec::new();
+    for (line, statement, address) in &statements {
+        if statement.mnemonic.as_deref() == Some(".ORIG") {
+            sections.push(Section {
+                origin: *address,
+                words: Vec::new(),
+            });
+            continue;
+        }
+        match encode(statement, *address, &labels) {
+            Ok(words) => {
+                let source = line
+                    .expansions
+                    .last()
+                    .map_or(&line.location, |outermost| &outermost.call_site);
+                for offset in 0..words.len() {
+                    let address = address.wrapping_add(offset as u16);
+                    symbols.insert_line(address, &source.file, source.line);
+                }
+                if let Some(section) = sections.last_mut() {
+                    section.words.extend(words);
+                }
+            }
+            Err(message) => errors.push(line.error(&message)),
+        }
+    }
+    if !errors.is_empty() {
+        return Err(VmError::AssemblyError(errors.join("\n")));
+    }
+
+    Ok(Assembly { sections, symbols })
+}
+
+/// Checks whether `name` is an instruction or trap alias, in any case.
+pub(crate) fn is_mnemonic(name: &str) -> bool {
+    let upper = name.to_ascii_uppercase();
+    if let Some(conditions) = upper.strip_prefix("BR") {
+        return ["", "N", "Z", "P", "NZ", "NP", "ZP", "NZP"].contains(&conditions);
+    }
+    [
+        "ADD", "AND", "NOT", "JMP", "RET", "JSR", "JSRR", "LD", "LDI", "LDR", "LEA", "ST", "STI",
+        "STR", "TRAP", "RTI", "GETC", "OUT", "PUTS", "IN", "PUTSP", "HALT",
+    ]
+    .contains(&upper.as_str())
+}
+
+fn parse_statement(line: &Line) -> Result<Statement<'_>, String> {
+    let tokens = &line.tokens[..];
+    let Some(first) = tokens.first() else {
+        return Ok(Statement {
+            label: None,
+            mnemonic: None,
+            operands: tokens,
+        });
+    };
+    let (label, rest) = if is_mnemonic(first) || first.starts_with('.') {
+        (None, tokens)
+    } else {
+        let label = first.trim_end_matches(':');
+        if !is_label(label) {
+            return Err(format!("invalid label {}", first));
+        }
+        (Some(label), &tokens[1..])
+    };
+    let mnemonic = match rest.first() {
+        Some(m) if is_mnemonic(m) => Some(m.to_ascii_uppercase()),
+        Some(d) if is_directive(d) => Some(d.to_ascii_uppercase()),
+        Some(other) => return Err(format!("unknown instruction {}", other)),
+        None => None,
+    };
+    Ok(Statement {
+        label,
+        mnemonic,
+        operands: rest.get(1..).unwrap_or(&[]),
+    })
+}
+
+fn is_directive(name: &str) -> bool {
+    [".ORIG", ".END", ".FILL", ".BLKW", ".STRINGZ"].contains(&name.to_ascii_uppercase().as_str())
+}
+
+/// Labels start with a letter, `_` or `@` (local macro labels) and contain letters, digits, `_` and `.`.
+fn is_label(name: &str) -> bool {
+    let mut chars = name.chars();
+    let valid_start = chars
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_' || c == '@');
+    valid_start
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '.')
+        && register(name).is_err()
+        && parse_number(name).is_none()
+}
+
+/// Returns how many words a statement occupies.
+fn size(statement: &Statement) -> Result<u32, String> {
+    Ok(match statement.mnemonic.as_deref() {
+        None | Some(".ORIG" | ".END") => 0,
+        Some(".BLKW") => match statement.operands {
+            [count] => parse_number(count)
+                .filter(|n| (0..=0xFFFF).contains(n))
+                .ok_or_else(|| format!("invalid .BLKW size {}", count))?
+                as u32,
+            _ => return Err(".BLKW expects a word count".to_string()),
+        },
+        Some(".STRINGZ") => match statement.operands {
+            [text] => unquote(text)?.chars().count() as u32 + 1,
+            _ => return Err(".STRINGZ expects a string".to_string()),
+        },
+        Some(_) => 1,
+    })
+}
+
+/// Encodes a statement placed at `address`.
+fn encode(
+    statement: &Statement,
+    address: u16,
+    labels: &HashMap<String, u16>,
+) -> Result<Vec<u16>, String> {
+    let Some(mnemonic) = statement.mnemonic.as_deref() else {
+        return Ok(Vec::new());
+    };
+    let ops = statement.operands;
+    let expect = |count: usize| {
+        if ops.len() == count {
+            Ok(())
+        } else {
+            Err(format!(
+                "{} expects {} operand(s), found {}",
+                mnemonic,
+                count,
+                ops.len()
+            ))
+        }
+    };
+    let offset = |token: &str, bits: u32| pc_offset(token, address, bits, labels);
+
+    let instruction = match mnemonic {
+        ".END" => return Ok(Vec::new()),
+        ".FILL" => {
+            expect(1)?;
+            let value = match parse_word(&ops[0]) {
+                Some(value) => value,
+                None => label_address(&ops[0], labels)?,
+            };
+            return Ok(vec![value]);
+        }
+        ".BLKW" => return Ok(vec![0; size(statement)? as usize]),
+        ".STRINGZ" => {
+            let mut words: Vec<u16> = unquote(&ops[0])?.chars().map(|c| c as u16).collect();
+            words.push(0);
+            return Ok(words);
+        }
+        "ADD" | "AND" => {
+            expect(3)?;
+            let (dr, sr1) = (register(&ops[0])?, register(&ops[1])?);
+            let src2 = match register(&ops[2]) {
+                Ok(sr2) => Operand::Register(sr2),
+                Err(_) => Operand::Immediate(immediate(&ops[2], 5)?),
+            };
+            if mnemonic == "ADD" {
+                Instruction::Add {
+                    dr,
+                    sr1,
+                    src2,
+                    reserved: 0,
+                }
+            } else {
+                Instruction::And {
+                    dr,
+                    sr1,
+                    src2,
+                    reserved: 0,
+                }
+            }
+        }
+        "NOT" => {
+            expect(2)?;
+            Instruction::Not {
+                dr: register(&ops[0])?,
+                sr: register(&ops[1])?,
+                reserved: 0,
+            }
+        }
+        "JMP" => {
+            expect(1)?;
+            Instruction::Jmp {
+                base: register(&ops[0])?,
+                reserved: 0,
+            }
+        }
+        "RET" => {
+            expect(0)?;
+            Instruction::Jmp {
+                base: 7,
+                reserved: 0,
+            }
+        }
+        "JSR" => {
+            expect(1)?;
+            Instruction::Jsr {
+                offset: offset(&ops[0], 11)?,
+            }
+        }
+        "JSRR" => {
+            expect(1)?;
+            Instruction::Jsrr {
+                base: register(&ops[0])?,
+                reserved: 0,
+            }
+        }
+        "LD" | "LDI" | "LEA" | "ST" | "STI" => {
+            expect(2)?;
+            let (r, offset) = (register(&ops[0])?, offset(&ops[1], 9)?);
+            match mnemonic {
+                "LD" => Instruction::Ld { dr: r, offset },
+                "LDI" => Instruction::Ldi { dr: r, offset },
+                "LEA" => Instruction::Lea { dr: r, offset },
+                "ST" => Instruction::St { sr: r, offset },
+                _ => Instruction::Sti { sr: r, offset },
+            }
+        }
+        "LDR" | "STR" => {
+            expect(3)?;
+            let (r, base) = (register(&ops[0])?, register(&ops[1])?);
+            let offset = immediate(&ops[2], 6)?;
+            if mnemonic == "LDR" {
+                Instruction::Ldr {
+                    dr: r,
+                    base,
+                    offset,
+                }
+            } else {
+                Instruction::Str {
+                    sr: r,
+                    base,
+                    offset,
+                }
+            }
+        }
+        "TRAP" => {
+            expect(1)?;
+            let vector = parse_number(&ops[0])
+                .filter(|n| (0..=0xFF).contains(n))
+                .ok_or_else(|| format!("invalid trap vector {}", ops[0]))?;
+            Instruction::Trap {
+                vector: vector as u8,
+                reserved: 0,
+            }
+        }
+        "GETC" | "OUT" | "PUTS" | "IN" | "PUTSP" | "HALT" => {
+            expect(0)?;
+            let vector = ["GETC", "OUT", "PUTS", "IN", "PUTSP", "HALT"]
+                .iter()
+                .position(|alias| *alias == mnemonic)
+                .unwrap_or_default() as u8;
+            Instruction::Trap {
+                vector: 0x20 + vector,
+                reserved: 0,
+            }
+        }
+        "RTI" => {
+            expect(0)?;
+            Instruction::Rti
+        }
+        branch => {
+            expect(1)?;
+            let flags = &branch[2..];
+            let mut conditions = 0;
+            for (flag, bit) in [('N', FL_NEG), ('Z', FL_ZRO), ('P', FL_POS)] {
+                if flags.is_empty() || flags.contains(flag) {
+                    conditions |= bit;
+                }
+            }
+            Instruction::Br {
+                conditions,
+                offset: offset(&ops[0], 9)?,
+            }
+        }
+    };
+    Ok(vec![instruction.encode()])
+}
+
+/// Parses a register operand, `R0` to `R7`.
+fn register(token: &str) -> Result<u16, String> {
+    match token.as_bytes() {
+        [b'R' | b'r', digit @ b'0'..=b'7'] => Ok((digit - b'0') as u16),
+        _ => Err(format!("invalid register {}", token)),
+    }
+}
+
+/// Parses a signed immediate that must fit in `bits` bits.
+fn immediate(token: &str, bits: u32) -> Result<i16, String> {
+    let value = parse_number(token).ok_or_else(|| format!("invalid operand {}", token))?;
+    let limit = 1 << (bits - 1);
+    if value < -limit || value >= limit {
+        return Err(format!(
+            "immediate {} does not fit in {} bits (range {}..{})",
+            token,
+            bits,
+            -limit,
+            limit - 1
+        ));
+    }
+    Ok(value as i16)
+}
+
+/// Computes a PC-relative offset field, from a label or a literal offset.
+fn pc_offset(
+    token: &str,
+    address: u16,
+    bits: u32,
+    labels: &HashMap<String, u16>,
+) -> Result<i16, String> {
+    if parse_number(token).is_some() {
+        return immediate(token, bits);
+    }
+    let target = label_address(token, labels)?;
+    let offset = target as i32 - (address as i32 + 1);
+    let limit = 1 << (bits - 1);
+    if offset < -limit || offset >= limit {
+        return Err(format!(
+            "label {} is too far away (offset {}, range {}..{})",
+            token,
+            offset,
+            -limit,
+            limit - 1
+        ));
+    }
+    Ok(offset as i16)
+}
+
+fn label_address(token: &str, labels: &HashMap<String, u16>) -> Result<u16, String> {
+    labels
+        .get(&token.to_ascii_uppercase())
+        .copied()
+        .ok_or_else(|| format!("undefined label {}", token))
+}
+
+/// The backends, for tests that must pass with each of them.
+#[cfg(test)]
+pub(crate) const BACKENDS: [crate::vm::Backend; 2] =
+    [crate::vm::Backend::Interpreter, crate::vm::Backend::Blocks];
+
+/// Assembles a test program into a new VM with a silent console.
+///
+/// The sections are added to the load map as if they were loaded from an image named `path`,
+/// so checkers can be enabled afterwards the way `main` does.
+///
+/// # Arguments
+///
+/// * `path` - The name of the program, used for error messages and in the load map.
+/// * `text` - The source code, which must assemble without errors.
+///
+#[cfg(test)]
+pub(crate) fn vm_from_source(path: &str, text: &str) -> crate::vm::Vm {
+    use crate::{
+        console::ScriptedConsole,
+        load_map::{LoadedImage, OverlapPolicy},
+        vm::Vm,
+    };
+
+    let assembly = assemble(path, text).unwrap();
+    let mut vm = Vm::new();
+    vm.memory.console = Box::new(ScriptedConsole::new(b""));
+    vm.load_sections(&assembly.sections);
+    let images = assembly
+        .sections
+        .iter()
+        .map(|section| LoadedImage {
+            path: path.to_string(),
+            origin: section.origin,
+            length: section.words.len(),
+        })
+        .collect();
+    vm.load_map.add_all(images, OverlapPolicy::Warn).unwrap();
+    vm.symbols = assembly.symbols;
+    vm
+}
+
+/// Like `vm_from_source`, with the program run by the given backend.
+#[cfg(test)]
+pub(crate) fn vm_on_backend(path: &str, text: &str, backend: crate::vm::Backend) -> crate::vm::Vm {
+    let mut vm = vm_from_source(path, text);
+    vm.backend = backend;
+    vm
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn words(source: &str) -> Vec<u16> {
+        let assembly = assemble("prog.asm", source).unwrap();
+        assembly.sections[0].words.clone()
+    }
+
+    fn error(source: &str) -> String {
+        match assemble("prog.asm", source) {
+            Err(VmError::AssemblyError(message)) => message,
+            other => panic!("expected an assembly error, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn assembles_every_instruction() {
+        let source = "\
+        .ORIG x3000
+START   ADD R1, R2, R3
+        ADD R1, R2, #-1
+        AND R0, R0, #0
+        NOT R4, R5
+        BRnp START
+        BR START
+        JMP R2
+        RET
+        JSR START
+        JSRR R3
+        LD R0, DATA
+        LDI R0, DATA
+        LDR R0, R6, #-2
+        LEA R0, DATA
+        ST R0, DATA
+        STI R0, DATA
+        STR R0, R6, #3
+        TRAP x23
+        PUTS
+        HALT
+        RTI
+DATA    .FILL START
+        .END
+";
+        assert_eq!(
+            words(source),
+            vec![
+                0x1283, 0x12BF, 0x5020, 0x997F, 0x0BFB, 0x0FFA, 0xC080, 0xC1C0, 0x4FF7, 0x40C0,
+                0x200A, 0xA009, 0x61BE, 0xE007, 0x3006, 0xB005, 0x7183, 0xF023, 0xF022, 0xF025,
+                0x8000, 0x3000,
+            ]
+        );
+    }
+
+    #[test]
+    fn assembles_data_directives() {
+        let source = "\
+.ORIG x3000
+.FILL #-1
+.FILL b101
+.BLKW 2
+.STRINGZ \"Hi\\n\"
+.END
+";
+        assert_eq!(
+            words(source),
+            vec![0xFFFF, 0x0005, 0, 0, 0x48, 0x69, 0x0A, 0]
+        );
+    }
+
+    #[test]
+    fn labels_are_recorded_in_the_symbol_table() {
+        let assembly = assemble(
+            "prog.asm",
+            ".ORIG x3000\nLOOP: BRnzp LOOP\nEND_ .FILL 0\n.END\n",
+        )
+        .unwrap();
+
+        assert_eq!(assembly.symbols.address_of("LOOP"), Some(0x3000));
+        assert_eq!(assembly.symbols.address_of("END_"), Some(0x3001));
+    }
+
+    #[test]
+    fn every_word_is_mapped_to_its_source_line() {
+        let source = "\
+.MACRO PUSH reg
+    ADD R6, R6, #-1
+    STR reg, R6, #0
+.ENDM
+.ORIG x3000
+    PUSH R7
+MSG .STRINGZ \"ab\"
+.END
+";
+        let assembly = assemble("prog.asm", source).unwrap();
+        let lines: Vec<(u16, u32)> = assembly
+            .symbols
+            .lines
+            .iter()
+            .map(|(address, line)| (*address, line.line))
+            .collect();
+
+        assert_eq!(
+            lines,
+            vec![
+                (0x3000, 6),
+                (0x3001, 6),
+                (0x3002, 7),
+                (0x3003, 7),
+                (0x3004, 7)
+            ]
+        );
+        assert_eq!(
+            assembly.symbols.format_address(0x3002),
+            "x3002 <MSG> (prog.asm:7)"
+        );
+    }
+
+    #[test]
+    fn several_origins_produce_several_sections() {
+        let assembly = assemble(
+            "prog.asm",
+            ".ORIG x3000\nHALT\n.END\n.ORIG x4000\nMSG .STRINGZ \"a\"\n.END\n",
+        )
+        .unwrap();
+
+        assert_eq!(
+            assembly.sections,
+            vec![
+                Section {
+                    origin: 0x3000,
+                    words: vec![0xF025],
+                },
+                Section {
+                    origin: 0x4000,
+                    words: vec![0x61, 0],
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn reports_every_error_with_its_line() {
+        let message = error(".ORIG x3000\nADD R1, R9, #1\nBR NOWHERE\nAND R0, R0, #16\n.END\n");
+
+        assert_eq!(
+            message,
+            "prog.asm:2: invalid register R9\nprog.asm:3: undefined label NOWHERE\n\
+             prog.asm:4: immediate #16 does not fit in 5 bits (range -16..15)"
+        );
+    }
+
+    #[test]
+    fn first_pass_errors() {
+        assert_eq!(error("HALT\n"), "prog.asm:1: code before .ORIG");
+        assert_eq!(
+            error(".ORIG x3000\nA HALT\nA HALT\n"),
+            "prog.asm:3: label A is already defined at prog.asm:2"
+        );
+        assert_eq!(
+            error(".ORIG x3000\nFOO R1\n"),
+            "prog.asm:2: unknown instruction R1"
+        );
+    }
+
+    #[test]
+    fn a_section_can_end_at_the_top_of_memory() {
+        let assembly = assemble("prog.asm", ".ORIG xFFFE\nHALT\nHALT\n.END\n").unwrap();
+        assert_eq!(assembly.sections[0].words, vec![0xF025, 0xF025]);
+
+        assert_eq!(
+            error(".ORIG xFFFF\nHALT\nHALT\n.END\n"),
+            "prog.asm:3: the program runs past the end of memory"
+        );
+    }
+
+    #[test]
+    fn far_labels_are_errors() {
+        let message = error(".ORIG x3000\nLD R0, FAR\n.BLKW 300\nFAR .FILL 0\n.END\n");
+
+        assert_eq!(
+            message,
+            "prog.asm:2: label FAR is too far away (offset 300, range -256..255)"
+        );
+    }
+
+    #[test]
+    fn macro_errors_point_at_definition_and_call_site() {
+        let source = "\
+.MACRO PUSH reg
+    ADD R6, R6, #-1
+    STR reg, R6, #0
+.ENDM
+.ORIG x3000
+    PUSH RX
+.END
+";
+        assert_eq!(
+            error(source),
+            "prog.asm:3: invalid register RX\n  in macro PUSH (defined at prog.asm:1), called from prog.asm:6"
+        );
+    }
+
+    #[test]
+    fn macros_expand_into_code() {
+        let source = "\
+.MACRO PUSH reg
+    ADD R6, R6, #-1
+    STR reg, R6, #0
+.ENDM
+.ORIG x3000
+    PUSH R7
+.END
+";
+        assert_eq!(words(source), vec![0x1DBF, 0x7F80]);
+    }
+}
@@ -0,0 +1,543 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::vm_error::VmError;
+
+/// How deeply macros can call other macros before the expansion is considered runaway.
+const MAX_EXPANSION_DEPTH: usize = 32;
+
+/// A position in a source file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Location {
+    pub file: String,
+    pub line: u32,
+}
+
+impl fmt::Display for Location {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:{}", self.file, self.line)
+    }
+}
+
+/// One level of macro expansion a line came from.
+///
+/// # Fields
+///
+/// * `name` - The macro that was expanded.
+/// * `definition` - Where the macro was defined.
+/// * `call_site` - The line that called the macro.
+///
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Expansion {
+    pub name: String,
+    pub definition: Location,
+    pub call_site: Location,
+}
+
+/// A line of assembly after includes and macros were expanded.
+///
+/// # Fields
+///
+/// * `tokens` - The label, mnemonic and operands of the line, without comments or commas.
+/// * `location` - The line of source text the tokens come from. For macro expansions,
+///   this is the line of the macro body.
+/// * `expansions` - The macro calls that produced the line, innermost first.
+///
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Line {
+    pub tokens: Vec<String>,
+    pub location: Location,
+    pub expansions: Vec<Expansion>,
+}
+
+impl Line {
+    /// Formats an error about this line, pointing at every macro definition and call site involved.
+    pub fn error(&self, message: &str) -> String {
+        let mut text = format!("{}: {}", self.location, message);
+        for expansion in &self.expansions {
+            text.push_str(&format!(
+                "\n  in macro {} (defined at {}), called from {}",
+                expansion.name, expansion.definition, expansion.call_site
+            ));
+        }
+        text
+    }
+}
+
+/// A macro defined with `.MACRO NAME param1, param2` and `.ENDM`.
+#[derive(Debug, Clone)]
+struct Macro {
+    name: String,
+    params: Vec<String>,
+    body: Vec<(Vec<String>, Location)>,
+    definition: Location,
+}
+
+/// Expands `.INCLUDE` directives and macros.
+///
+/// `.INCLUDE "file.asm"` is replaced by the lines of the file, which is looked up relative
+/// to the including file. Macros are defined with `.MACRO NAME param1, param2` ... `.ENDM`
+/// and called like an instruction, `NAME R1, #4`, optionally after a label. Inside the body,
+/// operands equal to a parameter name are replaced by the arguments, and labels starting
+/// with `@` are local: every expansion gets its own copy, named `@LABEL.n`.
+///
+#[derive(Default)]
+struct Preprocessor {
+    macros: HashMap<String, Macro>,
+    lines: Vec<Line>,
+    errors: Vec<String>,
+    includes: Vec<PathBuf>,
+    expansion_count: usize,
+}
+
+/// Preprocesses an assembly source file.
+///
+/// # Arguments
+///
+/// * `path` - The path of the source file, used for error messages and to resolve includes.
+/// * `text` - The contents of the source file.
+///
+/// # Returns
+///
+/// The expanded lines, or a `VmError::AssemblyError` listing every problem found.
+///
+pub fn preprocess(path: &str, text: &str) -> Result<Vec<Line>, VmError> {
+    let mut preprocessor = Preprocessor::default();
+    preprocessor.includes.push(canonical(Path::new(path)));
+    preprocessor.file(path, text);
+    if preprocessor.errors.is_empty() {
+        Ok(preprocessor.lines)
+    } else {
+        Err(VmError::AssemblyError(preprocessor.errors.join("\n")))
+    }
+}
+
+/// Splits a line into tokens, dropping the comment and the commas between operands.
+///
+/// String literals are kept as a single token, quotes and escapes included.
+///
+pub fn tokenize(text: &str) -> Result<Vec<String>, String> {
+    let mut tokens = Vec::new();
+    let mut chars = text.chars().peekable();
+    while let Some(&c) = chars.peek() {
+        match c {
+            ';' => break,
+            c if c.is_whitespace() || c == ',' => {
+                chars.next();
+            }
+            '"' => {
+                let mut token = String::from('"');
+                chars.next();
+                let mut closed = false;
+                while let Some(c) = chars.next() {
+                    token.push(c);
+                    if c == '\\' {
+                        if let Some(escaped) = chars.next() {
+                            token.push(escaped);
+                        }
+                    } else if c == '"' {
+                        closed = true;
+                        break;
+                    }
+                }
+                if !closed {
+                    return Err("unterminated string".to_string());
+                }
+                tokens.push(token);
+            }
+            _ => {
+                let mut token = String::new();
+                while let Some(&c) = chars.peek() {
+                    if c.is_whitespace() || c == ',' || c == ';' || c == '"' {
+                        break;
+                    }
+                    token.push(c);
+                    chars.next();
+                }
+                tokens.push(token);
+            }
+        }
+    }
+    Ok(tokens)
+}
+
+/// Parses the contents of a string literal token, handling `\n`, `\t`, `\r`, `\0`, `\\` and `\"`.
+pub fn unquote(token: &str) -> Result<String, String> {
+    let inner = token
+        .strip_prefix('"')
+        .and_then(|t| t.strip_suffix('"'))
+        .ok_or_else(|| format!("expected a string, found {}", token))?;
+    let mut text = String::new();
+    let mut chars = inner.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            text.push(c);
+            continue;
+        }
+        text.push(match chars.next() {
+            Some('n') => '\n',
+            Some('t') => '\t',
+            Some('r') => '\r',
+            Some('0') => '\0',
+            Some(c @ ('\\' | '"')) => c,
+            Some(c) => return Err(format!("unknown escape \\{}", c)),
+            None => return Err("unterminated escape".to_string()),
+        });
+    }
+    Ok(text)
+}
+
+impl Preprocessor {
+    fn file(&mut self, path: &str, text: &str) {
+        let mut definition: Option<Macro> = None;
+        for (index, raw) in text.lines().enumerate() {
+            let location = Location {
+                file: path.to_string(),
+                line: index as u32 + 1,
+            };
+            let tokens = match tokenize(raw) {
+                Ok(tokens) => tokens,
+                Err(message) => {
+                    self.errors.push(format!("{}: {}", location, message));
+                    continue;
+                }
+            };
+            let directive = tokens.first().map(|t| t.to_ascii_uppercase());
+
+            if let Some(current) = definition.as_mut() {
+                match directive.as_deref() {
+                    Some(".ENDM") => {}
+                    Some(".MACRO") => self.errors.push(format!(
+                        "{}: macro definitions cannot be nested (inside {} defined at {})",
+                        location, current.name, current.definition
+                    )),
+                    _ => current.body.push((tokens, location)),
+                }
+                if directive.as_deref() == Some(".ENDM") {
+                    if let Some(finished) = definition.take() {
+                        self.macros
+                            .insert(finished.name.to_ascii_uppercase(), finished);
+                    }
+                }
+                continue;
+            }
+
+            match directive.as_deref() {
+                Some(".INCLUDE") => self.include(path, &tokens, &location),
+                Some(".MACRO") => match self.define(&tokens, &location) {
+                    Ok(started) => definition = Some(started),
+                    Err(message) => self.errors.push(format!("{}: {}", location, message)),
+                },
+                Some(".ENDM") => self
+                    .errors
+                    .push(format!("{}: .ENDM without .MACRO", location)),
+                _ => self.line(tokens, location, Vec::new()),
+            }
+        }
+        if let Some(unfinished) = definition {
+            self.errors.push(format!(
+                "{}: macro {} is missing .ENDM",
+                unfinished.definition, unfinished.name
+            ));
+        }
+    }
+
+    fn include(&mut self, from: &str, tokens: &[String], location: &Location) {
+        let name = match tokens {
+            [_, name] => unquote(name),
+            _ => Err(".INCLUDE expects a file name in quotes".to_string()),
+        };
+        let name = match name {
+            Ok(name) => name,
+            Err(message) => return self.errors.push(format!("{}: {}", location, message)),
+        };
+        let path = Path::new(from)
+            .parent()
+            .unwrap_or(Path::new(""))
+            .join(&name);
+        let key = canonical(&path);
+        if self.includes.contains(&key) {
+            return self
+                .errors
+                .push(format!("{}: {} includes itself", location, name));
+        }
+        match fs::read_to_string(&path) {
+            Ok(text) => {
+                self.includes.push(key);
+                self.file(&path.to_string_lossy(), &text);
+                self.includes.pop();
+            }
+            Err(e) => self
+                .errors
+                .push(format!("{}: cannot include {}: {}", location, name, e)),
+        }
+    }
+
+    fn define(&self, tokens: &[String], location: &Location) -> Result<Macro, String> {
+        let name = tokens
+            .get(1)
+            .ok_or_else(|| ".MACRO expects a name".to_string())?;
+        if super::is_mnemonic(name) || name.starts_with('.') {
+            return Err(format!("{} cannot be used as a macro name", name));
+        }
+        if let Some(existing) = self.macros.get(&name.to_ascii_uppercase()) {
+            return Err(format!(
+                "macro {} is already defined at {}",
+                name, existing.definition
+            ));
+        }
+        Ok(Macro {
+            name: name.clone(),
+            params: tokens[2..].to_vec(),
+            body: Vec::new(),
+            definition: location.clone(),
+        })
+    }
+
+    /// Emits a line, expanding it if it calls a macro.
+    fn line(&mut self, tokens: Vec<String>, location: Location, expansions: Vec<Expansion>) {
+        let is_macro = |token: &String| self.macros.contains_key(&token.to_ascii_uppercase());
+        let call = match &tokens[..] {
+            [first, ..] if is_macro(first) => Some(0),
+            [label, second, ..]
+                if is_macro(second) && !super::is_mnemonic(label) && !label.starts_with('.') =>
+            {
+                Some(1)
+            }
+            _ => None,
+        };
+        let Some(position) = call else {
+            let line = Line {
+                tokens,
+                location,
+                expansions,
+            };
+            let local = line.tokens.iter().find(|t| t.starts_with('@'));
+            if let Some(local) = local.filter(|_| line.expansions.is_empty()) {
+                let message = line.error(&format!("local label {} used outside a macro", local));
+                self.errors.push(message);
+            } else {
+                self.lines.push(line);
+            }
+            return;
+        };
+
+        if position == 1 {
+            self.lines.push(Line {
+                tokens: tokens[..1].to_vec(),
+                location: location.clone(),
+                expansions: expansions.clone(),
+            });
+        }
+        let definition = self.macros[&tokens[position].to_ascii_uppercase()].clone();
+        let caller = Line {
+            tokens: tokens.clone(),
+            location: location.clone(),
+            expansions: expansions.clone(),
+        };
+        let args = &tokens[position + 1..];
+        if args.len() != definition.params.len() {
+            let message = caller.error(&format!(
+                "macro {} (defined at {}) takes {} argument(s), {} given",
+                definition.name,
+                definition.definition,
+                definition.params.len(),
+                args.len()
+            ));
+            return self.errors.push(message);
+        }
+        if expansions.len() >= MAX_EXPANSION_DEPTH {
+            let message = caller.error(&format!(
+                "macro {} expands too deeply (recursive macro?)",
+                definition.name
+            ));
+            return self.errors.push(message);
+        }
+
+        self.expansion_count += 1;
+        let mut inner = vec![Expansion {
+            name: definition.name.clone(),
+            definition: definition.definition.clone(),
+            call_site: location,
+        }];
+        inner.extend(expansions);
+        for (body, body_location) in &definition.body {
+            let tokens = body
+                .iter()
+                .map(|token| {
+                    if let Some(i) = definition
+                        .params
+                        .iter()
+                        .position(|p| p.eq_ignore_ascii_case(token))
+                    {
+                        args[i].clone()
+                    } else if token.starts_with('@') {
+                        format!("{}.{}", token, self.expansion_count)
+                    } else {
+                        token.clone()
+                    }
+                })
+                .collect();
+            self.line(tokens, body_location.clone(), inner.clone());
+        }
+    }
+}
+
+/// Normalizes a path so the same file included through different routes is recognized.
+fn canonical(path: &Path) -> PathBuf {
+    fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tokens(lines: &[Line]) -> Vec<String> {
+        lines.iter().map(|l| l.tokens.join(" ")).collect()
+    }
+
+    #[test]
+    fn tokenize_drops_comments_and_commas() {
+        assert_eq!(
+            tokenize("LOOP ADD R1, R1, #-1 ; count down").unwrap(),
+            vec!["LOOP", "ADD", "R1", "R1", "#-1"]
+        );
+        assert_eq!(
+            tokenize(".STRINGZ \"a; \\\"b\\\"\"").unwrap(),
+            vec![".STRINGZ", "\"a; \\\"b\\\"\""]
+        );
+        assert!(tokenize(".STRINGZ \"open").is_err());
+    }
+
+    #[test]
+    fn unquote_handles_escapes() {
+        assert_eq!(unquote("\"a\\n\\\"b\\\"\"").unwrap(), "a\n\"b\"");
+        assert!(unquote("\"\\q\"").is_err());
+    }
+
+    #[test]
+    fn expands_macros_with_arguments_and_local_labels() {
+        let source = "\
+.MACRO PUSH reg
+    ADD R6, R6, #-1
+    STR reg, R6, #0
+.ENDM
+.MACRO SKIP
+    BRnzp @DONE
+@DONE ADD R0, R0, #0
+.ENDM
+START PUSH R1
+    PUSH R7
+    SKIP
+    SKIP
+";
+        let lines = preprocess("prog.asm", source).unwrap();
+
+        assert_eq!(
+            tokens(&lines),
+            vec![
+                "START",
+                "ADD R6 R6 #-1",
+                "STR R1 R6 #0",
+                "ADD R6 R6 #-1",
+                "STR R7 R6 #0",
+                "BRnzp @DONE.3",
+                "@DONE.3 ADD R0 R0 #0",
+                "BRnzp @DONE.4",
+                "@DONE.4 ADD R0 R0 #0",
+            ]
+        );
+        assert_eq!(lines[2].location.line, 3);
+        assert_eq!(lines[2].expansions[0].call_site.line, 9);
+    }
+
+    #[test]
+    fn errors_point_at_definition_and_call_site() {
+        let source = "\
+.MACRO PUSH reg
+    STR reg, R6, #0
+.ENDM
+    PUSH R1, R2
+";
+        match preprocess("prog.asm", source) {
+            Err(VmError::AssemblyError(message)) => assert_eq!(
+                message,
+                "prog.asm:4: macro PUSH (defined at prog.asm:1) takes 1 argument(s), 2 given"
+            ),
+            other => panic!("expected an assembly error, got {:?}", other),
+        }
+
+        let line = Line {
+            tokens: Vec::new(),
+            location: Location {
+                file: "prog.asm".to_string(),
+                line: 2,
+            },
+            expansions: vec![Expansion {
+                name: "PUSH".to_string(),
+                definition: Location {
+                    file: "prog.asm".to_string(),
+                    line: 1,
+                },
+                call_site: Location {
+                    file: "prog.asm".to_string(),
+                    line: 4,
+                },
+            }],
+        };
+        assert_eq!(
+            line.error("invalid register RX"),
+            "prog.asm:2: invalid register RX\n  in macro PUSH (defined at prog.asm:1), called from prog.asm:4"
+        );
+    }
+
+    #[test]
+    fn recursive_macros_and_stray_directives_are_errors() {
+        let source = "\
+.MACRO LOOP
+    LOOP
+.ENDM
+    LOOP
+.ENDM
+.MACRO OPEN
+";
+        match preprocess("prog.asm", source) {
+            Err(VmError::AssemblyError(message)) => {
+                assert!(message.contains("expands too deeply"));
+                assert!(message.contains("prog.asm:5: .ENDM without .MACRO"));
+                assert!(message.contains("prog.asm:6: macro OPEN is missing .ENDM"));
+            }
+            other => panic!("expected an assembly error, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn includes_files_relative_to_the_includer() {
+        let dir = std::env::temp_dir().join("lc3_preprocessor_include");
+        std::fs::create_dir_all(dir.join("lib")).unwrap();
+        std::fs::write(
+            dir.join("lib/stack.asm"),
+            ".MACRO POP reg\n    LDR reg, R6, #0\n    ADD R6, R6, #1\n.ENDM\n",
+        )
+        .unwrap();
+        std::fs::write(dir.join("lib/self.asm"), ".INCLUDE \"self.asm\"\n").unwrap();
+        let path = dir.join("prog.asm").to_string_lossy().to_string();
+
+        let lines = preprocess(&path, ".INCLUDE \"lib/stack.asm\"\n    POP R3\n");
+        let cycle = preprocess(&path, ".INCLUDE \"lib/self.asm\"\n");
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(
+            tokens(&lines.unwrap()),
+            vec!["LDR R3 R6 #0", "ADD R6 R6 #1"]
+        );
+        assert!(matches!(cycle, Err(VmError::AssemblyError(m)) if m.contains("includes itself")));
+    }
+
+    #[test]
+    fn local_labels_outside_macros_are_errors() {
+        assert!(preprocess("prog.asm", "@X ADD R0, R0, #0\n").is_err());
+    }
+}
@@ -0,0 +1,377 @@
+use crate::{
+    constants::DEVICE_PAGE_START, instruction::Instruction, registers::Registers, shadow::Location,
+    utils::parse_word, vm::Vm, vm_error::VmError,
+};
+
+/// The return address given to a called routine when the options do not set one. It is the
+/// last word of the device page, which holds no device register and no code.
+pub const CALL_SENTINEL: u16 = 0xFFFF;
+
+/// The instruction budget of a call when the options do not set one.
+pub const DEFAULT_CALL_STEPS: u64 = 100_000;
+
+/// The most arguments a call can pass, in `R0` to `R5`.
+pub const MAX_CALL_ARGS: usize = 6;
+
+/// The routine to call: an address, or a label of the loaded symbol tables.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Routine<'a> {
+    Address(u16),
+    Symbol(&'a str),
+}
+
+impl From<u16> for Routine<'_> {
+    fn from(address: u16) -> Self {
+        Routine::Address(address)
+    }
+}
+
+impl<'a> From<&'a str> for Routine<'a> {
+    fn from(symbol: &'a str) -> Self {
+        Routine::Symbol(symbol)
+    }
+}
+
+/// Options of `Vm::call_with`.
+///
+/// # Fields
+///
+/// * `stack` - When set, `R6` is set to this address before the call, for routines that use
+///   a stack.
+/// * `max_steps` - The most instructions the routine may execute.
+/// * `sentinel` - The return address placed in `R7`; the call ends when a `RET` jumps to it.
+///
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CallOptions {
+    pub stack: Option<u16>,
+    pub max_steps: u64,
+    pub sentinel: u16,
+}
+
+impl Default for CallOptions {
+    fn default() -> Self {
+        CallOptions {
+            stack: None,
+            max_steps: DEFAULT_CALL_STEPS,
+            sentinel: CALL_SENTINEL,
+        }
+    }
+}
+
+/// A memory cell changed by a call.
+///
+/// # Fields
+///
+/// * `address` - The address of the cell.
+/// * `old` - Its value before the call.
+/// * `new` - Its value after the call.
+///
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MemoryChange {
+    pub address: u16,
+    pub old: u16,
+    pub new: u16,
+}
+
+/// What a routine left behind when it returned.
+///
+/// # Fields
+///
+/// * `registers` - The registers right after the `RET`, with the PC at the sentinel.
+/// * `changes` - Every memory cell outside the device page whose value changed, by address.
+/// * `steps` - How many instructions the routine executed, the `RET` included.
+///
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CallResult {
+    pub registers: Registers,
+    pub changes: Vec<MemoryChange>,
+    pub steps: u64,
+}
+
+impl Vm {
+    /// Calls a subroutine with the default options.
+    ///
+    /// See `call_with`.
+    ///
+    pub fn call<'a>(
+        &mut self,
+        routine: impl Into<Routine<'a>>,
+        args: &[u16],
+    ) -> Result<CallResult, VmError> {
+        self.call_with(routine, args, &CallOptions::default())
+    }
+
+    /// Calls a subroutine of the loaded program as if it was reached with `JSR`, and runs it
+    /// until it returns.
+    ///
+    /// The arguments go in `R0`, `R1` and so on, `R6` is set to the stack of the options if
+    /// there is one, and `R7` holds the sentinel address. The call ends when a `RET` (a `JMP R7`)
+    /// jumps to the sentinel. The other registers and memory are left as they are, so a
+    /// test can set up more state before the call. When the calling convention is checked,
+    /// the call is pushed on its shadow call stack like a `JSR`.
+    ///
+    /// # Arguments
+    ///
+    /// * `routine` - The address or the label of the routine.
+    /// * `args` - At most `MAX_CALL_ARGS` arguments.
+    /// * `options` - The stack, the instruction budget and the sentinel.
+    ///
+    /// # Returns
+    ///
+    /// The registers and the memory changes at the return, or a `VmError::CallFailed` if the
+    /// routine is unknown, halts, runs over its budget or escapes: stops on an illegal
+    /// instruction, reaches the sentinel without a `RET`, or jumps outside the loaded images.
+    /// Errors of the machine itself, such as a watchpoint hit, are returned as they are.
+    ///
+    pub fn call_with<'a>(
+        &mut self,
+        routine: impl Into<Routine<'a>>,
+        args: &[u16],
+        options: &CallOptions,
+    ) -> Result<CallResult, VmError> {
+        let address = match routine.into() {
+            Routine::Address(address) => address,
+            Routine::Symbol(name) => self
+                .symbols
+                .address_of(name)
+                .or_else(|| parse_word(name))
+                .ok_or_else(|| VmError::CallFailed(format!("unknown routine {}", name)))?,
+        };
+        if args.len() > MAX_CALL_ARGS {
+            return Err(VmError::CallFailed(format!(
+                "{} arguments given, at most {} fit in R0-R5",
+                args.len(),
+                MAX_CALL_ARGS
+            )));
+        }
+
+        for (r, arg) in args.iter().enumerate() {
+            self.registers[r as u16] = *arg;
+        }
+        if let Some(stack) = options.stack {
+            self.registers[6] = stack;
+        }
+        self.registers[7] = options.sentinel;
+        self.registers.pc = address;
+        if let Some(shadow) = self.shadow.as_mut() {
+            let stack = options.stack.map(|_| 6);
+            for r in (0..args.len() as u16).chain(stack).chain([7]) {
+                shadow.initialize(Location::Register(r));
+            }
+        }
+        if let Some(checker) = self.convention.as_mut() {
+            checker.call(address, options.sentinel, self.registers.gpr);
+        }
+        let before = self.memory.memory.to_vec();
+
+        let mut running = true;
+        let mut steps = 0;
+        loop {
+            if steps >= options.max_steps {
+                return Err(VmError::CallFailed(format!(
+                    "{} did not return within {} instructions",
+                    self.symbols.format_address(address),
+                    options.max_steps
+                )));
+            }
+            self.step(&mut running)?;
+            steps += 1;
+            let at = self.memory.last_fetch;
+            let instruction = Instruction::decode(self.memory.memory[at as usize]);
+            if !running {
+                let reason = match instruction {
+                    Instruction::Trap { .. } => "halted",
+                    _ => "stopped on an illegal instruction",
+                };
+                return Err(self.call_failed(reason, at));
+            }
+            if self.registers.pc == options.sentinel {
+                if matches!(instruction, Instruction::Jmp { base: 7, .. }) {
+                    break;
+                }
+                return Err(self.call_failed("reached the return address without a RET", at));
+            }
+            if !self.load_map.images.is_empty()
+                && self.load_map.image_for(self.registers.pc).is_none()
+            {
+                let target = self.symbols.format_address(self.registers.pc);
+                return Err(self.call_failed(
+                    &format!("jumped to {}, outside the loaded images", target),
+                    at,
+                ));
+            }
+        }
+
+        let changes = (0..DEVICE_PAGE_START)
+            .filter(|&a| before[a as usize] != self.memory.memory[a as usize])
+            .map(|address| MemoryChange {
+                address,
+                old: before[address as usize],
+                new: self.memory.memory[address as usize],
+            })
+            .collect();
+        Ok(CallResult {
+            registers: self.registers.clone(),
+            changes,
+            steps,
+        })
+    }
+
+    fn call_failed(&self, reason: &str, at: u16) -> VmError {
+        VmError::CallFailed(format!(
+            "the routine {} at {}",
+            reason,
+            self.symbols.format_address(at)
+        ))
+    }
+}
+
+/// Routines for the tests of `call` and of the properties built on it, some of them broken
+/// on purpose.
+#[cfg(test)]
+pub(crate) mod fixtures {
+    use crate::{assembler::vm_from_source, vm::Vm};
+
+    pub(crate) const ROUTINES: &str = "\
+        .ORIG x3000
+; R0 = R0 * R1, saving R1 on the stack.
+MULT    ADD R6, R6, #-1
+        STR R1, R6, #0
+        AND R2, R2, #0
+MLOOP   ADD R1, R1, #0
+        BRz MDONE
+        ADD R2, R2, R0
+        ADD R1, R1, #-1
+        BRnzp MLOOP
+MDONE   ADD R0, R2, #0
+        LDR R1, R6, #0
+        ADD R6, R6, #1
+        ST R0, LAST
+        RET
+LAST    .FILL #0
+STOP    HALT
+SPIN    BRnzp SPIN
+BAD     .FILL xD000
+JUMP    JMP R0
+; R0 = the length of the string at R0, but a space ends the string too.
+STRLEN  AND R1, R1, #0
+SLOOP   LDR R2, R0, #0
+        BRz SDONE
+        ADD R2, R2, #-16
+        ADD R2, R2, #-16
+        BRz SDONE
+        ADD R1, R1, #1
+        ADD R0, R0, #1
+        BRnzp SLOOP
+SDONE   ADD R0, R1, #0
+        RET
+; R0 = |R0|, except that even negative numbers are left as they are.
+ABS     ADD R0, R0, #0
+        BRzp ADONE
+        AND R1, R0, #1
+        BRz ADONE
+        NOT R0, R0
+        ADD R0, R0, #1
+ADONE   RET
+        .END
+";
+
+    /// Creates a VM with `ROUTINES` loaded from `routines.asm`.
+    pub(crate) fn routines() -> Vm {
+        vm_from_source("routines.asm", ROUTINES)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use fixtures::routines;
+
+    #[test]
+    fn call_returns_registers_and_memory_changes() {
+        let mut vm = routines();
+        let options = CallOptions {
+            stack: Some(0x4000),
+            ..CallOptions::default()
+        };
+
+        let result = vm.call_with("MULT", &[6, 7], &options).unwrap();
+
+        assert_eq!(result.registers[0], 42);
+        assert_eq!(result.registers[1], 7);
+        assert_eq!(result.registers[6], 0x4000);
+        assert_eq!(result.registers.pc, CALL_SENTINEL);
+        assert_eq!(
+            result.changes,
+            vec![
+                MemoryChange {
+                    address: vm.symbols.address_of("LAST").unwrap(),
+                    old: 0,
+                    new: 42
+                },
+                MemoryChange {
+                    address: 0x3FFF,
+                    old: 0,
+                    new: 7
+                },
+            ]
+        );
+        assert_eq!(result.steps, 3 + 5 * 7 + 2 + 5);
+    }
+
+    #[test]
+    fn call_by_address() {
+        let mut vm = routines();
+        vm.registers[6] = 0x4000;
+
+        let result = vm.call(0x3000, &[3, 0]).unwrap();
+
+        assert_eq!(result.registers[0], 0);
+        assert!(vm.call("NOWHERE", &[]).is_err());
+        assert!(vm.call(0x3000, &[0; 7]).is_err());
+    }
+
+    fn call_error(vm: &mut Vm, routine: &str, args: &[u16], options: &CallOptions) -> String {
+        match vm.call_with(routine, args, options) {
+            Err(VmError::CallFailed(message)) => message,
+            other => panic!("expected a failed call, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn halting_looping_and_escaping_routines_fail() {
+        let mut vm = routines();
+        let options = CallOptions {
+            max_steps: 50,
+            ..CallOptions::default()
+        };
+
+        assert_eq!(
+            call_error(&mut vm, "STOP", &[], &options),
+            "the routine halted at x300E <STOP> (routines.asm:17)"
+        );
+        assert_eq!(
+            call_error(&mut vm, "SPIN", &[], &options),
+            "x300F <SPIN> (routines.asm:18) did not return within 50 instructions"
+        );
+        assert_eq!(
+            call_error(&mut vm, "JUMP", &[CALL_SENTINEL], &options),
+            "the routine reached the return address without a RET at x3011 <JUMP> (routines.asm:20)"
+        );
+        assert_eq!(
+            call_error(&mut vm, "JUMP", &[0x3010], &options),
+            "the routine stopped on an illegal instruction at x3010 <BAD> (routines.asm:19)"
+        );
+    }
+
+    #[test]
+    fn jumping_outside_the_loaded_images_is_an_escape() {
+        let mut vm = routines();
+
+        assert_eq!(
+            call_error(&mut vm, "JUMP", &[0x5000], &CallOptions::default()),
+            "the routine jumped to x5000, outside the loaded images at x3011 <JUMP> (routines.asm:20)"
+        );
+        assert!(vm.call("MULT", &[2, 2]).is_ok());
+    }
+}
@@ -0,0 +1,114 @@
+use std::{cell::RefCell, collections::VecDeque, fmt, io::Read, rc::Rc};
+
+use crate::{utils::flush_stdout, vm_error::VmError};
+
+/// The keyboard and display of the machine, used by the console traps and the keyboard
+/// device registers.
+///
+/// `Terminal` talks to the real terminal. `ScriptedConsole` reads keys from a fixed input
+/// and records the output, for tests and for running a program without a terminal.
+///
+pub trait Console: fmt::Debug {
+    /// Reads one character from the keyboard, waiting until one is available.
+    ///
+    /// # Returns
+    ///
+    /// The character, `None` at the end of the input, or a `VmError` if reading failed.
+    ///
+    fn read_char(&mut self) -> Result<Option<u8>, VmError>;
+
+    /// Writes text to the display.
+    ///
+    /// # Returns
+    ///
+    /// An `Ok` result if the text was written, otherwise a `VmError`.
+    ///
+    fn write(&mut self, text: &str) -> Result<(), VmError>;
+}
+
+/// The console of the process: standard input and standard output.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Terminal;
+
+impl Console for Terminal {
+    fn read_char(&mut self) -> Result<Option<u8>, VmError> {
+        let mut buffer = [0; 1];
+        let read = std::io::stdin()
+            .read(&mut buffer)
+            .map_err(|e| VmError::FailedToReadStdin(e.to_string()))?;
+        Ok((read == 1).then_some(buffer[0]))
+    }
+
+    fn write(&mut self, text: &str) -> Result<(), VmError> {
+        print!("{}", text);
+        flush_stdout()
+    }
+}
+
+/// A console with scripted keyboard input whose output is recorded instead of printed.
+///
+/// The output is shared with the handle returned by `output`, so it can still be read after
+/// the console was moved into a machine.
+///
+/// # Fields
+///
+/// * `input` - The characters not read yet.
+/// * `output` - Everything written so far.
+///
+#[derive(Debug, Clone, Default)]
+pub struct ScriptedConsole {
+    input: VecDeque<u8>,
+    output: Rc<RefCell<String>>,
+}
+
+impl ScriptedConsole {
+    /// Creates a console that will type `input` and then report the end of the input.
+    pub fn new(input: &[u8]) -> ScriptedConsole {
+        ScriptedConsole {
+            input: input.iter().copied().collect(),
+            output: Rc::default(),
+        }
+    }
+
+    /// Returns a handle to the recorded output.
+    pub fn output(&self) -> Rc<RefCell<String>> {
+        Rc::clone(&self.output)
+    }
+}
+
+impl Console for ScriptedConsole {
+    fn read_char(&mut self) -> Result<Option<u8>, VmError> {
+        Ok(self.input.pop_front())
+    }
+
+    fn write(&mut self, text: &str) -> Result<(), VmError> {
+        self.output.borrow_mut().push_str(text);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn scripted_console_reads_its_input_then_ends() {
+        let mut console = ScriptedConsole::new(b"ab");
+
+        assert_eq!(console.read_char().unwrap(), Some(b'a'));
+        assert_eq!(console.read_char().unwrap(), Some(b'b'));
+        assert_eq!(console.read_char().unwrap(), None);
+    }
+
+    #[test]
+    fn scripted_console_output_outlives_the_console() {
+        let mut console = ScriptedConsole::new(b"");
+        let output = console.output();
+
+        console.write("Hello, ").unwrap();
+        console.write("world").unwrap();
+        drop(console);
+
+        assert_eq!(output.borrow().as_str(), "Hello, world");
+    }
+}
@@ -85,6 +85,20 @@ pub const TRAP_PUTSP: u16 = 0x24;
 /// Trap code for halting the program.
 pub const TRAP_HALT: u16 = 0x25;
 
+// MEMORY REGIONS
+
+/// Last address of the trap vector table, which starts at `x0000`.
+pub const TRAP_VECTOR_TABLE_END: u16 = 0x00FF;
+
+/// First address of the interrupt vector table.
+pub const INTERRUPT_VECTOR_TABLE_START: u16 = 0x0100;
+
+/// Last address of the interrupt vector table.
+pub const INTERRUPT_VECTOR_TABLE_END: u16 = 0x01FF;
+
+/// First address of the device register page, which runs to `xFFFF`.
+pub const DEVICE_PAGE_START: u16 = 0xFE00;
+
 // MEMORY MAPPED REGISTERS
 
 /// Memory-mapped register for the keyboard status.
@@ -0,0 +1,384 @@
+use std::collections::BTreeSet;
+
+use crate::{
+    instruction::Instruction,
+    symbols::{Describe, SymbolTable},
+    vm::Vm,
+};
+
+/// The registers a routine must preserve when the checker is not told otherwise.
+pub const DEFAULT_CALLEE_SAVED: [u16; 5] = [1, 2, 3, 4, 5];
+
+/// A subroutine call that has not returned yet.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Frame {
+    routine: u16,
+    return_address: u16,
+    registers: [u16; 8],
+}
+
+/// A breach of the calling convention, found when a routine returns.
+///
+/// Every variant holds `pc`, the address of the `RET` that found it.
+///
+/// * `WrongReturn` - The `RET` jumped to `actual` while the innermost call of `routine`
+///   expected `expected`, usually because a nested `JSR` clobbered `R7`.
+/// * `ReturnWithoutCall` - The `RET` jumped to `target` while no call was active.
+/// * `ClobberedRegister` - `routine` returned with a callee-saved register changed.
+/// * `UnbalancedStack` - `routine` returned with `R6` elsewhere than where it was at the call.
+///
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Violation {
+    WrongReturn {
+        pc: u16,
+        routine: u16,
+        expected: u16,
+        actual: u16,
+    },
+    ReturnWithoutCall {
+        pc: u16,
+        target: u16,
+    },
+    ClobberedRegister {
+        pc: u16,
+        routine: u16,
+        register: u16,
+        before: u16,
+        after: u16,
+    },
+    UnbalancedStack {
+        pc: u16,
+        routine: u16,
+        before: u16,
+        after: u16,
+    },
+}
+
+impl Describe for Violation {
+    fn describe(&self, symbols: &SymbolTable) -> String {
+        let at = |address: u16| symbols.format_address(address);
+        match *self {
+            Violation::WrongReturn {
+                pc,
+                routine,
+                expected,
+                actual,
+            } => format!(
+                "RET at {} returned to {}, but {} was called to return to {}",
+                at(pc),
+                at(actual),
+                at(routine),
+                at(expected)
+            ),
+            Violation::ReturnWithoutCall { pc, target } => format!(
+                "RET at {} returned to {} without a matching JSR",
+                at(pc),
+                at(target)
+            ),
+            Violation::ClobberedRegister {
+                pc,
+                routine,
+                register,
+                before,
+                after,
+            } => format!(
+                "{} changed callee-saved R{} from x{:04X} to x{:04X} (RET at {})",
+                at(routine),
+                register,
+                before,
+                after,
+                at(pc)
+            ),
+            Violation::UnbalancedStack {
+                pc,
+                routine,
+                before,
+                after,
+            } => format!(
+                "{} returned with R6 at x{:04X} instead of x{:04X} (RET at {})",
+                at(routine),
+                after,
+                before,
+                at(pc)
+            ),
+        }
+    }
+}
+
+impl Violation {
+    /// Identifies the kind of violation at its `RET`, so each is reported once.
+    fn key(&self) -> (u16, u16) {
+        match *self {
+            Violation::ClobberedRegister { pc, register, .. } => (pc, register),
+            Violation::WrongReturn { pc, .. } => (pc, 8),
+            Violation::ReturnWithoutCall { pc, .. } => (pc, 9),
+            Violation::UnbalancedStack { pc, .. } => (pc, 10),
+        }
+    }
+}
+
+/// Checks the `JSR`/`RET` discipline of a running program with a shadow call stack.
+///
+/// Every `JSR` and `JSRR` pushes a frame with the return address and the registers at the
+/// call, and every `RET` (`JMP R7`) is checked against the innermost frame: it must return to
+/// the address after the call, with the callee-saved registers and `R6` unchanged. A `RET`
+/// that returns to an outer frame pops the frames it skipped; one that matches no frame
+/// leaves the stack as it is. Each kind of violation is reported once per `RET`.
+///
+/// # Fields
+///
+/// * `callee_saved` - The registers a routine must restore before it returns.
+/// * `check_stack` - Whether `R6` must be back at its value of the call when a routine returns.
+/// * `violations` - The violations found so far, in the order they happened.
+///
+#[derive(Debug, Clone)]
+pub struct ConventionChecker {
+    pub callee_saved: Vec<u16>,
+    pub check_stack: bool,
+    pub violations: Vec<Violation>,
+    frames: Vec<Frame>,
+    reported: BTreeSet<(u16, u16)>,
+}
+
+impl Default for ConventionChecker {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ConventionChecker {
+    /// Creates a checker with `DEFAULT_CALLEE_SAVED` and the stack check enabled.
+    pub fn new() -> ConventionChecker {
+        ConventionChecker {
+            callee_saved: DEFAULT_CALLEE_SAVED.to_vec(),
+            check_stack: true,
+            violations: Vec::new(),
+            frames: Vec::new(),
+            reported: BTreeSet::new(),
+        }
+    }
+
+    /// Returns how many calls are active.
+    pub fn depth(&self) -> usize {
+        self.frames.len()
+    }
+
+    /// Records a call, as if a `JSR` to `routine` was just executed.
+    ///
+    /// # Arguments
+    ///
+    /// * `routine` - The address of the called routine.
+    /// * `return_address` - The address the routine must return to.
+    /// * `registers` - `R0`-`R7` at the call, before `R7` is overwritten.
+    ///
+    pub fn call(&mut self, routine: u16, return_address: u16, registers: [u16; 8]) {
+        self.frames.push(Frame {
+            routine,
+            return_address,
+            registers,
+        });
+    }
+
+    /// Checks a `RET` at `pc` that jumps to `target`, with `registers` as they are at the `RET`.
+    pub fn ret(&mut self, pc: u16, target: u16, registers: [u16; 8]) {
+        let Some(frame) = self.frames.last() else {
+            self.report(Violation::ReturnWithoutCall { pc, target });
+            return;
+        };
+        if frame.return_address != target {
+            self.report(Violation::WrongReturn {
+                pc,
+                routine: frame.routine,
+                expected: frame.return_address,
+                actual: target,
+            });
+            match self.frames.iter().rposition(|f| f.return_address == target) {
+                Some(outer) => self.frames.truncate(outer + 1),
+                None => return,
+            }
+        }
+
+        let frame = self.frames.pop().unwrap();
+        let clobbered: Vec<u16> = self
+            .callee_saved
+            .iter()
+            .copied()
+            .filter(|&r| frame.registers[r as usize] != registers[r as usize])
+            .collect();
+        for register in clobbered {
+            self.report(Violation::ClobberedRegister {
+                pc,
+                routine: frame.routine,
+                register,
+                before: frame.registers[register as usize],
+                after: registers[register as usize],
+            });
+        }
+        let (before, after) = (frame.registers[6], registers[6]);
+        if self.check_stack && before != after {
+            self.report(Violation::UnbalancedStack {
+                pc,
+                routine: frame.routine,
+                before,
+                after,
+            });
+        }
+    }
+
+    fn report(&mut self, violation: Violation) {
+        if self.reported.insert(violation.key()) {
+            self.violations.push(violation);
+        }
+    }
+}
+
+impl Vm {
+    /// Updates the shadow call stack with an instruction about to be executed, checking
+    /// returns against it. Does nothing when the checker is disabled.
+    ///
+    /// # Parameters
+    ///
+    /// - `pc`: The address of the instruction.
+    /// - `instruction`: The instruction about to be executed.
+    ///
+    pub(crate) fn check_convention(&mut self, pc: u16, instruction: Instruction) {
+        let Some(checker) = self.convention.as_mut() else {
+            return;
+        };
+        let registers = self.registers.gpr;
+        let return_address = pc.wrapping_add(1);
+        match instruction {
+            Instruction::Jsr { offset } => {
+                let routine = return_address.wrapping_add(offset as u16);
+                checker.call(routine, return_address, registers);
+            }
+            Instruction::Jsrr { base, .. } => {
+                checker.call(registers[base as usize], return_address, registers)
+            }
+            Instruction::Jmp { base: 7, .. } => checker.ret(pc, registers[7], registers),
+            _ => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assembler::{vm_from_source, BACKENDS};
+
+    fn violations(vm: &mut Vm) -> Vec<String> {
+        vm.run_until_halt().unwrap();
+        let checker = vm.convention.as_ref().unwrap();
+        checker
+            .violations
+            .iter()
+            .map(|v| v.describe(&vm.symbols))
+            .collect()
+    }
+
+    #[test]
+    fn well_behaved_routines_pass() {
+        let mut vm = vm_from_source(
+            "calls.asm",
+            "        .ORIG x3000
+        LD R6, STACK
+        JSR OUTER
+        HALT
+STACK   .FILL x4000
+OUTER   ADD R6, R6, #-1
+        STR R7, R6, #0
+        JSR INNER
+        LDR R7, R6, #0
+        ADD R6, R6, #1
+        RET
+INNER   ADD R0, R0, #1
+        RET
+        .END
+",
+        );
+        vm.convention = Some(ConventionChecker::new());
+
+        assert!(violations(&mut vm).is_empty());
+        assert_eq!(vm.convention.unwrap().depth(), 0);
+    }
+
+    #[test]
+    fn clobbered_return_address_is_reported() {
+        for backend in BACKENDS {
+            let mut vm = vm_from_source(
+                "calls.asm",
+                "        .ORIG x3000
+        JSR OUTER
+        HALT
+OUTER   JSR INNER
+        ADD R1, R1, #0
+        BRp DONE
+        ADD R1, R1, #1
+        RET
+DONE    HALT
+INNER   RET
+        .END
+",
+            );
+            vm.convention = Some(ConventionChecker::new());
+            vm.backend = backend;
+
+            assert_eq!(
+                violations(&mut vm),
+                vec![
+                    "RET at x3006 <OUTER+4> (calls.asm:8) returned to x3003 <OUTER+1> (calls.asm:5), \
+                     but x3002 <OUTER> (calls.asm:4) was called to return to x3001 (calls.asm:3)",
+                ]
+            );
+        }
+    }
+
+    #[test]
+    fn clobbered_registers_and_unbalanced_stacks_are_reported() {
+        let mut vm = vm_from_source(
+            "calls.asm",
+            "        .ORIG x3000
+        LD R6, STACK
+        JSR PUSH
+        HALT
+STACK   .FILL x4000
+PUSH    ADD R6, R6, #-1
+        STR R2, R6, #0
+        AND R2, R2, #0
+        AND R3, R3, #0
+        RET
+        .END
+",
+        );
+        vm.convention = Some(ConventionChecker::new());
+        vm.registers[3] = 7;
+        vm.convention.as_mut().unwrap().callee_saved = vec![3];
+
+        vm.run_until_halt().unwrap();
+        let checker = vm.convention.as_mut().unwrap();
+        checker.ret(0x3003, 0x1234, [0; 8]);
+
+        assert_eq!(
+            checker.violations,
+            vec![
+                Violation::ClobberedRegister {
+                    pc: 0x3008,
+                    routine: 0x3004,
+                    register: 3,
+                    before: 7,
+                    after: 0,
+                },
+                Violation::UnbalancedStack {
+                    pc: 0x3008,
+                    routine: 0x3004,
+                    before: 0x4000,
+                    after: 0x3FFF,
+                },
+                Violation::ReturnWithoutCall {
+                    pc: 0x3003,
+                    target: 0x1234,
+                },
+            ]
+        );
+    }
+}
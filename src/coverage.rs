@@ -0,0 +1,371 @@
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::fs;
+
+use crate::{load_map::LoadedImage, memory::Memory, symbols::SymbolTable};
+
+/// Coverage of one line of a file: a source line, or a single address of an image.
+#[derive(Debug, Default)]
+struct LineCoverage {
+    hits: u64,
+    addresses: Vec<u16>,
+    branches: Vec<BranchStats>,
+}
+
+/// Coverage of a source file, or of the addresses of an image without source lines.
+#[derive(Debug)]
+struct FileCoverage {
+    path: String,
+    source: bool,
+    lines: BTreeMap<u32, LineCoverage>,
+}
+
+/// Formats an execution count the way `gcov` does.
+fn format_count(hits: u64) -> String {
+    match hits {
+        0 => "#####".to_string(),
+        n => n.to_string(),
+    }
+}
+
+/// Appends the branch outcomes of a line to the listing.
+fn write_branches(out: &mut String, branches: &[BranchStats]) {
+    for stats in branches {
+        let _ = write!(
+            out,
+            "  branch taken {} / not taken {}",
+            stats.taken, stats.not_taken
+        );
+    }
+}
+
+/// Taken/not-taken counters for a single conditional `BR` instruction.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct BranchStats {
+    pub taken: u64,
+    pub not_taken: u64,
+}
+
+/// Collects code coverage data while a program runs.
+///
+/// Records how many times each address was executed as an instruction and,
+/// for every conditional `BR`, how many times the branch was taken or not.
+///
+#[derive(Debug, Default)]
+pub struct Coverage {
+    pub hits: BTreeMap<u16, u64>,
+    pub branches: BTreeMap<u16, BranchStats>,
+}
+
+impl Coverage {
+    /// Creates an empty `Coverage` collector.
+    pub fn new() -> Coverage {
+        Coverage::default()
+    }
+
+    /// Records that the instruction at `address` was executed.
+    pub fn record_execution(&mut self, address: u16) {
+        *self.hits.entry(address).or_insert(0) += 1;
+    }
+
+    /// Records the outcome of the conditional branch at `address`.
+    pub fn record_branch(&mut self, address: u16, taken: bool) {
+        let stats = self.branches.entry(address).or_default();
+        if taken {
+            stats.taken += 1;
+        } else {
+            stats.not_taken += 1;
+        }
+    }
+
+    /// Returns how many times the instruction at `address` was executed.
+    pub fn hit_count(&self, address: u16) -> u64 {
+        self.hits.get(&address).copied().unwrap_or(0)
+    }
+
+    /// Builds a human-readable annotated listing of the loaded images.
+    ///
+    /// Addresses with source line information are shown as the annotated source
+    /// file, with the execution count of each line next to it. The remaining
+    /// loaded words are listed by address, with their symbol and contents. Lines
+    /// and words that were never executed are marked with `#####`, as `gcov` does,
+    /// and lines without code with `-`.
+    ///
+    /// # Arguments
+    ///
+    /// * `memory` - The memory the program was loaded into.
+    /// * `images` - The images that were loaded, used to know which addresses to list.
+    /// * `symbols` - Symbols and source lines of the images.
+    ///
+    /// # Returns
+    ///
+    /// The annotated listing as a `String`.
+    ///
+    pub fn annotated_listing(
+        &self,
+        memory: &Memory,
+        images: &[LoadedImage],
+        symbols: &SymbolTable,
+    ) -> String {
+        let mut out = String::new();
+        for file in self.files(images, symbols) {
+            let _ = writeln!(out, "{}:", file.path);
+            match file.source.then(|| fs::read_to_string(&file.path)) {
+                Some(Ok(text)) => {
+                    for (number, source) in text.lines().enumerate() {
+                        let line = file.lines.get(&(number as u32 + 1));
+                        let count = match line {
+                            None => "-".to_string(),
+                            Some(line) => format_count(line.hits),
+                        };
+                        let _ = write!(out, "{:>9}:{:>5}:{}", count, number + 1, source);
+                        if let Some(line) = line {
+                            write_branches(&mut out, &line.branches);
+                        }
+                        out.push('\n');
+                    }
+                }
+                _ => {
+                    for line in file.lines.values() {
+                        let address = line.addresses[0];
+                        let _ = write!(
+                            out,
+                            "{:>9}  {}  x{:04X}",
+                            format_count(line.hits),
+                            symbols.format_address(address),
+                            memory.memory[address as usize]
+                        );
+                        write_branches(&mut out, &line.branches);
+                        out.push('\n');
+                    }
+                }
+            }
+        }
+        out.push_str(&self.summary(images));
+        out
+    }
+
+    /// Builds an lcov tracefile (`.info`) for the loaded images.
+    ///
+    /// Addresses with source line information are reported against their source
+    /// file and line. The remaining addresses are reported against the image file,
+    /// using the address itself as the line number.
+    ///
+    /// # Arguments
+    ///
+    /// * `images` - The images that were loaded.
+    /// * `symbols` - Symbols and source lines of the images.
+    ///
+    /// # Returns
+    ///
+    /// The tracefile contents as a `String`.
+    ///
+    pub fn lcov(&self, images: &[LoadedImage], symbols: &SymbolTable) -> String {
+        let mut out = String::new();
+        out.push_str("TN:\n");
+        for file in self.files(images, symbols) {
+            let _ = writeln!(out, "SF:{}", file.path);
+            let (mut lines_found, mut lines_hit) = (0, 0);
+            let (mut branches_found, mut branches_hit) = (0, 0);
+            for (number, line) in &file.lines {
+                let _ = writeln!(out, "DA:{},{}", number, line.hits);
+                lines_found += 1;
+                if line.hits > 0 {
+                    lines_hit += 1;
+                }
+                for (block, stats) in line.branches.iter().enumerate() {
+                    let _ = writeln!(out, "BRDA:{},{},0,{}", number, block, stats.taken);
+                    let _ = writeln!(out, "BRDA:{},{},1,{}", number, block, stats.not_taken);
+                    branches_found += 2;
+                    branches_hit += (stats.taken > 0) as u32 + (stats.not_taken > 0) as u32;
+                }
+            }
+            let _ = writeln!(out, "BRF:{}", branches_found);
+            let _ = writeln!(out, "BRH:{}", branches_hit);
+            let _ = writeln!(out, "LF:{}", lines_found);
+            let _ = writeln!(out, "LH:{}", lines_hit);
+            out.push_str("end_of_record\n");
+        }
+        out
+    }
+
+    /// Groups the coverage of every loaded address by the file and line it belongs to.
+    fn files(&self, images: &[LoadedImage], symbols: &SymbolTable) -> Vec<FileCoverage> {
+        let mut files: Vec<FileCoverage> = Vec::new();
+        for image in images {
+            for address in image.addresses() {
+                let (path, number, source) = match symbols.line_at(address) {
+                    Some(line) => (line.file.as_str(), line.line, true),
+                    None => (image.path.as_str(), address as u32, false),
+                };
+                let index = match files.iter().position(|f| f.path == path) {
+                    Some(index) => index,
+                    None => {
+                        files.push(FileCoverage {
+                            path: path.to_string(),
+                            source,
+                            lines: BTreeMap::new(),
+                        });
+                        files.len() - 1
+                    }
+                };
+                let line = files[index].lines.entry(number).or_default();
+                line.hits = line.hits.max(self.hit_count(address));
+                line.addresses.push(address);
+                if let Some(stats) = self.branches.get(&address) {
+                    line.branches.push(*stats);
+                }
+            }
+        }
+        files
+    }
+
+    /// Summarizes address and branch coverage over the loaded images.
+    fn summary(&self, images: &[LoadedImage]) -> String {
+        let (mut total, mut executed) = (0, 0);
+        for image in images {
+            for address in image.addresses() {
+                total += 1;
+                if self.hit_count(address) > 0 {
+                    executed += 1;
+                }
+            }
+        }
+        let outcomes = self.branches.len() * 2;
+        let seen: usize = self
+            .branches
+            .values()
+            .map(|s| (s.taken > 0) as usize + (s.not_taken > 0) as usize)
+            .sum();
+        format!(
+            "Executed {} of {} loaded words, {} of {} branch outcomes\n",
+            executed, total, seen, outcomes
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vm::Vm;
+
+    fn create_vm() -> Vm {
+        Vm::new()
+    }
+
+    fn image(origin: u16, length: usize) -> LoadedImage {
+        LoadedImage {
+            path: "test.obj".to_string(),
+            origin,
+            length,
+        }
+    }
+
+    #[test]
+    fn records_executed_addresses_and_branch_outcomes() {
+        let mut vm = create_vm();
+        vm.coverage = Some(Coverage::new());
+        vm.memory.write(0x3000, 0b0101_0000_0010_0000); // AND R0, R0, #0
+        vm.memory.write(0x3001, 0b0000_0100_0000_0001); // BRz #1
+        vm.memory.write(0x3002, 0b0001_0000_0010_0001); // ADD R0, R0, #1
+        vm.memory.write(0x3003, 0b0000_0010_0000_0001); // BRp #1
+        vm.memory.write(0x3004, 0xF025); // HALT
+
+        let mut running = true;
+        while running {
+            vm.step(&mut running).unwrap();
+        }
+
+        let coverage = vm.coverage.unwrap();
+        assert_eq!(coverage.hit_count(0x3000), 1);
+        assert_eq!(coverage.hit_count(0x3002), 0);
+        assert_eq!(coverage.hit_count(0x3003), 1);
+        assert_eq!(
+            coverage.branches[&0x3001],
+            BranchStats {
+                taken: 1,
+                not_taken: 0
+            }
+        );
+        assert_eq!(
+            coverage.branches[&0x3003],
+            BranchStats {
+                taken: 0,
+                not_taken: 1
+            }
+        );
+    }
+
+    #[test]
+    fn unconditional_branches_are_not_counted_as_branches() {
+        let mut vm = create_vm();
+        vm.coverage = Some(Coverage::new());
+
+        vm.op_br(0b111, 1); // BRnzp #1
+
+        assert!(vm.coverage.unwrap().branches.is_empty());
+    }
+
+    #[test]
+    fn annotated_listing_marks_unexecuted_words() {
+        let mut coverage = Coverage::new();
+        let mut memory = Memory::new();
+        memory.write(0x3000, 0x1234);
+        coverage.record_execution(0x3000);
+        coverage.record_branch(0x3001, false);
+
+        let listing = coverage.annotated_listing(&memory, &[image(0x3000, 2)], &SymbolTable::new());
+
+        assert!(listing.contains("        1  x3000  x1234"));
+        assert!(listing.contains("    #####  x3001  x0000  branch taken 0 / not taken 1"));
+        assert!(listing.contains("Executed 1 of 2 loaded words, 1 of 2 branch outcomes"));
+    }
+
+    #[test]
+    fn lcov_reports_lines_and_branches() {
+        let mut coverage = Coverage::new();
+        coverage.record_execution(0x3000);
+        coverage.record_execution(0x3000);
+        coverage.record_branch(0x3000, true);
+
+        let lcov = coverage.lcov(&[image(0x3000, 2)], &SymbolTable::new());
+
+        assert!(lcov.contains("SF:test.obj\n"));
+        assert!(lcov.contains("DA:12288,2\n"));
+        assert!(lcov.contains("DA:12289,0\n"));
+        assert!(lcov.contains("BRDA:12288,0,0,1\nBRDA:12288,0,1,0\n"));
+        assert!(lcov.contains("LF:2\nLH:1\nend_of_record\n"));
+    }
+
+    #[test]
+    fn reports_map_to_source_lines() {
+        let path = std::env::temp_dir().join("lc3_coverage_source.asm");
+        let path = path.to_string_lossy().to_string();
+        std::fs::write(
+            &path,
+            "; test\nAND R0, R0, #0\nBRz DONE\nADD R0, R0, #1\nDONE HALT\n",
+        )
+        .unwrap();
+        let mut symbols = SymbolTable::new();
+        for (address, line) in [(0x3000, 2), (0x3001, 3), (0x3002, 4), (0x3003, 5)] {
+            symbols.insert_line(address, &path, line);
+        }
+        let mut coverage = Coverage::new();
+        coverage.record_execution(0x3000);
+        coverage.record_execution(0x3001);
+        coverage.record_branch(0x3001, true);
+        coverage.record_execution(0x3003);
+
+        let images = [image(0x3000, 4)];
+        let listing = coverage.annotated_listing(&Memory::new(), &images, &symbols);
+        let lcov = coverage.lcov(&images, &symbols);
+        std::fs::remove_file(&path).unwrap();
+
+        assert!(listing.contains("        -:    1:; test\n"));
+        assert!(listing.contains("        1:    3:BRz DONE  branch taken 1 / not taken 0\n"));
+        assert!(listing.contains("    #####:    4:ADD R0, R0, #1\n"));
+        assert!(lcov.contains(&format!("SF:{}\n", path)));
+        assert!(lcov.contains("DA:3,1\nBRDA:3,0,0,1\nBRDA:3,0,1,0\nDA:4,0\n"));
+        assert!(lcov.contains("LF:4\nLH:3\n"));
+    }
+}
@@ -0,0 +1,859 @@
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::io::BufRead;
+
+use crate::{
+    constants::{FL_NEG, FL_POS},
+    expression::{Context, Expr, Template},
+    instruction::disassemble,
+    symbols::{Describe, SymbolTable},
+    utils::{flush_stdout, parse_word},
+    vm::Vm,
+    vm_error::VmError,
+    watchpoint::{ValueCondition, WatchMode, Watchpoint},
+};
+
+/// How many lines `list` shows before and after the current one.
+const LIST_CONTEXT: u32 = 5;
+
+/// How many instructions `disasm` shows by default.
+const DISASM_COUNT: u16 = 10;
+
+const HELP: &str = "Commands:
+  step [n]                      execute n instructions (default 1)
+  stepline [n]                  execute until the source line changes n times (default 1)
+  continue                      run until a breakpoint, watchpoint or HALT
+  break <addr> [if <expr>]      stop before executing the instruction at addr
+  log <addr> <message>          print a message when reaching addr without stopping,
+                                `{expr}` and `{expr:x}` are replaced by their value
+  condition <addr> [<expr>]     set or clear the condition of a breakpoint or logpoint
+  delete <addr>                 remove the breakpoint or logpoint at addr
+  watch <addr>[-<end>] [r|w|rw] [==<value>|!=<value>|changed]
+                                stop when memory in the range is accessed
+  unwatch <n>                   remove watchpoint number n
+  info                          list breakpoints and watchpoints
+  regs                          show the registers
+  mem <addr> [count]            show memory contents
+  list [addr]                   show the source around addr (default: the PC)
+  disasm [addr] [count]         disassemble count words from addr (default: the PC)
+  quit                          leave the debugger
+
+Addresses can be numbers or symbol names from the loaded symbol tables.
+Expressions use R0..R7, PC, COND, N, Z, P, mem[<expr>], hits, numbers
+(x3000, #10, 10, b101) and the C operators || && == != < <= > >= | ^ & + - * / % ! ~.
+";
+
+/// When `resume` stops on its own, besides breakpoints, watchpoints and HALT.
+#[derive(Debug, Clone, Copy)]
+enum Until {
+    /// Never.
+    Halt,
+    /// After this many instructions.
+    Steps(usize),
+    /// After the source line changed this many times.
+    Lines(usize),
+}
+
+/// A breakpoint or logpoint.
+///
+/// # Fields
+///
+/// * `condition` - Expression that must be non-zero for the breakpoint to trigger, and its source text.
+/// * `log` - Message printed instead of stopping, which turns the breakpoint into a logpoint.
+/// * `hits` - How many times execution reached the address, whether or not the condition held.
+///
+#[derive(Debug, Clone, Default)]
+pub struct Breakpoint {
+    pub condition: Option<(String, Expr)>,
+    pub log: Option<Template>,
+    pub hits: u64,
+}
+
+impl Breakpoint {
+    /// Creates an unconditional breakpoint.
+    pub fn new() -> Breakpoint {
+        Breakpoint::default()
+    }
+
+    /// Parses `text` and sets it as the condition of the breakpoint.
+    ///
+    /// # Returns
+    ///
+    /// The breakpoint, or a `VmError::InvalidExpression` if the condition is invalid.
+    ///
+    pub fn with_condition(mut self, text: &str) -> Result<Breakpoint, VmError> {
+        self.condition = Some((text.to_string(), Expr::parse(text)?));
+        Ok(self)
+    }
+
+    /// Parses `text` and sets it as the message of the logpoint.
+    ///
+    /// # Returns
+    ///
+    /// The logpoint, or a `VmError::InvalidExpression` if a placeholder is invalid.
+    ///
+    pub fn with_log(mut self, text: &str) -> Result<Breakpoint, VmError> {
+        self.log = Some(Template::parse(text)?);
+        Ok(self)
+    }
+}
+
+/// Interactive debugger that drives a `Vm` one instruction at a time.
+///
+/// # Fields
+///
+/// * `breakpoints` - Breakpoints and logpoints by address, checked before the instruction runs.
+/// * `running` - Whether the program is still running (cleared on HALT).
+/// * `finished` - Set when the user asks to leave the debugger.
+///
+#[derive(Debug)]
+pub struct Debugger {
+    pub breakpoints: BTreeMap<u16, Breakpoint>,
+    pub running: bool,
+    pub finished: bool,
+    checked: Option<u16>,
+}
+
+impl Default for Debugger {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Debugger {
+    /// Creates a new `Debugger` with no breakpoints.
+    pub fn new() -> Debugger {
+        Debugger {
+            breakpoints: BTreeMap::new(),
+            running: true,
+            finished: false,
+            checked: None,
+        }
+    }
+
+    /// Runs the debugger prompt on the standard input until the user quits or the input ends.
+    ///
+    /// # Arguments
+    ///
+    /// * `vm` - The VM with the program already loaded.
+    ///
+    /// # Returns
+    ///
+    /// `Ok(())` when the session ends normally, otherwise the `VmError` that stopped it.
+    ///
+    pub fn run(&mut self, vm: &mut Vm) -> Result<(), VmError> {
+        let stdin = std::io::stdin();
+        let mut line = String::new();
+        while !self.finished {
+            print!("(lc3db) ");
+            flush_stdout()?;
+            line.clear();
+            let read = stdin
+                .lock()
+                .read_line(&mut line)
+                .map_err(|e| VmError::FailedToReadStdin(e.to_string()))?;
+            if read == 0 {
+                break;
+            }
+            let output = self.execute(vm, &line)?;
+            print!("{}", output);
+            flush_stdout()?;
+        }
+        Ok(())
+    }
+
+    /// Executes a single debugger command.
+    ///
+    /// # Arguments
+    ///
+    /// * `vm` - The VM being debugged.
+    /// * `line` - The command line typed by the user.
+    ///
+    /// # Returns
+    ///
+    /// The text to show the user, or a `VmError` if the program failed while running.
+    ///
+    pub fn execute(&mut self, vm: &mut Vm, line: &str) -> Result<String, VmError> {
+        let mut out = String::new();
+        let words: Vec<&str> = line.split_whitespace().collect();
+        let Some((&command, args)) = words.split_first() else {
+            return Ok(out);
+        };
+
+        match command {
+            "s" | "step" | "sl" | "stepline" => {
+                let count = match args.first() {
+                    Some(n) => match n.parse::<usize>() {
+                        Ok(n) => n,
+                        Err(_) => return Ok(format!("Invalid step count: {}\n", n)),
+                    },
+                    None => 1,
+                };
+                if count == 0 {
+                    return Ok(out);
+                }
+                let until = match command {
+                    "s" | "step" => Until::Steps(count),
+                    _ if vm.symbols.lines.is_empty() => {
+                        return Ok("No source line information loaded\n".to_string())
+                    }
+                    _ => Until::Lines(count),
+                };
+                self.resume(vm, until, &mut out)?;
+            }
+            "c" | "continue" => self.resume(vm, Until::Halt, &mut out)?,
+            "b" | "break" => {
+                let address = args.first().and_then(|a| resolve(vm, a));
+                let condition = match args.get(1) {
+                    Some(&"if") if args.len() > 2 => Some(args[2..].join(" ")),
+                    None => None,
+                    _ => return Ok("Usage: break <addr> [if <expr>]\n".to_string()),
+                };
+                let Some(address) = address else {
+                    return Ok("Usage: break <addr> [if <expr>]\n".to_string());
+                };
+                let breakpoint = match condition {
+                    Some(text) => match Breakpoint::new().with_condition(&text) {
+                        Ok(breakpoint) => breakpoint,
+                        Err(e) => return Ok(error_message(e)),
+                    },
+                    None => Breakpoint::new(),
+                };
+                let _ = writeln!(out, "{}", describe(&vm.symbols, address, &breakpoint));
+                self.breakpoints.insert(address, breakpoint);
+            }
+            "l" | "log" => {
+                let mut parts = line.trim().splitn(3, char::is_whitespace);
+                parts.next();
+                let address = parts.next().and_then(|a| resolve(vm, a));
+                let message = parts.next().map(str::trim_start);
+                let (Some(address), Some(message)) = (address, message) else {
+                    return Ok("Usage: log <addr> <message>\n".to_string());
+                };
+                match Breakpoint::new().with_log(message) {
+                    Ok(logpoint) => {
+                        let _ = writeln!(out, "{}", describe(&vm.symbols, address, &logpoint));
+                        self.breakpoints.insert(address, logpoint);
+                    }
+                    Err(e) => return Ok(error_message(e)),
+                }
+            }
+            "condition" => {
+                let Some((address, breakpoint)) = args
+                    .first()
+                    .and_then(|a| resolve(vm, a))
+                    .and_then(|a| self.breakpoints.get_mut(&a).map(|b| (a, b)))
+                else {
+                    return Ok("Usage: condition <addr> [<expr>]\n".to_string());
+                };
+                if args.len() > 1 {
+                    let text = args[1..].join(" ");
+                    match Expr::parse(&text) {
+                        Ok(expr) => breakpoint.condition = Some((text, expr)),
+                        Err(e) => return Ok(error_message(e)),
+                    }
+                } else {
+                    breakpoint.condition = None;
+                }
+                let _ = writeln!(out, "{}", describe(&vm.symbols, address, breakpoint));
+            }
+            "d" | "delete" => match args.first().and_then(|a| resolve(vm, a)) {
+                Some(address) if self.breakpoints.remove(&address).is_some() => {
+                    let _ = writeln!(
+                        out,
+                        "Deleted breakpoint at {}",
+                        vm.symbols.format_address(address)
+                    );
+                }
+                Some(address) => {
+                    let _ = writeln!(
+                        out,
+                        "No breakpoint at {}",
+                        vm.symbols.format_address(address)
+                    );
+                }
+                None => out.push_str("Usage: delete <addr>\n"),
+            },
+            "w" | "watch" => match parse_watchpoint(vm, args) {
+                Some(watchpoint) => {
+                    let _ = writeln!(
+                        out,
+                        "Watchpoint {}: {}",
+                        vm.memory.watchpoints.len(),
+                        watchpoint
+                    );
+                    vm.memory.watchpoints.push(watchpoint);
+                }
+                None => out.push_str(
+                    "Usage: watch <addr>[-<end>] [r|w|rw] [==<value>|!=<value>|changed]\n",
+                ),
+            },
+            "unwatch" => match args.first().and_then(|a| a.parse::<usize>().ok()) {
+                Some(n) if n < vm.memory.watchpoints.len() => {
+                    let watchpoint = vm.memory.watchpoints.remove(n);
+                    let _ = writeln!(out, "Deleted watchpoint {}: {}", n, watchpoint);
+                }
+                _ => out.push_str("Usage: unwatch <n>\n"),
+            },
+            "i" | "info" => {
+                for (address, breakpoint) in &self.breakpoints {
+                    let _ = writeln!(out, "{}", describe(&vm.symbols, *address, breakpoint));
+                }
+                for (n, watchpoint) in vm.memory.watchpoints.iter().enumerate() {
+                    let _ = writeln!(out, "Watchpoint {}: {}", n, watchpoint);
+                }
+            }
+            "r" | "regs" => out.push_str(&format_registers(vm)),
+            "x" | "mem" => {
+                let start = args.first().and_then(|a| resolve(vm, a));
+                let count = args.get(1).and_then(|a| parse_word(a)).unwrap_or(1);
+                match start {
+                    Some(start) => {
+                        for i in 0..count {
+                            let address = start.wrapping_add(i);
+                            let _ = writeln!(
+                                out,
+                                "{}: x{:04X}",
+                                vm.symbols.format_address(address),
+                                vm.memory.memory[address as usize]
+                            );
+                        }
+                    }
+                    None => out.push_str("Usage: mem <addr> [count]\n"),
+                }
+            }
+            "list" => {
+                let address = match args.first() {
+                    Some(text) => match resolve(vm, text) {
+                        Some(address) => address,
+                        None => return Ok("Usage: list [addr]\n".to_string()),
+                    },
+                    None => vm.registers.pc,
+                };
+                out.push_str(&list_source(&vm.symbols, address));
+            }
+            "disasm" => {
+                let start = match args.first() {
+                    Some(text) => match resolve(vm, text) {
+                        Some(address) => address,
+                        None => return Ok("Usage: disasm [addr] [count]\n".to_string()),
+                    },
+                    None => vm.registers.pc,
+                };
+                let count = args
+                    .get(1)
+                    .and_then(|a| parse_word(a))
+                    .unwrap_or(DISASM_COUNT);
+                for i in 0..count {
+                    let address = start.wrapping_add(i);
+                    let marker = if address == vm.registers.pc {
+                        "=>"
+                    } else {
+                        "  "
+                    };
+                    let word = vm.memory.memory[address as usize];
+                    let _ = writeln!(
+                        out,
+                        "{} {}",
+                        marker,
+                        disassemble(address, word, &vm.symbols)
+                    );
+                }
+            }
+            "q" | "quit" => self.finished = true,
+            "h" | "help" => out.push_str(HELP),
+            _ => {
+                let _ = writeln!(out, "Unknown command: {} (try `help`)", command);
+            }
+        }
+        Ok(out)
+    }
+
+    /// Executes instructions until a breakpoint, a watchpoint, a HALT or the `until` limit.
+    ///
+    /// Breakpoints are checked before each instruction runs, except the one at the PC where
+    /// the last `resume` already checked it, so that execution can continue past it. Logpoints
+    /// reached on the way print their message without stopping. When stepping by line,
+    /// addresses without source line information (such as trap routines) do not count as a
+    /// new line.
+    fn resume(&mut self, vm: &mut Vm, until: Until, out: &mut String) -> Result<(), VmError> {
+        let mut steps = 0;
+        let mut line = vm.symbols.line_at(vm.registers.pc).cloned();
+        loop {
+            if !self.running {
+                out.push_str("The program is not running\n");
+                return Ok(());
+            }
+            if self.checked != Some(vm.registers.pc) {
+                self.checked = Some(vm.registers.pc);
+                if self.check_breakpoint(vm, out) {
+                    break;
+                }
+            }
+            let done = match until {
+                Until::Halt => false,
+                Until::Steps(limit) | Until::Lines(limit) => steps >= limit,
+            };
+            if done {
+                break;
+            }
+            self.checked = None;
+            match vm.step(&mut self.running) {
+                Ok(()) => {}
+                Err(VmError::WatchpointHit(hit)) => {
+                    let _ = writeln!(out, "{}", hit.with_symbols(&vm.symbols));
+                    break;
+                }
+                Err(VmError::AccessViolation(violation)) => {
+                    let _ = writeln!(out, "{}", violation.with_symbols(&vm.symbols));
+                    break;
+                }
+                Err(e) => return Err(e),
+            }
+            if !self.running {
+                out.push_str("Program halted\n");
+                return Ok(());
+            }
+            match until {
+                Until::Halt => {}
+                Until::Steps(_) => steps += 1,
+                Until::Lines(_) => match vm.symbols.line_at(vm.registers.pc) {
+                    Some(current) if line.as_ref() != Some(current) => {
+                        line = Some(current.clone());
+                        steps += 1;
+                    }
+                    _ => {}
+                },
+            }
+        }
+        let pc = vm.registers.pc;
+        let _ = writeln!(
+            out,
+            "Stopped at {}: x{:04X}",
+            vm.symbols.format_address(pc),
+            vm.memory.memory[pc as usize]
+        );
+        if let Some(line) = vm.symbols.line_at(pc) {
+            if let Some(text) = line.text() {
+                let _ = writeln!(out, "{:>5} | {}", line.line, text);
+            }
+        }
+        Ok(())
+    }
+
+    /// Updates the breakpoint at the current PC, if any, and decides whether to stop.
+    ///
+    /// # Returns
+    ///
+    /// `true` if execution should stop, which also happens when the condition or
+    /// the message cannot be evaluated.
+    ///
+    fn check_breakpoint(&mut self, vm: &Vm, out: &mut String) -> bool {
+        let pc = vm.registers.pc;
+        let Some(breakpoint) = self.breakpoints.get_mut(&pc) else {
+            return false;
+        };
+        breakpoint.hits += 1;
+        let ctx = Context {
+            vm,
+            hits: breakpoint.hits,
+        };
+
+        if let Some((_, condition)) = &breakpoint.condition {
+            match condition.evaluate(&ctx) {
+                Ok(0) => return false,
+                Ok(_) => {}
+                Err(e) => {
+                    let _ = write!(
+                        out,
+                        "Condition at {}: {}",
+                        vm.symbols.format_address(pc),
+                        error_message(e)
+                    );
+                    return true;
+                }
+            }
+        }
+
+        match &breakpoint.log {
+            Some(template) => match template.render(&ctx) {
+                Ok(message) => {
+                    let _ = writeln!(out, "{}", message);
+                    false
+                }
+                Err(e) => {
+                    let _ = write!(
+                        out,
+                        "Logpoint at {}: {}",
+                        vm.symbols.format_address(pc),
+                        error_message(e)
+                    );
+                    true
+                }
+            },
+            None => {
+                let _ = writeln!(
+                    out,
+                    "Breakpoint at {} (hit {})",
+                    vm.symbols.format_address(pc),
+                    breakpoint.hits
+                );
+                true
+            }
+        }
+    }
+}
+
+/// Describes a breakpoint or logpoint for the `info` command.
+fn describe(symbols: &SymbolTable, address: u16, breakpoint: &Breakpoint) -> String {
+    let address = symbols.format_address(address);
+    let mut text = match &breakpoint.log {
+        Some(template) => format!("Logpoint at {} \"{}\"", address, template.source),
+        None => format!("Breakpoint at {}", address),
+    };
+    if let Some((condition, _)) = &breakpoint.condition {
+        let _ = write!(text, " if {}", condition);
+    }
+    if breakpoint.hits > 0 {
+        let _ = write!(text, " (hit {})", breakpoint.hits);
+    }
+    text
+}
+
+/// Shows the source lines around the one `address` was assembled from, marking it with `=>`.
+fn list_source(symbols: &SymbolTable, address: u16) -> String {
+    let Some(line) = symbols.line_at(address) else {
+        return format!(
+            "No source line information for {}\n",
+            symbols.format_address(address)
+        );
+    };
+    let Ok(source) = std::fs::read_to_string(&line.file) else {
+        return format!("Cannot read {}\n", line.file);
+    };
+    let first = line.line.saturating_sub(LIST_CONTEXT).max(1);
+    let mut out = String::new();
+    for (number, text) in
+        (first..=line.line + LIST_CONTEXT).zip(source.lines().skip(first as usize - 1))
+    {
+        let marker = if number == line.line { "=>" } else { "  " };
+        let _ = writeln!(out, "{} {:>4} | {}", marker, number, text);
+    }
+    out
+}
+
+/// Turns an expression error into a line of text for the user.
+fn error_message(error: VmError) -> String {
+    match error {
+        VmError::InvalidExpression(message) => format!("Invalid expression: {}\n", message),
+        other => format!("{:?}\n", other),
+    }
+}
+
+/// Formats the general-purpose registers, the PC and the condition flags.
+fn format_registers(vm: &Vm) -> String {
+    let mut out = String::new();
+    for r in 0..8 {
+        let value = vm.registers.get(r).unwrap_or_default();
+        let _ = write!(out, "R{} x{:04X}  ", r, value);
+        if r == 3 || r == 7 {
+            out.truncate(out.trim_end().len());
+            out.push('\n');
+        }
+    }
+    let cond = match vm.registers.cond {
+        FL_NEG => "N",
+        FL_POS => "P",
+        _ => "Z",
+    };
+    let _ = writeln!(out, "PC x{:04X}  COND {}", vm.registers.pc, cond);
+    out
+}
+
+/// Parses an address given as a number or as a symbol name.
+fn resolve(vm: &Vm, text: &str) -> Option<u16> {
+    parse_word(text).or_else(|| vm.symbols.address_of(text))
+}
+
+/// Parses the arguments of the `watch` command.
+fn parse_watchpoint(vm: &Vm, args: &[&str]) -> Option<Watchpoint> {
+    let (range, rest) = args.split_first()?;
+    let (start, end) = match range.split_once('-') {
+        Some((start, end)) => (resolve(vm, start)?, resolve(vm, end)?),
+        None => {
+            let address = resolve(vm, range)?;
+            (address, address)
+        }
+    };
+    if end < start {
+        return None;
+    }
+
+    let mut watchpoint = Watchpoint::new(start, end, WatchMode::Write);
+    for arg in rest {
+        match *arg {
+            "r" => watchpoint.mode = WatchMode::Read,
+            "w" => watchpoint.mode = WatchMode::Write,
+            "rw" => watchpoint.mode = WatchMode::ReadWrite,
+            "changed" => watchpoint.condition = Some(ValueCondition::Changed),
+            _ => {
+                let condition = if let Some(value) = arg.strip_prefix("==") {
+                    ValueCondition::Equals(parse_word(value)?)
+                } else if let Some(value) = arg.strip_prefix("!=") {
+                    ValueCondition::NotEquals(parse_word(value)?)
+                } else {
+                    return None;
+                };
+                watchpoint.condition = Some(condition);
+            }
+        }
+    }
+    Some(watchpoint)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn create_vm() -> Vm {
+        let mut vm = Vm::new();
+        vm.memory.write(0x3000, 0b0101_0000_0010_0000); // AND R0, R0, #0
+        vm.memory.write(0x3001, 0b0001_0000_0010_0101); // ADD R0, R0, #5
+        vm.memory.write(0x3002, 0b0011_0000_0000_0010); // ST R0, #2
+        vm.memory.write(0x3003, 0xF025); // HALT
+        vm
+    }
+
+    #[test]
+    fn step_and_breakpoint() {
+        let mut vm = create_vm();
+        let mut debugger = Debugger::new();
+
+        let out = debugger.execute(&mut vm, "step").unwrap();
+        assert_eq!(out, "Stopped at x3001: x1025\n");
+
+        debugger.execute(&mut vm, "break x3002").unwrap();
+        let out = debugger.execute(&mut vm, "continue").unwrap();
+        assert!(out.starts_with("Breakpoint at x3002 (hit 1)\n"));
+        assert_eq!(vm.registers[0], 5);
+    }
+
+    #[test]
+    fn breakpoint_at_the_entry_point_is_hit_before_it_runs() {
+        let mut vm = create_vm();
+        let mut debugger = Debugger::new();
+
+        debugger.execute(&mut vm, "break x3000").unwrap();
+        let out = debugger.execute(&mut vm, "continue").unwrap();
+        assert_eq!(
+            out,
+            "Breakpoint at x3000 (hit 1)\nStopped at x3000: x5020\n"
+        );
+
+        let out = debugger.execute(&mut vm, "continue").unwrap();
+        assert_eq!(out, "Program halted\n");
+        assert_eq!(vm.registers[0], 5);
+    }
+
+    #[test]
+    fn step_zero_does_nothing() {
+        let mut vm = create_vm();
+        let mut debugger = Debugger::new();
+
+        let out = debugger.execute(&mut vm, "step 0").unwrap();
+
+        assert_eq!(out, "");
+        assert_eq!(vm.registers.pc, 0x3000);
+    }
+
+    #[test]
+    fn watchpoint_stops_with_old_and_new_value() {
+        let mut vm = create_vm();
+        let mut debugger = Debugger::new();
+
+        debugger.execute(&mut vm, "watch x3005 w ==5").unwrap();
+        let out = debugger.execute(&mut vm, "continue").unwrap();
+
+        assert!(out.contains("instruction at x3002 wrote x0005 to x3005 (was x0000)"));
+        assert!(out.contains("Stopped at x3003"));
+    }
+
+    #[test]
+    fn continue_until_halt() {
+        let mut vm = create_vm();
+        let mut debugger = Debugger::new();
+
+        let out = debugger.execute(&mut vm, "continue").unwrap();
+        assert_eq!(out, "Program halted\n");
+        let out = debugger.execute(&mut vm, "step").unwrap();
+        assert_eq!(out, "The program is not running\n");
+    }
+
+    fn counting_loop() -> Vm {
+        let mut vm = Vm::new();
+        vm.memory.write(0x3000, 0b0101_0000_0010_0000); // AND R0, R0, #0
+        vm.memory.write(0x3001, 0b0001_0000_0010_0001); // ADD R0, R0, #1
+        vm.memory.write(0x3002, 0b0001_0010_0011_1011); // ADD R1, R0, #-5
+        vm.memory.write(0x3003, 0b0000_1001_1111_1101); // BRn #-3
+        vm.memory.write(0x3004, 0xF025); // HALT
+        vm
+    }
+
+    #[test]
+    fn conditional_breakpoint_on_registers() {
+        let mut vm = counting_loop();
+        let mut debugger = Debugger::new();
+
+        debugger.execute(&mut vm, "break x3002 if R0 == 3").unwrap();
+        let out = debugger.execute(&mut vm, "continue").unwrap();
+
+        assert!(out.starts_with("Breakpoint at x3002 (hit 3)\n"));
+        assert_eq!(vm.registers[0], 3);
+    }
+
+    #[test]
+    fn hit_count_condition() {
+        let mut vm = counting_loop();
+        let mut debugger = Debugger::new();
+
+        debugger.execute(&mut vm, "break x3001").unwrap();
+        debugger
+            .execute(&mut vm, "condition x3001 hits > 3")
+            .unwrap();
+        debugger.execute(&mut vm, "continue").unwrap();
+
+        assert_eq!(vm.registers[0], 3);
+        let info = debugger.execute(&mut vm, "info").unwrap();
+        assert_eq!(info, "Breakpoint at x3001 if hits > 3 (hit 4)\n");
+    }
+
+    #[test]
+    fn logpoint_prints_without_stopping() {
+        let mut vm = counting_loop();
+        let mut debugger = Debugger::new();
+
+        debugger
+            .execute(&mut vm, "log x3002 R0 is {R0}, PC {PC:x}")
+            .unwrap();
+        let out = debugger.execute(&mut vm, "continue").unwrap();
+
+        assert_eq!(
+            out,
+            "R0 is 1, PC x3002\nR0 is 2, PC x3002\nR0 is 3, PC x3002\n\
+             R0 is 4, PC x3002\nR0 is 5, PC x3002\nProgram halted\n"
+        );
+    }
+
+    #[test]
+    fn invalid_condition_is_reported() {
+        let mut vm = counting_loop();
+        let mut debugger = Debugger::new();
+
+        let out = debugger.execute(&mut vm, "break x3002 if R9 == 1").unwrap();
+
+        assert_eq!(out, "Invalid expression: unknown name `R9`\n");
+        assert!(debugger.breakpoints.is_empty());
+    }
+
+    #[test]
+    fn symbols_in_commands_and_output() {
+        let mut vm = create_vm();
+        vm.symbols.insert("START", 0x3000);
+        vm.symbols.insert("STORE", 0x3002);
+        let mut debugger = Debugger::new();
+
+        let out = debugger.execute(&mut vm, "break STORE").unwrap();
+        assert_eq!(out, "Breakpoint at x3002 <STORE>\n");
+        let out = debugger.execute(&mut vm, "continue").unwrap();
+        assert_eq!(
+            out,
+            "Breakpoint at x3002 <STORE> (hit 1)\nStopped at x3002 <STORE>: x3002\n"
+        );
+        let out = debugger.execute(&mut vm, "mem START").unwrap();
+        assert_eq!(out, "x3000 <START>: x5020\n");
+    }
+
+    #[test]
+    fn stepline_and_list_show_the_source() {
+        let dir = std::env::temp_dir().join("lc3_debugger_source");
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("prog.asm").to_string_lossy().to_string();
+        let source = "\
+.MACRO INC reg
+    ADD reg, reg, #1
+    ADD reg, reg, #0
+.ENDM
+.ORIG x3000
+    AND R0, R0, #0
+    INC R0
+    HALT
+.END
+";
+        std::fs::write(&path, source).unwrap();
+        let assembly = crate::assembler::assemble(&path, source).unwrap();
+        let mut vm = Vm::new();
+        for (i, word) in assembly.sections[0].words.iter().enumerate() {
+            vm.memory.write(0x3000 + i as u16, *word);
+        }
+        vm.symbols = assembly.symbols;
+        let mut debugger = Debugger::new();
+
+        let first = debugger.execute(&mut vm, "stepline").unwrap();
+        let second = debugger.execute(&mut vm, "sl").unwrap();
+        let listing = debugger.execute(&mut vm, "list").unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert!(first.ends_with(":7): x1021\n    7 |     INC R0\n"));
+        assert_eq!(vm.registers[0], 1);
+        assert!(second.ends_with("    8 |     HALT\n"));
+        assert!(listing.starts_with("      3 |     ADD reg, reg, #0\n"));
+        assert!(listing.contains("=>    8 |     HALT\n"));
+    }
+
+    #[test]
+    fn stepline_needs_line_information() {
+        let mut vm = create_vm();
+        let mut debugger = Debugger::new();
+
+        let out = debugger.execute(&mut vm, "stepline").unwrap();
+
+        assert_eq!(out, "No source line information loaded\n");
+        assert_eq!(vm.registers.pc, 0x3000);
+    }
+
+    #[test]
+    fn disasm_marks_the_pc() {
+        let mut vm = create_vm();
+        vm.memory.write(0x3000, 0x1261); // ADD R1, R1, #1
+        vm.memory.write(0x3001, 0x0FFE); // BRnzp #-2
+        vm.symbols.insert("LOOP", 0x3000);
+        let mut debugger = Debugger::new();
+
+        let out = debugger.execute(&mut vm, "disasm LOOP 2").unwrap();
+
+        assert_eq!(
+            out,
+            "=> x3000 <LOOP>: x1261  ADD R1, R1, #1\n   x3001 <LOOP+1>: x0FFE  BRnzp #-2  ; x3000 <LOOP>\n"
+        );
+    }
+
+    #[test]
+    fn parse_watchpoint_arguments() {
+        assert_eq!(
+            parse_watchpoint(&Vm::new(), &["x4000-x40FF", "rw", "changed"]),
+            Some(
+                Watchpoint::new(0x4000, 0x40FF, WatchMode::ReadWrite)
+                    .with_condition(ValueCondition::Changed)
+            )
+        );
+        assert_eq!(
+            parse_watchpoint(&Vm::new(), &["x4000", "!=#0"]),
+            Some(
+                Watchpoint::new(0x4000, 0x4000, WatchMode::Write)
+                    .with_condition(ValueCondition::NotEquals(0))
+            )
+        );
+        assert_eq!(parse_watchpoint(&Vm::new(), &["x4001-x4000"]), None);
+        assert_eq!(parse_watchpoint(&Vm::new(), &["x4000", "sometimes"]), None);
+    }
+}
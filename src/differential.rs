@@ -0,0 +1,400 @@
+use std::collections::VecDeque;
+use std::fmt;
+
+use crate::{
+    constants::{FL_NEG, FL_POS, FL_ZRO},
+    instruction::disassemble,
+    translation::BlockTranslator,
+    vm::{Backend, Vm},
+    vm_error::VmError,
+};
+
+/// How many steps before a divergence are shown by default.
+pub const DEFAULT_WINDOW: usize = 16;
+
+/// A machine stepped one instruction at a time with its own backend.
+///
+/// # Fields
+///
+/// * `vm` - The machine.
+/// * `running` - Cleared when the program halts.
+/// * `translator` - The block translator, for machines using `Backend::Blocks`.
+///
+pub struct Runner {
+    pub vm: Vm,
+    pub running: bool,
+    translator: Option<BlockTranslator>,
+}
+
+impl Runner {
+    /// Creates a runner for a machine, stepping it with the backend selected in `vm.backend`.
+    pub fn new(vm: Vm) -> Runner {
+        let translator = match vm.backend {
+            Backend::Interpreter => None,
+            Backend::Blocks => Some(BlockTranslator::new()),
+        };
+        Runner {
+            vm,
+            running: true,
+            translator,
+        }
+    }
+
+    /// Executes a single instruction.
+    pub fn step(&mut self) -> Result<(), VmError> {
+        match self.translator.as_mut() {
+            Some(translator) => translator.step(&mut self.vm, &mut self.running),
+            None => self.vm.step(&mut self.running),
+        }
+    }
+}
+
+/// The state of the reference machine after one step, kept to show what led to a divergence.
+///
+/// # Fields
+///
+/// * `step` - The number of the step, starting at 1.
+/// * `disassembly` - The instruction that was executed, with its address.
+/// * `registers` - `R0` to `R7` after the step.
+/// * `cond` - The condition flags after the step.
+/// * `writes` - The memory writes of the step, as `(address, value)` pairs.
+///
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TraceEntry {
+    pub step: u64,
+    pub disassembly: String,
+    pub registers: [u16; 8],
+    pub cond: u16,
+    pub writes: Vec<(u16, u16)>,
+}
+
+/// The first step after which the two machines disagree.
+///
+/// # Fields
+///
+/// * `step` - The number of the step, starting at 1.
+/// * `disassembly` - The instruction executed by the reference machine in that step.
+/// * `differences` - Every difference found, like `R1: reference x0003, candidate x0004`.
+/// * `trace` - The steps before the divergence and the divergent one, oldest first.
+///
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Divergence {
+    pub step: u64,
+    pub disassembly: String,
+    pub differences: Vec<String>,
+    pub trace: Vec<TraceEntry>,
+}
+
+/// The results of one step on the reference and on the candidate machine.
+pub type StepResults = (Result<(), VmError>, Result<(), VmError>);
+
+/// How a lockstep run ended.
+#[derive(Debug)]
+pub enum Outcome {
+    /// Both programs halted after the same steps.
+    Halted { steps: u64 },
+    /// The step limit was reached without a divergence.
+    StepLimit { steps: u64 },
+    /// Both machines stopped with the same error.
+    Stopped { steps: u64, error: VmError },
+    /// The machines disagreed.
+    Diverged(Divergence),
+}
+
+/// Runs a reference and a candidate machine side by side, comparing them after every step.
+///
+/// After each instruction, the general-purpose registers, the PC, the condition flags, the
+/// halted state, the memory writes of the step and the result of the step must be the same
+/// on both machines.
+///
+/// # Fields
+///
+/// * `reference` - The machine whose behavior is trusted.
+/// * `candidate` - The machine being checked.
+/// * `window` - How many steps the trace of a divergence keeps.
+/// * `trace` - The most recent steps of the reference machine.
+/// * `steps` - How many steps were executed.
+///
+pub struct Lockstep {
+    pub reference: Runner,
+    pub candidate: Runner,
+    pub window: usize,
+    trace: VecDeque<TraceEntry>,
+    pub steps: u64,
+}
+
+impl Lockstep {
+    /// Creates a lockstep run of two machines, which should hold the same program.
+    ///
+    /// # Arguments
+    ///
+    /// * `reference` - The machine whose behavior is trusted.
+    /// * `candidate` - The machine being checked.
+    ///
+    pub fn new(mut reference: Vm, mut candidate: Vm) -> Lockstep {
+        reference.memory.write_log = Some(Vec::new());
+        candidate.memory.write_log = Some(Vec::new());
+        Lockstep {
+            reference: Runner::new(reference),
+            candidate: Runner::new(candidate),
+            window: DEFAULT_WINDOW,
+            trace: VecDeque::new(),
+            steps: 0,
+        }
+    }
+
+    /// Steps both machines until they halt, stop, diverge or reach the step limit.
+    ///
+    /// # Arguments
+    ///
+    /// * `max_steps` - The maximum number of steps to execute.
+    ///
+    pub fn run(&mut self, max_steps: u64) -> Outcome {
+        while self.steps < max_steps {
+            let (reference, candidate) = match self.step() {
+                Ok(results) => results,
+                Err(divergence) => return Outcome::Diverged(divergence),
+            };
+            match (reference, candidate) {
+                (Err(error), Err(_)) => {
+                    return Outcome::Stopped {
+                        steps: self.steps,
+                        error,
+                    }
+                }
+                _ if !self.reference.running => return Outcome::Halted { steps: self.steps },
+                _ => {}
+            }
+        }
+        Outcome::StepLimit { steps: self.steps }
+    }
+
+    /// Executes one instruction on both machines and compares them.
+    ///
+    /// # Returns
+    ///
+    /// The results of the step on both machines, or the `Divergence` if they disagree,
+    /// including when only one of them failed.
+    ///
+    pub fn step(&mut self) -> Result<StepResults, Divergence> {
+        let address = self.reference.vm.registers.pc;
+        let word = self.reference.vm.memory.memory[address as usize];
+        let disassembly = disassemble(address, word, &self.reference.vm.symbols);
+
+        let reference_result = self.reference.step();
+        let candidate_result = self.candidate.step();
+        self.steps += 1;
+
+        let reference_writes = take_writes(&mut self.reference.vm);
+        let candidate_writes = take_writes(&mut self.candidate.vm);
+        let (reference, candidate) = (&self.reference, &self.candidate);
+
+        let mut differences = Vec::new();
+        for r in 0..8 {
+            let (expected, actual) = (reference.vm.registers[r], candidate.vm.registers[r]);
+            if expected != actual {
+                differences.push(format!(
+                    "R{}: reference x{:04X}, candidate x{:04X}",
+                    r, expected, actual
+                ));
+            }
+        }
+        if reference.vm.registers.pc != candidate.vm.registers.pc {
+            differences.push(format!(
+                "PC: reference x{:04X}, candidate x{:04X}",
+                reference.vm.registers.pc, candidate.vm.registers.pc
+            ));
+        }
+        if reference.vm.registers.cond != candidate.vm.registers.cond {
+            differences.push(format!(
+                "COND: reference {}, candidate {}",
+                flags(reference.vm.registers.cond),
+                flags(candidate.vm.registers.cond)
+            ));
+        }
+        if reference.running != candidate.running {
+            differences.push(format!(
+                "halted: reference {}, candidate {}",
+                !reference.running, !candidate.running
+            ));
+        }
+        if reference_writes != candidate_writes {
+            differences.push(format!(
+                "writes: reference {}, candidate {}",
+                format_writes(&reference_writes),
+                format_writes(&candidate_writes)
+            ));
+        }
+        let (reference_outcome, candidate_outcome) = (
+            format_result(&reference_result),
+            format_result(&candidate_result),
+        );
+        if reference_outcome != candidate_outcome {
+            differences.push(format!(
+                "result: reference {}, candidate {}",
+                reference_outcome, candidate_outcome
+            ));
+        }
+
+        self.trace.push_back(TraceEntry {
+            step: self.steps,
+            disassembly: disassembly.clone(),
+            registers: reference.vm.registers.gpr,
+            cond: reference.vm.registers.cond,
+            writes: reference_writes,
+        });
+        while self.trace.len() > self.window.max(1) {
+            self.trace.pop_front();
+        }
+
+        if differences.is_empty() {
+            Ok((reference_result, candidate_result))
+        } else {
+            Err(Divergence {
+                step: self.steps,
+                disassembly,
+                differences,
+                trace: self.trace.iter().cloned().collect(),
+            })
+        }
+    }
+}
+
+/// Takes the writes logged since the last step.
+fn take_writes(vm: &mut Vm) -> Vec<(u16, u16)> {
+    vm.memory
+        .write_log
+        .as_mut()
+        .map(std::mem::take)
+        .unwrap_or_default()
+}
+
+/// Formats the condition flags as `n`, `z` or `p`, or `-` when none is set.
+fn flags(cond: u16) -> String {
+    let letters: String = [(FL_NEG, 'n'), (FL_ZRO, 'z'), (FL_POS, 'p')]
+        .iter()
+        .filter(|(flag, _)| cond & flag != 0)
+        .map(|(_, letter)| *letter)
+        .collect();
+    if letters.is_empty() {
+        "-".to_string()
+    } else {
+        letters
+    }
+}
+
+/// Formats memory writes as `[x4000=x0001, ...]`.
+fn format_writes(writes: &[(u16, u16)]) -> String {
+    let writes: Vec<String> = writes
+        .iter()
+        .map(|(address, value)| format!("x{:04X}=x{:04X}", address, value))
+        .collect();
+    format!("[{}]", writes.join(", "))
+}
+
+/// Formats the result of a step, so that errors can be compared.
+fn format_result(result: &Result<(), VmError>) -> String {
+    match result {
+        Ok(()) => "ok".to_string(),
+        Err(error) => format!("{:?}", error),
+    }
+}
+
+impl fmt::Display for TraceEntry {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:>8}  {}\n          ", self.step, self.disassembly)?;
+        for (r, value) in self.registers.iter().enumerate() {
+            write!(f, "R{}=x{:04X} ", r, value)?;
+        }
+        write!(f, "COND={}", flags(self.cond))?;
+        if !self.writes.is_empty() {
+            write!(f, " writes {}", format_writes(&self.writes))?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for Divergence {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "Divergence at step {}: {}", self.step, self.disassembly)?;
+        for difference in &self.differences {
+            writeln!(f, "  {}", difference)?;
+        }
+        writeln!(f, "Reference trace (last {} steps):", self.trace.len())?;
+        for entry in &self.trace {
+            writeln!(f, "{}", entry)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assembler::vm_on_backend;
+
+    const COUNTDOWN: &str = "
+        .ORIG x3000
+        AND R0, R0, #0
+        ADD R0, R0, #3
+LOOP    ST R0, LAST
+        ADD R0, R0, #-1
+        BRp LOOP
+        HALT
+LAST    .FILL 0
+        .END
+";
+
+    #[test]
+    fn backends_agree() {
+        let mut lockstep = Lockstep::new(
+            vm_on_backend("prog.asm", COUNTDOWN, Backend::Interpreter),
+            vm_on_backend("prog.asm", COUNTDOWN, Backend::Blocks),
+        );
+
+        match lockstep.run(1000) {
+            Outcome::Halted { steps } => assert_eq!(steps, 12),
+            other => panic!("expected both programs to halt, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn step_limit_is_respected() {
+        let mut lockstep = Lockstep::new(
+            vm_on_backend("prog.asm", COUNTDOWN, Backend::Interpreter),
+            vm_on_backend("prog.asm", COUNTDOWN, Backend::Interpreter),
+        );
+
+        assert!(matches!(lockstep.run(5), Outcome::StepLimit { steps: 5 }));
+    }
+
+    #[test]
+    fn first_divergence_is_reported_with_a_trace() {
+        let mut candidate = vm_on_backend("prog.asm", COUNTDOWN, Backend::Blocks);
+        candidate.memory.write(0x3003, 0x103E); // ADD R0, R0, #-2 instead of #-1
+        let mut lockstep = Lockstep::new(
+            vm_on_backend("prog.asm", COUNTDOWN, Backend::Interpreter),
+            candidate,
+        );
+        lockstep.window = 2;
+
+        let Outcome::Diverged(divergence) = lockstep.run(1000) else {
+            panic!("expected a divergence");
+        };
+
+        assert_eq!(divergence.step, 4);
+        assert_eq!(
+            divergence.disassembly,
+            "x3003 <LOOP+1> (prog.asm:6): x103F  ADD R0, R0, #-1"
+        );
+        assert_eq!(
+            divergence.differences,
+            vec!["R0: reference x0002, candidate x0001"]
+        );
+        assert_eq!(divergence.trace.len(), 2);
+        assert_eq!(divergence.trace[0].writes, vec![(0x3006, 3)]);
+        assert!(divergence.to_string().starts_with(
+            "Divergence at step 4: x3003 <LOOP+1> (prog.asm:6): x103F  ADD R0, R0, #-1\n  R0: "
+        ));
+    }
+}
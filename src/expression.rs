@@ -0,0 +1,452 @@
+use std::fmt;
+
+use crate::{
+    constants::{FL_NEG, FL_POS, FL_ZRO},
+    utils::parse_number,
+    vm::Vm,
+    vm_error::VmError,
+};
+
+/// Binary operators of the expression language, from lowest to highest precedence group.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BinaryOp {
+    Or,
+    And,
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    BitOr,
+    BitXor,
+    BitAnd,
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Rem,
+}
+
+/// Unary operators of the expression language.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UnaryOp {
+    Neg,
+    Not,
+    BitNot,
+}
+
+/// A parsed expression over the machine state.
+///
+/// Registers are written `R0`..`R7`, `PC` and `COND`, memory cells `mem[<expr>]`
+/// and the number of times the current breakpoint was reached `hits`. `N`, `Z`
+/// and `P` stand for the condition flag values, so `COND == Z` works as expected.
+/// Any other name is looked up in the symbol table of the VM, so `mem[COUNT]`
+/// reads the word labeled `COUNT`.
+/// Register and memory values are unsigned, so `xFFFF` has to be used instead of `-1`.
+///
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Expr {
+    Number(i64),
+    Register(u16),
+    Hits,
+    Symbol(String),
+    Memory(Box<Expr>),
+    Unary(UnaryOp, Box<Expr>),
+    Binary(BinaryOp, Box<Expr>, Box<Expr>),
+}
+
+/// Values the expression is evaluated against.
+pub struct Context<'a> {
+    pub vm: &'a Vm,
+    pub hits: u64,
+}
+
+impl Expr {
+    /// Parses an expression.
+    ///
+    /// # Returns
+    ///
+    /// The parsed `Expr`, or a `VmError::InvalidExpression` describing the problem.
+    ///
+    pub fn parse(text: &str) -> Result<Expr, VmError> {
+        let tokens = tokenize(text)?;
+        let mut parser = Parser { tokens, pos: 0 };
+        let expr = parser.expression(0)?;
+        match parser.peek() {
+            None => Ok(expr),
+            Some(token) => Err(invalid(format!("unexpected `{}`", token))),
+        }
+    }
+
+    /// Evaluates the expression.
+    ///
+    /// # Returns
+    ///
+    /// The value of the expression, or a `VmError` if it could not be computed (for example on a division by zero).
+    ///
+    pub fn evaluate(&self, ctx: &Context) -> Result<i64, VmError> {
+        let value = match self {
+            Expr::Number(n) => *n,
+            Expr::Register(r) => ctx.vm.registers.get(*r)? as i64,
+            Expr::Hits => ctx.hits as i64,
+            Expr::Symbol(name) => ctx
+                .vm
+                .symbols
+                .address_of(name)
+                .ok_or_else(|| invalid(format!("unknown symbol `{}`", name)))?
+                as i64,
+            Expr::Memory(address) => {
+                let address = address.evaluate(ctx)? as u16;
+                ctx.vm.memory.memory[address as usize] as i64
+            }
+            Expr::Unary(op, operand) => {
+                let v = operand.evaluate(ctx)?;
+                match op {
+                    UnaryOp::Neg => v.wrapping_neg(),
+                    UnaryOp::Not => (v == 0) as i64,
+                    UnaryOp::BitNot => !v & 0xFFFF,
+                }
+            }
+            Expr::Binary(BinaryOp::And, lhs, rhs) => {
+                (lhs.evaluate(ctx)? != 0 && rhs.evaluate(ctx)? != 0) as i64
+            }
+            Expr::Binary(BinaryOp::Or, lhs, rhs) => {
+                (lhs.evaluate(ctx)? != 0 || rhs.evaluate(ctx)? != 0) as i64
+            }
+            Expr::Binary(op, lhs, rhs) => {
+                let (a, b) = (lhs.evaluate(ctx)?, rhs.evaluate(ctx)?);
+                match op {
+                    BinaryOp::Eq => (a == b) as i64,
+                    BinaryOp::Ne => (a != b) as i64,
+                    BinaryOp::Lt => (a < b) as i64,
+                    BinaryOp::Le => (a <= b) as i64,
+                    BinaryOp::Gt => (a > b) as i64,
+                    BinaryOp::Ge => (a >= b) as i64,
+                    BinaryOp::BitOr => a | b,
+                    BinaryOp::BitXor => a ^ b,
+                    BinaryOp::BitAnd => a & b,
+                    BinaryOp::Add => a.wrapping_add(b),
+                    BinaryOp::Sub => a.wrapping_sub(b),
+                    BinaryOp::Mul => a.wrapping_mul(b),
+                    BinaryOp::Div | BinaryOp::Rem if b == 0 => {
+                        return Err(invalid("division by zero".to_string()))
+                    }
+                    BinaryOp::Div => a.wrapping_div(b),
+                    BinaryOp::Rem => a.wrapping_rem(b),
+                    BinaryOp::And | BinaryOp::Or => unreachable!(),
+                }
+            }
+        };
+        Ok(value)
+    }
+}
+
+/// A logpoint message: literal text with `{expr}` (decimal) and `{expr:x}` (hexadecimal) placeholders.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Template {
+    pub source: String,
+    parts: Vec<TemplatePart>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum TemplatePart {
+    Text(String),
+    Value(Expr, bool),
+}
+
+impl Template {
+    /// Parses a message template.
+    ///
+    /// # Returns
+    ///
+    /// The parsed `Template`, or a `VmError::InvalidExpression` if a placeholder is malformed.
+    ///
+    pub fn parse(text: &str) -> Result<Template, VmError> {
+        let mut parts = Vec::new();
+        let mut rest = text;
+        while let Some(open) = rest.find('{') {
+            if open > 0 {
+                parts.push(TemplatePart::Text(rest[..open].to_string()));
+            }
+            let close = rest[open..]
+                .find('}')
+                .ok_or_else(|| invalid("unclosed `{` in message".to_string()))?
+                + open;
+            let inner = &rest[open + 1..close];
+            let (expr, hex) = match inner.strip_suffix(":x") {
+                Some(expr) => (expr, true),
+                None => (inner, false),
+            };
+            parts.push(TemplatePart::Value(Expr::parse(expr)?, hex));
+            rest = &rest[close + 1..];
+        }
+        if !rest.is_empty() {
+            parts.push(TemplatePart::Text(rest.to_string()));
+        }
+        Ok(Template {
+            source: text.to_string(),
+            parts,
+        })
+    }
+
+    /// Renders the message, evaluating every placeholder.
+    pub fn render(&self, ctx: &Context) -> Result<String, VmError> {
+        let mut out = String::new();
+        for part in &self.parts {
+            match part {
+                TemplatePart::Text(text) => out.push_str(text),
+                TemplatePart::Value(expr, true) => {
+                    out.push_str(&format!("x{:04X}", expr.evaluate(ctx)? as u16))
+                }
+                TemplatePart::Value(expr, false) => out.push_str(&expr.evaluate(ctx)?.to_string()),
+            }
+        }
+        Ok(out)
+    }
+}
+
+fn invalid(message: String) -> VmError {
+    VmError::InvalidExpression(message)
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Token {
+    Number(i64),
+    Ident(String),
+    Op(&'static str),
+}
+
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Token::Number(n) => write!(f, "{}", n),
+            Token::Ident(name) => write!(f, "{}", name),
+            Token::Op(op) => write!(f, "{}", op),
+        }
+    }
+}
+
+const OPERATORS: [&str; 22] = [
+    "||", "&&", "==", "!=", "<=", ">=", "<", ">", "|", "^", "&", "+", "-", "*", "/", "%", "!", "~",
+    "(", ")", "[", "]",
+];
+
+fn tokenize(text: &str) -> Result<Vec<Token>, VmError> {
+    let mut tokens = Vec::new();
+    let mut rest = text.trim_start();
+    while !rest.is_empty() {
+        if let Some(op) = OPERATORS.iter().find(|op| rest.starts_with(**op)) {
+            tokens.push(Token::Op(op));
+            rest = &rest[op.len()..];
+        } else {
+            let skip = if rest.starts_with("#-") { 2 } else { 0 };
+            let end = rest[skip..]
+                .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_' || c == '#'))
+                .map_or(rest.len(), |end| end + skip);
+            if end == 0 {
+                return Err(invalid(format!("unexpected character in `{}`", rest)));
+            }
+            let word = &rest[..end];
+            let is_name = word.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_');
+            match parse_number(word) {
+                Some(n) => tokens.push(Token::Number(n as i64)),
+                None if is_name => tokens.push(Token::Ident(word.to_string())),
+                None => return Err(invalid(format!("invalid number `{}`", word))),
+            }
+            rest = &rest[end..];
+        }
+        rest = rest.trim_start();
+    }
+    Ok(tokens)
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    pos: usize,
+}
+
+/// Binary operators grouped by precedence level, lowest first.
+const LEVELS: [&[(&str, BinaryOp)]; 8] = [
+    &[("||", BinaryOp::Or)],
+    &[("&&", BinaryOp::And)],
+    &[
+        ("==", BinaryOp::Eq),
+        ("!=", BinaryOp::Ne),
+        ("<", BinaryOp::Lt),
+        ("<=", BinaryOp::Le),
+        (">", BinaryOp::Gt),
+        (">=", BinaryOp::Ge),
+    ],
+    &[("|", BinaryOp::BitOr)],
+    &[("^", BinaryOp::BitXor)],
+    &[("&", BinaryOp::BitAnd)],
+    &[("+", BinaryOp::Add), ("-", BinaryOp::Sub)],
+    &[
+        ("*", BinaryOp::Mul),
+        ("/", BinaryOp::Div),
+        ("%", BinaryOp::Rem),
+    ],
+];
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos)
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.pos).cloned();
+        self.pos += 1;
+        token
+    }
+
+    fn expect(&mut self, op: &str) -> Result<(), VmError> {
+        match self.next() {
+            Some(Token::Op(found)) if found == op => Ok(()),
+            Some(token) => Err(invalid(format!("expected `{}`, found `{}`", op, token))),
+            None => Err(invalid(format!("expected `{}`", op))),
+        }
+    }
+
+    fn expression(&mut self, level: usize) -> Result<Expr, VmError> {
+        if level == LEVELS.len() {
+            return self.unary();
+        }
+        let mut lhs = self.expression(level + 1)?;
+        while let Some(Token::Op(op)) = self.peek() {
+            let Some(&(_, binary)) = LEVELS[level].iter().find(|(s, _)| s == op) else {
+                break;
+            };
+            self.pos += 1;
+            let rhs = self.expression(level + 1)?;
+            lhs = Expr::Binary(binary, Box::new(lhs), Box::new(rhs));
+        }
+        Ok(lhs)
+    }
+
+    fn unary(&mut self) -> Result<Expr, VmError> {
+        let op = match self.peek() {
+            Some(Token::Op("-")) => UnaryOp::Neg,
+            Some(Token::Op("!")) => UnaryOp::Not,
+            Some(Token::Op("~")) => UnaryOp::BitNot,
+            _ => return self.primary(),
+        };
+        self.pos += 1;
+        Ok(Expr::Unary(op, Box::new(self.unary()?)))
+    }
+
+    fn primary(&mut self) -> Result<Expr, VmError> {
+        match self.next() {
+            Some(Token::Number(n)) => Ok(Expr::Number(n)),
+            Some(Token::Op("(")) => {
+                let expr = self.expression(0)?;
+                self.expect(")")?;
+                Ok(expr)
+            }
+            Some(Token::Ident(name)) => {
+                let upper = name.to_ascii_uppercase();
+                match upper.as_str() {
+                    "PC" => Ok(Expr::Register(8)),
+                    "COND" => Ok(Expr::Register(9)),
+                    "HITS" => Ok(Expr::Hits),
+                    "N" => Ok(Expr::Number(FL_NEG as i64)),
+                    "Z" => Ok(Expr::Number(FL_ZRO as i64)),
+                    "P" => Ok(Expr::Number(FL_POS as i64)),
+                    "MEM" => {
+                        self.expect("[")?;
+                        let address = self.expression(0)?;
+                        self.expect("]")?;
+                        Ok(Expr::Memory(Box::new(address)))
+                    }
+                    _ => match upper.strip_prefix('R').and_then(|r| r.parse::<u16>().ok()) {
+                        Some(r) if r < 8 => Ok(Expr::Register(r)),
+                        Some(_) => Err(invalid(format!("unknown name `{}`", name))),
+                        None => Ok(Expr::Symbol(name)),
+                    },
+                }
+            }
+            Some(token) => Err(invalid(format!("unexpected `{}`", token))),
+            None => Err(invalid("unexpected end of expression".to_string())),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn eval(text: &str, vm: &Vm, hits: u64) -> i64 {
+        Expr::parse(text)
+            .unwrap()
+            .evaluate(&Context { vm, hits })
+            .unwrap()
+    }
+
+    #[test]
+    fn registers_memory_and_hits() {
+        let mut vm = Vm::new();
+        vm.registers[3] = 7;
+        vm.memory.write(0x4000, 0x1234);
+
+        assert_eq!(eval("R3 + 1", &vm, 0), 8);
+        assert_eq!(eval("PC == x3000", &vm, 0), 1);
+        assert_eq!(eval("COND == Z", &vm, 0), 1);
+        assert_eq!(eval("mem[x4000]", &vm, 0), 0x1234);
+        assert_eq!(eval("mem[x3FFF + 1] & xFF", &vm, 0), 0x34);
+        assert_eq!(eval("hits > 10", &vm, 11), 1);
+        assert_eq!(eval("hits > 10", &vm, 10), 0);
+    }
+
+    #[test]
+    fn symbols_are_resolved_when_evaluated() {
+        let mut vm = Vm::new();
+        vm.symbols.insert("COUNT", 0x4000);
+        vm.memory.write(0x4000, 9);
+
+        assert_eq!(eval("mem[COUNT] == 9", &vm, 0), 1);
+        let missing = Expr::parse("mem[TOTAL]").unwrap();
+        assert!(missing.evaluate(&Context { vm: &vm, hits: 0 }).is_err());
+    }
+
+    #[test]
+    fn precedence_and_logic() {
+        let vm = Vm::new();
+
+        assert_eq!(eval("1 + 2 * 3", &vm, 0), 7);
+        assert_eq!(eval("(1 + 2) * 3", &vm, 0), 9);
+        assert_eq!(eval("1 < 2 && 2 < 1 || 3 == 3", &vm, 0), 1);
+        assert_eq!(eval("!(R0 != 0)", &vm, 0), 1);
+        assert_eq!(eval("~0", &vm, 0), 0xFFFF);
+        assert_eq!(eval("#-5 + 5", &vm, 0), 0);
+    }
+
+    #[test]
+    fn parse_errors() {
+        assert!(Expr::parse("R8").is_err());
+        assert!(Expr::parse("R0 +").is_err());
+        assert!(Expr::parse("mem[x4000").is_err());
+        assert!(Expr::parse("(R0").is_err());
+        assert!(Expr::parse("R0 R1").is_err());
+    }
+
+    #[test]
+    fn division_by_zero_is_an_error() {
+        let vm = Vm::new();
+        let expr = Expr::parse("R0 / 0").unwrap();
+
+        assert!(expr.evaluate(&Context { vm: &vm, hits: 0 }).is_err());
+    }
+
+    #[test]
+    fn template_rendering() {
+        let mut vm = Vm::new();
+        vm.registers[0] = 0x41;
+        let template = Template::parse("R0={R0} ({R0:x}) hit {hits}").unwrap();
+
+        assert_eq!(
+            template.render(&Context { vm: &vm, hits: 2 }).unwrap(),
+            "R0=65 (x0041) hit 2"
+        );
+        assert!(Template::parse("{R0").is_err());
+    }
+}
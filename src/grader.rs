@@ -0,0 +1,681 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs;
+use std::path::Path;
+
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    console::ScriptedConsole,
+    constants::{FL_NEG, FL_POS, FL_ZRO},
+    instruction::Instruction,
+    utils::parse_word,
+    vm::{LoadOptions, Vm},
+    vm_error::VmError,
+};
+
+/// The instruction limit of a test case that does not set one.
+pub const DEFAULT_MAX_STEPS: u64 = 1_000_000;
+
+/// The message the VM prints when a program halts, which is not part of the graded output.
+const HALT_MESSAGE: &str = "HALT\n";
+
+/// A test specification: the test cases a program is graded on.
+///
+/// Specifications are written in TOML, or in JSON when the file ends in `.json`:
+///
+/// ```toml
+/// max_steps = 100000
+///
+/// [[case]]
+/// name = "adds two digits"
+/// points = 2
+/// input = "34"
+/// registers = { R6 = "xFE00" }
+/// memory = { "x4000" = [1, 2, 3] }
+///
+/// [case.expect]
+/// output = "7\n"
+/// registers = { R0 = 7 }
+/// memory = { RESULT = "x0007" }
+/// ```
+///
+/// Values are numbers, LC-3 literals such as `"x3000"` or `"#-1"`, or labels of the image's
+/// symbol table. Memory keys are addresses or labels, and a list of values covers the
+/// following addresses too.
+///
+/// # Fields
+///
+/// * `max_steps` - The instruction limit of cases that do not set their own.
+/// * `cases` - The test cases, `[[case]]` in TOML or `"cases"` in JSON.
+///
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Spec {
+    #[serde(default)]
+    pub max_steps: Option<u64>,
+    #[serde(rename = "case", alias = "cases", default)]
+    pub cases: Vec<TestCase>,
+}
+
+/// One test case: how the machine is set up before the run, and what is expected after it.
+///
+/// # Fields
+///
+/// * `name` - The name shown in the report.
+/// * `points` - What the case is worth, 1 by default.
+/// * `input` - The keys typed on the keyboard during the run.
+/// * `max_steps` - The most instructions the program may execute.
+/// * `registers` - Initial values of `R0`-`R7` and `PC`.
+/// * `memory` - Initial memory contents, written after the image is loaded.
+/// * `expect` - The expected outcome.
+///
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TestCase {
+    pub name: String,
+    #[serde(default = "default_points")]
+    pub points: u32,
+    #[serde(default)]
+    pub input: String,
+    #[serde(default)]
+    pub max_steps: Option<u64>,
+    #[serde(default)]
+    pub registers: BTreeMap<String, Value>,
+    #[serde(default)]
+    pub memory: BTreeMap<String, Values>,
+    #[serde(default)]
+    pub expect: Expectations,
+}
+
+/// What a test case expects of the run.
+///
+/// # Fields
+///
+/// * `output` - The exact console output, without the `HALT` message of the VM.
+/// * `output_regex` - A regular expression the console output must match somewhere in.
+/// * `halt` - Whether the program must reach a `HALT` within the instruction limit, which
+///   is the default.
+/// * `registers` - Final values of `R0`-`R7`, `PC` and `COND` (`"n"`, `"z"` or `"p"`).
+/// * `memory` - Final memory contents.
+///
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Expectations {
+    #[serde(default)]
+    pub output: Option<String>,
+    #[serde(default)]
+    pub output_regex: Option<String>,
+    #[serde(default = "default_halt")]
+    pub halt: bool,
+    #[serde(default)]
+    pub registers: BTreeMap<String, Value>,
+    #[serde(default)]
+    pub memory: BTreeMap<String, Values>,
+}
+
+impl Default for Expectations {
+    fn default() -> Self {
+        Expectations {
+            output: None,
+            output_regex: None,
+            halt: true,
+            registers: BTreeMap::new(),
+            memory: BTreeMap::new(),
+        }
+    }
+}
+
+/// A word in a specification: a number, or text holding an LC-3 literal or a label.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(untagged)]
+pub enum Value {
+    Number(i64),
+    Text(String),
+}
+
+/// One word, or a list of words stored at consecutive addresses.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(untagged)]
+pub enum Values {
+    One(Value),
+    Many(Vec<Value>),
+}
+
+fn default_points() -> u32 {
+    1
+}
+
+fn default_halt() -> bool {
+    true
+}
+
+/// The result of one test case.
+///
+/// # Fields
+///
+/// * `name` - The name of the case.
+/// * `passed` - Whether every expectation was met.
+/// * `points` - The points earned: all of them if the case passed, none otherwise.
+/// * `max_points` - What the case is worth.
+/// * `steps` - How many instructions were executed.
+/// * `halted` - Whether the program reached a `HALT`.
+/// * `output` - The console output of the program.
+/// * `failures` - Every expectation that was not met, and problems with the case itself.
+///
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct CaseResult {
+    pub name: String,
+    pub passed: bool,
+    pub points: u32,
+    pub max_points: u32,
+    pub steps: u64,
+    pub halted: bool,
+    pub output: String,
+    pub failures: Vec<String>,
+}
+
+/// The result of grading an image against a specification.
+///
+/// # Fields
+///
+/// * `image` - The graded image file.
+/// * `score` - The points earned.
+/// * `max_score` - The points of every case.
+/// * `cases` - The result of each case, in the order of the specification.
+///
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct GradeReport {
+    pub image: String,
+    pub score: u32,
+    pub max_score: u32,
+    pub cases: Vec<CaseResult>,
+}
+
+impl Spec {
+    /// Parses a specification, in JSON if `path` ends in `.json` and in TOML otherwise.
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - The path of the specification, used to pick the format and in errors.
+    /// * `text` - The specification.
+    ///
+    /// # Returns
+    ///
+    /// The parsed `Spec`, or a `VmError::InvalidSpec` if it is malformed.
+    ///
+    pub fn parse(path: &str, text: &str) -> Result<Spec, VmError> {
+        let json = Path::new(path)
+            .extension()
+            .is_some_and(|e| e.eq_ignore_ascii_case("json"));
+        let spec = if json {
+            serde_json::from_str(text).map_err(|e| e.to_string())
+        } else {
+            toml::from_str(text).map_err(|e| e.to_string())
+        };
+        spec.map_err(|e| VmError::InvalidSpec(format!("{}: {}", path, e)))
+    }
+
+    /// Reads a specification file.
+    ///
+    /// # Returns
+    ///
+    /// The parsed `Spec`, or a `VmError` if the file cannot be read or parsed.
+    ///
+    pub fn from_file(path: &str) -> Result<Spec, VmError> {
+        let text =
+            fs::read_to_string(path).map_err(|e| VmError::FailedToOpenFile(e.to_string()))?;
+        Spec::parse(path, &text)
+    }
+}
+
+/// Grades an image: runs every test case of the specification on a fresh machine with the
+/// image and its symbol table loaded.
+///
+/// An image that cannot be loaded fails every case instead of stopping the grading, so a
+/// broken submission still gets a report.
+///
+/// # Arguments
+///
+/// * `spec` - The test cases.
+/// * `image` - The path of the image to grade.
+///
+pub fn grade(spec: &Spec, image: &str) -> GradeReport {
+    let load = || -> Result<Vm, VmError> {
+        let mut vm = Vm::new();
+        vm.load_image(image, &LoadOptions::default())?;
+        Ok(vm)
+    };
+    let cases: Vec<CaseResult> = spec
+        .cases
+        .iter()
+        .map(|case| {
+            let max_steps = case
+                .max_steps
+                .or(spec.max_steps)
+                .unwrap_or(DEFAULT_MAX_STEPS);
+            match load() {
+                Ok(vm) => run_case(vm, case, max_steps),
+                Err(error) => case_result(
+                    case,
+                    0,
+                    false,
+                    String::new(),
+                    vec![format!("cannot load {}: {:?}", image, error)],
+                ),
+            }
+        })
+        .collect();
+    GradeReport {
+        image: image.to_string(),
+        score: cases.iter().map(|c| c.points).sum(),
+        max_score: cases.iter().map(|c| c.max_points).sum(),
+        cases,
+    }
+}
+
+/// Sets up the machine for a test case, runs it and checks the expectations.
+fn run_case(mut vm: Vm, case: &TestCase, max_steps: u64) -> CaseResult {
+    let console = ScriptedConsole::new(case.input.as_bytes());
+    let output = console.output();
+    vm.memory.console = Box::new(console);
+    if let Err(message) = set_up(&mut vm, case) {
+        return case_result(case, 0, false, String::new(), vec![message]);
+    }
+
+    let mut running = true;
+    let mut steps = 0;
+    let mut error = None;
+    while running && steps < max_steps {
+        steps += 1;
+        if let Err(e) = vm.step(&mut running) {
+            error = Some(e);
+            break;
+        }
+    }
+    let stopped_at = vm.memory.last_fetch;
+    let halted = !running
+        && error.is_none()
+        && matches!(
+            Instruction::decode(vm.memory.memory[stopped_at as usize]),
+            Instruction::Trap { .. }
+        );
+
+    let mut failures = Vec::new();
+    let mut output = output.borrow().clone();
+    if halted {
+        if let Some(stripped) = output.strip_suffix(HALT_MESSAGE) {
+            output.truncate(stripped.len());
+        }
+    }
+
+    if case.expect.halt && !halted {
+        failures.push(match error {
+            Some(error) => format!("stopped at x{:04X} with {:?}", stopped_at, error),
+            None if running => format!("did not halt within {} instructions", max_steps),
+            None => format!("stopped at x{:04X} on an illegal instruction", stopped_at),
+        });
+    }
+    if let Some(expected) = &case.expect.output {
+        if output != *expected {
+            failures.push(format!(
+                "output: expected {:?}, found {:?}",
+                expected, output
+            ));
+        }
+    }
+    if let Some(pattern) = &case.expect.output_regex {
+        match Regex::new(pattern) {
+            Ok(regex) if regex.is_match(&output) => {}
+            Ok(_) => failures.push(format!("output: {:?} does not match /{}/", output, pattern)),
+            Err(e) => failures.push(format!("invalid output_regex: {}", e)),
+        }
+    }
+    check_registers(&vm, &case.expect.registers, &mut failures);
+    check_memory(&vm, &case.expect.memory, &mut failures);
+
+    case_result(case, steps, halted, output, failures)
+}
+
+/// Builds the result of a test case, which passed if nothing failed.
+fn case_result(
+    case: &TestCase,
+    steps: u64,
+    halted: bool,
+    output: String,
+    failures: Vec<String>,
+) -> CaseResult {
+    let passed = failures.is_empty();
+    CaseResult {
+        name: case.name.clone(),
+        passed,
+        points: if passed { case.points } else { 0 },
+        max_points: case.points,
+        steps,
+        halted,
+        output,
+        failures,
+    }
+}
+
+/// Writes the initial registers and memory of a test case.
+fn set_up(vm: &mut Vm, case: &TestCase) -> Result<(), String> {
+    for (name, value) in &case.registers {
+        let value = resolve(vm, value)?;
+        match name.to_ascii_uppercase().as_str() {
+            "PC" => vm.registers.pc = value,
+            other => vm.registers[register(other)?] = value,
+        }
+    }
+    for (location, values) in &case.memory {
+        let address = resolve_text(vm, location)?;
+        for (i, value) in values.iter().enumerate() {
+            let value = resolve(vm, value)?;
+            vm.memory.write(address.wrapping_add(i as u16), value);
+        }
+    }
+    Ok(())
+}
+
+fn check_registers(vm: &Vm, expected: &BTreeMap<String, Value>, failures: &mut Vec<String>) {
+    for (name, value) in expected {
+        let upper = name.to_ascii_uppercase();
+        let checked = match upper.as_str() {
+            "PC" => resolve(vm, value).map(|value| (value, vm.registers.pc)),
+            "COND" => condition(value).map(|value| (value, vm.registers.cond)),
+            other => register(other)
+                .and_then(|r| resolve(vm, value).map(|value| (value, vm.registers[r]))),
+        };
+        match checked {
+            Ok((expected, actual)) if expected != actual => failures.push(format!(
+                "{}: expected x{:04X}, found x{:04X}",
+                upper, expected, actual
+            )),
+            Ok(_) => {}
+            Err(message) => failures.push(message),
+        }
+    }
+}
+
+fn check_memory(vm: &Vm, expected: &BTreeMap<String, Values>, failures: &mut Vec<String>) {
+    for (location, values) in expected {
+        let address = match resolve_text(vm, location) {
+            Ok(address) => address,
+            Err(message) => {
+                failures.push(message);
+                continue;
+            }
+        };
+        for (i, value) in values.iter().enumerate() {
+            let address = address.wrapping_add(i as u16);
+            let actual = vm.memory.memory[address as usize];
+            match resolve(vm, value) {
+                Ok(expected) if expected != actual => failures.push(format!(
+                    "memory {}: expected x{:04X}, found x{:04X}",
+                    vm.symbols.format_address(address),
+                    expected,
+                    actual
+                )),
+                Ok(_) => {}
+                Err(message) => failures.push(message),
+            }
+        }
+    }
+}
+
+fn register(name: &str) -> Result<u16, String> {
+    match name.strip_prefix('R').and_then(|n| n.parse::<u16>().ok()) {
+        Some(register) if register < 8 => Ok(register),
+        _ => Err(format!("unknown register {}", name)),
+    }
+}
+
+fn condition(value: &Value) -> Result<u16, String> {
+    match value {
+        Value::Text(text) if text.eq_ignore_ascii_case("n") => Ok(FL_NEG),
+        Value::Text(text) if text.eq_ignore_ascii_case("z") => Ok(FL_ZRO),
+        Value::Text(text) if text.eq_ignore_ascii_case("p") => Ok(FL_POS),
+        _ => Err(format!(
+            "COND must be \"n\", \"z\" or \"p\", found {}",
+            value
+        )),
+    }
+}
+
+fn resolve(vm: &Vm, value: &Value) -> Result<u16, String> {
+    match value {
+        Value::Number(n) if (-0x8000..=0xFFFF).contains(n) => Ok(*n as u16),
+        Value::Number(n) => Err(format!("{} does not fit in 16 bits", n)),
+        Value::Text(text) => resolve_text(vm, text),
+    }
+}
+
+fn resolve_text(vm: &Vm, text: &str) -> Result<u16, String> {
+    parse_word(text)
+        .or_else(|| vm.symbols.address_of(text))
+        .ok_or_else(|| format!("{} is neither a number nor a label of the image", text))
+}
+
+impl Values {
+    fn iter(&self) -> impl Iterator<Item = &Value> {
+        match self {
+            Values::One(value) => std::slice::from_ref(value).iter(),
+            Values::Many(values) => values.iter(),
+        }
+    }
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Value::Number(n) => write!(f, "{}", n),
+            Value::Text(text) => write!(f, "{:?}", text),
+        }
+    }
+}
+
+impl GradeReport {
+    /// Serializes the report to JSON, for other tools to collect the scores.
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(self).unwrap_or_default()
+    }
+}
+
+impl fmt::Display for GradeReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for case in &self.cases {
+            writeln!(
+                f,
+                "{}  {} ({}/{})",
+                if case.passed { "PASS" } else { "FAIL" },
+                case.name,
+                case.points,
+                case.max_points
+            )?;
+            for failure in &case.failures {
+                writeln!(f, "      {}", failure)?;
+            }
+        }
+        writeln!(f, "Score: {}/{}", self.score, self.max_score)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        assembler::assemble,
+        image_format::{write_image, ImageFormat},
+        utils::write_file,
+    };
+
+    const PROGRAM: &str = "\
+        .ORIG x3000
+        GETC
+        ADD R1, R0, #-16
+        ADD R1, R1, #-16
+        ADD R1, R1, #-16
+        GETC
+        ADD R0, R0, R1
+        ST R0, RESULT
+        OUT
+        HALT
+RESULT  .FILL #0
+        .END
+";
+
+    /// Assembles `source` into an image with its symbol table in the temporary directory.
+    fn image(name: &str, source: &str) -> String {
+        let assembly = assemble("prog.asm", source).unwrap();
+        let dir = std::env::temp_dir();
+        let path = dir.join(format!("{}.obj", name));
+        write_file(
+            path.to_str().unwrap(),
+            write_image(ImageFormat::Obj, &assembly.sections).unwrap(),
+        )
+        .unwrap();
+        write_file(
+            dir.join(format!("{}.sym.json", name)).to_str().unwrap(),
+            assembly.symbols.to_json(),
+        )
+        .unwrap();
+        path.to_str().unwrap().to_string()
+    }
+
+    #[test]
+    fn parses_toml_and_json_specs() {
+        let toml = Spec::parse(
+            "spec.toml",
+            "max_steps = 50\n[[case]]\nname = \"a\"\ninput = \"12\"\n\
+             [case.expect]\noutput = \"3\"\nmemory = { RESULT = [\"x0033\", 0] }\n",
+        )
+        .unwrap();
+        let json = Spec::parse(
+            "spec.json",
+            r#"{"max_steps": 50, "cases": [{"name": "a", "input": "12",
+                "expect": {"output": "3", "memory": {"RESULT": ["x0033", 0]}}}]}"#,
+        )
+        .unwrap();
+
+        assert_eq!(toml, json);
+        assert_eq!(toml.cases[0].points, 1);
+        assert!(toml.cases[0].expect.halt);
+        assert!(matches!(
+            Spec::parse("spec.toml", "[[case]]\nname = \"a\"\ntimeout = 3\n"),
+            Err(VmError::InvalidSpec(_))
+        ));
+    }
+
+    #[test]
+    fn grades_every_case() {
+        let image = image("lc3_grader_sum", PROGRAM);
+        let spec = Spec::parse(
+            "spec.toml",
+            r#"
+[[case]]
+name = "adds"
+points = 3
+input = "34"
+expect = { output = "7", registers = { R1 = 3, COND = "p" }, memory = { RESULT = "x0037" } }
+
+[[case]]
+name = "set up"
+input = "11"
+registers = { PC = "x3004" }
+memory = { RESULT = 9 }
+expect = { output_regex = "^1$", registers = { R1 = 0 }, memory = { RESULT = "x0031" } }
+
+[[case]]
+name = "wrong"
+input = "12"
+expect = { output = "4", registers = { R0 = 52 }, memory = { RESULT = [52, 1] } }
+"#,
+        )
+        .unwrap();
+
+        let report = grade(&spec, &image);
+
+        assert_eq!((report.score, report.max_score), (4, 5));
+        assert!(report.cases[0].passed);
+        assert_eq!(report.cases[0].steps, 9);
+        // Starting at the second GETC, the first key is added to R1 = 0 and overwrites RESULT.
+        assert!(report.cases[1].passed, "{:?}", report.cases[1].failures);
+        assert_eq!(
+            report.cases[2].failures,
+            vec![
+                "output: expected \"4\", found \"3\"",
+                "R0: expected x0034, found x0033",
+                "memory x3009 <RESULT> (prog.asm:11): expected x0034, found x0033",
+                "memory x300A <RESULT+1>: expected x0001, found x0000",
+            ]
+        );
+    }
+
+    #[test]
+    fn programs_must_halt() {
+        let image = image("lc3_grader_loop", ".ORIG x3000\nLOOP BRnzp LOOP\n.END\n");
+        let spec = Spec::parse(
+            "spec.toml",
+            "[[case]]\nname = \"loops\"\nmax_steps = 10\n\
+             [[case]]\nname = \"may loop\"\nmax_steps = 10\nexpect = { halt = false }\n",
+        )
+        .unwrap();
+
+        let report = grade(&spec, &image);
+
+        assert_eq!(
+            report.cases[0].failures,
+            vec!["did not halt within 10 instructions"]
+        );
+        assert!(report.cases[1].passed);
+        assert_eq!(report.cases[1].steps, 10);
+    }
+
+    #[test]
+    fn an_image_that_cannot_be_loaded_fails_every_case() {
+        let spec = Spec::parse("spec.toml", "[[case]]\nname = \"a\"\n").unwrap();
+
+        let report = grade(&spec, "/nonexistent/lc3_grader.obj");
+
+        assert_eq!(report.score, 0);
+        assert!(report.cases[0].failures[0].starts_with("cannot load"));
+    }
+
+    #[test]
+    fn report_lists_failures_under_their_case() {
+        let report = GradeReport {
+            image: "prog.obj".to_string(),
+            score: 2,
+            max_score: 3,
+            cases: vec![
+                CaseResult {
+                    name: "first".to_string(),
+                    passed: true,
+                    points: 2,
+                    max_points: 2,
+                    steps: 5,
+                    halted: true,
+                    output: String::new(),
+                    failures: vec![],
+                },
+                CaseResult {
+                    name: "second".to_string(),
+                    passed: false,
+                    points: 0,
+                    max_points: 1,
+                    steps: 5,
+                    halted: false,
+                    output: String::new(),
+                    failures: vec!["did not halt within 5 instructions".to_string()],
+                },
+            ],
+        };
+
+        assert_eq!(
+            report.to_string(),
+            "PASS  first (2/2)\nFAIL  second (0/1)\n      did not halt within 5 instructions\n\
+             Score: 2/3\n"
+        );
+    }
+}
@@ -0,0 +1,421 @@
+use std::fs;
+use std::path::Path;
+
+use crate::vm_error::VmError;
+
+/// Header that starts every lc3tools object file.
+const LC3TOOLS_MAGIC: [u8; 8] = [0x1C, 0x30, 0x15, 0xC0, 0x01, 0x01, 0x01, 0x01];
+
+/// The file formats an image can be stored in.
+///
+/// * `Obj` - Raw big-endian words, the first one being the origin.
+/// * `Hex` - One 4-digit hexadecimal word per line, the first one being the origin.
+/// * `Bin` - One word per line written as 16 `0`/`1` characters, the first one being the origin.
+/// * `Lc3Tools` - The object format of lc3tools, which can hold several sections,
+///   each starting at its own `.ORIG`.
+///
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ImageFormat {
+    Obj,
+    Hex,
+    Bin,
+    Lc3Tools,
+}
+
+/// A run of consecutive words loaded at `origin`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Section {
+    pub origin: u16,
+    pub words: Vec<u16>,
+}
+
+impl ImageFormat {
+    /// Returns the format with the given name (`obj`, `hex`, `bin` or `lc3tools`).
+    pub fn from_name(name: &str) -> Option<ImageFormat> {
+        match name.to_ascii_lowercase().as_str() {
+            "obj" => Some(ImageFormat::Obj),
+            "hex" => Some(ImageFormat::Hex),
+            "bin" => Some(ImageFormat::Bin),
+            "lc3tools" => Some(ImageFormat::Lc3Tools),
+            _ => None,
+        }
+    }
+
+    /// Returns the format implied by the extension of `path`, if it is a known one.
+    ///
+    /// `.obj` files are reported as `Obj`, since lc3tools objects share the extension
+    /// and can only be told apart by their content.
+    ///
+    pub fn from_extension(path: &str) -> Option<ImageFormat> {
+        let extension = Path::new(path).extension()?.to_str()?;
+        match ImageFormat::from_name(extension)? {
+            ImageFormat::Lc3Tools => None,
+            format => Some(format),
+        }
+    }
+
+    /// Detects the format of an image from its extension and content.
+    ///
+    /// lc3tools objects are recognized by their header whatever the extension. Files
+    /// with an unknown extension are checked for the `.bin` and `.hex` text layouts,
+    /// and are otherwise read as raw `.obj` files.
+    ///
+    pub fn detect(path: &str, bytes: &[u8]) -> ImageFormat {
+        if bytes.starts_with(&LC3TOOLS_MAGIC) {
+            return ImageFormat::Lc3Tools;
+        }
+        if let Some(format) = ImageFormat::from_extension(path) {
+            return format;
+        }
+        let text = match std::str::from_utf8(bytes) {
+            Ok(text) if !text.trim().is_empty() => text,
+            _ => return ImageFormat::Obj,
+        };
+        let lines: Vec<&str> = text
+            .lines()
+            .map(str::trim)
+            .filter(|l| !l.is_empty())
+            .collect();
+        if lines
+            .iter()
+            .all(|l| l.len() == 16 && l.chars().all(|c| c == '0' || c == '1'))
+        {
+            ImageFormat::Bin
+        } else if lines
+            .iter()
+            .all(|l| l.len() == 4 && l.chars().all(|c| c.is_ascii_hexdigit()))
+        {
+            ImageFormat::Hex
+        } else {
+            ImageFormat::Obj
+        }
+    }
+}
+
+/// Reads an image file in any supported format.
+///
+/// # Returns
+///
+/// The sections of the image, or a `VmError` if the file cannot be read or is malformed.
+///
+pub fn read_sections(path: &str) -> Result<Vec<Section>, VmError> {
+    let bytes = fs::read(path).map_err(|e| VmError::FailedToOpenFile(e.to_string()))?;
+    parse_image(ImageFormat::detect(path, &bytes), &bytes)
+}
+
+/// Parses the contents of an image file.
+///
+/// # Arguments
+///
+/// * `format` - The format the contents are stored in.
+/// * `bytes` - The contents of the file.
+///
+/// # Returns
+///
+/// The sections of the image, or a `VmError` if the contents are malformed.
+///
+pub fn parse_image(format: ImageFormat, bytes: &[u8]) -> Result<Vec<Section>, VmError> {
+    match format {
+        ImageFormat::Obj => parse_obj(bytes),
+        ImageFormat::Hex => parse_text(bytes, 16, 4),
+        ImageFormat::Bin => parse_text(bytes, 2, 16),
+        ImageFormat::Lc3Tools => parse_lc3tools(bytes),
+    }
+}
+
+/// Serializes the sections of an image.
+///
+/// # Arguments
+///
+/// * `format` - The format to write.
+/// * `sections` - The sections of the image.
+///
+/// # Returns
+///
+/// The contents of the file, or a `VmError` if the format cannot hold the sections
+/// (every format except `Lc3Tools` holds a single section).
+///
+pub fn write_image(format: ImageFormat, sections: &[Section]) -> Result<Vec<u8>, VmError> {
+    if format == ImageFormat::Lc3Tools {
+        return Ok(write_lc3tools(sections));
+    }
+    let [section] = sections else {
+        return Err(VmError::InvalidImage(format!(
+            "the {:?} format holds exactly one section, the image has {}",
+            format,
+            sections.len()
+        )));
+    };
+    let words = std::iter::once(section.origin).chain(section.words.iter().copied());
+    let bytes = match format {
+        ImageFormat::Obj => words.flat_map(u16::to_be_bytes).collect(),
+        ImageFormat::Hex => words
+            .map(|w| format!("{:04X}\n", w))
+            .collect::<String>()
+            .into_bytes(),
+        ImageFormat::Bin => words
+            .map(|w| format!("{:016b}\n", w))
+            .collect::<String>()
+            .into_bytes(),
+        ImageFormat::Lc3Tools => unreachable!(),
+    };
+    Ok(bytes)
+}
+
+fn parse_obj(bytes: &[u8]) -> Result<Vec<Section>, VmError> {
+    if !bytes.len().is_multiple_of(2) {
+        return Err(VmError::InvalidImage(format!(
+            "odd file length ({} bytes), the last word is truncated",
+            bytes.len()
+        )));
+    }
+    let mut words = bytes
+        .chunks_exact(2)
+        .map(|pair| u16::from_be_bytes([pair[0], pair[1]]));
+    let origin = words
+        .next()
+        .ok_or_else(|| VmError::InvalidImage("the image does not contain an origin".to_string()))?;
+    Ok(vec![Section {
+        origin,
+        words: words.collect(),
+    }])
+}
+
+fn parse_text(bytes: &[u8], radix: u32, digits: usize) -> Result<Vec<Section>, VmError> {
+    let text = std::str::from_utf8(bytes).map_err(|e| VmError::InvalidImage(e.to_string()))?;
+    let mut words = Vec::new();
+    for (number, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+        let word = if line.len() == digits {
+            u16::from_str_radix(line, radix).ok()
+        } else {
+            None
+        };
+        match word {
+            Some(word) => words.push(word),
+            None => {
+                return Err(VmError::InvalidImage(format!(
+                    "line {}: expected {} base-{} digits, found `{}`",
+                    number + 1,
+                    digits,
+                    radix,
+                    line
+                )))
+            }
+        }
+    }
+    let Some((&origin, words)) = words.split_first() else {
+        return Err(VmError::InvalidImage(
+            "the image does not contain an origin".to_string(),
+        ));
+    };
+    Ok(vec![Section {
+        origin,
+        words: words.to_vec(),
+    }])
+}
+
+/// Parses an lc3tools object: the header followed by entries made of a little-endian
+/// word, a flag telling whether the word is an `.ORIG`, and the source line that
+/// produced it (a little-endian 32-bit length followed by the text).
+fn parse_lc3tools(bytes: &[u8]) -> Result<Vec<Section>, VmError> {
+    let truncated = || VmError::InvalidImage("truncated lc3tools object".to_string());
+    let mut rest = bytes
+        .strip_prefix(&LC3TOOLS_MAGIC[..])
+        .ok_or_else(|| VmError::InvalidImage("missing lc3tools header".to_string()))?;
+
+    let mut sections: Vec<Section> = Vec::new();
+    while !rest.is_empty() {
+        if rest.len() < 7 {
+            return Err(truncated());
+        }
+        let value = u16::from_le_bytes([rest[0], rest[1]]);
+        let is_orig = rest[2] != 0;
+        let length = u32::from_le_bytes([rest[3], rest[4], rest[5], rest[6]]) as usize;
+        rest = rest.get(7 + length..).ok_or_else(truncated)?;
+
+        if is_orig {
+            sections.push(Section {
+                origin: value,
+                words: Vec::new(),
+            });
+        } else {
+            match sections.last_mut() {
+                Some(section) => section.words.push(value),
+                None => {
+                    return Err(VmError::InvalidImage(
+                        "lc3tools object has a word before its first .ORIG".to_string(),
+                    ))
+                }
+            }
+        }
+    }
+    Ok(sections)
+}
+
+fn write_lc3tools(sections: &[Section]) -> Vec<u8> {
+    let mut bytes = LC3TOOLS_MAGIC.to_vec();
+    for section in sections {
+        let entries =
+            std::iter::once((section.origin, 1)).chain(section.words.iter().map(|&w| (w, 0)));
+        for (value, is_orig) in entries {
+            bytes.extend_from_slice(&value.to_le_bytes());
+            bytes.push(is_orig);
+            bytes.extend_from_slice(&0u32.to_le_bytes());
+        }
+    }
+    bytes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn section() -> Section {
+        Section {
+            origin: 0x3000,
+            words: vec![0x5020, 0xF025],
+        }
+    }
+
+    #[test]
+    fn parse_hex_and_bin() {
+        let hex = parse_image(ImageFormat::Hex, b"3000\n5020\nf025\n").unwrap();
+        let bin = parse_image(
+            ImageFormat::Bin,
+            b"0011000000000000\n0101000000100000\n1111000000100101\n",
+        )
+        .unwrap();
+
+        assert_eq!(hex, vec![section()]);
+        assert_eq!(bin, vec![section()]);
+    }
+
+    #[test]
+    fn parse_text_reports_bad_line() {
+        let result = parse_image(ImageFormat::Hex, b"3000\n50G0\n");
+
+        match result {
+            Err(VmError::InvalidImage(message)) => assert!(message.starts_with("line 2:")),
+            other => panic!("expected an invalid image, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn round_trip_every_format() {
+        for format in [
+            ImageFormat::Obj,
+            ImageFormat::Hex,
+            ImageFormat::Bin,
+            ImageFormat::Lc3Tools,
+        ] {
+            let bytes = write_image(format, &[section()]).unwrap();
+            assert_eq!(parse_image(format, &bytes).unwrap(), vec![section()]);
+        }
+    }
+
+    #[test]
+    fn lc3tools_objects_hold_several_sections() {
+        let sections = vec![
+            section(),
+            Section {
+                origin: 0x4000,
+                words: vec![0x0041, 0x0000],
+            },
+        ];
+        let bytes = write_image(ImageFormat::Lc3Tools, &sections).unwrap();
+
+        assert_eq!(
+            parse_image(ImageFormat::Lc3Tools, &bytes).unwrap(),
+            sections
+        );
+        assert!(matches!(
+            write_image(ImageFormat::Obj, &sections),
+            Err(VmError::InvalidImage(_))
+        ));
+    }
+
+    #[test]
+    fn truncated_and_empty_obj_are_rejected() {
+        assert!(matches!(
+            parse_image(ImageFormat::Obj, &[0x30, 0x00, 0xF0]),
+            Err(VmError::InvalidImage(_))
+        ));
+        assert!(matches!(
+            parse_image(ImageFormat::Obj, &[]),
+            Err(VmError::InvalidImage(_))
+        ));
+    }
+
+    #[test]
+    fn lc3tools_source_lines_are_skipped() {
+        let mut bytes = LC3TOOLS_MAGIC.to_vec();
+        bytes.extend_from_slice(&[0x00, 0x30, 1, 11, 0, 0, 0]);
+        bytes.extend_from_slice(b".ORIG x3000");
+        bytes.extend_from_slice(&[0x25, 0xF0, 0, 4, 0, 0, 0]);
+        bytes.extend_from_slice(b"HALT");
+
+        assert_eq!(
+            parse_image(ImageFormat::Lc3Tools, &bytes).unwrap(),
+            vec![Section {
+                origin: 0x3000,
+                words: vec![0xF025],
+            }]
+        );
+        assert!(parse_image(ImageFormat::Lc3Tools, &bytes[..bytes.len() - 1]).is_err());
+    }
+
+    #[test]
+    fn detect_by_extension_and_content() {
+        let lc3tools = write_image(ImageFormat::Lc3Tools, &[section()]).unwrap();
+
+        assert_eq!(
+            ImageFormat::detect("a.obj", &[0x30, 0x00]),
+            ImageFormat::Obj
+        );
+        assert_eq!(
+            ImageFormat::detect("a.obj", &lc3tools),
+            ImageFormat::Lc3Tools
+        );
+        assert_eq!(ImageFormat::detect("a.hex", b"3000\n"), ImageFormat::Hex);
+        assert_eq!(
+            ImageFormat::detect("a.txt", b"3000\r\nF025\r\n"),
+            ImageFormat::Hex
+        );
+        assert_eq!(
+            ImageFormat::detect("a", b"0011000000000000\n"),
+            ImageFormat::Bin
+        );
+        assert_eq!(ImageFormat::detect("a", &[0x30, 0x00]), ImageFormat::Obj);
+    }
+
+    #[test]
+    fn every_section_is_added_to_the_load_map() {
+        let path = std::env::temp_dir().join("lc3_image_format_sections.obj");
+        let path = path.to_string_lossy().to_string();
+        let sections = vec![
+            section(),
+            Section {
+                origin: 0x4000,
+                words: vec![0x0041],
+            },
+        ];
+        std::fs::write(
+            &path,
+            write_image(ImageFormat::Lc3Tools, &sections).unwrap(),
+        )
+        .unwrap();
+
+        let mut vm = crate::vm::Vm::new();
+        vm.load_image(&path, &Default::default()).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(vm.load_map.images.len(), 2);
+        assert_eq!(vm.load_map.images[1].origin, 0x4000);
+        assert_eq!(vm.memory.memory[0x3001], 0xF025);
+        assert_eq!(vm.memory.memory[0x4000], 0x0041);
+    }
+}
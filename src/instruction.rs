@@ -0,0 +1,511 @@
+use std::fmt;
+
+use crate::{
+    constants::{
+        OP_ADD, OP_AND, OP_BR, OP_JMP, OP_JSR, OP_LD, OP_LDI, OP_LDR, OP_LEA, OP_NOT, OP_RTI,
+        OP_ST, OP_STI, OP_STR, OP_TRAP, TRAP_GETC, TRAP_HALT, TRAP_IN, TRAP_OUT, TRAP_PUTS,
+        TRAP_PUTSP,
+    },
+    symbols::SymbolTable,
+    utils::sign_extend,
+};
+
+/// The second source of `ADD` and `AND`: a register or a 5-bit immediate.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Operand {
+    Register(u16),
+    Immediate(i16),
+}
+
+/// A decoded LC-3 instruction.
+///
+/// Registers are numbers from 0 to 7, offsets and immediates are already sign-extended,
+/// and the conditions of `Br` use the same bits as the condition flags (`FL_NEG`,
+/// `FL_ZRO`, `FL_POS`). The bits an instruction ignores are kept in `reserved`, XORed with
+/// their canonical value, so `reserved` is 0 for a well-formed word and a word like a `NOT`
+/// without its low bits set still decodes to (and runs as) a `NOT`. Words with the reserved
+/// opcode, and `RTI`s with any low bit set, decode to `Illegal`, which keeps the word. Thus
+/// `Instruction::decode(word).encode() == word` for every word.
+///
+/// `Display` writes the instruction in assembly syntax, with PC-relative offsets as `#n`.
+///
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Instruction {
+    Br {
+        conditions: u16,
+        offset: i16,
+    },
+    Add {
+        dr: u16,
+        sr1: u16,
+        src2: Operand,
+        reserved: u16,
+    },
+    Ld {
+        dr: u16,
+        offset: i16,
+    },
+    St {
+        sr: u16,
+        offset: i16,
+    },
+    Jsr {
+        offset: i16,
+    },
+    Jsrr {
+        base: u16,
+        reserved: u16,
+    },
+    And {
+        dr: u16,
+        sr1: u16,
+        src2: Operand,
+        reserved: u16,
+    },
+    Ldr {
+        dr: u16,
+        base: u16,
+        offset: i16,
+    },
+    Str {
+        sr: u16,
+        base: u16,
+        offset: i16,
+    },
+    Rti,
+    Not {
+        dr: u16,
+        sr: u16,
+        reserved: u16,
+    },
+    Ldi {
+        dr: u16,
+        offset: i16,
+    },
+    Sti {
+        sr: u16,
+        offset: i16,
+    },
+    Jmp {
+        base: u16,
+        reserved: u16,
+    },
+    Lea {
+        dr: u16,
+        offset: i16,
+    },
+    Trap {
+        vector: u8,
+        reserved: u16,
+    },
+    Illegal(u16),
+}
+
+impl Instruction {
+    /// Decodes an instruction word.
+    ///
+    /// # Arguments
+    ///
+    /// * `word` - The 16-bit instruction.
+    ///
+    /// # Returns
+    ///
+    /// The decoded `Instruction`, `Illegal` if the word is not a valid instruction.
+    ///
+    pub fn decode(word: u16) -> Instruction {
+        let r9 = (word >> 9) & 0x7;
+        let r6 = (word >> 6) & 0x7;
+        let offset9 = sign_extend(word & 0x1FF, 9);
+        let (src2, src2_reserved) = if word & 0x20 != 0 {
+            (Operand::Immediate(sign_extend(word & 0x1F, 5)), 0)
+        } else {
+            (Operand::Register(word & 0x7), word & 0x18)
+        };
+
+        let instruction = match word >> 12 {
+            OP_BR => Some(Instruction::Br {
+                conditions: r9,
+                offset: offset9,
+            }),
+            OP_ADD => Some(Instruction::Add {
+                dr: r9,
+                sr1: r6,
+                src2,
+                reserved: src2_reserved,
+            }),
+            OP_LD => Some(Instruction::Ld {
+                dr: r9,
+                offset: offset9,
+            }),
+            OP_ST => Some(Instruction::St {
+                sr: r9,
+                offset: offset9,
+            }),
+            OP_JSR if word & 0x800 != 0 => Some(Instruction::Jsr {
+                offset: sign_extend(word & 0x7FF, 11),
+            }),
+            OP_JSR => Some(Instruction::Jsrr {
+                base: r6,
+                reserved: word & 0x0E3F,
+            }),
+            OP_AND => Some(Instruction::And {
+                dr: r9,
+                sr1: r6,
+                src2,
+                reserved: src2_reserved,
+            }),
+            OP_LDR => Some(Instruction::Ldr {
+                dr: r9,
+                base: r6,
+                offset: sign_extend(word & 0x3F, 6),
+            }),
+            OP_STR => Some(Instruction::Str {
+                sr: r9,
+                base: r6,
+                offset: sign_extend(word & 0x3F, 6),
+            }),
+            OP_RTI => (word & 0x0FFF == 0).then_some(Instruction::Rti),
+            OP_NOT => Some(Instruction::Not {
+                dr: r9,
+                sr: r6,
+                reserved: !word & 0x3F,
+            }),
+            OP_LDI => Some(Instruction::Ldi {
+                dr: r9,
+                offset: offset9,
+            }),
+            OP_STI => Some(Instruction::Sti {
+                sr: r9,
+                offset: offset9,
+            }),
+            OP_JMP => Some(Instruction::Jmp {
+                base: r6,
+                reserved: word & 0x0E3F,
+            }),
+            OP_LEA => Some(Instruction::Lea {
+                dr: r9,
+                offset: offset9,
+            }),
+            OP_TRAP => Some(Instruction::Trap {
+                vector: word as u8,
+                reserved: word & 0x0F00,
+            }),
+            _ => None,
+        };
+        instruction.unwrap_or(Instruction::Illegal(word))
+    }
+
+    /// Encodes the instruction into a word.
+    ///
+    /// Fields are masked to their width, so out-of-range registers or offsets are truncated;
+    /// the assembler checks ranges before building instructions. `reserved` is masked to the
+    /// bits the instruction ignores.
+    ///
+    pub fn encode(&self) -> u16 {
+        let r9 = |r: u16| (r & 0x7) << 9;
+        let r6 = |r: u16| (r & 0x7) << 6;
+        let bits = |value: i16, width: u16| value as u16 & ((1 << width) - 1);
+        let src2 = |src2: &Operand, reserved: u16| match src2 {
+            Operand::Register(r) => r & 0x7 | reserved & 0x18,
+            Operand::Immediate(imm) => 0x20 | bits(*imm, 5),
+        };
+
+        match self {
+            Instruction::Br { conditions, offset } => {
+                OP_BR << 12 | r9(*conditions) | bits(*offset, 9)
+            }
+            Instruction::Add {
+                dr,
+                sr1,
+                src2: s,
+                reserved,
+            } => OP_ADD << 12 | r9(*dr) | r6(*sr1) | src2(s, *reserved),
+            Instruction::Ld { dr, offset } => OP_LD << 12 | r9(*dr) | bits(*offset, 9),
+            Instruction::St { sr, offset } => OP_ST << 12 | r9(*sr) | bits(*offset, 9),
+            Instruction::Jsr { offset } => OP_JSR << 12 | 0x800 | bits(*offset, 11),
+            Instruction::Jsrr { base, reserved } => OP_JSR << 12 | r6(*base) | reserved & 0x0E3F,
+            Instruction::And {
+                dr,
+                sr1,
+                src2: s,
+                reserved,
+            } => OP_AND << 12 | r9(*dr) | r6(*sr1) | src2(s, *reserved),
+            Instruction::Ldr { dr, base, offset } => {
+                OP_LDR << 12 | r9(*dr) | r6(*base) | bits(*offset, 6)
+            }
+            Instruction::Str { sr, base, offset } => {
+                OP_STR << 12 | r9(*sr) | r6(*base) | bits(*offset, 6)
+            }
+            Instruction::Rti => OP_RTI << 12,
+            Instruction::Not { dr, sr, reserved } => {
+                OP_NOT << 12 | r9(*dr) | r6(*sr) | (0x3F ^ reserved & 0x3F)
+            }
+            Instruction::Ldi { dr, offset } => OP_LDI << 12 | r9(*dr) | bits(*offset, 9),
+            Instruction::Sti { sr, offset } => OP_STI << 12 | r9(*sr) | bits(*offset, 9),
+            Instruction::Jmp { base, reserved } => OP_JMP << 12 | r6(*base) | reserved & 0x0E3F,
+            Instruction::Lea { dr, offset } => OP_LEA << 12 | r9(*dr) | bits(*offset, 9),
+            Instruction::Trap { vector, reserved } => {
+                OP_TRAP << 12 | reserved & 0x0F00 | *vector as u16
+            }
+            Instruction::Illegal(word) => *word,
+        }
+    }
+
+    /// Returns the bits the instruction ignores, XORed with their canonical value: 0 for a
+    /// well-formed word and for the instructions that use every bit.
+    pub fn reserved(&self) -> u16 {
+        match self {
+            Instruction::Add { reserved, .. }
+            | Instruction::And { reserved, .. }
+            | Instruction::Not { reserved, .. }
+            | Instruction::Jmp { reserved, .. }
+            | Instruction::Jsrr { reserved, .. }
+            | Instruction::Trap { reserved, .. } => *reserved,
+            _ => 0,
+        }
+    }
+
+    /// Returns the address a PC-relative instruction at `address` refers to.
+    ///
+    /// # Returns
+    ///
+    /// The target of `BR`, `JSR`, `LD`, `LDI`, `LEA`, `ST` and `STI`, `None` for the other
+    /// instructions and for a `BR` without conditions, which never branches.
+    ///
+    pub fn target(&self, address: u16) -> Option<u16> {
+        let offset = match self {
+            Instruction::Br { conditions: 0, .. } => return None,
+            Instruction::Br { offset, .. }
+            | Instruction::Jsr { offset }
+            | Instruction::Ld { offset, .. }
+            | Instruction::Ldi { offset, .. }
+            | Instruction::Lea { offset, .. }
+            | Instruction::St { offset, .. }
+            | Instruction::Sti { offset, .. } => *offset,
+            _ => return None,
+        };
+        Some(address.wrapping_add(1).wrapping_add(offset as u16))
+    }
+}
+
+/// Disassembles the word at an address into a line of a listing.
+///
+/// # Arguments
+///
+/// * `address` - The address of the word.
+/// * `word` - The word to disassemble.
+/// * `symbols` - The symbol table used to name the address and the target of the instruction.
+///
+/// # Returns
+///
+/// A line like `x3003 <LOOP>: x0BFB  BRnp #-5  ; x3000 <START>`, without a newline.
+///
+pub fn disassemble(address: u16, word: u16, symbols: &SymbolTable) -> String {
+    let instruction = Instruction::decode(word);
+    let mut line = format!(
+        "{}: x{:04X}  {}",
+        symbols.format_address(address),
+        word,
+        instruction
+    );
+    if let Some(target) = instruction.target(address) {
+        line.push_str(&format!("  ; {}", symbols.format_address(target)));
+    }
+    line
+}
+
+impl fmt::Display for Operand {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Operand::Register(r) => write!(f, "R{}", r),
+            Operand::Immediate(imm) => write!(f, "#{}", imm),
+        }
+    }
+}
+
+impl fmt::Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Instruction::Br {
+                conditions: 0,
+                offset: _,
+            } => write!(f, "NOP"),
+            Instruction::Br { conditions, offset } => {
+                write!(f, "BR")?;
+                for (bit, flag) in [(0x4, "n"), (0x2, "z"), (0x1, "p")] {
+                    if conditions & bit != 0 {
+                        write!(f, "{}", flag)?;
+                    }
+                }
+                write!(f, " #{}", offset)
+            }
+            Instruction::Add { dr, sr1, src2, .. } => {
+                write!(f, "ADD R{}, R{}, {}", dr, sr1, src2)
+            }
+            Instruction::Ld { dr, offset } => write!(f, "LD R{}, #{}", dr, offset),
+            Instruction::St { sr, offset } => write!(f, "ST R{}, #{}", sr, offset),
+            Instruction::Jsr { offset } => write!(f, "JSR #{}", offset),
+            Instruction::Jsrr { base, .. } => write!(f, "JSRR R{}", base),
+            Instruction::And { dr, sr1, src2, .. } => {
+                write!(f, "AND R{}, R{}, {}", dr, sr1, src2)
+            }
+            Instruction::Ldr { dr, base, offset } => {
+                write!(f, "LDR R{}, R{}, #{}", dr, base, offset)
+            }
+            Instruction::Str { sr, base, offset } => {
+                write!(f, "STR R{}, R{}, #{}", sr, base, offset)
+            }
+            Instruction::Rti => write!(f, "RTI"),
+            Instruction::Not { dr, sr, .. } => write!(f, "NOT R{}, R{}", dr, sr),
+            Instruction::Ldi { dr, offset } => write!(f, "LDI R{}, #{}", dr, offset),
+            Instruction::Sti { sr, offset } => write!(f, "STI R{}, #{}", sr, offset),
+            Instruction::Jmp { base: 7, .. } => write!(f, "RET"),
+            Instruction::Jmp { base, .. } => write!(f, "JMP R{}", base),
+            Instruction::Lea { dr, offset } => write!(f, "LEA R{}, #{}", dr, offset),
+            Instruction::Trap { vector, .. } => match *vector as u16 {
+                TRAP_GETC => write!(f, "GETC"),
+                TRAP_OUT => write!(f, "OUT"),
+                TRAP_PUTS => write!(f, "PUTS"),
+                TRAP_IN => write!(f, "IN"),
+                TRAP_PUTSP => write!(f, "PUTSP"),
+                TRAP_HALT => write!(f, "HALT"),
+                _ => write!(f, "TRAP x{:02X}", vector),
+            },
+            Instruction::Illegal(word) => write!(f, ".FILL x{:04X}", word),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn every_word_round_trips() {
+        for word in 0..=u16::MAX {
+            assert_eq!(Instruction::decode(word).encode(), word, "x{:04X}", word);
+        }
+    }
+
+    #[test]
+    fn decodes_fields() {
+        assert_eq!(
+            Instruction::decode(0x12BF),
+            Instruction::Add {
+                dr: 1,
+                sr1: 2,
+                src2: Operand::Immediate(-1),
+                reserved: 0,
+            }
+        );
+        assert_eq!(
+            Instruction::decode(0x0BFB),
+            Instruction::Br {
+                conditions: 0x5,
+                offset: -5
+            }
+        );
+        assert_eq!(
+            Instruction::decode(0x61BE),
+            Instruction::Ldr {
+                dr: 0,
+                base: 6,
+                offset: -2
+            }
+        );
+        assert_eq!(Instruction::decode(0x4FF7), Instruction::Jsr { offset: -9 });
+    }
+
+    #[test]
+    fn ignored_bits_are_kept_in_reserved() {
+        assert_eq!(
+            Instruction::decode(0x1288),
+            Instruction::Add {
+                dr: 1,
+                sr1: 2,
+                src2: Operand::Register(0),
+                reserved: 0x08,
+            }
+        );
+        assert_eq!(
+            Instruction::decode(0x903E),
+            Instruction::Not {
+                dr: 0,
+                sr: 0,
+                reserved: 0x01,
+            }
+        );
+        assert_eq!(
+            Instruction::decode(0xC1C1),
+            Instruction::Jmp {
+                base: 7,
+                reserved: 0x01,
+            }
+        );
+        assert_eq!(
+            Instruction::decode(0xF125),
+            Instruction::Trap {
+                vector: 0x25,
+                reserved: 0x0100,
+            }
+        );
+        assert_eq!(Instruction::decode(0xFF89).to_string(), "TRAP x89");
+    }
+
+    #[test]
+    fn reserved_opcode_and_malformed_rti_are_illegal() {
+        for word in [0xD000, 0xDFFF, 0x8001] {
+            assert_eq!(Instruction::decode(word), Instruction::Illegal(word));
+        }
+    }
+
+    #[test]
+    fn displays_assembly() {
+        let listing: Vec<String> = [
+            0x1283, 0x12BF, 0x0BFB, 0x0000, 0xC1C0, 0xC080, 0xF025, 0xF026, 0x997F, 0xD000,
+        ]
+        .iter()
+        .map(|w| Instruction::decode(*w).to_string())
+        .collect();
+
+        assert_eq!(
+            listing,
+            vec![
+                "ADD R1, R2, R3",
+                "ADD R1, R2, #-1",
+                "BRnp #-5",
+                "NOP",
+                "RET",
+                "JMP R2",
+                "HALT",
+                "TRAP x26",
+                "NOT R4, R5",
+                ".FILL xD000",
+            ]
+        );
+    }
+
+    #[test]
+    fn pc_relative_targets() {
+        assert_eq!(Instruction::decode(0x0BFB).target(0x3004), Some(0x3000));
+        assert_eq!(Instruction::decode(0x200A).target(0x300A), Some(0x3015));
+        assert_eq!(Instruction::decode(0x1283).target(0x3000), None);
+        assert_eq!(Instruction::decode(0x0005).target(0x3000), None);
+    }
+
+    #[test]
+    fn disassembly_names_the_target() {
+        let mut symbols = SymbolTable::new();
+        symbols.insert("START", 0x3000);
+        symbols.insert("LOOP", 0x3003);
+
+        assert_eq!(
+            disassemble(0x3003, 0x0FFC, &symbols),
+            "x3003 <LOOP>: x0FFC  BRnzp #-4  ; x3000 <START>"
+        );
+        assert_eq!(
+            disassemble(0x3004, 0xF025, &symbols),
+            "x3004 <LOOP+1>: xF025  HALT"
+        );
+    }
+}
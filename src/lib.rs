@@ -1,8 +1,31 @@
+pub mod assembler;
+pub mod call;
+pub mod console;
 pub mod constants;
+pub mod convention;
+pub mod coverage;
+pub mod debugger;
+pub mod differential;
+pub mod expression;
+pub mod grader;
+pub mod image_format;
 pub mod input_buffering;
+pub mod instruction;
+pub mod linker;
+pub mod load_map;
 pub mod memory;
 pub mod operations;
+pub mod permissions;
+pub mod property;
 pub mod registers;
+pub mod rng;
+pub mod self_modification;
+pub mod shadow;
+pub mod stack;
+pub mod symbols;
+pub mod translation;
 pub mod utils;
+pub mod validation;
 pub mod vm;
 pub mod vm_error;
+pub mod watchpoint;
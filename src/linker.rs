@@ -0,0 +1,464 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+
+use crate::{image_format::Section, symbols::SymbolTable, vm_error::VmError};
+
+/// The kinds of fields a relocation can patch.
+///
+/// * `PcOffset9` - The 9-bit PC-relative offset of `BR`, `LD`, `LDI`, `LEA`, `ST` and `STI`.
+/// * `PcOffset11` - The 11-bit PC-relative offset of `JSR`.
+/// * `Fill` - A whole word holding an address, as written by `.FILL label`.
+///
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RelocationKind {
+    PcOffset9,
+    PcOffset11,
+    Fill,
+}
+
+/// A field that can only be filled in once the address of `symbol` is known.
+///
+/// # Fields
+///
+/// * `offset` - The word to patch, relative to the start of the object.
+/// * `kind` - Which field of the word to patch.
+/// * `symbol` - The symbol the field refers to, defined in the object or imported.
+/// * `addend` - A constant added to the address of the symbol, as in `.FILL label+2`.
+///
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Relocation {
+    pub offset: u16,
+    pub kind: RelocationKind,
+    pub symbol: String,
+    #[serde(default)]
+    pub addend: i32,
+}
+
+/// A relocatable object: code whose load address is chosen by the linker.
+///
+/// Objects are stored as JSON, with symbol values relative to the start of the object:
+///
+/// ```json
+/// {
+///   "words": [8193, 18431, 61477, 0],
+///   "symbols": { "MAIN": 0, "COUNT": 3 },
+///   "exports": ["MAIN"],
+///   "imports": ["PRINT"],
+///   "relocations": [
+///     { "offset": 0, "kind": "pcoffset9", "symbol": "COUNT" },
+///     { "offset": 1, "kind": "pcoffset11", "symbol": "PRINT" }
+///   ]
+/// }
+/// ```
+///
+/// An optional `"origin"` pins the object to a fixed address.
+///
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ObjectFile {
+    #[serde(skip)]
+    pub name: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub origin: Option<u16>,
+    pub words: Vec<u16>,
+    #[serde(default)]
+    pub symbols: BTreeMap<String, u16>,
+    #[serde(default)]
+    pub exports: Vec<String>,
+    #[serde(default)]
+    pub imports: Vec<String>,
+    #[serde(default)]
+    pub relocations: Vec<Relocation>,
+}
+
+/// The result of linking: the image to load and the symbols of every object.
+///
+/// Exported symbols keep their name. So do the other symbols of an object, unless another
+/// object defines or exports the same name: then they are qualified with the name of their
+/// object, as in `main.rel:LOOP`.
+///
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LinkedImage {
+    pub sections: Vec<Section>,
+    pub symbols: SymbolTable,
+}
+
+impl ObjectFile {
+    /// Parses a relocatable object from its JSON form.
+    ///
+    /// # Arguments
+    ///
+    /// * `name` - The name used for the object in error messages.
+    /// * `text` - The JSON text.
+    ///
+    /// # Returns
+    ///
+    /// The parsed `ObjectFile`, or a `VmError::LinkError` if the JSON is malformed.
+    ///
+    pub fn parse(name: &str, text: &str) -> Result<ObjectFile, VmError> {
+        let mut object: ObjectFile = serde_json::from_str(text)
+            .map_err(|e| VmError::LinkError(format!("{}: {}", name, e)))?;
+        object.name = name.to_string();
+        Ok(object)
+    }
+
+    /// Reads a relocatable object file.
+    ///
+    /// # Returns
+    ///
+    /// The parsed `ObjectFile`, or a `VmError` if the file cannot be read or parsed.
+    ///
+    pub fn from_file(path: &str) -> Result<ObjectFile, VmError> {
+        let text =
+            fs::read_to_string(path).map_err(|e| VmError::FailedToOpenFile(e.to_string()))?;
+        let name = Path::new(path)
+            .file_name()
+            .map_or(path.to_string(), |n| n.to_string_lossy().to_string());
+        ObjectFile::parse(&name, &text)
+    }
+
+    /// Serializes the object to its JSON form.
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(self).unwrap_or_default()
+    }
+}
+
+/// Links relocatable objects into a loadable image.
+///
+/// Objects are placed one after the other starting at `origin`, except for objects with
+/// a fixed origin, which are placed there (and the following objects after them). Every
+/// relocation is then resolved against the symbols of its own object first, and against
+/// the symbols exported by the other objects for imported names.
+///
+/// # Arguments
+///
+/// * `objects` - The objects, in placement order.
+/// * `origin` - Where the first object without a fixed origin is placed.
+///
+/// # Returns
+///
+/// The linked image, or a `VmError::LinkError` for overlapping objects, duplicate or
+/// missing exports, undefined symbols and offsets that do not fit their field.
+///
+pub fn link(objects: &[ObjectFile], origin: u16) -> Result<LinkedImage, VmError> {
+    let mut bases = Vec::new();
+    let mut next = origin as usize;
+    for object in objects {
+        let base = object.origin.map_or(next, usize::from);
+        next = base + object.words.len();
+        if next > u16::MAX as usize + 1 {
+            return Err(link_error(format!(
+                "{} does not fit in memory when placed at x{:04X}",
+                object.name, base
+            )));
+        }
+        bases.push(base as u16);
+    }
+    for (i, a) in objects.iter().enumerate() {
+        for (j, b) in objects.iter().enumerate().skip(i + 1) {
+            let (start_a, start_b) = (bases[i] as usize, bases[j] as usize);
+            if start_a < start_b + b.words.len() && start_b < start_a + a.words.len() {
+                return Err(link_error(format!("{} and {} overlap", a.name, b.name)));
+            }
+        }
+    }
+
+    let mut exports: BTreeMap<&str, (u16, &str)> = BTreeMap::new();
+    for (object, base) in objects.iter().zip(&bases) {
+        for name in &object.exports {
+            let offset = object.symbols.get(name).ok_or_else(|| {
+                link_error(format!("{} exports undefined symbol {}", object.name, name))
+            })?;
+            let address = base.wrapping_add(*offset);
+            if let Some((_, other)) = exports.insert(name, (address, &object.name)) {
+                return Err(link_error(format!(
+                    "{} is exported by both {} and {}",
+                    name, other, object.name
+                )));
+            }
+        }
+    }
+
+    let mut symbols = SymbolTable::new();
+    for (name, (address, _)) in &exports {
+        symbols.insert(name, *address);
+    }
+    let is_local = |object: &ObjectFile, name: &str| !object.exports.iter().any(|e| e == name);
+    let mut definitions: BTreeMap<&str, usize> = BTreeMap::new();
+    for object in objects {
+        for name in object.symbols.keys() {
+            if is_local(object, name) {
+                *definitions.entry(name).or_default() += 1;
+            }
+        }
+    }
+    let mut sections: Vec<Section> = Vec::new();
+    for (object, &base) in objects.iter().zip(&bases) {
+        let mut words = object.words.clone();
+        for relocation in &object.relocations {
+            let target = resolve(object, base, &relocation.symbol, &exports)?
+                .wrapping_add(relocation.addend as u16);
+            let address = base.wrapping_add(relocation.offset);
+            let word = words.get_mut(relocation.offset as usize).ok_or_else(|| {
+                link_error(format!(
+                    "{}: relocation for {} at offset {} is past the end of the object",
+                    object.name, relocation.symbol, relocation.offset
+                ))
+            })?;
+            *word = patch(*word, address, target, relocation).map_err(|message| {
+                link_error(format!("{}: x{:04X}: {}", object.name, address, message))
+            })?;
+        }
+        for (name, offset) in &object.symbols {
+            let address = base.wrapping_add(*offset);
+            if !is_local(object, name) {
+                continue;
+            } else if definitions[name.as_str()] > 1 || exports.contains_key(name.as_str()) {
+                symbols.insert(&format!("{}:{}", object.name, name), address);
+            } else {
+                symbols.insert(name, address);
+            }
+        }
+
+        match sections.last_mut() {
+            Some(last) if last.origin as usize + last.words.len() == base as usize => {
+                last.words.extend(words)
+            }
+            _ => sections.push(Section {
+                origin: base,
+                words,
+            }),
+        }
+    }
+
+    Ok(LinkedImage { sections, symbols })
+}
+
+/// Finds the address a relocation of `object` refers to.
+fn resolve(
+    object: &ObjectFile,
+    base: u16,
+    name: &str,
+    exports: &BTreeMap<&str, (u16, &str)>,
+) -> Result<u16, VmError> {
+    if let Some(offset) = object.symbols.get(name) {
+        return Ok(base.wrapping_add(*offset));
+    }
+    if !object.imports.iter().any(|import| import == name) {
+        return Err(link_error(format!(
+            "{}: undefined symbol {} (missing import?)",
+            object.name, name
+        )));
+    }
+    exports
+        .get(name)
+        .map(|(address, _)| *address)
+        .ok_or_else(|| {
+            link_error(format!(
+                "{}: imported symbol {} is not exported by any object",
+                object.name, name
+            ))
+        })
+}
+
+/// Writes the reference to `target` into the field of `word` at `address`.
+///
+/// Offsets wrap around the address space like the PC does, so an instruction near xFFFF can
+/// refer to a label near x0000.
+///
+fn patch(word: u16, address: u16, target: u16, relocation: &Relocation) -> Result<u16, String> {
+    let bits = match relocation.kind {
+        RelocationKind::Fill => return Ok(target),
+        RelocationKind::PcOffset9 => 9,
+        RelocationKind::PcOffset11 => 11,
+    };
+    let offset = target.wrapping_sub(address.wrapping_add(1)) as i16 as i32;
+    let limit = 1 << (bits - 1);
+    if offset < -limit || offset >= limit {
+        return Err(format!(
+            "PCoffset{} to {} (x{:04X}) is {}, out of range {}..{}",
+            bits,
+            relocation.symbol,
+            target,
+            offset,
+            -limit,
+            limit - 1
+        ));
+    }
+    let mask = (1u16 << bits) - 1;
+    Ok((word & !mask) | (offset as u16 & mask))
+}
+
+fn link_error(message: String) -> VmError {
+    VmError::LinkError(message)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn relocation(offset: u16, kind: RelocationKind, symbol: &str) -> Relocation {
+        Relocation {
+            offset,
+            kind,
+            symbol: symbol.to_string(),
+            addend: 0,
+        }
+    }
+
+    /// main: LD R0, COUNT; JSR PRINT; HALT; COUNT .FILL 0
+    fn main_object() -> ObjectFile {
+        ObjectFile {
+            name: "main.rel".to_string(),
+            words: vec![0x2000, 0x4800, 0xF025, 0x0000],
+            symbols: BTreeMap::from([("MAIN".to_string(), 0), ("COUNT".to_string(), 3)]),
+            exports: vec!["MAIN".to_string()],
+            imports: vec!["PRINT".to_string()],
+            relocations: vec![
+                relocation(0, RelocationKind::PcOffset9, "COUNT"),
+                relocation(1, RelocationKind::PcOffset11, "PRINT"),
+            ],
+            ..Default::default()
+        }
+    }
+
+    /// print: OUT; RET; PTR .FILL PRINT
+    fn print_object() -> ObjectFile {
+        ObjectFile {
+            name: "print.rel".to_string(),
+            words: vec![0xF021, 0xC1C0, 0x0000],
+            symbols: BTreeMap::from([("PRINT".to_string(), 0)]),
+            exports: vec!["PRINT".to_string()],
+            relocations: vec![relocation(2, RelocationKind::Fill, "PRINT")],
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn links_objects_one_after_the_other() {
+        let image = link(&[main_object(), print_object()], 0x3000).unwrap();
+
+        assert_eq!(
+            image.sections,
+            vec![Section {
+                origin: 0x3000,
+                words: vec![0x2002, 0x4802, 0xF025, 0x0000, 0xF021, 0xC1C0, 0x3004],
+            }]
+        );
+        assert_eq!(image.symbols.address_of("PRINT"), Some(0x3004));
+        assert_eq!(image.symbols.address_of("COUNT"), Some(0x3003));
+    }
+
+    #[test]
+    fn fixed_origin_starts_a_new_section() {
+        let mut print = print_object();
+        print.origin = Some(0x3100);
+
+        let image = link(&[main_object(), print], 0x3000).unwrap();
+
+        assert_eq!(image.sections.len(), 2);
+        assert_eq!(image.sections[1].words[2], 0x3100);
+        assert_eq!(image.sections[0].words[1], 0x4800 | (0x3100 - 0x3002));
+    }
+
+    #[test]
+    fn out_of_range_offset_is_an_error() {
+        let mut print = print_object();
+        print.origin = Some(0x4000);
+        let mut main = main_object();
+        main.relocations[1].kind = RelocationKind::PcOffset9;
+
+        match link(&[main, print], 0x3000) {
+            Err(VmError::LinkError(message)) => assert_eq!(
+                message,
+                "main.rel: x3001: PCoffset9 to PRINT (x4000) is 4094, out of range -256..255"
+            ),
+            other => panic!("expected a link error, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn offsets_wrap_around_the_address_space() {
+        // BRnzp NEXT at xFFFF; NOP, and NEXT at x0001
+        let branch = ObjectFile {
+            name: "branch.rel".to_string(),
+            origin: Some(0xFFFF),
+            words: vec![0x0E00],
+            imports: vec!["NEXT".to_string()],
+            relocations: vec![relocation(0, RelocationKind::PcOffset9, "NEXT")],
+            ..Default::default()
+        };
+        let next = ObjectFile {
+            name: "next.rel".to_string(),
+            origin: Some(0x0000),
+            words: vec![0x0000, 0xF025],
+            symbols: BTreeMap::from([("NEXT".to_string(), 1)]),
+            exports: vec!["NEXT".to_string()],
+            ..Default::default()
+        };
+
+        let image = link(&[branch, next], 0x3000).unwrap();
+
+        assert_eq!(image.sections[0].words, vec![0x0E01]);
+    }
+
+    #[test]
+    fn locals_defined_by_several_objects_are_qualified() {
+        let mut main = main_object();
+        main.symbols.insert("LOOP".to_string(), 1);
+        main.symbols.insert("PRINT_PTR".to_string(), 2);
+        let mut print = print_object();
+        print.symbols.insert("LOOP".to_string(), 1);
+        print.symbols.insert("MAIN".to_string(), 2);
+
+        let image = link(&[main, print], 0x3000).unwrap();
+
+        assert_eq!(image.symbols.address_of("LOOP"), None);
+        assert_eq!(image.symbols.address_of("main.rel:LOOP"), Some(0x3001));
+        assert_eq!(image.symbols.address_of("print.rel:LOOP"), Some(0x3005));
+        assert_eq!(image.symbols.address_of("PRINT_PTR"), Some(0x3002));
+        assert_eq!(image.symbols.address_of("MAIN"), Some(0x3000));
+        assert_eq!(image.symbols.address_of("print.rel:MAIN"), Some(0x3006));
+    }
+
+    #[test]
+    fn undefined_and_duplicate_symbols_are_errors() {
+        assert!(matches!(
+            link(&[main_object()], 0x3000),
+            Err(VmError::LinkError(_))
+        ));
+        assert!(matches!(
+            link(&[main_object(), print_object(), print_object()], 0x3000),
+            Err(VmError::LinkError(_))
+        ));
+
+        let mut main = main_object();
+        main.imports.clear();
+        assert!(matches!(
+            link(&[main, print_object()], 0x3000),
+            Err(VmError::LinkError(_))
+        ));
+    }
+
+    #[test]
+    fn overlapping_objects_are_errors() {
+        let mut print = print_object();
+        print.origin = Some(0x3002);
+
+        assert!(matches!(
+            link(&[main_object(), print], 0x3000),
+            Err(VmError::LinkError(_))
+        ));
+    }
+
+    #[test]
+    fn json_round_trip() {
+        let object = main_object();
+        let parsed = ObjectFile::parse("main.rel", &object.to_json()).unwrap();
+
+        assert_eq!(parsed, object);
+    }
+}
@@ -0,0 +1,284 @@
+use std::fmt;
+
+use crate::vm_error::VmError;
+
+/// Describes where an image file was placed in memory.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LoadedImage {
+    pub path: String,
+    pub origin: u16,
+    pub length: usize,
+}
+
+impl LoadedImage {
+    /// Returns an iterator over every address the image was loaded into.
+    pub fn addresses(&self) -> impl Iterator<Item = u16> {
+        let origin = self.origin;
+        (0..self.length).map(move |i| origin.wrapping_add(i as u16))
+    }
+
+    /// Returns the last address the image was loaded into, or `None` for an empty image.
+    pub fn end(&self) -> Option<u16> {
+        if self.length == 0 {
+            None
+        } else {
+            Some(self.origin.wrapping_add((self.length - 1) as u16))
+        }
+    }
+
+    /// Checks whether `address` was loaded from this image.
+    pub fn contains(&self, address: u16) -> bool {
+        match self.end() {
+            Some(end) => address >= self.origin && address <= end,
+            None => false,
+        }
+    }
+}
+
+/// What to do when an image is loaded over memory already used by a previous image.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum OverlapPolicy {
+    /// Refuse to load the image.
+    Error,
+    /// Print a warning and let the later image overwrite the earlier one.
+    #[default]
+    Warn,
+}
+
+/// A range of memory written by two different images.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Overlap {
+    pub earlier: String,
+    pub later: String,
+    pub start: u16,
+    pub end: u16,
+}
+
+impl fmt::Display for Overlap {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} overwrites x{:04X}-x{:04X} loaded from {}",
+            self.later, self.start, self.end, self.earlier
+        )
+    }
+}
+
+/// Records every image loaded into memory, in load order.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct LoadMap {
+    pub images: Vec<LoadedImage>,
+}
+
+impl LoadMap {
+    /// Creates an empty `LoadMap`.
+    pub fn new() -> LoadMap {
+        LoadMap::default()
+    }
+
+    /// Finds the regions of `image` that overlap images already in the map.
+    pub fn overlaps(&self, image: &LoadedImage) -> Vec<Overlap> {
+        let Some(end) = image.end() else {
+            return Vec::new();
+        };
+        self.images
+            .iter()
+            .filter_map(|earlier| {
+                let earlier_end = earlier.end()?;
+                let start = image.origin.max(earlier.origin);
+                let stop = end.min(earlier_end);
+                (start <= stop).then(|| Overlap {
+                    earlier: earlier.path.clone(),
+                    later: image.path.clone(),
+                    start,
+                    end: stop,
+                })
+            })
+            .collect()
+    }
+
+    /// Adds an image to the map, checking it against the images loaded before it.
+    ///
+    /// # Arguments
+    ///
+    /// * `image` - The image that was just loaded.
+    /// * `policy` - Whether overlaps are errors or warnings.
+    ///
+    /// # Returns
+    ///
+    /// The overlaps found (only when they are allowed by the policy), otherwise a `VmError::OverlappingImages`.
+    ///
+    pub fn add(
+        &mut self,
+        image: LoadedImage,
+        policy: OverlapPolicy,
+    ) -> Result<Vec<Overlap>, VmError> {
+        self.add_all(vec![image], policy)
+    }
+
+    /// Adds the sections of one image file to the map, all or none of them.
+    ///
+    /// Every section is checked against the images loaded before, but not against the other
+    /// sections of the same file, and nothing is added if one of them is refused.
+    ///
+    /// # Arguments
+    ///
+    /// * `images` - The sections of the file.
+    /// * `policy` - Whether overlaps are errors or warnings.
+    ///
+    /// # Returns
+    ///
+    /// The overlaps found (only when they are allowed by the policy), otherwise a `VmError::OverlappingImages`.
+    ///
+    pub fn add_all(
+        &mut self,
+        images: Vec<LoadedImage>,
+        policy: OverlapPolicy,
+    ) -> Result<Vec<Overlap>, VmError> {
+        let overlaps: Vec<Overlap> = images
+            .iter()
+            .flat_map(|image| self.overlaps(image))
+            .collect();
+        if policy == OverlapPolicy::Error && !overlaps.is_empty() {
+            let message = overlaps
+                .iter()
+                .map(|o| o.to_string())
+                .collect::<Vec<_>>()
+                .join("; ");
+            return Err(VmError::OverlappingImages(message));
+        }
+        self.images.extend(images);
+        Ok(overlaps)
+    }
+
+    /// Finds the image that the current contents of `address` came from.
+    ///
+    /// When several images covered the address, the last one loaded wins, since
+    /// it overwrote the others.
+    ///
+    pub fn image_for(&self, address: u16) -> Option<&LoadedImage> {
+        self.images.iter().rev().find(|i| i.contains(address))
+    }
+}
+
+impl fmt::Display for LoadMap {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "Load map:")?;
+        for image in &self.images {
+            match image.end() {
+                Some(end) => writeln!(
+                    f,
+                    "  x{:04X}-x{:04X}  {:>5} words  {}",
+                    image.origin, end, image.length, image.path
+                )?,
+                None => writeln!(
+                    f,
+                    "  x{:04X}        {:>5} words  {}",
+                    image.origin, image.length, image.path
+                )?,
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn image(path: &str, origin: u16, length: usize) -> LoadedImage {
+        LoadedImage {
+            path: path.to_string(),
+            origin,
+            length,
+        }
+    }
+
+    #[test]
+    fn detects_overlapping_range() {
+        let mut map = LoadMap::new();
+        map.add(image("a.obj", 0x3000, 0x100), OverlapPolicy::Warn)
+            .unwrap();
+
+        let overlaps = map
+            .add(image("b.obj", 0x30F0, 0x20), OverlapPolicy::Warn)
+            .unwrap();
+
+        assert_eq!(
+            overlaps,
+            vec![Overlap {
+                earlier: "a.obj".to_string(),
+                later: "b.obj".to_string(),
+                start: 0x30F0,
+                end: 0x30FF,
+            }]
+        );
+        assert_eq!(map.images.len(), 2);
+    }
+
+    #[test]
+    fn adjacent_images_do_not_overlap() {
+        let mut map = LoadMap::new();
+        map.add(image("a.obj", 0x3000, 0x10), OverlapPolicy::Error)
+            .unwrap();
+
+        let overlaps = map
+            .add(image("b.obj", 0x3010, 0x10), OverlapPolicy::Error)
+            .unwrap();
+
+        assert!(overlaps.is_empty());
+    }
+
+    #[test]
+    fn error_policy_rejects_overlap() {
+        let mut map = LoadMap::new();
+        map.add(image("a.obj", 0x3000, 0x10), OverlapPolicy::Error)
+            .unwrap();
+
+        let result = map.add(image("b.obj", 0x2FF8, 0x10), OverlapPolicy::Error);
+
+        assert!(matches!(result, Err(VmError::OverlappingImages(_))));
+        assert_eq!(map.images.len(), 1);
+    }
+
+    #[test]
+    fn sections_of_one_file_are_not_checked_against_each_other() {
+        let mut map = LoadMap::new();
+
+        let overlaps = map
+            .add_all(
+                vec![image("a.obj", 0x3000, 0x10), image("a.obj", 0x3008, 0x10)],
+                OverlapPolicy::Error,
+            )
+            .unwrap();
+
+        assert!(overlaps.is_empty());
+        assert_eq!(map.images.len(), 2);
+    }
+
+    #[test]
+    fn attributes_address_to_last_image() {
+        let mut map = LoadMap::new();
+        map.add(image("a.obj", 0x3000, 0x10), OverlapPolicy::Warn)
+            .unwrap();
+        map.add(image("b.obj", 0x3008, 0x10), OverlapPolicy::Warn)
+            .unwrap();
+
+        assert_eq!(map.image_for(0x3000).unwrap().path, "a.obj");
+        assert_eq!(map.image_for(0x3008).unwrap().path, "b.obj");
+        assert_eq!(map.image_for(0x3017).unwrap().path, "b.obj");
+        assert!(map.image_for(0x3018).is_none());
+    }
+
+    #[test]
+    fn display_lists_images() {
+        let mut map = LoadMap::new();
+        map.add(image("a.obj", 0x3000, 0x10), OverlapPolicy::Warn)
+            .unwrap();
+
+        assert_eq!(
+            map.to_string(),
+            "Load map:\n  x3000-x300F     16 words  a.obj\n"
+        );
+    }
+}
@@ -1,17 +1,591 @@
 use std::env;
+use std::io::Read;
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
 
-use lc_3_vm::{vm::Vm, vm_error::VmError};
+use lc_3_vm::{
+    assembler::assemble_file,
+    console::ScriptedConsole,
+    constants::PC_START,
+    convention::ConventionChecker,
+    coverage::Coverage,
+    debugger::Debugger,
+    differential::{Lockstep, Outcome, DEFAULT_WINDOW},
+    grader::{self, Spec},
+    image_format::{read_sections, write_image, ImageFormat, Section},
+    instruction::disassemble,
+    linker::{self, ObjectFile},
+    load_map::OverlapPolicy,
+    permissions::{protect_code, Permissions, Region},
+    self_modification::SelfModification,
+    shadow::Shadow,
+    stack::StackMonitor,
+    symbols::{Describe, SymbolTable},
+    utils::{parse_word, write_file},
+    validation::{check_images, Severity},
+    vm::{Backend, LoadOptions, Vm},
+    vm_error::VmError,
+};
+
+const USAGE: &str = "Usage: lc3 [options] [image-file1] ...
+       lc3 debug [options] [image-file1] ...
+       lc3 check [image-file1] ...
+       lc3 diff [--backend <interp|blocks>] [--steps <n>] [--window <n>] [image-file1] ...
+       lc3 disasm [image-file1] ...
+       lc3 grade <spec-file> <image-file> [--json <output>]
+       lc3 convert <input> <output> [--to obj|hex|bin|lc3tools]
+       lc3 asm <source-file> [-o <output>] [--to <format>]
+       lc3 link -o <output> [--origin <address>] [--to <format>] [object-file1] ...
+
+Options:
+  --backend <interp|blocks> execute one instruction at a time (default) or translated basic blocks
+  --callee-saved <list>  the registers --check-calls expects routines to preserve, such as
+                         R1,R2,R3 (default: R1-R5)
+  --check-calls          report JSR/RET mismatches, clobbered callee-saved registers and
+                         unbalanced stacks when the program ends
+  --coverage <file>      write an annotated coverage listing when the program ends
+  --lcov <file>          write lcov coverage data when the program ends
+  --load-map             print where every image was loaded
+  --overlap <error|warn> what to do when images overlap (default: warn)
+  --protect-code         make the code of the images read-only and their data non-executable
+  --region <low>-<high>:<rwx>
+                         restrict what the program may do with the given cells, stopping
+                         it on a violation (later regions override earlier ones)
+  --randomize            start with random registers and unloaded memory instead of zeros
+  --seed <n>             like --randomize, with the given seed
+  --self-modifying       list the instructions that were overwritten after they ran
+  --stack <low>-<high>   report R6 stack overflows and underflows past the given cells, and
+                         the deepest the stack went, when the program ends
+  --uninit               report reads of registers and memory that were never written";
+
+/// Command line options shared by every mode.
+struct Options {
+    images: Vec<String>,
+    coverage_path: Option<String>,
+    lcov_path: Option<String>,
+    print_load_map: bool,
+    check_uninit: bool,
+    self_modifying: bool,
+    convention: Option<ConventionChecker>,
+    stack: Option<StackMonitor>,
+    protect_code: bool,
+    regions: Vec<Region>,
+    random_seed: Option<u64>,
+    load: LoadOptions,
+    backend: Backend,
+}
 
 fn main() -> Result<(), VmError> {
     let args: Vec<String> = env::args().collect();
 
-    if args.len() < 2 {
-        return Err(VmError::BadArgsLength(
-            "Usage: lc3 [image-file1] ...".to_string(),
-        ));
+    match args.get(1).map(String::as_str) {
+        Some("debug") => debug(parse_options(&args[0], &args[2..])?),
+        Some("check") => check(&args[2..]),
+        Some("diff") => diff(&args[0], &args[2..]),
+        Some("disasm") => disasm(&args[2..]),
+        Some("grade") => grade(&args[2..]),
+        Some("convert") => convert(&args[2..]),
+        Some("link") => link(&args[2..]),
+        Some("asm") => asm(&args[2..]),
+        _ => run(parse_options(&args[0], &args[1..])?),
+    }
+}
+
+/// Runs the images given on the command line.
+fn run(options: Options) -> Result<(), VmError> {
+    let mut vm = load(&options)?;
+    instrument(&options, &mut vm);
+
+    let result = vm.run();
+    if let Err(VmError::AccessViolation(violation)) = &result {
+        println!("{}", violation.with_symbols(&vm.symbols));
+    }
+    report(&options, &vm)?;
+
+    if let Err(VmError::AccessViolation(_)) = result {
+        std::process::exit(1);
+    }
+    result
+}
+
+/// Loads the images and starts the interactive debugger, printing the reports of the
+/// checkers when the session ends.
+fn debug(options: Options) -> Result<(), VmError> {
+    let mut vm = load(&options)?;
+    instrument(&options, &mut vm);
+    let result = Debugger::new().run(&mut vm);
+    report(&options, &vm)?;
+    result
+}
+
+/// Enables the coverage recording and the checkers requested on the command line.
+fn instrument(options: &Options, vm: &mut Vm) {
+    if options.coverage_path.is_some() || options.lcov_path.is_some() {
+        vm.coverage = Some(Coverage::new());
+    }
+    if options.check_uninit {
+        vm.shadow = Some(Shadow::with_images(&vm.load_map.images));
+    }
+    vm.convention = options.convention.clone();
+    vm.stack = options.stack.clone();
+    if options.self_modifying {
+        vm.memory.self_modification = Some(SelfModification::new());
+    }
+}
+
+/// Prints what the checkers found and writes the coverage files, once the program ended.
+fn report(options: &Options, vm: &Vm) -> Result<(), VmError> {
+    if let Some(shadow) = &vm.shadow {
+        for read in &shadow.reads {
+            println!("Warning: {}", read.with_symbols(&vm.symbols));
+        }
+    }
+    if let Some(checker) = &vm.convention {
+        for violation in &checker.violations {
+            println!("Warning: {}", violation.with_symbols(&vm.symbols));
+        }
+    }
+    if let Some(monitor) = &vm.stack {
+        for violation in &monitor.violations {
+            println!(
+                "Warning: {}",
+                violation.describe(&vm.symbols, monitor.size())
+            );
+        }
+        println!("{}", monitor);
+    }
+    if let Some(tracker) = &vm.memory.self_modification {
+        print!("{}", tracker.report(&vm.symbols));
+    }
+
+    if let Some(coverage) = &vm.coverage {
+        let images = &vm.load_map.images;
+        if let Some(path) = &options.coverage_path {
+            write_file(
+                path,
+                coverage.annotated_listing(&vm.memory, images, &vm.symbols),
+            )?;
+        }
+        if let Some(path) = &options.lcov_path {
+            write_file(path, coverage.lcov(images, &vm.symbols))?;
+        }
+    }
+    Ok(())
+}
+
+/// Checks the images without running them, exiting with a failure status if any has errors.
+fn check(paths: &[String]) -> Result<(), VmError> {
+    if paths.is_empty() {
+        return Err(VmError::BadArgsLength(USAGE.to_string()));
+    }
+    let diagnostics = check_images(paths);
+    for diagnostic in &diagnostics {
+        println!("{}", diagnostic);
+    }
+    let errors = diagnostics
+        .iter()
+        .filter(|d| d.severity == Severity::Error)
+        .count();
+    println!(
+        "{} error(s), {} warning(s)",
+        errors,
+        diagnostics.len() - errors
+    );
+    if errors > 0 {
+        std::process::exit(1);
+    }
+    Ok(())
+}
+
+/// Runs the images on the plain interpreter and on another backend in lockstep, exiting with
+/// a failure status at the first step where they disagree.
+///
+/// The reference decodes every instruction on each fetch; the candidate uses `--backend`
+/// (`blocks` by default) with the predecode cache. Both machines run until they halt or
+/// `--steps` instructions (10 million by default) were executed.
+///
+/// Standard input is read to its end first and typed on the keyboard of both machines. The
+/// console output of the reference is printed once, and it is a divergence too when the
+/// candidate printed something else.
+///
+fn diff(program: &str, args: &[String]) -> Result<(), VmError> {
+    let usage = || VmError::BadArgsLength(USAGE.to_string());
+    let mut backend = Backend::Blocks;
+    let mut max_steps = 10_000_000;
+    let mut window = DEFAULT_WINDOW;
+    let mut images = vec![program.to_string()];
+    let mut iter = args.iter();
+    while let Some(arg) = iter.next() {
+        match arg.as_str() {
+            "--backend" => {
+                backend = Backend::from_name(&option_value(&mut iter)?).ok_or_else(usage)?
+            }
+            "--steps" => max_steps = option_value(&mut iter)?.parse().map_err(|_| usage())?,
+            "--window" => window = option_value(&mut iter)?.parse().map_err(|_| usage())?,
+            _ => images.push(arg.clone()),
+        }
+    }
+    if images.len() < 2 {
+        return Err(usage());
+    }
+
+    let mut input = Vec::new();
+    std::io::stdin()
+        .read_to_end(&mut input)
+        .map_err(|e| VmError::FailedToReadStdin(e.to_string()))?;
+    let reference_console = ScriptedConsole::new(&input);
+    let reference_output = reference_console.output();
+    let candidate_console = ScriptedConsole::new(&input);
+    let candidate_output = candidate_console.output();
+
+    let mut reference = Vm::new_from_images(images.clone())?;
+    reference.memory.predecode = false;
+    reference.memory.console = Box::new(reference_console);
+    let mut candidate = Vm::new_from_images(images)?;
+    candidate.backend = backend;
+    candidate.memory.console = Box::new(candidate_console);
+    let mut lockstep = Lockstep::new(reference, candidate);
+    lockstep.window = window;
+
+    let outcome = lockstep.run(max_steps);
+    print!("{}", reference_output.borrow());
+    if *reference_output.borrow() != *candidate_output.borrow() {
+        println!(
+            "Console output differs after {} steps, candidate printed:\n{}",
+            lockstep.steps,
+            candidate_output.borrow()
+        );
+        std::process::exit(1);
+    }
+    match outcome {
+        Outcome::Halted { steps } => {
+            println!("No divergence, both programs halted after {} steps", steps)
+        }
+        Outcome::StepLimit { steps } => println!("No divergence in {} steps", steps),
+        Outcome::Stopped { steps, error } => {
+            println!(
+                "No divergence, both programs stopped after {} steps: {:?}",
+                steps, error
+            )
+        }
+        Outcome::Diverged(divergence) => {
+            print!("{}", divergence);
+            std::process::exit(1);
+        }
+    }
+    Ok(())
+}
+
+/// Grades an image against a test specification and prints a report of every case.
+///
+/// With `--json`, the report is also written to a file as JSON, scores included. Failing
+/// cases do not change the exit status, which only reports whether grading was possible.
+///
+fn grade(args: &[String]) -> Result<(), VmError> {
+    let usage = || VmError::BadArgsLength(USAGE.to_string());
+    let mut json = None;
+    let mut paths = Vec::new();
+    let mut iter = args.iter();
+    while let Some(arg) = iter.next() {
+        match arg.as_str() {
+            "--json" => json = Some(option_value(&mut iter)?),
+            _ => paths.push(arg),
+        }
+    }
+    let [spec, image] = paths[..] else {
+        return Err(usage());
+    };
+
+    let report = grader::grade(&Spec::from_file(spec)?, image);
+    print!("{}", report);
+    if let Some(path) = json {
+        write_file(&path, report.to_json())?;
+    }
+    Ok(())
+}
+
+/// Prints a disassembly of every word of the images, using their symbol tables if any.
+fn disasm(paths: &[String]) -> Result<(), VmError> {
+    if paths.is_empty() {
+        return Err(VmError::BadArgsLength(USAGE.to_string()));
+    }
+    for path in paths {
+        let symbols = SymbolTable::for_image(path)?.unwrap_or_default();
+        println!("{}:", path);
+        for section in read_sections(path)? {
+            for (i, word) in section.words.iter().enumerate() {
+                let address = section.origin.wrapping_add(i as u16);
+                println!("  {}", disassemble(address, *word, &symbols));
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Converts an image file to another format.
+///
+/// The input format is detected like when loading images. The output format is taken
+/// from `--to`, or else from the extension of the output file (`.obj` by default).
+///
+fn convert(args: &[String]) -> Result<(), VmError> {
+    let mut paths = Vec::new();
+    let mut format = None;
+    let mut iter = args.iter();
+    while let Some(arg) = iter.next() {
+        match arg.as_str() {
+            "--to" => {
+                format = Some(
+                    ImageFormat::from_name(&option_value(&mut iter)?)
+                        .ok_or_else(|| VmError::BadArgsLength(USAGE.to_string()))?,
+                )
+            }
+            _ => paths.push(arg.clone()),
+        }
+    }
+    let [input, output] = &paths[..] else {
+        return Err(VmError::BadArgsLength(USAGE.to_string()));
+    };
+
+    let format = format
+        .or_else(|| ImageFormat::from_extension(output))
+        .unwrap_or(ImageFormat::Obj);
+    let sections = read_sections(input)?;
+    write_file(output, write_image(format, &sections)?)
+}
+
+/// Links relocatable objects into an image file, and writes its symbols next to it.
+///
+/// Objects are placed from `--origin` (x3000 by default). The output format is taken from
+/// `--to`, or else chosen by `write_program`.
+///
+fn link(args: &[String]) -> Result<(), VmError> {
+    let usage = || VmError::BadArgsLength(USAGE.to_string());
+    let mut output = None;
+    let mut origin = PC_START;
+    let mut format = None;
+    let mut objects = Vec::new();
+    let mut iter = args.iter();
+    while let Some(arg) = iter.next() {
+        match arg.as_str() {
+            "-o" => output = Some(option_value(&mut iter)?),
+            "--origin" => origin = parse_word(&option_value(&mut iter)?).ok_or_else(usage)?,
+            "--to" => {
+                format = Some(ImageFormat::from_name(&option_value(&mut iter)?).ok_or_else(usage)?)
+            }
+            _ => objects.push(ObjectFile::from_file(arg)?),
+        }
+    }
+    let output = output.ok_or_else(usage)?;
+    if objects.is_empty() {
+        return Err(usage());
     }
 
-    let mut vm = Vm::new_from_images(args)?;
+    let image = linker::link(&objects, origin)?;
+    write_program(&output, format, &image.sections, &image.symbols)
+}
+
+/// Assembles a source file into an image file, and writes its symbols next to it.
+///
+/// The output defaults to the source file with an `.obj` extension, and its format is
+/// chosen like for `link`.
+///
+fn asm(args: &[String]) -> Result<(), VmError> {
+    let usage = || VmError::BadArgsLength(USAGE.to_string());
+    let mut output = None;
+    let mut format = None;
+    let mut sources = Vec::new();
+    let mut iter = args.iter();
+    while let Some(arg) = iter.next() {
+        match arg.as_str() {
+            "-o" => output = Some(option_value(&mut iter)?),
+            "--to" => {
+                format = Some(ImageFormat::from_name(&option_value(&mut iter)?).ok_or_else(usage)?)
+            }
+            _ => sources.push(arg.clone()),
+        }
+    }
+    let [source] = &sources[..] else {
+        return Err(usage());
+    };
+    let output = output.unwrap_or_else(|| {
+        Path::new(source)
+            .with_extension("obj")
+            .to_string_lossy()
+            .to_string()
+    });
+
+    let assembly = match assemble_file(source) {
+        Ok(assembly) => assembly,
+        Err(VmError::AssemblyError(errors)) => {
+            eprintln!("{}", errors);
+            std::process::exit(1);
+        }
+        Err(other) => return Err(other),
+    };
+    write_program(&output, format, &assembly.sections, &assembly.symbols)
+}
+
+/// Writes an image file and its symbol table (`prog.sym.json` for `prog.obj`).
+///
+/// Without an explicit format, the extension of `output` decides, except that images with
+/// several sections are written in the lc3tools format (which can hold them) instead of `.obj`.
+///
+fn write_program(
+    output: &str,
+    format: Option<ImageFormat>,
+    sections: &[Section],
+    symbols: &SymbolTable,
+) -> Result<(), VmError> {
+    let format = format.unwrap_or_else(|| match ImageFormat::from_extension(output) {
+        Some(ImageFormat::Obj) | None if sections.len() > 1 => ImageFormat::Lc3Tools,
+        Some(format) => format,
+        None => ImageFormat::Obj,
+    });
+    write_file(output, write_image(format, sections)?)?;
+    let symbols_path = Path::new(output).with_extension("sym.json");
+    write_file(&symbols_path.to_string_lossy(), symbols.to_json())
+}
+
+/// Loads the images, printing the load map if requested, and randomizes the initial state
+/// if requested, printing the seed.
+fn load(options: &Options) -> Result<Vm, VmError> {
+    let mut vm = Vm::new_from_images_with_options(options.images.clone(), &options.load)?;
+    vm.backend = options.backend;
+    if options.print_load_map {
+        print!("{}", vm.load_map);
+    }
+    if let Some(seed) = options.random_seed {
+        vm.randomize(seed);
+        println!(
+            "Randomized registers and memory with seed {} (rerun with --seed {})",
+            seed, seed
+        );
+    }
+    if options.protect_code {
+        for path in &options.images[1..] {
+            vm.memory
+                .regions
+                .extend(protect_code(&read_sections(path)?));
+        }
+    }
+    vm.memory.regions.extend(&options.regions);
+    Ok(vm)
+}
+
+/// Parses the options and image files that follow the program name (and the mode, if any).
+fn parse_options(program: &str, args: &[String]) -> Result<Options, VmError> {
+    let mut options = Options {
+        images: vec![program.to_string()],
+        coverage_path: None,
+        lcov_path: None,
+        print_load_map: false,
+        check_uninit: false,
+        self_modifying: false,
+        convention: None,
+        stack: None,
+        protect_code: false,
+        regions: Vec::new(),
+        random_seed: None,
+        load: LoadOptions::default(),
+        backend: Backend::default(),
+    };
+
+    let mut iter = args.iter();
+    while let Some(arg) = iter.next() {
+        match arg.as_str() {
+            "--coverage" => options.coverage_path = Some(option_value(&mut iter)?),
+            "--lcov" => options.lcov_path = Some(option_value(&mut iter)?),
+            "--load-map" => options.print_load_map = true,
+            "--uninit" => options.check_uninit = true,
+            "--self-modifying" => options.self_modifying = true,
+            "--check-calls" => {
+                options
+                    .convention
+                    .get_or_insert_with(ConventionChecker::new);
+            }
+            "--callee-saved" => {
+                let registers = parse_registers(&option_value(&mut iter)?)
+                    .ok_or_else(|| VmError::BadArgsLength(USAGE.to_string()))?;
+                options
+                    .convention
+                    .get_or_insert_with(ConventionChecker::new)
+                    .callee_saved = registers;
+            }
+            "--stack" => {
+                let range = option_value(&mut iter)?;
+                let (low, high) = range
+                    .split_once('-')
+                    .and_then(|(low, high)| Some((parse_word(low)?, parse_word(high)?)))
+                    .filter(|(low, high)| low <= high)
+                    .ok_or_else(|| VmError::BadArgsLength(USAGE.to_string()))?;
+                options.stack = Some(StackMonitor::new(low, high));
+            }
+            "--protect-code" => options.protect_code = true,
+            "--region" => {
+                let region = option_value(&mut iter)?;
+                options.regions.push(
+                    parse_region(&region)
+                        .ok_or_else(|| VmError::BadArgsLength(USAGE.to_string()))?,
+                );
+            }
+            "--randomize" => {
+                let seed = SystemTime::now()
+                    .duration_since(UNIX_EPOCH)
+                    .map_or(0, |d| d.as_nanos() as u64);
+                options.random_seed = options.random_seed.or(Some(seed));
+            }
+            "--seed" => {
+                options.random_seed = Some(
+                    option_value(&mut iter)?
+                        .parse()
+                        .map_err(|_| VmError::BadArgsLength(USAGE.to_string()))?,
+                )
+            }
+            "--backend" => {
+                options.backend = Backend::from_name(&option_value(&mut iter)?)
+                    .ok_or_else(|| VmError::BadArgsLength(USAGE.to_string()))?
+            }
+            "--overlap" => {
+                options.load.overlap_policy = match option_value(&mut iter)?.as_str() {
+                    "error" => OverlapPolicy::Error,
+                    "warn" => OverlapPolicy::Warn,
+                    _ => return Err(VmError::BadArgsLength(USAGE.to_string())),
+                }
+            }
+            _ => options.images.push(arg.clone()),
+        }
+    }
+
+    if options.images.len() < 2 {
+        return Err(VmError::BadArgsLength(USAGE.to_string()));
+    }
+    Ok(options)
+}
+
+/// Parses a region given as `<low>-<high>:<permissions>`, such as `x3000-x30FF:r-x`.
+fn parse_region(text: &str) -> Option<Region> {
+    let (range, permissions) = text.split_once(':')?;
+    let (low, high) = range.split_once('-')?;
+    let (low, high) = (parse_word(low)?, parse_word(high)?);
+    let permissions = Permissions::parse(permissions)?;
+    (low <= high).then(|| Region::new(low, high, permissions))
+}
+
+/// Parses a comma-separated list of registers, such as `R1,R2,R5`. An empty list is valid.
+fn parse_registers(list: &str) -> Option<Vec<u16>> {
+    list.split(',')
+        .map(str::trim)
+        .filter(|r| !r.is_empty())
+        .map(|r| {
+            let n = r.strip_prefix(['R', 'r'])?.parse().ok()?;
+            (n < 8).then_some(n)
+        })
+        .collect()
+}
 
-    vm.run()
+/// Takes the value that follows a command line option.
+fn option_value<'a>(iter: &mut impl Iterator<Item = &'a String>) -> Result<String, VmError> {
+    iter.next()
+        .cloned()
+        .ok_or_else(|| VmError::BadArgsLength(USAGE.to_string()))
 }
@@ -1,8 +1,11 @@
-use std::io::Read;
-
 use crate::{
-    constants::{MEMORY_SIZE, MR_KBDR, MR_KBSR},
+    console::{Console, Terminal},
+    constants::{DEVICE_PAGE_START, MEMORY_SIZE, MR_KBDR, MR_KBSR},
+    instruction::Instruction,
+    permissions::{Access, AccessViolation, Region},
+    self_modification::SelfModification,
     vm_error::VmError,
+    watchpoint::{AccessKind, WatchHit, Watchpoint},
 };
 
 /// Represents the memory of the LC-3 virtual machine.
@@ -10,9 +13,38 @@ use crate::{
 /// Contains an array representing the memory of the virtual machine,
 /// allowing for reading and writing operations at specific memory addresses.
 ///
+/// # Fields
+///
+/// * `memory` - The memory cells.
+/// * `watchpoints` - Watchpoints checked on every data read and write.
+/// * `watch_hit` - The first watchpoint triggered since it was last taken.
+/// * `last_fetch` - Address of the most recently fetched instruction.
+/// * `predecode` - Whether `fetch_instruction` keeps decoded instructions in a cache.
+/// * `decoded` - The predecode cache, one entry per address, cleared by `write`.
+/// * `write_log` - When present, every write is appended to it as an `(address, value)` pair.
+/// * `translated` - Addresses that are part of a block translated by `BlockTranslator`.
+/// * `invalidated` - Translated addresses written since the translator last looked.
+/// * `console` - The keyboard and display, behind the keyboard registers and the console traps.
+/// * `regions` - Permissions of memory regions, the last region containing an address deciding.
+///   Addresses outside every region allow everything.
+/// * `access_violation` - A write refused by `regions` since it was last taken.
+/// * `self_modification` - When present, tracks fetched addresses and reports writes to them.
+///
 #[derive(Debug)]
 pub struct Memory {
     pub memory: [u16; MEMORY_SIZE],
+    pub watchpoints: Vec<Watchpoint>,
+    pub watch_hit: Option<WatchHit>,
+    pub last_fetch: u16,
+    pub predecode: bool,
+    decoded: Vec<Option<Instruction>>,
+    pub write_log: Option<Vec<(u16, u16)>>,
+    pub(crate) translated: Vec<bool>,
+    pub(crate) invalidated: Vec<u16>,
+    pub console: Box<dyn Console>,
+    pub regions: Vec<Region>,
+    pub access_violation: Option<AccessViolation>,
+    pub self_modification: Option<SelfModification>,
 }
 
 impl Default for Memory {
@@ -31,13 +63,25 @@ impl Memory {
     pub fn new() -> Memory {
         Memory {
             memory: [0; MEMORY_SIZE],
+            watchpoints: Vec::new(),
+            watch_hit: None,
+            last_fetch: 0,
+            predecode: true,
+            decoded: vec![None; MEMORY_SIZE],
+            write_log: None,
+            translated: vec![false; MEMORY_SIZE],
+            invalidated: Vec::new(),
+            console: Box::new(Terminal),
+            regions: Vec::new(),
+            access_violation: None,
+            self_modification: None,
         }
     }
 
     /// Reads the value stored at the specified memory address.
     ///
     /// If the address corresponds to the keyboard status register (`MR_KBSR`), this method
-    /// checks for input from the standard input. If a character is available, it updates
+    /// checks for input from the console. If a character is available, it updates
     /// the keyboard status register and the keyboard data register (`MR_KBDR`) accordingly.
     ///
     /// # Arguments
@@ -47,16 +91,82 @@ impl Memory {
     /// # Returns
     ///
     /// The value stored at the specified memory address. If the address corresponds to
-    /// `MR_KBSR` and an error occurs while reading from standard input, the function
-    /// will return a `VmError`.
+    /// `MR_KBSR` and an error occurs while reading from the console, or its input has ended,
+    /// the function will return a `VmError`, as it does with `VmError::AccessViolation` when
+    /// the region of the address is not readable.
     ///
     pub fn read(&mut self, address: u16) -> Result<u16, VmError> {
+        self.check_access(address, Access::Read)
+            .map_err(VmError::AccessViolation)?;
+        let value = self.load(address)?;
+        self.check_watchpoints(address, AccessKind::Read, value, value);
+        Ok(value)
+    }
+
+    /// Reads the instruction stored at the specified memory address.
+    ///
+    /// Unlike `read`, instruction fetches do not trigger watchpoints. The address is
+    /// remembered in `last_fetch` so that accesses can be attributed to the instruction
+    /// that performed them.
+    ///
+    /// # Arguments
+    ///
+    /// * `address` - A `u16` value representing the address of the instruction.
+    ///
+    /// # Returns
+    ///
+    /// The instruction stored at the address, or a `VmError` if reading it failed or the
+    /// region of the address is not executable.
+    ///
+    pub fn fetch(&mut self, address: u16) -> Result<u16, VmError> {
+        self.check_access(address, Access::Execute)
+            .map_err(VmError::AccessViolation)?;
+        self.record_fetch(address);
+        self.load(address)
+    }
+
+    /// Fetches and decodes the instruction stored at the specified memory address.
+    ///
+    /// Decoded instructions are cached per address while `predecode` is set, and an entry is
+    /// dropped whenever its address is written, so self-modifying code sees its changes. The
+    /// device page is never cached, since device registers change without a `write`.
+    ///
+    /// # Arguments
+    ///
+    /// * `address` - A `u16` value representing the address of the instruction.
+    ///
+    /// # Returns
+    ///
+    /// The decoded instruction, or a `VmError` if reading it failed.
+    ///
+    pub fn fetch_instruction(&mut self, address: u16) -> Result<Instruction, VmError> {
+        if !self.predecode || address >= DEVICE_PAGE_START {
+            return Ok(Instruction::decode(self.fetch(address)?));
+        }
+        self.check_access(address, Access::Execute)
+            .map_err(VmError::AccessViolation)?;
+        self.record_fetch(address);
+        let word = self.memory[address as usize];
+        Ok(*self.decoded[address as usize].get_or_insert_with(|| Instruction::decode(word)))
+    }
+
+    /// Remembers the address of the instruction being fetched in `last_fetch`, and in the
+    /// self-modification tracker if there is one.
+    pub(crate) fn record_fetch(&mut self, address: u16) {
+        self.last_fetch = address;
+        if let Some(tracker) = self.self_modification.as_mut() {
+            tracker.record_fetch(address);
+        }
+    }
+
+    /// Reads a memory cell, polling the keyboard when the keyboard status register is read.
+    fn load(&mut self, address: u16) -> Result<u16, VmError> {
         if address == MR_KBSR {
-            let mut buffer = [0; 1];
-            std::io::stdin()
-                .read_exact(&mut buffer)
-                .map_err(|e| VmError::FailedToReadStdin(e.to_string()))?;
-            let char = buffer[0] as u16;
+            let char = self
+                .console
+                .read_char()?
+                .ok_or_else(|| VmError::FailedToReadStdin("end of input".to_string()))?
+                as u16;
 
             if char == 0 {
                 self.memory[MR_KBSR as usize] = 0;
@@ -70,12 +180,83 @@ impl Memory {
 
     /// Writes a value to the specified memory address.
     ///
+    /// The predecoded instruction at the address is dropped, and if the address belongs to a
+    /// translated block, it is queued in `invalidated` for the translator. If the region of
+    /// the address is not writable, nothing is written and the violation is kept in
+    /// `access_violation` instead.
+    ///
     /// # Arguments
     ///
     /// * `address` - A `u16` value representing the memory address to write to.
     /// * `val` - The value to store at the specified memory address.
     ///
     pub fn write(&mut self, address: u16, val: u16) {
+        if let Err(violation) = self.check_access(address, Access::Write) {
+            self.access_violation.get_or_insert(violation);
+            return;
+        }
+        let old = self.memory[address as usize];
         self.memory[address as usize] = val;
+        self.decoded[address as usize] = None;
+        if let Some(log) = self.write_log.as_mut() {
+            log.push((address, val));
+        }
+        if let Some(tracker) = self.self_modification.as_mut() {
+            tracker.record_write(self.last_fetch, address, old, val);
+        }
+        if self.translated[address as usize] {
+            self.translated[address as usize] = false;
+            self.invalidated.push(address);
+        }
+        self.check_watchpoints(address, AccessKind::Write, old, val);
+    }
+
+    /// Checks an access against `regions`, attributing it to the instruction in `last_fetch`.
+    ///
+    /// # Arguments
+    ///
+    /// * `address` - The accessed address.
+    /// * `access` - What is attempted.
+    ///
+    /// # Returns
+    ///
+    /// An `Ok` result if the access is allowed, otherwise the violation.
+    ///
+    pub fn check_access(&self, address: u16, access: Access) -> Result<(), AccessViolation> {
+        match self.regions.iter().rev().find(|r| r.contains(address)) {
+            Some(region) if !region.permissions.allows(access) => Err(AccessViolation {
+                pc: self.last_fetch,
+                address,
+                access,
+                region: *region,
+            }),
+            _ => Ok(()),
+        }
+    }
+
+    /// Records a `WatchHit` if any watchpoint matches the access and none is pending yet.
+    fn check_watchpoints(
+        &mut self,
+        address: u16,
+        kind: AccessKind,
+        old_value: u16,
+        new_value: u16,
+    ) {
+        if self.watch_hit.is_some() {
+            return;
+        }
+        if self
+            .watchpoints
+            .iter()
+            .any(|w| w.matches(address, kind, old_value, new_value))
+        {
+            self.watch_hit = Some(WatchHit {
+                pc: self.last_fetch,
+                address,
+                kind,
+                old_value,
+                new_value,
+            });
+        }
     }
 }
@@ -1,4 +1,4 @@
-use crate::{utils::sign_extend, vm::Vm, vm_error::VmError};
+use crate::{instruction::Operand, vm::Vm, vm_error::VmError};
 
 impl Vm {
     /// Executes the ADD operation.
@@ -9,32 +9,23 @@ impl Vm {
     ///
     /// # Parameters
     ///
-    /// - `instr`: A 16-bit instruction.
+    /// - `dr`: The destination register.
+    /// - `sr1`: The register holding the first operand.
+    /// - `src2`: The second operand, a register or an immediate value.
     ///
     /// # Returns
     ///
     /// Returns `Ok(())` if the operation was successful, otherwise returns a `VmError`.
     ///
-    pub fn op_add(&mut self, instr: u16) -> Result<(), VmError> {
-        let r0 = (instr >> 9) & 0x7;
-        let r1 = (instr >> 6) & 0x7;
-        let imm_flag = (instr >> 5) & 0x1;
-
-        if imm_flag == 1 {
-            let imm5: i16 = sign_extend(instr & 0x1F, 5);
-            self.registers
-                .set(r0, self.registers.get(r1)?.wrapping_add(imm5 as u16))?;
-        } else {
-            let r2 = instr & 0x7;
-            self.registers.set(
-                r0,
-                self.registers
-                    .get(r1)?
-                    .wrapping_add(self.registers.get(r2)?),
-            )?;
-        }
-
-        self.registers.update_flags(r0)
+    pub fn op_add(&mut self, dr: u16, sr1: u16, src2: Operand) -> Result<(), VmError> {
+        let value = match src2 {
+            Operand::Immediate(imm5) => imm5 as u16,
+            Operand::Register(sr2) => self.registers[sr2],
+        };
+        let result = self.registers[sr1].wrapping_add(value);
+        self.registers[dr] = result;
+        self.registers.set_flags(result);
+        Ok(())
     }
 }
 
@@ -78,19 +69,14 @@ impl Vm {
 
 #[cfg(test)]
 mod tests {
-
     use crate::{
         constants::{FL_NEG, FL_POS, FL_ZRO},
-        memory::Memory,
-        registers::Registers,
+        instruction::Instruction,
         vm::Vm,
     };
 
     fn create_vm() -> Vm {
-        Vm {
-            registers: Registers::new(),
-            memory: Memory::new(),
-        }
+        Vm::new()
     }
 
     #[test]
@@ -101,7 +87,7 @@ mod tests {
         vm.registers.set(2, 15).unwrap();
 
         let instr: u16 = 0b0001_0000_0100_0010;
-        vm.op_add(instr).unwrap();
+        vm.execute(Instruction::decode(instr), &mut true).unwrap();
 
         assert_eq!(vm.registers.get(0).unwrap(), 25);
     }
@@ -112,7 +98,7 @@ mod tests {
         vm.registers.set(1, 10).unwrap();
 
         let instr: u16 = 0b0001_0000_0110_0001;
-        vm.op_add(instr).unwrap();
+        vm.execute(Instruction::decode(instr), &mut true).unwrap();
 
         assert_eq!(vm.registers.get(0).unwrap(), 11);
     }
@@ -123,7 +109,7 @@ mod tests {
         vm.registers.set(1, 10).unwrap();
 
         let instr: u16 = 0b0001_0000_0111_1111;
-        vm.op_add(instr).unwrap();
+        vm.execute(Instruction::decode(instr), &mut true).unwrap();
 
         assert_eq!(vm.registers.get(0).unwrap(), 9);
     }
@@ -134,7 +120,7 @@ mod tests {
         vm.registers.set(1, 0).unwrap();
 
         let instr: u16 = 0b0001_0000_0111_1111;
-        vm.op_add(instr).unwrap();
+        vm.execute(Instruction::decode(instr), &mut true).unwrap();
 
         assert_eq!(vm.registers.get(0).unwrap(), 0xFFFF);
         assert_eq!(vm.registers.cond, FL_NEG);
@@ -146,7 +132,7 @@ mod tests {
         vm.registers.set(1, 1).unwrap();
 
         let instr: u16 = 0b0001_0000_0111_1111;
-        vm.op_add(instr).unwrap();
+        vm.execute(Instruction::decode(instr), &mut true).unwrap();
 
         assert_eq!(vm.registers.get(0).unwrap(), 0);
         assert_eq!(vm.registers.cond, FL_ZRO);
@@ -158,7 +144,7 @@ mod tests {
         vm.registers.set(1, 1).unwrap();
 
         let instr: u16 = 0b0001_0000_0110_0001;
-        vm.op_add(instr).unwrap();
+        vm.execute(Instruction::decode(instr), &mut true).unwrap();
 
         assert_eq!(vm.registers.get(0).unwrap(), 2);
         assert_eq!(vm.registers.cond, FL_POS);
@@ -1,4 +1,4 @@
-use crate::{utils::sign_extend, vm::Vm, vm_error::VmError};
+use crate::{instruction::Operand, vm::Vm, vm_error::VmError};
 
 impl Vm {
     /// Performs a bitwise AND operation between two operands.
@@ -10,27 +10,23 @@ impl Vm {
     ///
     /// # Parameters
     ///
-    /// - `instr`: A 16-bit instruction.
+    /// - `dr`: The destination register.
+    /// - `sr1`: The register holding the first operand.
+    /// - `src2`: The second operand, a register or an immediate value.
     ///
     /// # Returns
     ///
     /// Returns `Ok(())` if the operation was successful, otherwise returns a `VmError`.
     ///
-    pub fn op_and(&mut self, instr: u16) -> Result<(), VmError> {
-        let r0 = (instr >> 9) & 0x7;
-        let r1 = (instr >> 6) & 0x7;
-        let imm_flag = (instr >> 5) & 0x1;
-
-        if imm_flag == 1 {
-            let imm5 = sign_extend(instr & 0x1F, 5);
-            self.registers
-                .set(r0, (self.registers.get(r1)? as i16 & imm5) as u16)?;
-        } else {
-            let r2 = instr & 0x7;
-            self.registers
-                .set(r0, self.registers.get(r1)? & self.registers.get(r2)?)?;
-        }
-        self.registers.update_flags(r0)
+    pub fn op_and(&mut self, dr: u16, sr1: u16, src2: Operand) -> Result<(), VmError> {
+        let value = match src2 {
+            Operand::Immediate(imm5) => imm5 as u16,
+            Operand::Register(sr2) => self.registers[sr2],
+        };
+        let result = self.registers[sr1] & value;
+        self.registers[dr] = result;
+        self.registers.set_flags(result);
+        Ok(())
     }
 }
 
@@ -38,16 +34,12 @@ impl Vm {
 mod tests {
     use crate::{
         constants::{FL_NEG, FL_POS, FL_ZRO},
-        memory::Memory,
-        registers::Registers,
+        instruction::Instruction,
         vm::Vm,
     };
 
     fn create_vm() -> Vm {
-        Vm {
-            registers: Registers::new(),
-            memory: Memory::new(),
-        }
+        Vm::new()
     }
 
     #[test]
@@ -57,7 +49,7 @@ mod tests {
         vm.registers.set(2, 0b1010).unwrap();
 
         let instr: u16 = 0b0101_0000_0100_0010;
-        vm.op_and(instr).unwrap();
+        vm.execute(Instruction::decode(instr), &mut true).unwrap();
 
         assert_eq!(vm.registers.get(0).unwrap(), 0b1000);
     }
@@ -68,7 +60,7 @@ mod tests {
         vm.registers.set(1, 0b1100).unwrap();
 
         let instr: u16 = 0b0101_0000_0110_0101;
-        vm.op_and(instr).unwrap();
+        vm.execute(Instruction::decode(instr), &mut true).unwrap();
 
         assert_eq!(vm.registers.get(0).unwrap(), 0b0100);
     }
@@ -79,7 +71,7 @@ mod tests {
         vm.registers.set(1, 0b1100).unwrap();
 
         let instr: u16 = 0b0101_0000_0110_0110;
-        vm.op_and(instr).unwrap();
+        vm.execute(Instruction::decode(instr), &mut true).unwrap();
 
         assert_eq!(vm.registers.get(0).unwrap(), 0b0100);
         assert_eq!(vm.registers.cond, FL_POS);
@@ -91,7 +83,7 @@ mod tests {
         vm.registers.set(1, 0xFFFF).unwrap();
 
         let instr: u16 = 0b0101_0000_0111_1111;
-        vm.op_and(instr).unwrap();
+        vm.execute(Instruction::decode(instr), &mut true).unwrap();
 
         assert_eq!(vm.registers.get(0).unwrap(), 0xFFFF);
         assert_eq!(vm.registers.cond, FL_NEG);
@@ -104,7 +96,7 @@ mod tests {
         vm.registers.set(2, 0b0011).unwrap();
 
         let instr: u16 = 0b0101_0000_0100_0010;
-        vm.op_and(instr).unwrap();
+        vm.execute(Instruction::decode(instr), &mut true).unwrap();
 
         assert_eq!(vm.registers.get(0).unwrap(), 0b0011);
         assert_eq!(vm.registers.cond, FL_POS);
@@ -117,7 +109,7 @@ mod tests {
         vm.registers.set(2, 0b1010).unwrap();
 
         let instr: u16 = 0b0101_0000_0100_0010;
-        vm.op_and(instr).unwrap();
+        vm.execute(Instruction::decode(instr), &mut true).unwrap();
 
         assert_eq!(vm.registers.get(0).unwrap(), 0b0000);
         assert_eq!(vm.registers.cond, FL_ZRO);
@@ -1,4 +1,4 @@
-use crate::{utils::sign_extend, vm::Vm};
+use crate::vm::Vm;
 
 impl Vm {
     /// Executes the BRANCH operation.
@@ -6,29 +6,32 @@ impl Vm {
     /// Conditionally updates the program counter based on the
     /// specified condition flags. If the condition is met, the program counter
     /// is adjusted by the sign-extended offset to branch to a new location.
+    /// When coverage is enabled, the outcome of conditional branches is recorded.
     ///
     /// # Parameters
     ///
-    /// - `instr`: A 16-bit instruction.
+    /// - `conditions`: The `n`, `z` and `p` bits, tested against the condition flags.
+    /// - `offset`: The offset from the program counter to branch to.
     ///
-    pub fn op_br(&mut self, instr: u16) {
-        let pc_offset = sign_extend(instr & 0x1FF, 9);
-        let cond_flag = (instr >> 9) & 0x7;
-        if cond_flag & self.registers.cond != 0 {
-            self.registers.pc = ((self.registers.pc as i16) + pc_offset) as u16;
+    pub fn op_br(&mut self, conditions: u16, offset: i16) {
+        let taken = conditions & self.registers.cond != 0;
+        if let Some(coverage) = self.coverage.as_mut() {
+            if conditions != 0 && conditions != 0x7 {
+                coverage.record_branch(self.registers.pc.wrapping_sub(1), taken);
+            }
+        }
+        if taken {
+            self.registers.pc = self.registers.pc.wrapping_add(offset as u16);
         }
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::{constants::FL_POS, memory::Memory, registers::Registers, vm::Vm};
+    use crate::{constants::FL_POS, instruction::Instruction, vm::Vm};
 
     fn create_vm() -> Vm {
-        Vm {
-            registers: Registers::new(),
-            memory: Memory::new(),
-        }
+        Vm::new()
     }
 
     // BR TESTS
@@ -38,7 +41,7 @@ mod tests {
         vm.registers.cond = FL_POS;
 
         let instr: u16 = 0b0000_0010_0000_0101;
-        vm.op_br(instr);
+        vm.execute(Instruction::decode(instr), &mut true).unwrap();
 
         assert_eq!(vm.registers.pc, 0x3005);
     }
@@ -49,7 +52,7 @@ mod tests {
         vm.registers.cond = FL_POS;
 
         let instr: u16 = 0b0000_0100_0000_0101;
-        vm.op_br(instr);
+        vm.execute(Instruction::decode(instr), &mut true).unwrap();
 
         assert_eq!(vm.registers.pc, 0x3000);
     }
@@ -60,7 +63,7 @@ mod tests {
         vm.registers.cond = FL_POS;
 
         let instr: u16 = 0b0000_0011_1111_1011;
-        vm.op_br(instr);
+        vm.execute(Instruction::decode(instr), &mut true).unwrap();
 
         assert_eq!(vm.registers.pc, 0x2FFB);
     }
@@ -71,10 +74,21 @@ mod tests {
         vm.registers.cond = FL_POS;
 
         let instr: u16 = 0b0000_0010_0000_0000;
-        vm.op_br(instr);
+        vm.execute(Instruction::decode(instr), &mut true).unwrap();
 
         println!("PC: {}", vm.registers.pc);
 
         assert_eq!(vm.registers.pc, 0x3000);
     }
+
+    #[test]
+    fn op_br_wraps_below_x8000() {
+        let mut vm = create_vm();
+        vm.registers.pc = 0x8000;
+
+        let instr: u16 = 0b0000_1111_1111_1111; // BRnzp #-1
+        vm.execute(Instruction::decode(instr), &mut true).unwrap();
+
+        assert_eq!(vm.registers.pc, 0x7FFF);
+    }
 }
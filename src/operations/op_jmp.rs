@@ -8,27 +8,23 @@ impl Vm {
     ///
     /// # Parameters
     ///
-    /// - `instr`: A 16-bit instruction.
+    /// - `base`: The register holding the address to jump to.
     ///
     /// # Returns
     ///
     /// Returns `Ok(())` if the operation was successful, otherwise returns a `VmError`.
     ///
-    pub fn op_jmp(&mut self, instr: u16) -> Result<(), VmError> {
-        let r1 = (instr >> 6) & 0x7;
-        self.registers.pc = self.registers.get(r1)?;
+    pub fn op_jmp(&mut self, base: u16) -> Result<(), VmError> {
+        self.registers.pc = self.registers[base];
         Ok(())
     }
 }
 #[cfg(test)]
 mod tests {
-    use crate::{memory::Memory, registers::Registers, vm::Vm};
+    use crate::{instruction::Instruction, vm::Vm};
 
     fn create_vm() -> Vm {
-        Vm {
-            registers: Registers::new(),
-            memory: Memory::new(),
-        }
+        Vm::new()
     }
 
     #[test]
@@ -37,7 +33,7 @@ mod tests {
         vm.registers.set(1, 0x3033).unwrap();
 
         let instr: u16 = 0b1100_0000_0100_0000;
-        vm.op_jmp(instr).unwrap();
+        vm.execute(Instruction::decode(instr), &mut true).unwrap();
 
         assert_eq!(vm.registers.pc, 0x3033);
     }
@@ -48,7 +44,7 @@ mod tests {
         vm.registers.set(2, 0x0000).unwrap();
 
         let instr: u16 = 0b1100_0000_1000_0000;
-        vm.op_jmp(instr).unwrap();
+        vm.execute(Instruction::decode(instr), &mut true).unwrap();
 
         assert_eq!(vm.registers.pc, 0x0000);
     }
@@ -59,7 +55,7 @@ mod tests {
         vm.registers.set(3, 0xFFFF).unwrap();
 
         let instr: u16 = 0b1100_0000_1100_0000;
-        vm.op_jmp(instr).unwrap();
+        vm.execute(Instruction::decode(instr), &mut true).unwrap();
 
         assert_eq!(vm.registers.pc, 0xFFFF);
     }
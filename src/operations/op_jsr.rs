@@ -1,44 +1,52 @@
-use crate::{utils::sign_extend, vm::Vm, vm_error::VmError};
+use crate::{vm::Vm, vm_error::VmError};
 
 impl Vm {
     /// Executes the JSR operation.
     ///
     /// Performs a jump to a subroutine. It saves the current program counter
-    /// in the R7 register and then updates the PC to the target address, which can be provided
-    /// either by a direct offset (JSR) or by a register (JSRR).
+    /// in the R7 register and then updates the PC by adding an offset to it.
     ///
     /// # Parameters
     ///
-    /// - `instr`: A 16-bit instruction.
+    /// - `offset`: The offset from the program counter to the subroutine.
     ///
     /// # Returns
     ///
     /// Returns `Ok(())` if the operation was successful, otherwise returns a `VmError`.
     ///
-    pub fn op_jsr(&mut self, instr: u16) -> Result<(), VmError> {
-        let long_flag = (instr >> 11) & 1;
-        self.registers.r7 = self.registers.pc;
-        if long_flag == 1 {
-            let long_pc_offset = sign_extend(instr & 0x7FF, 11);
-            self.registers.pc = (self.registers.pc as i16 + long_pc_offset) as u16;
-        /* JSR */
-        } else {
-            let r1 = (instr >> 6) & 0x7;
-            self.registers.pc = self.registers.get(r1)?; /* JSRR */
-        }
+    pub fn op_jsr(&mut self, offset: i16) -> Result<(), VmError> {
+        self.registers[7] = self.registers.pc;
+        self.registers.pc = self.registers.pc.wrapping_add(offset as u16);
+        Ok(())
+    }
+
+    /// Executes the JSRR operation.
+    ///
+    /// Like JSR, but the address of the subroutine is taken from a register. The register
+    /// is read before R7 is overwritten, so `JSRR R7` jumps to the old value of R7.
+    ///
+    /// # Parameters
+    ///
+    /// - `base`: The register holding the address of the subroutine.
+    ///
+    /// # Returns
+    ///
+    /// Returns `Ok(())` if the operation was successful, otherwise returns a `VmError`.
+    ///
+    pub fn op_jsrr(&mut self, base: u16) -> Result<(), VmError> {
+        let target = self.registers[base];
+        self.registers[7] = self.registers.pc;
+        self.registers.pc = target;
         Ok(())
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::{memory::Memory, registers::Registers, vm::Vm};
+    use crate::{instruction::Instruction, vm::Vm};
 
     fn create_vm() -> Vm {
-        Vm {
-            registers: Registers::new(),
-            memory: Memory::new(),
-        }
+        Vm::new()
     }
 
     #[test]
@@ -47,9 +55,9 @@ mod tests {
         vm.registers.pc = 0x3000;
 
         let instr: u16 = 0b0100_1000_0001_0000;
-        vm.op_jsr(instr).unwrap();
+        vm.execute(Instruction::decode(instr), &mut true).unwrap();
 
-        assert_eq!(vm.registers.r7, 0x3000);
+        assert_eq!(vm.registers[7], 0x3000);
         assert_eq!(vm.registers.pc, 0x3010);
     }
 
@@ -59,9 +67,9 @@ mod tests {
         vm.registers.pc = 0x3000;
 
         let instr: u16 = 0b0100_1111_1111_1111;
-        vm.op_jsr(instr).unwrap();
+        vm.execute(Instruction::decode(instr), &mut true).unwrap();
 
-        assert_eq!(vm.registers.r7, 0x3000);
+        assert_eq!(vm.registers[7], 0x3000);
         assert_eq!(vm.registers.pc, 0x2FFF);
     }
 
@@ -71,10 +79,23 @@ mod tests {
         vm.registers.pc = 0x3000;
         vm.registers.set(2, 0x4000).unwrap();
 
-        let instr: u16 = 0b0_1000_0000_1000_0000;
-        vm.op_jsr(instr).unwrap();
+        let instr: u16 = 0b0100_0000_1000_0000; // JSRR R2
+        vm.execute(Instruction::decode(instr), &mut true).unwrap();
+
+        assert_eq!(vm.registers[7], 0x3000);
+        assert_eq!(vm.registers.pc, 0x4000);
+    }
+
+    #[test]
+    fn op_jsrr_r7_jumps_to_the_old_r7() {
+        let mut vm = create_vm();
+        vm.registers.pc = 0x3000;
+        vm.registers[7] = 0x4000;
+
+        let instr: u16 = 0b0100_0001_1100_0000; // JSRR R7
+        vm.execute(Instruction::decode(instr), &mut true).unwrap();
 
-        assert_eq!(vm.registers.r7, 0x3000);
+        assert_eq!(vm.registers[7], 0x3000);
         assert_eq!(vm.registers.pc, 0x4000);
     }
 
@@ -85,10 +106,10 @@ mod tests {
         vm.registers.set(1, 0xABCD).unwrap();
         vm.registers.set(2, 0x1234).unwrap();
 
-        let instr: u16 = 0b0_1000_0000_1000_0000;
-        vm.op_jsr(instr).unwrap();
+        let instr: u16 = 0b0100_0000_1000_0000; // JSRR R2
+        vm.execute(Instruction::decode(instr), &mut true).unwrap();
 
-        assert_eq!(vm.registers.r7, 0x3000);
+        assert_eq!(vm.registers[7], 0x3000);
         assert_eq!(vm.registers.pc, 0x1234);
         assert_eq!(vm.registers.get(1).unwrap(), 0xABCD);
     }
@@ -99,12 +120,25 @@ mod tests {
         vm.registers.pc = 0x3000;
 
         let instr_jsr: u16 = 0b0100_1000_0000_0010;
-        vm.op_jsr(instr_jsr).unwrap();
+        vm.execute(Instruction::decode(instr_jsr), &mut true)
+            .unwrap();
 
-        assert_eq!(vm.registers.r7, 0x3000);
+        assert_eq!(vm.registers[7], 0x3000);
         assert_eq!(vm.registers.pc, 0x3002);
 
-        vm.registers.pc = vm.registers.r7;
+        vm.registers.pc = vm.registers[7];
         assert_eq!(vm.registers.pc, 0x3000);
     }
+
+    #[test]
+    fn op_jsr_wraps_past_x7fff() {
+        let mut vm = create_vm();
+        vm.registers.pc = 0x7FFF;
+
+        let instr: u16 = 0b0100_1000_0000_0001; // JSR #1
+        vm.execute(Instruction::decode(instr), &mut true).unwrap();
+
+        assert_eq!(vm.registers[7], 0x7FFF);
+        assert_eq!(vm.registers.pc, 0x8000);
+    }
 }
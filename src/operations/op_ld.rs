@@ -1,4 +1,4 @@
-use crate::{utils::sign_extend, vm::Vm, vm_error::VmError};
+use crate::{vm::Vm, vm_error::VmError};
 
 impl Vm {
     /// Executes the LD operation.
@@ -8,29 +8,27 @@ impl Vm {
     ///
     /// # Parameters
     ///
-    /// - `instr`: A 16-bit instruction.
+    /// - `dr`: The destination register.
+    /// - `offset`: The offset from the program counter to the loaded address.
     ///
     /// # Returns
     ///
     /// Returns `Ok(())` if the operation was successful, otherwise returns a `VmError`.
     ///
-    pub fn op_ld(&mut self, instr: u16) -> Result<(), VmError> {
-        let r0 = (instr >> 9) & 0x7;
-        let pc_offset = sign_extend(instr & 0x1FF, 9);
-        let address = (self.registers.pc as i16 + pc_offset) as u16;
-        self.registers.set(r0, self.memory.read(address)?)?;
-        self.registers.update_flags(r0)
+    pub fn op_ld(&mut self, dr: u16, offset: i16) -> Result<(), VmError> {
+        let address = self.registers.pc.wrapping_add(offset as u16);
+        let value = self.memory.read(address)?;
+        self.registers[dr] = value;
+        self.registers.set_flags(value);
+        Ok(())
     }
 }
 #[cfg(test)]
 mod tests {
-    use crate::{constants::FL_ZRO, memory::Memory, registers::Registers, vm::Vm};
+    use crate::{constants::FL_ZRO, instruction::Instruction, vm::Vm};
 
     fn create_vm() -> Vm {
-        Vm {
-            registers: Registers::new(),
-            memory: Memory::new(),
-        }
+        Vm::new()
     }
 
     #[test]
@@ -40,7 +38,7 @@ mod tests {
         vm.memory.write(0x3002, 0x1234);
 
         let instr: u16 = 0b0010_0000_0000_0010; // LD R0, PC+2
-        vm.op_ld(instr).unwrap();
+        vm.execute(Instruction::decode(instr), &mut true).unwrap();
 
         assert_eq!(vm.registers.get(0).unwrap(), 0x1234);
     }
@@ -53,7 +51,7 @@ mod tests {
         vm.memory.write(0x2FFE, 0xABCD);
 
         let instr: u16 = 0b0010_0001_1111_1110; // LD R0, PC-2
-        vm.op_ld(instr).unwrap();
+        vm.execute(Instruction::decode(instr), &mut true).unwrap();
 
         assert_eq!(vm.registers.get(0).unwrap(), 0xABCD);
     }
@@ -66,7 +64,7 @@ mod tests {
         vm.memory.write(0x3000, 0x5678);
 
         let instr: u16 = 0b0010_0000_0000_0000; // LD R0, PC+0
-        vm.op_ld(instr).unwrap();
+        vm.execute(Instruction::decode(instr), &mut true).unwrap();
 
         assert_eq!(vm.registers.get(0).unwrap(), 0x5678);
     }
@@ -79,7 +77,7 @@ mod tests {
         vm.memory.write(0x3000, 0x0000);
 
         let instr: u16 = 0b0010_0000_0000_0000; // LD R0, PC+0
-        vm.op_ld(instr).unwrap();
+        vm.execute(Instruction::decode(instr), &mut true).unwrap();
 
         assert_eq!(vm.registers.get(0).unwrap(), 0x0000);
         assert_eq!(vm.registers.cond, FL_ZRO);
@@ -92,8 +90,21 @@ mod tests {
         vm.registers.pc = 0x3000;
 
         let instr: u16 = 0b0010_0000_0000_0010; // LD R0, PC+2
-        vm.op_ld(instr).unwrap();
+        vm.execute(Instruction::decode(instr), &mut true).unwrap();
 
         assert_eq!(vm.registers.pc, 0x3000);
     }
+
+    #[test]
+    fn op_ld_wraps_around_memory() {
+        let mut vm = create_vm();
+
+        vm.registers.pc = 0xFFFF;
+        vm.memory.write(0x0001, 0x4321);
+
+        let instr: u16 = 0b0010_0000_0000_0010; // LD R0, PC+2
+        vm.execute(Instruction::decode(instr), &mut true).unwrap();
+
+        assert_eq!(vm.registers.get(0).unwrap(), 0x4321);
+    }
 }
@@ -1,4 +1,4 @@
-use crate::{utils::sign_extend, vm::Vm, vm_error::VmError};
+use crate::{vm::Vm, vm_error::VmError};
 
 impl Vm {
     /// Executes the LDI operation.
@@ -10,31 +10,29 @@ impl Vm {
     ///
     /// # Parameters
     ///
-    /// - `instr`: A 16-bit instruction.
+    /// - `dr`: The destination register.
+    /// - `offset`: The offset from the program counter to the pointer.
     ///
     /// # Returns
     ///
     /// Returns `Ok(())` if the operation was successful, otherwise returns a `VmError`.
     ///
-    pub fn op_ldi(&mut self, instr: u16) -> Result<(), VmError> {
-        let r0 = (instr >> 9) & 0x7;
-        let pc_offset = sign_extend(instr & 0x1FF, 9);
-        let addr = (self.registers.pc as i16 + pc_offset) as u16;
+    pub fn op_ldi(&mut self, dr: u16, offset: i16) -> Result<(), VmError> {
+        let addr = self.registers.pc.wrapping_add(offset as u16);
         let indirect_addr = self.memory.read(addr)?;
-        self.registers.set(r0, self.memory.read(indirect_addr)?)?;
-        self.registers.update_flags(r0)
+        let value = self.memory.read(indirect_addr)?;
+        self.registers[dr] = value;
+        self.registers.set_flags(value);
+        Ok(())
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::{constants::FL_ZRO, memory::Memory, registers::Registers, vm::Vm};
+    use crate::{constants::FL_ZRO, instruction::Instruction, vm::Vm};
 
     fn create_vm() -> Vm {
-        Vm {
-            registers: Registers::new(),
-            memory: Memory::new(),
-        }
+        Vm::new()
     }
 
     #[test]
@@ -45,7 +43,7 @@ mod tests {
         vm.memory.write(0x4000, 0x1234);
 
         let instr: u16 = 0b1010_0000_0000_0010; // LDI R0, PC+2
-        vm.op_ldi(instr).unwrap();
+        vm.execute(Instruction::decode(instr), &mut true).unwrap();
 
         assert_eq!(vm.registers.get(0).unwrap(), 0x1234);
     }
@@ -59,7 +57,7 @@ mod tests {
         vm.memory.write(0x4000, 0xABCD);
 
         let instr: u16 = 0b1010_0001_1111_1110; // LDI R0, PC-2
-        vm.op_ldi(instr).unwrap();
+        vm.execute(Instruction::decode(instr), &mut true).unwrap();
 
         assert_eq!(vm.registers.get(0).unwrap(), 0xABCD);
     }
@@ -73,7 +71,7 @@ mod tests {
         vm.memory.write(0x5000, 0x5678);
 
         let instr: u16 = 0b1010_0000_0000_0000; // LDI R0, PC+0
-        vm.op_ldi(instr).unwrap();
+        vm.execute(Instruction::decode(instr), &mut true).unwrap();
 
         assert_eq!(vm.registers.get(0).unwrap(), 0x5678);
     }
@@ -87,7 +85,7 @@ mod tests {
         vm.memory.write(0x0000, 0x0000);
 
         let instr: u16 = 0b1010_0000_0000_0000; // LDI R0, PC+0
-        vm.op_ldi(instr).unwrap();
+        vm.execute(Instruction::decode(instr), &mut true).unwrap();
 
         assert_eq!(vm.registers.get(0).unwrap(), 0x0000);
         assert_eq!(vm.registers.cond, FL_ZRO);
@@ -100,7 +98,7 @@ mod tests {
         vm.registers.pc = 0x3000;
 
         let instr: u16 = 0b1010_0000_0000_0010; // LDI R0, PC+2
-        vm.op_ldi(instr).unwrap();
+        vm.execute(Instruction::decode(instr), &mut true).unwrap();
 
         assert_eq!(vm.registers.pc, 0x3000);
     }
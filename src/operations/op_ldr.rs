@@ -1,4 +1,4 @@
-use crate::{utils::sign_extend, vm::Vm, vm_error::VmError};
+use crate::{vm::Vm, vm_error::VmError};
 
 impl Vm {
     /// Executes the LDR operation.
@@ -10,33 +10,31 @@ impl Vm {
     ///
     /// # Parameters
     ///
-    /// - `instr`: A 16-bit instruction.
+    /// - `dr`: The destination register.
+    /// - `base`: The register holding the base address.
+    /// - `offset`: The offset from the base address.
     ///
     /// # Returns
     ///
     /// Returns `Ok(())` if the operation was successful, otherwise returns a `VmError`.
     ///
-    pub fn op_ldr(&mut self, instr: u16) -> Result<(), VmError> {
-        let r0 = (instr >> 9) & 0x7;
-        let r1 = (instr >> 6) & 0x7;
-        let offset = sign_extend(instr & 0x3F, 6);
-        let addr = (self.registers.get(r1)? as i16 + offset) as u16;
-        self.registers.set(r0, self.memory.read(addr)?)?;
-        self.registers.update_flags(r0)
+    pub fn op_ldr(&mut self, dr: u16, base: u16, offset: i16) -> Result<(), VmError> {
+        let addr = self.registers[base].wrapping_add(offset as u16);
+        let value = self.memory.read(addr)?;
+        self.registers[dr] = value;
+        self.registers.set_flags(value);
+        Ok(())
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::{constants::FL_ZRO, memory::Memory, registers::Registers};
+    use crate::{constants::FL_ZRO, instruction::Instruction};
 
     use super::*;
 
     fn create_vm() -> Vm {
-        Vm {
-            registers: Registers::new(),
-            memory: Memory::new(),
-        }
+        Vm::new()
     }
 
     #[test]
@@ -47,7 +45,7 @@ mod tests {
         vm.memory.write(0x3002, 0xABCD);
 
         let instr: u16 = 0b0110_0000_0100_0010; // LDR R0, R1, #2
-        vm.op_ldr(instr).unwrap();
+        vm.execute(Instruction::decode(instr), &mut true).unwrap();
 
         assert_eq!(vm.registers.get(0).unwrap(), 0xABCD);
     }
@@ -60,7 +58,7 @@ mod tests {
         vm.memory.write(0x3000, 0x5678);
 
         let instr: u16 = 0b0110_0000_0111_1110; // LDR R0, R1, #-2
-        vm.op_ldr(instr).unwrap();
+        vm.execute(Instruction::decode(instr), &mut true).unwrap();
 
         assert_eq!(vm.registers.get(0).unwrap(), 0x5678);
     }
@@ -73,7 +71,7 @@ mod tests {
         vm.memory.write(0x3000, 0x9ABC);
 
         let instr: u16 = 0b0110_0000_0100_0000; // LDR R0, R1, #0
-        vm.op_ldr(instr).unwrap();
+        vm.execute(Instruction::decode(instr), &mut true).unwrap();
 
         assert_eq!(vm.registers.get(0).unwrap(), 0x9ABC);
     }
@@ -86,7 +84,7 @@ mod tests {
         vm.memory.write(0x3000, 0x0000);
 
         let instr: u16 = 0b0110_0000_0100_0000; // LDR R0, R1, #0
-        vm.op_ldr(instr).unwrap();
+        vm.execute(Instruction::decode(instr), &mut true).unwrap();
 
         assert_eq!(vm.registers.get(0).unwrap(), 0x0000);
         assert_eq!(vm.registers.cond, FL_ZRO);
@@ -100,8 +98,21 @@ mod tests {
 
         let instr: u16 = 0b0110_0000_0100_0010; // LDR R0, R1, #2
         let initial_pc = vm.registers.pc;
-        vm.op_ldr(instr).unwrap();
+        vm.execute(Instruction::decode(instr), &mut true).unwrap();
 
         assert_eq!(vm.registers.pc, initial_pc);
     }
+
+    #[test]
+    fn op_ldr_wraps_past_x7fff() {
+        let mut vm = create_vm();
+
+        vm.registers.set(1, 0x7FFF).unwrap();
+        vm.memory.write(0x8000, 0x0042);
+
+        let instr: u16 = 0b0110_0000_0100_0001; // LDR R0, R1, #1
+        vm.execute(Instruction::decode(instr), &mut true).unwrap();
+
+        assert_eq!(vm.registers.get(0).unwrap(), 0x0042);
+    }
 }
@@ -1,4 +1,4 @@
-use crate::{utils::sign_extend, vm::Vm, vm_error::VmError};
+use crate::{vm::Vm, vm_error::VmError};
 
 impl Vm {
     /// Executes the LEA operation.
@@ -9,31 +9,27 @@ impl Vm {
     ///
     /// # Parameters
     ///
-    /// - `instr`: A 16-bit instruction.
+    /// - `dr`: The destination register.
+    /// - `offset`: The offset from the program counter.
     ///
     /// # Returns
     ///
     /// Returns `Ok(())` if the operation was successful, otherwise returns a `VmError`.
     ///
-    pub fn op_lea(&mut self, instr: u16) -> Result<(), VmError> {
-        let r0 = (instr >> 9) & 0x7;
-        let pc_offset = sign_extend(instr & 0x1FF, 9);
-        self.registers
-            .set(r0, (self.registers.pc as i16 + pc_offset) as u16)?;
-        self.registers.update_flags(r0)
+    pub fn op_lea(&mut self, dr: u16, offset: i16) -> Result<(), VmError> {
+        let address = self.registers.pc.wrapping_add(offset as u16);
+        self.registers[dr] = address;
+        self.registers.set_flags(address);
+        Ok(())
     }
 }
 
 #[cfg(test)]
 mod tests {
-
-    use crate::{constants::FL_ZRO, memory::Memory, registers::Registers, vm::Vm};
+    use crate::{constants::FL_ZRO, instruction::Instruction, vm::Vm};
 
     fn create_vm() -> Vm {
-        Vm {
-            registers: Registers::new(),
-            memory: Memory::new(),
-        }
+        Vm::new()
     }
 
     #[test]
@@ -41,7 +37,7 @@ mod tests {
         let mut vm = create_vm();
 
         let instr: u16 = 0b1110_0000_0000_0101; // LEA R0, PC + 5
-        vm.op_lea(instr).unwrap();
+        vm.execute(Instruction::decode(instr), &mut true).unwrap();
 
         assert_eq!(vm.registers.get(0).unwrap(), 0x3005);
     }
@@ -51,7 +47,7 @@ mod tests {
         let mut vm = create_vm();
 
         let instr: u16 = 0b1110_0001_1111_1011; // LEA R0, PC - 5
-        vm.op_lea(instr).unwrap();
+        vm.execute(Instruction::decode(instr), &mut true).unwrap();
 
         assert_eq!(vm.registers.get(0).unwrap(), 0x2FFB);
     }
@@ -61,7 +57,7 @@ mod tests {
         let mut vm = create_vm();
 
         let instr: u16 = 0b1110_0000_0000_0000; // LEA R0, PC + 0
-        vm.op_lea(instr).unwrap();
+        vm.execute(Instruction::decode(instr), &mut true).unwrap();
 
         assert_eq!(vm.registers.get(0).unwrap(), 0x3000);
     }
@@ -72,7 +68,7 @@ mod tests {
         vm.registers.pc = 0x0000;
 
         let instr: u16 = 0b1110_0000_0000_0000; // LEA R0, PC + 0
-        vm.op_lea(instr).unwrap();
+        vm.execute(Instruction::decode(instr), &mut true).unwrap();
 
         assert_eq!(vm.registers.get(0).unwrap(), 0x0000);
         assert_eq!(vm.registers.cond, FL_ZRO);
@@ -84,8 +80,19 @@ mod tests {
 
         let instr: u16 = 0b1110_0000_0000_0101; // LEA R0, PC + 5
         let initial_pc = vm.registers.pc;
-        vm.op_lea(instr).unwrap();
+        vm.execute(Instruction::decode(instr), &mut true).unwrap();
 
         assert_eq!(vm.registers.pc, initial_pc);
     }
+
+    #[test]
+    fn op_lea_wraps_below_x8000() {
+        let mut vm = create_vm();
+        vm.registers.pc = 0x8000;
+
+        let instr: u16 = 0b1110_0001_1111_1111; // LEA R0, #-1
+        vm.execute(Instruction::decode(instr), &mut true).unwrap();
+
+        assert_eq!(vm.registers.get(0).unwrap(), 0x7FFF);
+    }
 }
@@ -9,35 +9,31 @@ impl Vm {
     ///
     /// # Parameters
     ///
-    /// - `instr`: A 16-bit instruction.
+    /// - `dr`: The destination register.
+    /// - `sr`: The source register.
     ///
     /// # Returns
     ///
     /// Returns `Ok(())` if the operation was successful, otherwise returns a `VmError`.
     ///
-    pub fn op_not(&mut self, instr: u16) -> Result<(), VmError> {
-        let r0 = (instr >> 9) & 0x7;
-        let r1 = (instr >> 6) & 0x7;
-        self.registers.set(r0, !self.registers.get(r1)?)?;
-        self.registers.update_flags(r0)
+    pub fn op_not(&mut self, dr: u16, sr: u16) -> Result<(), VmError> {
+        let result = !self.registers[sr];
+        self.registers[dr] = result;
+        self.registers.set_flags(result);
+        Ok(())
     }
 }
 
 #[cfg(test)]
 mod tests {
-
     use crate::{
         constants::{FL_NEG, FL_ZRO},
-        memory::Memory,
-        registers::Registers,
+        instruction::Instruction,
         vm::Vm,
     };
 
     fn create_vm() -> Vm {
-        Vm {
-            registers: Registers::new(),
-            memory: Memory::new(),
-        }
+        Vm::new()
     }
 
     #[test]
@@ -46,7 +42,7 @@ mod tests {
         vm.registers.set(1, 0x0F0F).unwrap();
 
         let instr: u16 = 0b1001_0000_0111_1111; // NOT R0, R1
-        vm.op_not(instr).unwrap();
+        vm.execute(Instruction::decode(instr), &mut true).unwrap();
 
         assert_eq!(vm.registers.get(0).unwrap(), 0xF0F0);
     }
@@ -57,7 +53,7 @@ mod tests {
         vm.registers.set(1, 0x0000).unwrap();
 
         let instr: u16 = 0b1001_0000_0111_1111; // NOT R0, R1
-        vm.op_not(instr).unwrap();
+        vm.execute(Instruction::decode(instr), &mut true).unwrap();
 
         assert_eq!(vm.registers.get(0).unwrap(), 0xFFFF);
     }
@@ -68,7 +64,7 @@ mod tests {
         vm.registers.set(1, 0xFFFF).unwrap();
 
         let instr: u16 = 0b1001_0000_0111_1111; // NOT R0, R1
-        vm.op_not(instr).unwrap();
+        vm.execute(Instruction::decode(instr), &mut true).unwrap();
 
         assert_eq!(vm.registers.get(0).unwrap(), 0x0000);
         assert_eq!(vm.registers.cond, FL_ZRO);
@@ -80,7 +76,7 @@ mod tests {
         vm.registers.set(1, 0x7FFF).unwrap();
 
         let instr: u16 = 0b1001_0000_0111_1111; // NOT R0, R1
-        vm.op_not(instr).unwrap();
+        vm.execute(Instruction::decode(instr), &mut true).unwrap();
 
         assert_eq!(vm.registers.get(0).unwrap(), 0x8000);
         assert_eq!(vm.registers.cond, FL_NEG);
@@ -1,4 +1,4 @@
-use crate::{utils::sign_extend, vm::Vm, vm_error::VmError};
+use crate::{vm::Vm, vm_error::VmError};
 
 impl Vm {
     /// Executes the ST operation.
@@ -8,18 +8,17 @@ impl Vm {
     ///
     /// # Parameters
     ///
-    /// - `instr`: A 16-bit instruction.
+    /// - `sr`: The register holding the value to store.
+    /// - `offset`: The offset from the program counter to the stored address.
     ///
     /// # Returns
     ///
     /// Returns `Ok(())` if the operation was successful, otherwise returns a `VmError`.
     ///
-    pub fn op_st(&mut self, instr: u16) -> Result<(), VmError> {
-        let r0 = (instr >> 9) & 0x7;
-        let pc_offset = sign_extend(instr & 0x1FF, 9);
+    pub fn op_st(&mut self, sr: u16, offset: i16) -> Result<(), VmError> {
         self.memory.write(
-            (self.registers.pc as i16 + pc_offset) as u16,
-            self.registers.get(r0)?,
+            self.registers.pc.wrapping_add(offset as u16),
+            self.registers[sr],
         );
         Ok(())
     }
@@ -27,13 +26,10 @@ impl Vm {
 
 #[cfg(test)]
 mod tests {
-    use crate::{memory::Memory, registers::Registers, vm::Vm};
+    use crate::{instruction::Instruction, vm::Vm};
 
     fn create_vm() -> Vm {
-        Vm {
-            registers: Registers::new(),
-            memory: Memory::new(),
-        }
+        Vm::new()
     }
 
     #[test]
@@ -43,7 +39,7 @@ mod tests {
         vm.registers.set(0, 0x1234).unwrap();
 
         let instr: u16 = 0b0011_0000_0000_0010; // ST R0, #2
-        vm.op_st(instr).unwrap();
+        vm.execute(Instruction::decode(instr), &mut true).unwrap();
 
         assert_eq!(vm.memory.read(vm.registers.pc + 2).unwrap(), 0x1234);
     }
@@ -56,7 +52,7 @@ mod tests {
         vm.registers.set(0, 0x5678).unwrap();
 
         let instr: u16 = 0b0011_0001_1111_1110; // ST R0, #-2
-        vm.op_st(instr).unwrap();
+        vm.execute(Instruction::decode(instr), &mut true).unwrap();
 
         assert_eq!(vm.memory.read(0x2FFE).unwrap(), 0x5678);
     }
@@ -69,7 +65,7 @@ mod tests {
         vm.registers.set(0, 0xABCD).unwrap();
 
         let instr: u16 = 0b0011_0000_0000_0000; // ST R0, #0
-        vm.op_st(instr).unwrap();
+        vm.execute(Instruction::decode(instr), &mut true).unwrap();
 
         assert_eq!(vm.memory.read(0x3000).unwrap(), 0xABCD);
     }
@@ -82,7 +78,7 @@ mod tests {
         vm.registers.set(0, 0x4321).unwrap();
 
         let instr: u16 = 0b0011_0000_0000_0001; // ST R0, #1
-        vm.op_st(instr).unwrap();
+        vm.execute(Instruction::decode(instr), &mut true).unwrap();
 
         assert_eq!(vm.memory.read(0x0000).unwrap(), 0x4321);
     }
@@ -96,7 +92,7 @@ mod tests {
         vm.registers.set(1, 0x8888).unwrap();
 
         let instr: u16 = 0b0011_0000_0000_0010; // ST R0, #2
-        vm.op_st(instr).unwrap();
+        vm.execute(Instruction::decode(instr), &mut true).unwrap();
 
         assert_eq!(vm.registers.get(1).unwrap(), 0x8888);
     }
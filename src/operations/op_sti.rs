@@ -1,4 +1,4 @@
-use crate::{utils::sign_extend, vm::Vm, vm_error::VmError};
+use crate::{vm::Vm, vm_error::VmError};
 
 impl Vm {
     /// Executes the STI operation.
@@ -8,32 +8,28 @@ impl Vm {
     ///
     /// # Parameters
     ///
-    /// - `instr`: A 16-bit instruction.
+    /// - `sr`: The register holding the value to store.
+    /// - `offset`: The offset from the program counter to the pointer.
     ///
     /// # Returns
     ///
     /// Returns `Ok(())` if the operation was successful, otherwise returns a `VmError`.
     ///
-    pub fn op_sti(&mut self, instr: u16) -> Result<(), VmError> {
-        let r0 = (instr >> 9) & 0x7;
-        let pc_offset = sign_extend(instr & 0x1FF, 9);
+    pub fn op_sti(&mut self, sr: u16, offset: i16) -> Result<(), VmError> {
         let addr = self
             .memory
-            .read((self.registers.pc as i16 + pc_offset) as u16)?;
-        self.memory.write(addr, self.registers.get(r0)?);
+            .read(self.registers.pc.wrapping_add(offset as u16))?;
+        self.memory.write(addr, self.registers[sr]);
         Ok(())
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::{memory::Memory, registers::Registers, vm::Vm};
+    use crate::{instruction::Instruction, vm::Vm};
 
     fn create_vm() -> Vm {
-        Vm {
-            registers: Registers::new(),
-            memory: Memory::new(),
-        }
+        Vm::new()
     }
 
     #[test]
@@ -46,7 +42,7 @@ mod tests {
         vm.memory.write(0x3002, 0x4000);
 
         let instr: u16 = 0b1011_0000_0000_0010; // STI R0, #2
-        vm.op_sti(instr).unwrap();
+        vm.execute(Instruction::decode(instr), &mut true).unwrap();
 
         assert_eq!(vm.memory.read(0x4000).unwrap(), 0x1234);
     }
@@ -61,7 +57,7 @@ mod tests {
         vm.memory.write(0x2FFE, 0x5000);
 
         let instr: u16 = 0b1011_0001_1111_1110; // STI R0, #-2
-        vm.op_sti(instr).unwrap();
+        vm.execute(Instruction::decode(instr), &mut true).unwrap();
 
         assert_eq!(vm.memory.read(0x5000).unwrap(), 0x5678);
     }
@@ -76,7 +72,7 @@ mod tests {
         vm.memory.write(0x3000, 0x6000);
 
         let instr: u16 = 0b1011_0000_0000_0000; // STI R0, #0
-        vm.op_sti(instr).unwrap();
+        vm.execute(Instruction::decode(instr), &mut true).unwrap();
 
         assert_eq!(vm.memory.read(0x6000).unwrap(), 0xABCD);
     }
@@ -91,7 +87,7 @@ mod tests {
         vm.memory.write(0x0000, 0x7000);
 
         let instr: u16 = 0b1011_0000_0000_0001; // STI R0, #1
-        vm.op_sti(instr).unwrap();
+        vm.execute(Instruction::decode(instr), &mut true).unwrap();
 
         assert_eq!(vm.memory.read(0x7000).unwrap(), 0x4321);
     }
@@ -107,7 +103,7 @@ mod tests {
         vm.memory.write(0x3002, 0x8000);
 
         let instr: u16 = 0b1011_0000_0000_0010; // STI R0, #2
-        vm.op_sti(instr).unwrap();
+        vm.execute(Instruction::decode(instr), &mut true).unwrap();
 
         assert_eq!(vm.registers.get(1).unwrap(), 0x8888);
     }
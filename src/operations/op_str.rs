@@ -1,4 +1,4 @@
-use crate::{utils::sign_extend, vm::Vm, vm_error::VmError};
+use crate::{vm::Vm, vm_error::VmError};
 
 impl Vm {
     /// Executes the STR operation.
@@ -8,19 +8,18 @@ impl Vm {
     ///
     /// # Parameters
     ///
-    /// - `instr`: A 16-bit instruction.
+    /// - `sr`: The register holding the value to store.
+    /// - `base`: The register holding the base address.
+    /// - `offset`: The offset from the base address.
     ///
     /// # Returns
     ///
     /// Returns `Ok(())` if the operation was successful, otherwise returns a `VmError`.
     ///
-    pub fn op_str(&mut self, instr: u16) -> Result<(), VmError> {
-        let r0 = (instr >> 9) & 0x7;
-        let r1 = (instr >> 6) & 0x7;
-        let offset = sign_extend(instr & 0x3F, 6);
+    pub fn op_str(&mut self, sr: u16, base: u16, offset: i16) -> Result<(), VmError> {
         self.memory.write(
-            (self.registers.get(r1)? as i16 + offset) as u16,
-            self.registers.get(r0)?,
+            self.registers[base].wrapping_add(offset as u16),
+            self.registers[sr],
         );
         Ok(())
     }
@@ -28,13 +27,10 @@ impl Vm {
 
 #[cfg(test)]
 mod tests {
-    use crate::{memory::Memory, registers::Registers, vm::Vm};
+    use crate::{instruction::Instruction, vm::Vm};
 
     fn create_vm() -> Vm {
-        Vm {
-            registers: Registers::new(),
-            memory: Memory::new(),
-        }
+        Vm::new()
     }
 
     #[test]
@@ -45,7 +41,7 @@ mod tests {
         vm.registers.set(1, 0x3000).unwrap();
 
         let instr: u16 = 0b0111_0000_0100_0010; // STR R0, R1, #2
-        vm.op_str(instr).unwrap();
+        vm.execute(Instruction::decode(instr), &mut true).unwrap();
 
         assert_eq!(vm.memory.read(0x3002).unwrap(), 0xABCD);
     }
@@ -58,7 +54,7 @@ mod tests {
         vm.registers.set(1, 0x3004).unwrap();
 
         let instr: u16 = 0b0111_0000_0111_1110; // STR R0, R1, #-2
-        vm.op_str(instr).unwrap();
+        vm.execute(Instruction::decode(instr), &mut true).unwrap();
 
         assert_eq!(vm.memory.read(0x3002).unwrap(), 0x1234);
     }
@@ -71,7 +67,7 @@ mod tests {
         vm.registers.set(1, 0x4000).unwrap();
 
         let instr: u16 = 0b0111_0000_0100_0000; // STR R0, R1, #0
-        vm.op_str(instr).unwrap();
+        vm.execute(Instruction::decode(instr), &mut true).unwrap();
 
         assert_eq!(vm.memory.read(0x4000).unwrap(), 0x5678);
     }
@@ -84,7 +80,7 @@ mod tests {
         vm.registers.set(1, 0x1000).unwrap();
 
         let instr: u16 = 0b0111_0000_0100_1111; // STR R0, R1, #15
-        vm.op_str(instr).unwrap();
+        vm.execute(Instruction::decode(instr), &mut true).unwrap();
 
         assert_eq!(vm.memory.read(0x100F).unwrap(), 0x9ABC);
     }
@@ -97,8 +93,21 @@ mod tests {
         vm.registers.set(1, 0xFFFF).unwrap();
 
         let instr: u16 = 0b0111_0000_0100_0001; // STR R0, R1, #1
-        vm.op_str(instr).unwrap();
+        vm.execute(Instruction::decode(instr), &mut true).unwrap();
 
         assert_eq!(vm.memory.read(0x0000).unwrap(), 0x4321);
     }
+
+    #[test]
+    fn op_str_wraps_below_x8000() {
+        let mut vm = create_vm();
+
+        vm.registers.set(0, 0xBEEF).unwrap();
+        vm.registers.set(1, 0x8000).unwrap();
+
+        let instr: u16 = 0b0111_0000_0111_1111; // STR R0, R1, #-1
+        vm.execute(Instruction::decode(instr), &mut true).unwrap();
+
+        assert_eq!(vm.memory.memory[0x7FFF], 0xBEEF);
+    }
 }
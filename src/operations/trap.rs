@@ -1,8 +1,5 @@
-use std::io::Read;
-
 use crate::{
     constants::{TRAP_GETC, TRAP_IN, TRAP_OUT, TRAP_PUTS, TRAP_PUTSP},
-    utils::flush_stdout,
     vm::Vm,
     vm_error::VmError,
 };
@@ -10,8 +7,8 @@ use crate::{
 impl Vm {
     /// Handles the `GETC` TRAP instruction.
     ///
-    /// This function reads a single character from the standard input (stdin)
-    /// and stores it in the `R0` register. The condition flags are updated
+    /// This function reads a single character from the console, or `0` at the end of its
+    /// input, and stores it in the `R0` register. The condition flags are updated
     /// based on the value of `R0`.
     ///
     /// # Returns
@@ -19,11 +16,7 @@ impl Vm {
     /// Returns `Ok(())` if the operation was successful, otherwise returns a `VmError`.
     ///
     fn trap_getc(&mut self) -> Result<(), VmError> {
-        let mut buffer = [0; 1];
-        std::io::stdin()
-            .read(&mut buffer)
-            .map_err(|e| VmError::FailedToReadStdin(e.to_string()))?;
-        self.registers.r0 = buffer[0] as u16;
+        self.registers[0] = self.memory.console.read_char()?.unwrap_or(0) as u16;
         self.registers.update_flags(0)
     }
 
@@ -36,9 +29,8 @@ impl Vm {
     /// Returns `Ok(())` if the operation was successful, otherwise returns a `VmError`.
     ///
     fn trap_out(&mut self) -> Result<(), VmError> {
-        let ch = char::from((self.registers.r0 & 0xFF) as u8);
-        print!("{}", ch);
-        flush_stdout()
+        let ch = char::from((self.registers[0] & 0xFF) as u8);
+        self.memory.console.write(&ch.to_string())
     }
 
     /// Handles the `PUTS` TRAP instruction.
@@ -51,14 +43,15 @@ impl Vm {
     /// Returns `Ok(())` if the operation was successful, otherwise returns a `VmError`.
     ///
     fn trap_puts(&mut self) -> Result<(), VmError> {
-        let mut i = self.registers.r0;
+        let mut i = self.registers[0];
+        let mut text = String::new();
         let mut c = self.memory.read(i)?;
         while c != 0 {
-            print!("{}", (c as u8) as char);
-            i += 1;
+            text.push((c as u8) as char);
+            i = i.wrapping_add(1);
             c = self.memory.read(i)?;
         }
-        flush_stdout()
+        self.memory.console.write(&text)
     }
 
     /// Handles the `IN` TRAP instruction.
@@ -71,19 +64,12 @@ impl Vm {
     /// Returns `Ok(())` if the operation was successful, otherwise returns a `VmError`.
     ///
     fn trap_in(&mut self) -> Result<(), VmError> {
-        print!("Enter a character: ");
-        flush_stdout()?;
-
-        let mut buffer = [0; 1];
-        std::io::stdin()
-            .read(&mut buffer)
-            .map_err(|e| VmError::FailedToReadStdin(e.to_string()))?;
+        self.memory.console.write("Enter a character: ")?;
 
-        let c = buffer[0] as char;
+        let c = self.memory.console.read_char()?.unwrap_or(0) as char;
 
-        print!("{}", c);
-        flush_stdout()?;
-        self.registers.r0 = c as u16;
+        self.memory.console.write(&c.to_string())?;
+        self.registers[0] = c as u16;
 
         self.registers.update_flags(0)
     }
@@ -98,24 +84,25 @@ impl Vm {
     /// Returns `Ok(())` if the operation was successful, otherwise returns a `VmError`.
     ///
     fn trap_putsp(&mut self) -> Result<(), VmError> {
-        let mut i = self.registers.r0;
+        let mut i = self.registers[0];
+        let mut text = String::new();
         let mut char = self.memory.read(i)?;
         while char != 0 {
             let char1 = (char & 0xFF) as u8 as char;
             if char1 == '\0' {
                 break;
             }
-            print!("{}", char1);
+            text.push(char1);
 
             let char2 = (char >> 8) as u8 as char;
             if char2 != '\0' {
-                print!("{}", char2 as u8 as char);
+                text.push(char2);
             }
             i = i.wrapping_add(1);
             char = self.memory.read(i)?;
         }
 
-        flush_stdout()
+        self.memory.console.write(&text)
     }
 
     /// Handles the `HALT` TRAP instruction.
@@ -131,17 +118,16 @@ impl Vm {
     ///
     /// Returns `Ok(())` if the operation was successful, otherwise returns a `VmError`.
     ///
-    pub fn trap_halt(&self, running: &mut bool) -> Result<(), VmError> {
-        println!("HALT");
+    pub fn trap_halt(&mut self, running: &mut bool) -> Result<(), VmError> {
         *running = false;
-        flush_stdout()
+        self.memory.console.write("HALT\n")
     }
 
-    /// Handles the correct trap routine based on the instruction.
+    /// Handles the correct trap routine based on the trap vector.
     ///
     /// # Parameters
     ///
-    /// - `instr`: The 16-bit instruction containing the TRAP opcode.
+    /// - `vector`: The trap vector of the TRAP instruction.
     /// - `running`: A mutable reference to a boolean flag that indicates if the
     ///   program is running.
     ///
@@ -149,10 +135,9 @@ impl Vm {
     ///
     /// Returns `Ok(())` if the handling was successful, otherwise returns a `VmError`.
     ///
-    pub fn handle_trap(&mut self, instr: u16, running: &mut bool) -> Result<(), VmError> {
-        self.registers.r7 = self.registers.pc;
-        let trap_instr = instr & 0xFF;
-        match trap_instr {
+    pub fn handle_trap(&mut self, vector: u8, running: &mut bool) -> Result<(), VmError> {
+        self.registers[7] = self.registers.pc;
+        match vector as u16 {
             TRAP_GETC => self.trap_getc(),
             TRAP_OUT => self.trap_out(),
             TRAP_PUTS => self.trap_puts(),
@@ -165,63 +150,67 @@ impl Vm {
 
 #[cfg(test)]
 mod tests {
-    use crate::{memory::Memory, registers::Registers};
+    use std::{cell::RefCell, rc::Rc};
 
     use super::*;
-    use std::io::Cursor;
+    use crate::{
+        console::ScriptedConsole,
+        constants::{FL_ZRO, TRAP_HALT},
+    };
 
     fn create_vm() -> Vm {
-        Vm {
-            registers: Registers::new(),
-            memory: Memory::new(),
-        }
+        Vm::new()
     }
 
-    // TRAP GETC
-    fn trap_getc_with_input(registers: &mut Registers, input: &mut dyn std::io::Read) {
-        let mut buffer = [0; 1];
-        registers.r0 = match input.read_exact(&mut buffer) {
-            Ok(_) => buffer[0] as u16,
-            Err(e) => {
-                println!("Error reading from input: {}", e);
-                0
-            }
-        };
-        registers.update_flags(0).unwrap();
+    /// Gives the machine a scripted console typing `input` and returns its output.
+    fn script(vm: &mut Vm, input: &[u8]) -> Rc<RefCell<String>> {
+        let console = ScriptedConsole::new(input);
+        let output = console.output();
+        vm.memory.console = Box::new(console);
+        output
     }
 
+    // TRAP GETC
     #[test]
     fn trap_getc_valid_input() {
         let mut vm = create_vm();
-        let mut input = Cursor::new(vec![b'A']);
+        let output = script(&mut vm, b"A");
 
-        trap_getc_with_input(&mut vm.registers, &mut input);
-        assert_eq!(vm.registers.r0, b'A' as u16);
+        vm.handle_trap(TRAP_GETC as u8, &mut true).unwrap();
+
+        assert_eq!(vm.registers[0], b'A' as u16);
+        assert_eq!(output.borrow().as_str(), "");
     }
 
     #[test]
-    fn trap_getc_invalid_input() {
+    fn trap_getc_end_of_input() {
         let mut vm = create_vm();
-        let mut input = Cursor::new(vec![]);
+        script(&mut vm, b"");
+        vm.registers[0] = 0x1234;
+
+        vm.handle_trap(TRAP_GETC as u8, &mut true).unwrap();
 
-        trap_getc_with_input(&mut vm.registers, &mut input);
-        assert_eq!(vm.registers.r0, 0);
+        assert_eq!(vm.registers[0], 0);
+        assert_eq!(vm.registers.cond, FL_ZRO);
     }
 
     // TRAP OUT
     #[test]
     fn trap_out_prints_a() {
         let mut vm = create_vm();
-        vm.registers.r0 = 'A' as u16;
+        let output = script(&mut vm, b"");
+        vm.registers[0] = 'A' as u16;
 
-        vm.trap_out().unwrap();
-        // prints 'A' in stdout
+        vm.handle_trap(TRAP_OUT as u8, &mut true).unwrap();
+
+        assert_eq!(output.borrow().as_str(), "A");
     }
 
     // TRAP PUTS
     #[test]
     fn trap_puts_prints_string() {
         let mut vm = create_vm();
+        let output = script(&mut vm, b"");
 
         let message = "Hello";
         for (i, &byte) in message.as_bytes().iter().enumerate() {
@@ -229,52 +218,73 @@ mod tests {
         }
         vm.memory.write(message.len() as u16, 0);
 
-        vm.registers.r0 = 0;
-
-        vm.trap_puts().unwrap();
-        // prints 'Hello' in stdout
-    }
-
-    // TRAP IN
-    fn trap_in_with_input(registers: &mut Registers, input: &mut dyn std::io::Read) {
-        print!("Enter a character: ");
-        let mut buffer = [0; 1];
-        let c = match input.read_exact(&mut buffer) {
-            Ok(_) => buffer[0] as char,
-            Err(e) => {
-                println!("Error reading from stdin: {}", e);
-                ' '
-            }
-        };
-        print!("{}", c);
-        flush_stdout().unwrap();
-        registers.set(0, c as u16).unwrap();
+        vm.registers[0] = 0;
 
-        registers.update_flags(0).unwrap();
+        vm.handle_trap(TRAP_PUTS as u8, &mut true).unwrap();
+        assert_eq!(output.borrow().as_str(), "Hello");
     }
 
     // TRAP IN
     #[test]
     fn trap_in() {
         let mut vm = create_vm();
-        let mut input = Cursor::new(vec![b'F']);
+        let output = script(&mut vm, b"F");
 
-        trap_in_with_input(&mut vm.registers, &mut input);
-        assert_eq!(vm.registers.r0, b'F' as u16);
-        // And prints correctly
+        vm.handle_trap(TRAP_IN as u8, &mut true).unwrap();
+
+        assert_eq!(vm.registers[0], b'F' as u16);
+        assert_eq!(output.borrow().as_str(), "Enter a character: F");
     }
 
     // TRAP PUTSP
     #[test]
     fn trap_putsp_prints_ab() {
         let mut vm = create_vm();
+        let output = script(&mut vm, b"");
 
         vm.memory.write(0x3000, 0x4241); // "AB" -> 0x4241
         vm.memory.write(0x3001, 0x0000); // null terminator
 
-        vm.registers.r0 = 0x3000;
+        vm.registers[0] = 0x3000;
+
+        vm.handle_trap(TRAP_PUTSP as u8, &mut true).unwrap();
+        assert_eq!(output.borrow().as_str(), "AB");
+    }
+
+    // TRAP HALT
+    #[test]
+    fn trap_halt_stops_the_program() {
+        let mut vm = create_vm();
+        let output = script(&mut vm, b"");
+        let mut running = true;
+
+        vm.handle_trap(TRAP_HALT as u8, &mut running).unwrap();
+
+        assert!(!running);
+        assert_eq!(output.borrow().as_str(), "HALT\n");
+    }
+
+    #[test]
+    fn trap_saves_the_return_address() {
+        let mut vm = create_vm();
+        script(&mut vm, b"");
+        vm.registers.pc = 0x3001;
+
+        vm.handle_trap(TRAP_OUT as u8, &mut true).unwrap();
+
+        assert_eq!(vm.registers[7], 0x3001);
+    }
+
+    #[test]
+    fn trap_puts_wraps_around_memory() {
+        let mut vm = create_vm();
+        let output = script(&mut vm, b"");
+
+        vm.memory.write(0xFFFF, 'A' as u16);
+        vm.memory.write(0x0000, 0);
+        vm.registers[0] = 0xFFFF;
 
-        vm.trap_putsp().unwrap();
-        // output: "AB"
+        vm.handle_trap(TRAP_PUTS as u8, &mut true).unwrap();
+        assert_eq!(output.borrow().as_str(), "A");
     }
 }
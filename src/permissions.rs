@@ -0,0 +1,292 @@
+use std::fmt;
+
+use crate::{
+    image_format::Section,
+    symbols::{Describe, SymbolTable},
+    validation::find_code,
+};
+
+/// What a region of memory may be used for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Permissions {
+    pub read: bool,
+    pub write: bool,
+    pub execute: bool,
+}
+
+impl Permissions {
+    /// Everything is allowed, as in memory outside every region.
+    pub const ALL: Permissions = Permissions {
+        read: true,
+        write: true,
+        execute: true,
+    };
+    /// Code: it can be read and executed, but not written.
+    pub const CODE: Permissions = Permissions {
+        read: true,
+        write: false,
+        execute: true,
+    };
+    /// Data: it can be read and written, but not executed.
+    pub const DATA: Permissions = Permissions {
+        read: true,
+        write: true,
+        execute: false,
+    };
+
+    /// Parses permissions written like `rwx`, `r-x` or `rw`: the letters that are present
+    /// are allowed. `-` is accepted as a placeholder.
+    pub fn parse(text: &str) -> Option<Permissions> {
+        let mut permissions = Permissions {
+            read: false,
+            write: false,
+            execute: false,
+        };
+        for c in text.chars() {
+            match c.to_ascii_lowercase() {
+                'r' => permissions.read = true,
+                'w' => permissions.write = true,
+                'x' => permissions.execute = true,
+                '-' => {}
+                _ => return None,
+            }
+        }
+        Some(permissions)
+    }
+
+    /// Returns whether an access of the given kind is allowed.
+    pub fn allows(&self, access: Access) -> bool {
+        match access {
+            Access::Read => self.read,
+            Access::Write => self.write,
+            Access::Execute => self.execute,
+        }
+    }
+}
+
+impl fmt::Display for Permissions {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let flag = |allowed: bool, c: char| if allowed { c } else { '-' };
+        write!(
+            f,
+            "{}{}{}",
+            flag(self.read, 'r'),
+            flag(self.write, 'w'),
+            flag(self.execute, 'x')
+        )
+    }
+}
+
+/// A range of memory with its permissions.
+///
+/// # Fields
+///
+/// * `start`, `end` - Inclusive bounds of the region.
+/// * `permissions` - The accesses allowed in the region.
+///
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Region {
+    pub start: u16,
+    pub end: u16,
+    pub permissions: Permissions,
+}
+
+impl Region {
+    /// Creates a region over the inclusive range `start..=end`.
+    pub fn new(start: u16, end: u16, permissions: Permissions) -> Region {
+        Region {
+            start,
+            end,
+            permissions,
+        }
+    }
+
+    /// Returns whether the region contains an address.
+    pub fn contains(&self, address: u16) -> bool {
+        address >= self.start && address <= self.end
+    }
+}
+
+impl fmt::Display for Region {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "x{:04X}-x{:04X} {}",
+            self.start, self.end, self.permissions
+        )
+    }
+}
+
+/// Splits the sections of an image into code regions, which become read-only, and data
+/// regions, which become non-executable.
+///
+/// Code is found by following the control flow like `lc-3-vm check` does, so code that is
+/// only reached through a register (`JSRR`, or `JMP` to a computed address) counts as data.
+///
+/// # Arguments
+///
+/// * `sections` - The sections of the image.
+///
+/// # Returns
+///
+/// One region per run of code or data words, by address.
+///
+pub fn protect_code(sections: &[Section]) -> Vec<Region> {
+    let code = find_code(sections);
+    let mut regions: Vec<Region> = Vec::new();
+    for section in sections {
+        for i in 0..section.words.len() {
+            let Some(address) = u16::try_from(section.origin as usize + i).ok() else {
+                break;
+            };
+            let permissions = match code.contains(&address) {
+                true => Permissions::CODE,
+                false => Permissions::DATA,
+            };
+            match regions.last_mut() {
+                Some(last)
+                    if last.permissions == permissions
+                        && last.end.checked_add(1) == Some(address) =>
+                {
+                    last.end = address
+                }
+                _ => regions.push(Region::new(address, address, permissions)),
+            }
+        }
+    }
+    regions
+}
+
+/// The kind of memory access checked against the permissions.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Access {
+    Read,
+    Write,
+    Execute,
+}
+
+/// An access that the permissions of its region do not allow.
+///
+/// # Fields
+///
+/// * `pc` - Address of the instruction that performed the access. For an `Execute` access,
+///   the instruction that led to the address: the jump, or the one right before it.
+/// * `address` - The accessed address.
+/// * `access` - What was attempted.
+/// * `region` - The region that refused the access.
+///
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AccessViolation {
+    pub pc: u16,
+    pub address: u16,
+    pub access: Access,
+    pub region: Region,
+}
+
+impl Describe for AccessViolation {
+    fn describe(&self, symbols: &SymbolTable) -> String {
+        let (verb, allowed) = match self.access {
+            Access::Read => ("read", "readable"),
+            Access::Write => ("wrote to", "writable"),
+            Access::Execute => ("jumped or fell through to", "executable"),
+        };
+        format!(
+            "Access violation: instruction at {} {} {}, which is not {} (region {})",
+            symbols.format_address(self.pc),
+            verb,
+            symbols.format_address(self.address),
+            allowed,
+            self.region
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        assembler::{assemble, vm_from_source, BACKENDS},
+        vm::Vm,
+        vm_error::VmError,
+    };
+
+    #[test]
+    fn permissions_parse_and_print() {
+        assert_eq!(Permissions::parse("r-x"), Some(Permissions::CODE));
+        assert_eq!(Permissions::parse("RW"), Some(Permissions::DATA));
+        assert_eq!(Permissions::parse("rwq"), None);
+        assert_eq!(Permissions::parse("").unwrap().to_string(), "---");
+    }
+
+    const PROGRAM: &str = "        .ORIG x3000
+        LD R0, COUNT
+        ADD R0, R0, #1
+        ST R0, COUNT
+        LEA R1, DATA
+        JMP R1
+COUNT   .FILL #0
+DATA    .FILL x1021
+        HALT
+        .END
+";
+
+    fn violation(vm: &mut Vm) -> String {
+        match vm.run_until_halt() {
+            Err(VmError::AccessViolation(violation)) => violation.describe(&vm.symbols),
+            other => panic!("expected an access violation, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn code_is_read_only_and_data_is_not_executable() {
+        let assembly = assemble("perms.asm", PROGRAM).unwrap();
+
+        assert_eq!(
+            protect_code(&assembly.sections),
+            vec![
+                Region::new(0x3000, 0x3004, Permissions::CODE),
+                Region::new(0x3005, 0x3007, Permissions::DATA),
+            ]
+        );
+    }
+
+    #[test]
+    fn violations_stop_the_vm() {
+        let regions = protect_code(&assemble("perms.asm", PROGRAM).unwrap().sections);
+        for backend in BACKENDS {
+            let mut vm = vm_from_source("perms.asm", PROGRAM);
+            vm.backend = backend;
+            vm.memory.regions = regions.clone();
+
+            assert_eq!(
+                violation(&mut vm),
+                "Access violation: instruction at x3004 (perms.asm:6) jumped or fell through to \
+                 x3006 <DATA> (perms.asm:8), which is not executable (region x3005-x3007 rw-)"
+            );
+            assert_eq!(vm.memory.memory[0x3005], 1);
+            assert_eq!(vm.registers.pc, 0x3006);
+
+            let mut vm = vm_from_source("perms.asm", PROGRAM);
+            vm.backend = backend;
+            vm.memory.regions = vec![Region::new(0x3005, 0x3005, Permissions::CODE)];
+
+            assert_eq!(
+                violation(&mut vm),
+                "Access violation: instruction at x3002 (perms.asm:4) wrote to \
+                 x3005 <COUNT> (perms.asm:7), which is not writable (region x3005-x3005 r-x)"
+            );
+            assert_eq!(vm.memory.memory[0x3005], 0);
+
+            let mut vm = vm_from_source("perms.asm", PROGRAM);
+            vm.backend = backend;
+            vm.memory.regions = vec![Region::new(0x3005, 0x3005, Permissions::parse("").unwrap())];
+
+            assert_eq!(
+                violation(&mut vm),
+                "Access violation: instruction at x3000 (perms.asm:2) read \
+                 x3005 <COUNT> (perms.asm:7), which is not readable (region x3005-x3005 ---)"
+            );
+            assert_eq!(vm.registers[0], 0);
+        }
+    }
+}
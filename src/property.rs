@@ -0,0 +1,596 @@
+use std::fmt;
+
+use crate::{
+    call::{CallOptions, Routine},
+    rng::Rng,
+    vm::Vm,
+    vm_error::VmError,
+};
+
+/// How many random inputs a property is checked on when it does not say.
+pub const DEFAULT_CASES: u32 = 100;
+
+/// The seed of a property that does not set one, so runs are reproducible.
+pub const DEFAULT_SEED: u64 = 0x4C43_3300;
+
+/// The most shrinking steps tried on a failing input.
+const MAX_SHRINKS: u32 = 1000;
+
+/// How many of the values closest to its target a number is shrunk to, before halving.
+const NEAR: i32 = 4;
+
+/// How a value of a random input is generated, and how it is shrunk.
+///
+/// * `Constant(value)` - Always `value`, such as the address of a buffer. Never shrunk.
+/// * `Range(low, high)` - An unsigned word in `low..=high`, shrunk towards `low`.
+/// * `Signed(low, high)` - A two's complement word in `low..=high`, shrunk towards zero.
+/// * `Text { min, max }` - A string of `min..=max` printable ASCII characters, one per word,
+///   followed by a zero. Shrunk by removing characters and turning them into `a`.
+/// * `Words { min, max, element }` - `min..=max` words made by `element`, a generator of
+///   single words, shrunk by removing words and shrinking each of them.
+///
+/// Bounds given in the wrong order, such as `Range(10, 5)`, are swapped.
+///
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Gen {
+    Constant(u16),
+    Range(u16, u16),
+    Signed(i16, i16),
+    Text {
+        min: usize,
+        max: usize,
+    },
+    Words {
+        min: usize,
+        max: usize,
+        element: Box<Gen>,
+    },
+}
+
+/// A generated input of a routine.
+///
+/// # Fields
+///
+/// * `args` - The arguments, passed in `R0`, `R1` and so on.
+/// * `memory` - The memory blocks written before the call, as `(address, words)`. Text
+///   blocks include their terminating zero.
+///
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Input {
+    pub args: Vec<u16>,
+    pub memory: Vec<(u16, Vec<u16>)>,
+}
+
+impl Input {
+    /// Returns the words of the `index`th memory block.
+    pub fn words(&self, index: usize) -> &[u16] {
+        &self.memory[index].1
+    }
+
+    /// Returns the `index`th memory block as a string, up to its first zero.
+    pub fn text(&self, index: usize) -> String {
+        self.words(index)
+            .iter()
+            .take_while(|&&w| w != 0)
+            .map(|&w| (w as u8) as char)
+            .collect()
+    }
+}
+
+/// What a routine must leave behind for an input, as computed by the reference function.
+/// Registers and memory that are not mentioned are not checked.
+///
+/// # Fields
+///
+/// * `registers` - `(register, value)` pairs.
+/// * `memory` - `(address, words)` pairs.
+///
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Expected {
+    pub registers: Vec<(u16, u16)>,
+    pub memory: Vec<(u16, Vec<u16>)>,
+}
+
+impl Expected {
+    /// Creates an expectation that checks nothing yet.
+    pub fn new() -> Expected {
+        Expected::default()
+    }
+
+    /// Expects register `r` to hold `value`.
+    pub fn register(mut self, r: u16, value: u16) -> Expected {
+        self.registers.push((r, value));
+        self
+    }
+
+    /// Expects memory to hold `words` from `address` on.
+    pub fn memory(mut self, address: u16, words: &[u16]) -> Expected {
+        self.memory.push((address, words.to_vec()));
+        self
+    }
+}
+
+/// A minimal failing input found by `Property::check`.
+///
+/// # Fields
+///
+/// * `routine` - The routine that was checked.
+/// * `seed` - The seed of the run, which replays it.
+/// * `cases` - How many inputs were tried before the first failure, that one included.
+/// * `shrinks` - How many times the failing input was made smaller.
+/// * `input` - The smallest failing input found.
+/// * `failures` - What went wrong on it.
+///
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Counterexample {
+    pub routine: String,
+    pub seed: u64,
+    pub cases: u32,
+    pub shrinks: u32,
+    pub input: Input,
+    pub failures: Vec<String>,
+}
+
+/// A property of a subroutine: on random inputs, it must behave like a Rust reference
+/// function.
+///
+/// ```text
+/// Property::new("MULT")
+///     .arg(Gen::Range(0, 200))
+///     .arg(Gen::Range(0, 200))
+///     .check(load, |input| Expected::new().register(0, input.args[0] * input.args[1]))
+/// ```
+///
+/// # Fields
+///
+/// * `routine` - The label or address of the routine, as for `Vm::call`.
+/// * `args` - The generators of the arguments.
+/// * `memory` - The generators of the memory blocks and their addresses.
+/// * `cases` - How many random inputs to try.
+/// * `seed` - The seed of the random inputs.
+/// * `options` - The options of each call.
+///
+#[derive(Debug, Clone)]
+pub struct Property {
+    pub routine: String,
+    pub args: Vec<Gen>,
+    pub memory: Vec<(u16, Gen)>,
+    pub cases: u32,
+    pub seed: u64,
+    pub options: CallOptions,
+}
+
+impl Property {
+    /// Creates a property of the routine with the given label or address, with no inputs.
+    pub fn new(routine: &str) -> Property {
+        Property {
+            routine: routine.to_string(),
+            args: Vec::new(),
+            memory: Vec::new(),
+            cases: DEFAULT_CASES,
+            seed: DEFAULT_SEED,
+            options: CallOptions::default(),
+        }
+    }
+
+    /// Adds an argument, passed in the next register.
+    pub fn arg(mut self, gen: Gen) -> Property {
+        self.args.push(gen);
+        self
+    }
+
+    /// Adds a memory block written at `address` before each call.
+    pub fn memory(mut self, address: u16, gen: Gen) -> Property {
+        self.memory.push((address, gen));
+        self
+    }
+
+    /// Sets how many random inputs are tried.
+    pub fn cases(mut self, cases: u32) -> Property {
+        self.cases = cases;
+        self
+    }
+
+    /// Sets the seed of the random inputs.
+    pub fn seed(mut self, seed: u64) -> Property {
+        self.seed = seed;
+        self
+    }
+
+    /// Sets the options of each call, such as its stack or its instruction budget.
+    pub fn options(mut self, options: CallOptions) -> Property {
+        self.options = options;
+        self
+    }
+
+    /// Checks the property: calls the routine on random inputs, each time on a new machine,
+    /// and compares it with the reference. The first failing input is shrunk to a minimal one.
+    ///
+    /// # Arguments
+    ///
+    /// * `load` - Creates a machine with the program loaded, such as from an assembled source.
+    /// * `reference` - Computes what the routine must leave behind for an input.
+    ///
+    /// # Returns
+    ///
+    /// How many inputs passed, or the `Counterexample` if one failed.
+    ///
+    pub fn check(
+        &self,
+        mut load: impl FnMut() -> Vm,
+        reference: impl Fn(&Input) -> Expected,
+    ) -> Result<u32, Counterexample> {
+        let gens: Vec<&Gen> = self
+            .args
+            .iter()
+            .chain(self.memory.iter().map(|(_, gen)| gen))
+            .collect();
+        let mut rng = Rng::new(self.seed);
+        for case in 1..=self.cases {
+            let mut draws: Vec<Vec<u16>> = gens.iter().map(|gen| gen.generate(&mut rng)).collect();
+            let mut failures = self.run(&mut load, &reference, &draws);
+            if failures.is_empty() {
+                continue;
+            }
+
+            let mut shrinks = 0;
+            'shrinking: while shrinks < MAX_SHRINKS {
+                for (i, gen) in gens.iter().enumerate() {
+                    for candidate in gen.shrink(&draws[i]) {
+                        let mut smaller = draws.clone();
+                        smaller[i] = candidate;
+                        let smaller_failures = self.run(&mut load, &reference, &smaller);
+                        if !smaller_failures.is_empty() {
+                            draws = smaller;
+                            failures = smaller_failures;
+                            shrinks += 1;
+                            continue 'shrinking;
+                        }
+                    }
+                }
+                break;
+            }
+
+            return Err(Counterexample {
+                routine: self.routine.clone(),
+                seed: self.seed,
+                cases: case,
+                shrinks,
+                input: self.input(&draws),
+                failures,
+            });
+        }
+        Ok(self.cases)
+    }
+
+    /// Turns one draw per generator into an input.
+    fn input(&self, draws: &[Vec<u16>]) -> Input {
+        let (args, blocks) = draws.split_at(self.args.len());
+        Input {
+            args: args.iter().map(|draw| draw[0]).collect(),
+            memory: self
+                .memory
+                .iter()
+                .zip(blocks)
+                .map(|((address, gen), draw)| (*address, gen.materialize(draw)))
+                .collect(),
+        }
+    }
+
+    /// Runs the routine on one input and returns every difference with the reference.
+    fn run(
+        &self,
+        load: &mut impl FnMut() -> Vm,
+        reference: &impl Fn(&Input) -> Expected,
+        draws: &[Vec<u16>],
+    ) -> Vec<String> {
+        let input = self.input(draws);
+        let expected = reference(&input);
+        let mut vm = load();
+        for (address, words) in &input.memory {
+            for (i, word) in words.iter().enumerate() {
+                vm.memory.write(address.wrapping_add(i as u16), *word);
+            }
+        }
+
+        let routine = Routine::Symbol(&self.routine);
+        let result = match vm.call_with(routine, &input.args, &self.options) {
+            Ok(result) => result,
+            Err(VmError::CallFailed(message)) => return vec![message],
+            Err(error) => return vec![format!("{:?}", error)],
+        };
+        let mut failures = Vec::new();
+        for (r, value) in &expected.registers {
+            let actual = result.registers[*r];
+            if actual != *value {
+                failures.push(format!(
+                    "R{}: expected x{:04X}, found x{:04X}",
+                    r, value, actual
+                ));
+            }
+        }
+        for (address, words) in &expected.memory {
+            for (i, value) in words.iter().enumerate() {
+                let address = address.wrapping_add(i as u16);
+                let actual = vm.memory.memory[address as usize];
+                if actual != *value {
+                    failures.push(format!(
+                        "memory x{:04X}: expected x{:04X}, found x{:04X}",
+                        address, value, actual
+                    ));
+                }
+            }
+        }
+        failures
+    }
+}
+
+impl Gen {
+    /// Draws a random value: one word for the scalar generators, the characters or the
+    /// words of the block for the others.
+    fn generate(&self, rng: &mut Rng) -> Vec<u16> {
+        match self {
+            Gen::Constant(value) => vec![*value],
+            Gen::Range(low, high) => vec![rng.range(*low as u32, *high as u32) as u16],
+            Gen::Signed(low, high) => {
+                let (low, high) = ((*low).min(*high) as i32, (*low).max(*high) as i32);
+                let span = rng.range(0, (high - low) as u32);
+                vec![(low + span as i32) as u16]
+            }
+            Gen::Text { min, max } => {
+                let len = rng.range(*min as u32, *max as u32);
+                (0..len).map(|_| rng.range(0x20, 0x7E) as u16).collect()
+            }
+            Gen::Words { min, max, element } => {
+                let len = rng.range(*min as u32, *max as u32);
+                (0..len).flat_map(|_| element.generate(rng)).collect()
+            }
+        }
+    }
+
+    /// Returns smaller variants of a drawn value, the most aggressive first.
+    fn shrink(&self, draw: &[u16]) -> Vec<Vec<u16>> {
+        match self {
+            Gen::Constant(_) => Vec::new(),
+            Gen::Range(low, high) => shrink_towards(draw[0] as i32, *low.min(high) as i32)
+                .into_iter()
+                .map(|v| vec![v as u16])
+                .collect(),
+            Gen::Signed(low, high) => {
+                let target = 0.clamp((*low).min(*high) as i32, (*low).max(*high) as i32);
+                shrink_towards(draw[0] as i16 as i32, target)
+                    .into_iter()
+                    .map(|v| vec![v as u16])
+                    .collect()
+            }
+            Gen::Text { min, max } => shrink_sequence(draw, *min.min(max), |c| {
+                if c == b'a' as u16 {
+                    Vec::new()
+                } else {
+                    vec![b'a' as u16]
+                }
+            }),
+            Gen::Words { min, max, element } => shrink_sequence(draw, *min.min(max), |w| {
+                element.shrink(&[w]).into_iter().flatten().collect()
+            }),
+        }
+    }
+
+    /// Turns a drawn value into the words written to memory.
+    fn materialize(&self, draw: &[u16]) -> Vec<u16> {
+        let mut words = draw.to_vec();
+        if let Gen::Text { .. } = self {
+            words.push(0);
+        }
+        words
+    }
+}
+
+/// Returns values between `value` and `target`: first the `NEAR` values closest to `target`,
+/// then values halving the distance from `value`, ending with the neighbour of `value`.
+fn shrink_towards(value: i32, target: i32) -> Vec<i32> {
+    let distance = value - target;
+    let mut candidates: Vec<i32> = (0..distance.abs().min(NEAR))
+        .map(|k| target + k * distance.signum())
+        .collect();
+    let mut step = distance / 2;
+    while step != 0 {
+        if !candidates.contains(&(value - step)) {
+            candidates.push(value - step);
+        }
+        step /= 2;
+    }
+    candidates
+}
+
+/// Returns shorter sequences (down to `min` elements), then the sequence with one element
+/// replaced by each of its smaller variants given by `shrink_element`.
+fn shrink_sequence(
+    draw: &[u16],
+    min: usize,
+    shrink_element: impl Fn(u16) -> Vec<u16>,
+) -> Vec<Vec<u16>> {
+    let mut candidates = Vec::new();
+    if draw.len() > min {
+        candidates.push(draw[..min].to_vec());
+        let half = (draw.len() + min) / 2;
+        if half > min && half < draw.len() {
+            candidates.push(draw[..half].to_vec());
+        }
+        for i in 0..draw.len() {
+            let mut shorter = draw.to_vec();
+            shorter.remove(i);
+            candidates.push(shorter);
+        }
+    }
+    for (i, &word) in draw.iter().enumerate() {
+        for smaller in shrink_element(word) {
+            let mut candidate = draw.to_vec();
+            candidate[i] = smaller;
+            candidates.push(candidate);
+        }
+    }
+    candidates
+}
+
+impl fmt::Display for Input {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut parts: Vec<String> = self
+            .args
+            .iter()
+            .enumerate()
+            .map(|(r, value)| format!("R{}=x{:04X}", r, value))
+            .collect();
+        for (address, words) in &self.memory {
+            let words: Vec<String> = words.iter().map(|w| format!("x{:04X}", w)).collect();
+            parts.push(format!("x{:04X}: [{}]", address, words.join(", ")));
+        }
+        write!(f, "{}", parts.join(" "))
+    }
+}
+
+impl fmt::Display for Counterexample {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "Property of {} failed after {} case(s) (seed {}), shrunk {} time(s):",
+            self.routine, self.cases, self.seed, self.shrinks
+        )?;
+        writeln!(f, "  input: {}", self.input)?;
+        for failure in &self.failures {
+            writeln!(f, "  {}", failure)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::call::fixtures::routines as load;
+
+    #[test]
+    fn a_correct_routine_passes_every_case() {
+        let passed = Property::new("MULT")
+            .arg(Gen::Range(0, 300))
+            .arg(Gen::Range(0, 300))
+            .options(CallOptions {
+                stack: Some(0x4000),
+                ..CallOptions::default()
+            })
+            .cases(50)
+            .check(load, |input| {
+                Expected::new().register(0, input.args[0].wrapping_mul(input.args[1]))
+            });
+
+        assert_eq!(passed, Ok(50));
+    }
+
+    #[test]
+    fn a_failing_string_is_shrunk_to_a_minimal_one() {
+        let counterexample = Property::new("STRLEN")
+            .arg(Gen::Constant(0x4000))
+            .memory(0x4000, Gen::Text { min: 0, max: 20 })
+            .check(load, |input| {
+                Expected::new().register(0, input.text(0).len() as u16)
+            })
+            .unwrap_err();
+
+        assert_eq!(counterexample.input.args, vec![0x4000]);
+        assert_eq!(counterexample.input.memory, vec![(0x4000, vec![0x20, 0])]);
+        assert_eq!(
+            counterexample.failures,
+            vec!["R0: expected x0001, found x0000"]
+        );
+    }
+
+    #[test]
+    fn a_failing_number_is_shrunk_towards_zero() {
+        let counterexample = Property::new("ABS")
+            .arg(Gen::Signed(-1000, 1000))
+            .check(load, |input| {
+                Expected::new().register(0, (input.args[0] as i16).unsigned_abs())
+            })
+            .unwrap_err();
+
+        assert_eq!(counterexample.input.args, vec![(-2i16) as u16]);
+        assert_eq!(
+            counterexample.to_string(),
+            format!(
+                "Property of ABS failed after {} case(s) (seed {}), shrunk {} time(s):\n  \
+                 input: R0=xFFFE\n  R0: expected x0002, found xFFFE\n",
+                counterexample.cases, DEFAULT_SEED, counterexample.shrinks
+            )
+        );
+    }
+
+    #[test]
+    fn call_failures_are_counterexamples() {
+        let counterexample = Property::new("MULT")
+            .arg(Gen::Range(0, 10))
+            .arg(Gen::Range(100, 200))
+            .options(CallOptions {
+                stack: Some(0x4000),
+                max_steps: 50,
+                ..CallOptions::default()
+            })
+            .check(load, |_| Expected::new())
+            .unwrap_err();
+
+        assert_eq!(counterexample.input.args, vec![0, 100]);
+        assert!(counterexample.failures[0].contains("did not return within 50 instructions"));
+    }
+
+    #[test]
+    fn runs_are_reproducible_from_the_seed() {
+        let run = |seed| {
+            Property::new("STRLEN")
+                .arg(Gen::Constant(0x4000))
+                .memory(0x4000, Gen::Text { min: 5, max: 20 })
+                .seed(seed)
+                .check(load, |input| {
+                    Expected::new().register(0, input.text(0).len() as u16)
+                })
+                .unwrap_err()
+                .cases
+        };
+
+        assert_eq!(run(1), run(1));
+    }
+
+    #[test]
+    fn words_shrink_by_removing_and_shrinking_elements() {
+        let gen = Gen::Words {
+            min: 1,
+            max: 4,
+            element: Box::new(Gen::Range(0, 100)),
+        };
+
+        let candidates = gen.shrink(&[7, 3]);
+
+        assert_eq!(candidates[0], vec![7]);
+        assert!(candidates.contains(&vec![3]));
+        assert!(candidates.contains(&vec![0, 3]));
+        assert!(candidates.contains(&vec![7, 0]));
+        assert_eq!(shrink_towards(10, 0), vec![0, 1, 2, 3, 5, 8, 9]);
+        assert_eq!(shrink_towards(-3, 0), vec![0, -1, -2]);
+        assert_eq!(shrink_towards(5, 5), Vec::<i32>::new());
+    }
+
+    #[test]
+    fn reversed_bounds_are_swapped() {
+        let mut rng = Rng::new(DEFAULT_SEED);
+        for _ in 0..100 {
+            let word = Gen::Range(10, 5).generate(&mut rng)[0];
+            assert!((5..=10).contains(&word));
+            let number = Gen::Signed(5, -5).generate(&mut rng)[0] as i16;
+            assert!((-5..=5).contains(&number));
+            let text = Gen::Text { min: 3, max: 1 }.generate(&mut rng);
+            assert!((1..=3).contains(&text.len()));
+        }
+
+        assert_eq!(Gen::Range(10, 5).shrink(&[7])[0], vec![5]);
+        assert_eq!(Gen::Signed(5, -5).shrink(&[3])[0], vec![0]);
+        assert_eq!(Gen::Text { min: 3, max: 1 }.shrink(&[97, 97])[0], vec![97]);
+    }
+}
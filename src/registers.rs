@@ -1,3 +1,5 @@
+use std::ops::{Index, IndexMut};
+
 use crate::{
     constants::{FL_NEG, FL_POS, FL_ZRO, PC_START},
     vm_error::VmError,
@@ -7,20 +9,13 @@ use crate::{
 ///
 /// # Fields
 ///
-/// * `r0`, `r1`, `r2`, `r3`, `r4`, `r5`, `r6`, `r7` - General-purpose registers.
+/// * `gpr` - The general-purpose registers `R0` to `R7`, also reachable as `registers[n]`.
 /// * `pc` - The program counter, which holds the address of the next instruction to execute.
 /// * `cond` - The condition register, which holds flags indicating the result of the last operation.
 ///
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct Registers {
-    pub r0: u16,
-    pub r1: u16,
-    pub r2: u16,
-    pub r3: u16,
-    pub r4: u16,
-    pub r5: u16,
-    pub r6: u16,
-    pub r7: u16,
+    pub gpr: [u16; 8],
     pub pc: u16,
     pub cond: u16,
 }
@@ -40,14 +35,7 @@ impl Registers {
     ///
     pub fn new() -> Self {
         Registers {
-            r0: 0,
-            r1: 0,
-            r2: 0,
-            r3: 0,
-            r4: 0,
-            r5: 0,
-            r6: 0,
-            r7: 0,
+            gpr: [0; 8],
             pc: PC_START,
             cond: FL_ZRO,
         }
@@ -65,14 +53,7 @@ impl Registers {
     ///
     pub fn get(&self, r: u16) -> Result<u16, VmError> {
         let res = match r {
-            0 => self.r0,
-            1 => self.r1,
-            2 => self.r2,
-            3 => self.r3,
-            4 => self.r4,
-            5 => self.r5,
-            6 => self.r6,
-            7 => self.r7,
+            0..=7 => self.gpr[r as usize],
             8 => self.pc,
             9 => self.cond,
             _ => {
@@ -97,14 +78,7 @@ impl Registers {
     ///
     pub fn set(&mut self, r: u16, val: u16) -> Result<(), VmError> {
         match r {
-            0 => self.r0 = val,
-            1 => self.r1 = val,
-            2 => self.r2 = val,
-            3 => self.r3 = val,
-            4 => self.r4 = val,
-            5 => self.r5 = val,
-            6 => self.r6 = val,
-            7 => self.r7 = val,
+            0..=7 => self.gpr[r as usize] = val,
             8 => self.pc = val,
             9 => self.cond = val,
             _ => {
@@ -124,15 +98,42 @@ impl Registers {
     ///
     pub fn update_flags(&mut self, r: u16) -> Result<(), VmError> {
         let r_value = self.get(r)?;
+        self.set_flags(r_value);
+        Ok(())
+    }
 
-        if r_value == 0 {
-            self.cond = FL_ZRO;
-        } else if (r_value >> 15) & 1 == 1 {
-            self.cond = FL_NEG;
+    /// Sets the condition flags from the value just written to a register.
+    ///
+    /// # Arguments
+    ///
+    /// * `value` - The value the flags describe.
+    ///
+    pub fn set_flags(&mut self, value: u16) {
+        self.cond = if value == 0 {
+            FL_ZRO
+        } else if (value >> 15) & 1 == 1 {
+            FL_NEG
         } else {
-            self.cond = FL_POS;
-        }
+            FL_POS
+        };
+    }
+}
 
-        Ok(())
+/// Gives direct access to the general-purpose registers, without the checks of `get` and `set`.
+///
+/// Register numbers come from decoded instructions, so they are always between 0 and 7;
+/// indexing with a larger number panics.
+///
+impl Index<u16> for Registers {
+    type Output = u16;
+
+    fn index(&self, r: u16) -> &u16 {
+        &self.gpr[r as usize]
+    }
+}
+
+impl IndexMut<u16> for Registers {
+    fn index_mut(&mut self, r: u16) -> &mut u16 {
+        &mut self.gpr[r as usize]
     }
 }
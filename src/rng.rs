@@ -0,0 +1,73 @@
+/// A small, seedable pseudo-random number generator (xorshift64*).
+///
+/// Runs are reproducible: the same seed always gives the same numbers, so a failing random
+/// test can be replayed from its seed.
+///
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Rng {
+    state: u64,
+}
+
+impl Rng {
+    /// Creates a generator from a seed. Every seed is valid, including zero.
+    pub fn new(seed: u64) -> Rng {
+        // Spread the seed (splitmix64) so nearby seeds give unrelated sequences, and so the
+        // state is never zero, which xorshift cannot leave.
+        let mut z = seed.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        Rng {
+            state: (z ^ (z >> 31)) | 1,
+        }
+    }
+
+    /// Returns the next 64 random bits.
+    pub fn next_u64(&mut self) -> u64 {
+        self.state ^= self.state >> 12;
+        self.state ^= self.state << 25;
+        self.state ^= self.state >> 27;
+        self.state.wrapping_mul(0x2545_F491_4F6C_DD1D)
+    }
+
+    /// Returns a random word.
+    pub fn next_u16(&mut self) -> u16 {
+        (self.next_u64() >> 48) as u16
+    }
+
+    /// Returns a random number between `low` and `high` inclusive, which may be given in
+    /// either order.
+    pub fn range(&mut self, low: u32, high: u32) -> u32 {
+        let (low, high) = (low.min(high), low.max(high));
+        let span = (high - low) as u64 + 1;
+        low + (self.next_u64() % span) as u32
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn same_seed_same_numbers() {
+        let mut a = Rng::new(7);
+        let mut b = Rng::new(7);
+        let mut c = Rng::new(8);
+
+        let first: Vec<u64> = (0..4).map(|_| a.next_u64()).collect();
+        assert_eq!(first, (0..4).map(|_| b.next_u64()).collect::<Vec<_>>());
+        assert_ne!(first, (0..4).map(|_| c.next_u64()).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn range_stays_in_bounds_and_covers_it() {
+        let mut rng = Rng::new(0);
+        let mut seen = [false; 5];
+        for _ in 0..200 {
+            let n = rng.range(3, 7);
+            assert!((3..=7).contains(&n));
+            seen[(n - 3) as usize] = true;
+        }
+        assert!(seen.iter().all(|&s| s));
+        assert!((3..=7).contains(&rng.range(7, 3)));
+    }
+}
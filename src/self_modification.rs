@@ -0,0 +1,179 @@
+use std::collections::BTreeMap;
+use std::fmt::Write;
+
+use crate::{constants::MEMORY_SIZE, instruction::Instruction, symbols::SymbolTable};
+
+/// Writes of one instruction to one address that had already been executed.
+///
+/// # Fields
+///
+/// * `address` - The overwritten instruction.
+/// * `writer` - Address of the instruction that wrote it.
+/// * `old` - The instruction before the first of these writes.
+/// * `new` - The value written last.
+/// * `count` - How many times `writer` changed the word.
+///
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CodeWrite {
+    pub address: u16,
+    pub writer: u16,
+    pub old: u16,
+    pub new: u16,
+    pub count: u64,
+}
+
+/// Detects self-modifying code: writes to addresses that were fetched as instructions.
+///
+/// Writes that store the value a cell already holds are not modifications. The writes of
+/// an instruction to the same address are merged into a single `CodeWrite`.
+///
+#[derive(Debug, Clone)]
+pub struct SelfModification {
+    executed: Vec<bool>,
+    writes: BTreeMap<(u16, u16), CodeWrite>,
+}
+
+impl Default for SelfModification {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl SelfModification {
+    /// Creates a tracker that has seen no instruction yet.
+    pub fn new() -> SelfModification {
+        SelfModification {
+            executed: vec![false; MEMORY_SIZE],
+            writes: BTreeMap::new(),
+        }
+    }
+
+    /// Records that the word at `address` was fetched as an instruction.
+    pub fn record_fetch(&mut self, address: u16) {
+        self.executed[address as usize] = true;
+    }
+
+    /// Records a write, which is a modification if `address` was executed before.
+    ///
+    /// # Arguments
+    ///
+    /// * `writer` - The address of the instruction performing the write.
+    /// * `address` - The written address.
+    /// * `old`, `new` - The word before and after the write.
+    ///
+    pub fn record_write(&mut self, writer: u16, address: u16, old: u16, new: u16) {
+        if !self.executed[address as usize] || old == new {
+            return;
+        }
+        let write = self.writes.entry((address, writer)).or_insert(CodeWrite {
+            address,
+            writer,
+            old,
+            new,
+            count: 0,
+        });
+        write.new = new;
+        write.count += 1;
+    }
+
+    /// Returns the modifications, by overwritten address and then by writer.
+    pub fn writes(&self) -> Vec<CodeWrite> {
+        self.writes.values().copied().collect()
+    }
+
+    /// Builds a report listing every modified instruction with its old and new decoding and
+    /// the instruction that wrote it.
+    ///
+    /// # Arguments
+    ///
+    /// * `symbols` - Symbols and source lines used to show the addresses.
+    ///
+    /// # Returns
+    ///
+    /// The report, or an empty `String` if no instruction was modified.
+    ///
+    pub fn report(&self, symbols: &SymbolTable) -> String {
+        let mut out = String::new();
+        if self.writes.is_empty() {
+            return out;
+        }
+        let _ = writeln!(
+            out,
+            "Self-modifying code: {} write(s) to instructions that had run",
+            self.writes.len()
+        );
+        for write in self.writes.values() {
+            let _ = writeln!(
+                out,
+                "  {}: x{:04X} {} -> x{:04X} {}, by {} ({} time(s))",
+                symbols.format_address(write.address),
+                write.old,
+                Instruction::decode(write.old),
+                write.new,
+                Instruction::decode(write.new),
+                symbols.format_address(write.writer),
+                write.count
+            );
+        }
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assembler::{vm_from_source, BACKENDS};
+
+    const PROGRAM: &str = "        .ORIG x3000
+        ADD R2, R2, #4
+PATCH   ADD R1, R1, #1
+        LD R3, NEWINSTR
+        ST R3, PATCH
+        ADD R2, R2, #-1
+        BRp PATCH
+        HALT
+NEWINSTR ADD R1, R1, #2
+        .END
+";
+
+    #[test]
+    fn writes_to_executed_instructions_are_reported() {
+        for backend in BACKENDS {
+            let mut vm = vm_from_source("smc.asm", PROGRAM);
+            vm.memory.self_modification = Some(SelfModification::new());
+            vm.backend = backend;
+
+            vm.run_until_halt().unwrap();
+
+            let tracker = vm.memory.self_modification.as_ref().unwrap();
+            assert_eq!(
+                tracker.writes(),
+                vec![CodeWrite {
+                    address: 0x3001,
+                    writer: 0x3003,
+                    old: 0x1261,
+                    new: 0x1262,
+                    count: 1,
+                }]
+            );
+            assert_eq!(
+                tracker.report(&vm.symbols),
+                "Self-modifying code: 1 write(s) to instructions that had run\n  \
+                 x3001 <PATCH> (smc.asm:3): x1261 ADD R1, R1, #1 -> x1262 ADD R1, R1, #2, \
+                 by x3003 <PATCH+2> (smc.asm:5) (1 time(s))\n"
+            );
+        }
+    }
+
+    #[test]
+    fn unchanged_words_and_code_that_never_ran_are_not_modifications() {
+        let mut tracker = SelfModification::new();
+        tracker.record_fetch(0x3000);
+
+        tracker.record_write(0x3005, 0x3000, 0x1234, 0x1234);
+        tracker.record_write(0x3005, 0x3001, 0x1234, 0x5678);
+
+        assert!(tracker.writes().is_empty());
+        assert_eq!(tracker.report(&SymbolTable::new()), "");
+    }
+}
@@ -0,0 +1,355 @@
+use std::collections::BTreeSet;
+
+use crate::{
+    constants::{
+        DEVICE_PAGE_START, MEMORY_SIZE, TRAP_GETC, TRAP_IN, TRAP_OUT, TRAP_PUTS, TRAP_PUTSP,
+    },
+    instruction::{Instruction, Operand},
+    load_map::LoadedImage,
+    symbols::{Describe, SymbolTable},
+    vm::Vm,
+};
+
+/// A register or a memory cell.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Location {
+    Register(u16),
+    Memory(u16),
+}
+
+impl Describe for Location {
+    fn describe(&self, symbols: &SymbolTable) -> String {
+        match self {
+            Location::Register(r) => format!("R{}", r),
+            Location::Memory(address) => symbols.format_address(*address),
+        }
+    }
+}
+
+/// A read of a register or memory cell that was never written.
+///
+/// # Fields
+///
+/// * `pc` - Address of the instruction that performed the read.
+/// * `word` - The instruction, as it was when it was executed.
+/// * `location` - The register or memory cell that was read.
+///
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct UninitializedRead {
+    pub pc: u16,
+    pub word: u16,
+    pub location: Location,
+}
+
+impl Describe for UninitializedRead {
+    fn describe(&self, symbols: &SymbolTable) -> String {
+        format!(
+            "Uninitialized read of {} at {}: {}",
+            self.location.describe(symbols),
+            symbols.format_address(self.pc),
+            Instruction::decode(self.word)
+        )
+    }
+}
+
+/// Shadow state that tracks which registers and memory cells hold a value.
+///
+/// Registers start uninitialized, and so does memory, except for the device page. A memory
+/// cell is initialized when an image or section is loaded over it while the shadow is
+/// enabled, or when an instruction stores to it; a register when an instruction writes it.
+/// Every read of uninitialized state by an instruction is recorded, once per instruction
+/// address and location.
+///
+/// # Fields
+///
+/// * `reads` - The uninitialized reads found so far, in the order they happened.
+///
+#[derive(Debug, Clone)]
+pub struct Shadow {
+    registers: [bool; 8],
+    memory: Vec<bool>,
+    reported: BTreeSet<(u16, Location)>,
+    pub reads: Vec<UninitializedRead>,
+}
+
+impl Default for Shadow {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Shadow {
+    /// Creates a shadow where nothing but the device page is initialized.
+    pub fn new() -> Shadow {
+        let mut memory = vec![false; MEMORY_SIZE];
+        memory[DEVICE_PAGE_START as usize..].fill(true);
+        Shadow {
+            registers: [false; 8],
+            memory,
+            reported: BTreeSet::new(),
+            reads: Vec::new(),
+        }
+    }
+
+    /// Creates a shadow where the given images are already loaded.
+    ///
+    /// # Arguments
+    ///
+    /// * `images` - The images loaded before the shadow was enabled, such as the load map.
+    ///
+    pub fn with_images(images: &[LoadedImage]) -> Shadow {
+        let mut shadow = Shadow::new();
+        for address in images.iter().flat_map(LoadedImage::addresses) {
+            shadow.initialize(Location::Memory(address));
+        }
+        shadow
+    }
+
+    /// Marks a register or memory cell as holding a value.
+    pub fn initialize(&mut self, location: Location) {
+        match location {
+            Location::Register(r) => self.registers[r as usize] = true,
+            Location::Memory(address) => self.memory[address as usize] = true,
+        }
+    }
+
+    /// Returns whether a register or memory cell holds a value.
+    pub fn is_initialized(&self, location: Location) -> bool {
+        match location {
+            Location::Register(r) => self.registers[r as usize],
+            Location::Memory(address) => self.memory[address as usize],
+        }
+    }
+
+    /// Records a read, if the location is uninitialized and was not reported for this `pc`.
+    fn read(&mut self, pc: u16, word: u16, location: Location) {
+        if !self.is_initialized(location) && self.reported.insert((pc, location)) {
+            self.reads.push(UninitializedRead { pc, word, location });
+        }
+    }
+}
+
+impl Vm {
+    /// Checks the reads of an instruction against the shadow state and records its writes.
+    ///
+    /// Called before the instruction is executed, so addresses are computed from the
+    /// registers and memory it will see. Does nothing when the shadow is disabled.
+    ///
+    /// # Parameters
+    ///
+    /// - `pc`: The address of the instruction.
+    /// - `instruction`: The instruction about to be executed.
+    ///
+    pub(crate) fn check_shadow(&mut self, pc: u16, instruction: Instruction) {
+        let Some(shadow) = self.shadow.as_mut() else {
+            return;
+        };
+        let r = |n: u16| self.registers[n];
+        let m = |address: u16| self.memory.memory[address as usize];
+        let relative = |offset: i16| pc.wrapping_add(1).wrapping_add(offset as u16);
+        let indexed = |base: u16, offset: i16| r(base).wrapping_add(offset as u16);
+
+        let mut reads = Vec::new();
+        let mut writes = Vec::new();
+        match instruction {
+            Instruction::And {
+                src2: Operand::Immediate(0),
+                dr,
+                ..
+            } => writes.push(Location::Register(dr)),
+            Instruction::Add { dr, sr1, src2, .. } | Instruction::And { dr, sr1, src2, .. } => {
+                reads.push(Location::Register(sr1));
+                if let Operand::Register(sr2) = src2 {
+                    reads.push(Location::Register(sr2));
+                }
+                writes.push(Location::Register(dr));
+            }
+            Instruction::Not { dr, sr, .. } => {
+                reads.push(Location::Register(sr));
+                writes.push(Location::Register(dr));
+            }
+            Instruction::Jmp { base, .. } => reads.push(Location::Register(base)),
+            Instruction::Jsr { .. } => writes.push(Location::Register(7)),
+            Instruction::Jsrr { base, .. } => {
+                reads.push(Location::Register(base));
+                writes.push(Location::Register(7));
+            }
+            Instruction::Ld { dr, offset } => {
+                reads.push(Location::Memory(relative(offset)));
+                writes.push(Location::Register(dr));
+            }
+            Instruction::Ldi { dr, offset } => {
+                reads.push(Location::Memory(relative(offset)));
+                reads.push(Location::Memory(m(relative(offset))));
+                writes.push(Location::Register(dr));
+            }
+            Instruction::Ldr { dr, base, offset } => {
+                reads.push(Location::Register(base));
+                reads.push(Location::Memory(indexed(base, offset)));
+                writes.push(Location::Register(dr));
+            }
+            Instruction::Lea { dr, .. } => writes.push(Location::Register(dr)),
+            Instruction::St { sr, offset } => {
+                reads.push(Location::Register(sr));
+                writes.push(Location::Memory(relative(offset)));
+            }
+            Instruction::Sti { sr, offset } => {
+                reads.push(Location::Register(sr));
+                reads.push(Location::Memory(relative(offset)));
+                writes.push(Location::Memory(m(relative(offset))));
+            }
+            Instruction::Str { sr, base, offset } => {
+                reads.push(Location::Register(sr));
+                reads.push(Location::Register(base));
+                writes.push(Location::Memory(indexed(base, offset)));
+            }
+            Instruction::Trap { vector, .. } => {
+                // Every trap saves the return address in R7.
+                writes.push(Location::Register(7));
+                match vector as u16 {
+                    TRAP_GETC | TRAP_IN => writes.push(Location::Register(0)),
+                    TRAP_OUT => reads.push(Location::Register(0)),
+                    TRAP_PUTS | TRAP_PUTSP => {
+                        reads.push(Location::Register(0));
+                        // The string ends at its terminator, or at its first uninitialized
+                        // cell, which is the only one reported.
+                        let mut address = r(0);
+                        loop {
+                            reads.push(Location::Memory(address));
+                            let word = m(address);
+                            let ended =
+                                word == 0 || (vector as u16 == TRAP_PUTSP && word & 0xFF == 0);
+                            if ended || !shadow.is_initialized(Location::Memory(address)) {
+                                break;
+                            }
+                            address = address.wrapping_add(1);
+                        }
+                    }
+                    _ => {}
+                }
+            }
+            Instruction::Br { .. } | Instruction::Rti | Instruction::Illegal(_) => {}
+        }
+
+        let word = instruction.encode();
+        for location in reads {
+            shadow.read(pc, word, location);
+        }
+        for location in writes {
+            shadow.initialize(location);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assembler::{vm_from_source, BACKENDS};
+
+    fn create_vm(source: &str) -> Vm {
+        let mut vm = vm_from_source("shadow.asm", source);
+        vm.shadow = Some(Shadow::with_images(&vm.load_map.images));
+        vm
+    }
+
+    fn reads(vm: &mut Vm) -> Vec<String> {
+        vm.run_until_halt().unwrap();
+        let shadow = vm.shadow.as_ref().unwrap();
+        shadow
+            .reads
+            .iter()
+            .map(|read| read.describe(&vm.symbols))
+            .collect()
+    }
+
+    #[test]
+    fn reports_uninitialized_registers_once_per_instruction() {
+        for backend in BACKENDS {
+            let mut vm = create_vm(
+                "        .ORIG x3000
+        AND R0, R0, #0
+        ADD R2, R2, #2
+LOOP    ADD R0, R0, R1
+        ADD R2, R2, #-1
+        BRp LOOP
+        HALT
+        .END
+",
+            );
+            vm.backend = backend;
+
+            assert_eq!(
+                reads(&mut vm),
+                vec![
+                    "Uninitialized read of R2 at x3001 (shadow.asm:3): ADD R2, R2, #2",
+                    "Uninitialized read of R1 at x3002 <LOOP> (shadow.asm:4): ADD R0, R0, R1",
+                ]
+            );
+        }
+    }
+
+    #[test]
+    fn reports_uninitialized_memory() {
+        let mut vm = create_vm(
+            "        .ORIG x3000
+        LD R0, VALUE
+        LD R1, POINTER
+        LDR R2, R1, #0
+        STR R0, R1, #1
+        LDR R3, R1, #1
+        LDI R4, POINTER
+        ADD R0, R1, #1
+        PUTS
+        HALT
+VALUE   .FILL #5
+POINTER .FILL x4000
+        .END
+",
+        );
+
+        assert_eq!(
+            reads(&mut vm),
+            vec![
+                "Uninitialized read of x4000 at x3002 (shadow.asm:4): LDR R2, R1, #0",
+                "Uninitialized read of x4000 at x3005 (shadow.asm:7): LDI R4, #4",
+                "Uninitialized read of x4002 at x3007 (shadow.asm:9): PUTS",
+            ]
+        );
+    }
+
+    #[test]
+    fn traps_initialize_r7() {
+        for backend in BACKENDS {
+            let mut vm = create_vm(
+                "        .ORIG x3000
+        AND R0, R0, #0
+        OUT
+        ADD R1, R7, #0
+        ST R7, SAVE
+        HALT
+SAVE    .BLKW 1
+        .END
+",
+            );
+            vm.backend = backend;
+
+            assert!(reads(&mut vm).is_empty());
+        }
+    }
+
+    #[test]
+    fn images_loaded_before_the_shadow_count_as_initialized() {
+        let image = LoadedImage {
+            path: "test.obj".to_string(),
+            origin: 0x3000,
+            length: 2,
+        };
+
+        let shadow = Shadow::with_images(&[image]);
+
+        assert!(shadow.is_initialized(Location::Memory(0x3001)));
+        assert!(!shadow.is_initialized(Location::Memory(0x3002)));
+        assert!(shadow.is_initialized(Location::Memory(DEVICE_PAGE_START)));
+        assert!(!shadow.is_initialized(Location::Register(0)));
+    }
+}
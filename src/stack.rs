@@ -0,0 +1,285 @@
+use std::collections::BTreeSet;
+use std::fmt;
+
+use crate::{
+    instruction::{Instruction, Operand},
+    symbols::SymbolTable,
+    vm::Vm,
+};
+
+/// The register that holds the stack pointer by convention.
+pub const STACK_POINTER: u16 = 6;
+
+/// Which bound of the stack was crossed.
+///
+/// * `Overflow` - Below the lowest cell of the stack: it grew too deep.
+/// * `Underflow` - Above the highest cell: more was popped than pushed.
+///
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum StackError {
+    Overflow,
+    Underflow,
+}
+
+/// An instruction that moved `R6` or accessed memory through it outside the stack.
+///
+/// # Fields
+///
+/// * `pc` - Address of the instruction.
+/// * `word` - The instruction: an `ADD` that moved `R6`, or an `LDR` or `STR` based on it.
+/// * `error` - Which bound was crossed.
+/// * `address` - The new value of `R6`, or the address that was accessed.
+/// * `depth` - How many words deep `address` is in the stack; negative past an underflow.
+///
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StackViolation {
+    pub pc: u16,
+    pub word: u16,
+    pub error: StackError,
+    pub address: u16,
+    pub depth: i32,
+}
+
+impl StackViolation {
+    /// Describes the violation, with addresses formatted by `symbols` and the depth shown
+    /// against a stack of `size` words.
+    pub fn describe(&self, symbols: &SymbolTable, size: u32) -> String {
+        let instruction = Instruction::decode(self.word);
+        let access = match instruction {
+            Instruction::Ldr { .. } => "reads",
+            Instruction::Str { .. } => "writes",
+            _ => "moves R6 to",
+        };
+        let error = match self.error {
+            StackError::Overflow => "Stack overflow",
+            StackError::Underflow => "Stack underflow",
+        };
+        format!(
+            "{} at {}: {} {} x{:04X}, {} words deep in a {}-word stack",
+            error,
+            symbols.format_address(self.pc),
+            instruction,
+            access,
+            self.address,
+            self.depth,
+            size
+        )
+    }
+}
+
+/// Watches an `R6`-based stack that grows down through a region of memory.
+///
+/// The stack uses the cells `low` to `high`, so `R6` is `high + 1` when it is empty. Every
+/// `ADD R6, R6, ..` that moves `R6` past either bound and every `LDR` or `STR` based on `R6`
+/// that accesses a cell outside the region is recorded, once per instruction address and
+/// bound. Instructions that load `R6`, such as `LD R6, STACK`, are not checked, so the
+/// program can set up its stack pointer.
+///
+/// # Fields
+///
+/// * `low`, `high` - Inclusive bounds of the stack region.
+/// * `violations` - The violations found so far, in the order they happened.
+/// * `high_water` - The deepest the stack has been, in words.
+///
+#[derive(Debug, Clone)]
+pub struct StackMonitor {
+    pub low: u16,
+    pub high: u16,
+    pub violations: Vec<StackViolation>,
+    pub high_water: u32,
+    reported: BTreeSet<(u16, StackError)>,
+}
+
+impl StackMonitor {
+    /// Creates a monitor for a stack in the inclusive range `low..=high`.
+    pub fn new(low: u16, high: u16) -> StackMonitor {
+        StackMonitor {
+            low,
+            high,
+            violations: Vec::new(),
+            high_water: 0,
+            reported: BTreeSet::new(),
+        }
+    }
+
+    /// Returns how many words the stack can hold.
+    pub fn size(&self) -> u32 {
+        self.high as u32 + 1 - self.low as u32
+    }
+
+    /// Returns how many words deep an address is: 0 for the empty stack pointer, `size` for
+    /// the lowest cell.
+    pub fn depth(&self, address: u16) -> i32 {
+        self.high as i32 + 1 - address as i32
+    }
+
+    /// Checks a stack pointer or an accessed address, recording a violation if it is outside
+    /// the stack and the new depth otherwise.
+    ///
+    /// # Arguments
+    ///
+    /// * `pc` - The address of the instruction.
+    /// * `word` - The instruction.
+    /// * `address` - The new value of `R6`, or the accessed address.
+    /// * `pointer` - Whether `address` is a stack pointer, which may also be `high + 1`.
+    ///
+    fn check(&mut self, pc: u16, word: u16, address: u16, pointer: bool) {
+        let depth = self.depth(address);
+        let error = if address < self.low {
+            StackError::Overflow
+        } else if depth < 0 || (depth == 0 && !pointer) {
+            StackError::Underflow
+        } else {
+            self.high_water = self.high_water.max(depth as u32);
+            return;
+        };
+        if self.reported.insert((pc, error)) {
+            self.violations.push(StackViolation {
+                pc,
+                word,
+                error,
+                address,
+                depth,
+            });
+        }
+    }
+}
+
+impl Vm {
+    /// Checks an instruction about to be executed against the stack region. Does nothing
+    /// when the stack is not monitored.
+    ///
+    /// # Parameters
+    ///
+    /// - `pc`: The address of the instruction.
+    /// - `instruction`: The instruction about to be executed.
+    ///
+    pub(crate) fn check_stack(&mut self, pc: u16, instruction: Instruction) {
+        let Some(monitor) = self.stack.as_mut() else {
+            return;
+        };
+        let sp = self.registers[STACK_POINTER];
+        let word = instruction.encode();
+        match instruction {
+            Instruction::Add {
+                dr: STACK_POINTER,
+                sr1: STACK_POINTER,
+                src2,
+                ..
+            } => {
+                let amount = match src2 {
+                    Operand::Immediate(imm) => imm as u16,
+                    Operand::Register(r) => self.registers[r],
+                };
+                monitor.check(pc, word, sp.wrapping_add(amount), true);
+            }
+            Instruction::Ldr {
+                base: STACK_POINTER,
+                offset,
+                ..
+            }
+            | Instruction::Str {
+                base: STACK_POINTER,
+                offset,
+                ..
+            } => monitor.check(pc, word, sp.wrapping_add(offset as u16), false),
+            _ => {}
+        }
+    }
+}
+
+impl fmt::Display for StackMonitor {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "Stack high-water mark: {} of {} words (x{:04X}-x{:04X})",
+            self.high_water,
+            self.size(),
+            self.low,
+            self.high
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assembler::{vm_from_source, BACKENDS};
+
+    fn violations(vm: &mut Vm) -> Vec<String> {
+        vm.run_until_halt().unwrap();
+        let monitor = vm.stack.as_ref().unwrap();
+        monitor
+            .violations
+            .iter()
+            .map(|v| v.describe(&vm.symbols, monitor.size()))
+            .collect()
+    }
+
+    #[test]
+    fn a_stack_within_bounds_records_its_high_water_mark() {
+        let mut vm = vm_from_source(
+            "stack.asm",
+            "        .ORIG x3000
+        LD R6, STACK
+        LD R1, COUNT
+PUSH    ADD R6, R6, #-1
+        STR R1, R6, #0
+        ADD R1, R1, #-1
+        BRp PUSH
+        LDR R0, R6, #3
+        ADD R6, R6, #4
+        HALT
+STACK   .FILL x4000
+COUNT   .FILL #4
+        .END
+",
+        );
+        vm.stack = Some(StackMonitor::new(0x3F00, 0x3FFF));
+
+        assert!(violations(&mut vm).is_empty());
+        let monitor = vm.stack.unwrap();
+        assert_eq!(monitor.high_water, 4);
+        assert_eq!(
+            monitor.to_string(),
+            "Stack high-water mark: 4 of 256 words (x3F00-x3FFF)"
+        );
+    }
+
+    #[test]
+    fn overflows_and_underflows_are_reported() {
+        for backend in BACKENDS {
+            let mut vm = vm_from_source(
+                "stack.asm",
+                "        .ORIG x3000
+        LD R6, STACK
+        ADD R6, R6, #-1
+        ADD R6, R6, #-2
+        STR R0, R6, #0
+        LDR R0, R6, #5
+        ADD R6, R6, #4
+        HALT
+STACK   .FILL x4000
+        .END
+",
+            );
+            vm.stack = Some(StackMonitor::new(0x3FFE, 0x3FFF));
+            vm.backend = backend;
+
+            assert_eq!(
+                violations(&mut vm),
+                vec![
+                    "Stack overflow at x3002 (stack.asm:4): ADD R6, R6, #-2 moves R6 to x3FFD, \
+                     3 words deep in a 2-word stack",
+                    "Stack overflow at x3003 (stack.asm:5): STR R0, R6, #0 writes x3FFD, \
+                     3 words deep in a 2-word stack",
+                    "Stack underflow at x3004 (stack.asm:6): LDR R0, R6, #5 reads x4002, \
+                     -2 words deep in a 2-word stack",
+                    "Stack underflow at x3005 (stack.asm:7): ADD R6, R6, #4 moves R6 to x4001, \
+                     -1 words deep in a 2-word stack",
+                ]
+            );
+            assert_eq!(vm.stack.unwrap().high_water, 1);
+        }
+    }
+}
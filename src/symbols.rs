@@ -0,0 +1,361 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs;
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+
+use crate::vm_error::VmError;
+
+/// How far past a symbol an address can be and still be shown relative to it.
+const MAX_SYMBOL_OFFSET: u16 = 0x100;
+
+/// The source location an address was assembled from.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SourceLine {
+    pub file: String,
+    pub line: u32,
+}
+
+/// Maps symbol names to addresses and, when available, addresses to source lines.
+///
+/// Symbol tables are read from the `.sym` files written by the classic LC-3 assembler,
+/// or from a JSON variant that also carries the source line of every address:
+///
+/// ```json
+/// {
+///   "symbols": { "START": 12288, "LOOP": 12291 },
+///   "lines": [ { "address": 12288, "file": "prog.asm", "line": 3 } ]
+/// }
+/// ```
+///
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SymbolTable {
+    pub symbols: BTreeMap<String, u16>,
+    pub addresses: BTreeMap<u16, String>,
+    pub lines: BTreeMap<u16, SourceLine>,
+}
+
+impl SourceLine {
+    /// Reads the text of the line from its source file.
+    ///
+    /// # Returns
+    ///
+    /// The text of the line, or `None` if the file cannot be read or is too short.
+    ///
+    pub fn text(&self) -> Option<String> {
+        let source = fs::read_to_string(&self.file).ok()?;
+        source
+            .lines()
+            .nth((self.line as usize).checked_sub(1)?)
+            .map(str::to_string)
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct JsonSymbolTable {
+    #[serde(default)]
+    symbols: BTreeMap<String, u16>,
+    #[serde(default)]
+    lines: Vec<JsonLine>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct JsonLine {
+    address: u16,
+    file: String,
+    line: u32,
+}
+
+impl SymbolTable {
+    /// Creates an empty `SymbolTable`.
+    pub fn new() -> SymbolTable {
+        SymbolTable::default()
+    }
+
+    /// Adds a symbol. If several symbols share an address, the first one is used to name it.
+    pub fn insert(&mut self, name: &str, address: u16) {
+        self.symbols.insert(name.to_string(), address);
+        self.addresses
+            .entry(address)
+            .or_insert_with(|| name.to_string());
+    }
+
+    /// Records the source line an address was assembled from.
+    pub fn insert_line(&mut self, address: u16, file: &str, line: u32) {
+        self.lines.insert(
+            address,
+            SourceLine {
+                file: file.to_string(),
+                line,
+            },
+        );
+    }
+
+    /// Parses a symbol table in the format written by the LC-3 assembler (`lc3as`).
+    ///
+    /// Every symbol line looks like `//  NAME  3000`, with the address in hexadecimal. The
+    /// header, which ends with a line of dashes, is skipped, as are blank lines.
+    ///
+    /// # Returns
+    ///
+    /// The parsed `SymbolTable`, or a `VmError` if a symbol line is malformed.
+    ///
+    pub fn parse_sym(text: &str) -> Result<SymbolTable, VmError> {
+        let lines: Vec<&str> = text
+            .lines()
+            .map(|line| line.trim_start_matches("//").trim())
+            .collect();
+        let body = match lines.iter().position(|line| line.starts_with('-')) {
+            Some(separator) => &lines[separator + 1..],
+            None => &lines[..],
+        };
+
+        let mut table = SymbolTable::new();
+        for line in body.iter().filter(|line| !line.is_empty()) {
+            let words: Vec<&str> = line.split_whitespace().collect();
+            let [name, address] = words[..] else {
+                return Err(VmError::InvalidSymbolTable(format!(
+                    "invalid symbol line: {}",
+                    line
+                )));
+            };
+            let digits = address.trim_start_matches(['x', 'X']);
+            let address = u16::from_str_radix(digits, 16).map_err(|_| {
+                VmError::InvalidSymbolTable(format!("invalid address for {}: {}", name, address))
+            })?;
+            table.insert(name, address);
+        }
+        Ok(table)
+    }
+
+    /// Parses a symbol table in the JSON format, which can also contain source lines.
+    ///
+    /// # Returns
+    ///
+    /// The parsed `SymbolTable`, or a `VmError` if the JSON is malformed.
+    ///
+    pub fn parse_json(text: &str) -> Result<SymbolTable, VmError> {
+        let json: JsonSymbolTable =
+            serde_json::from_str(text).map_err(|e| VmError::InvalidSymbolTable(e.to_string()))?;
+        let mut table = SymbolTable::new();
+        for (name, address) in &json.symbols {
+            table.insert(name, *address);
+        }
+        for line in json.lines {
+            table.insert_line(line.address, &line.file, line.line);
+        }
+        Ok(table)
+    }
+
+    /// Serializes the symbol table to the JSON format.
+    pub fn to_json(&self) -> String {
+        let json = JsonSymbolTable {
+            symbols: self.symbols.clone(),
+            lines: self
+                .lines
+                .iter()
+                .map(|(address, source)| JsonLine {
+                    address: *address,
+                    file: source.file.clone(),
+                    line: source.line,
+                })
+                .collect(),
+        };
+        serde_json::to_string_pretty(&json).unwrap_or_default()
+    }
+
+    /// Reads a symbol table file, detecting the JSON format by its content.
+    ///
+    /// # Returns
+    ///
+    /// The parsed `SymbolTable`, or a `VmError` if the file cannot be read or parsed.
+    ///
+    pub fn from_file(path: &str) -> Result<SymbolTable, VmError> {
+        let text =
+            fs::read_to_string(path).map_err(|e| VmError::FailedToOpenFile(e.to_string()))?;
+        if text.trim_start().starts_with('{') {
+            SymbolTable::parse_json(&text)
+        } else {
+            SymbolTable::parse_sym(&text)
+        }
+    }
+
+    /// Looks for a symbol table next to an image file: `prog.sym` or `prog.sym.json` for `prog.obj`.
+    ///
+    /// # Returns
+    ///
+    /// The symbol table if one was found, `None` if there is none, or a `VmError` if it is invalid.
+    ///
+    pub fn for_image(image_path: &str) -> Result<Option<SymbolTable>, VmError> {
+        let sym = Path::new(image_path).with_extension("sym");
+        let json = Path::new(image_path).with_extension("sym.json");
+        for candidate in [sym, json] {
+            if candidate.is_file() {
+                return SymbolTable::from_file(&candidate.to_string_lossy()).map(Some);
+            }
+        }
+        Ok(None)
+    }
+
+    /// Adds every symbol and source line of `other` to this table.
+    pub fn merge(&mut self, other: SymbolTable) {
+        for (name, address) in other.symbols {
+            self.insert(&name, address);
+        }
+        self.lines.extend(other.lines);
+    }
+
+    /// Returns the address of a symbol.
+    pub fn address_of(&self, name: &str) -> Option<u16> {
+        self.symbols.get(name).copied()
+    }
+
+    /// Returns the closest symbol at or before `address` and the offset from it.
+    ///
+    /// Symbols more than `MAX_SYMBOL_OFFSET` words before the address are not considered,
+    /// so addresses far from any label are not shown as a huge offset from the last one.
+    ///
+    pub fn nearest(&self, address: u16) -> Option<(&str, u16)> {
+        self.addresses
+            .range(..=address)
+            .next_back()
+            .map(|(start, name)| (name.as_str(), address - start))
+            .filter(|(_, offset)| *offset <= MAX_SYMBOL_OFFSET)
+    }
+
+    /// Returns the source line `address` was assembled from.
+    pub fn line_at(&self, address: u16) -> Option<&SourceLine> {
+        self.lines.get(&address)
+    }
+
+    /// Formats an address for humans, e.g. `x3005 <LOOP+2> (prog.asm:12)`.
+    ///
+    /// Without symbols or line information this is just `x3005`.
+    ///
+    pub fn format_address(&self, address: u16) -> String {
+        let mut text = format!("x{:04X}", address);
+        match self.nearest(address) {
+            Some((name, 0)) => text.push_str(&format!(" <{}>", name)),
+            Some((name, offset)) => text.push_str(&format!(" <{}+{}>", name, offset)),
+            None => {}
+        }
+        if let Some(source) = self.line_at(address) {
+            text.push_str(&format!(" ({}:{})", source.file, source.line));
+        }
+        text
+    }
+}
+
+/// Something reported to the user that mentions addresses, such as a watchpoint hit or a
+/// checker finding.
+///
+/// Its `Display` form, through `with_symbols`, shows every address with `format_address`,
+/// so the report names labels and source lines when symbols are loaded.
+///
+pub trait Describe {
+    /// Writes the report, with addresses formatted by `symbols`.
+    fn describe(&self, symbols: &SymbolTable) -> String;
+
+    /// Pairs the report with the symbols to display it with.
+    fn with_symbols<'a>(&'a self, symbols: &'a SymbolTable) -> Described<'a, Self> {
+        Described {
+            item: self,
+            symbols,
+        }
+    }
+}
+
+/// A report paired with a symbol table, displayed with `Describe::describe`.
+pub struct Described<'a, T: ?Sized> {
+    item: &'a T,
+    symbols: &'a SymbolTable,
+}
+
+impl<T: Describe + ?Sized> fmt::Display for Described<'_, T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.item.describe(self.symbols))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LC3AS_SYM: &str = "// Symbol table
+// Scope level 0:
+//	Symbol Name       Page Address
+//	----------------  ------------
+//	START             3000
+//	LOOP              3003
+//	DATA              3010
+";
+
+    #[test]
+    fn parses_lc3as_format() {
+        let table = SymbolTable::parse_sym(LC3AS_SYM).unwrap();
+
+        assert_eq!(table.symbols.len(), 3);
+        assert_eq!(table.address_of("LOOP"), Some(0x3003));
+        assert_eq!(table.address_of("Symbol"), None);
+    }
+
+    #[test]
+    fn invalid_sym_address_is_an_error() {
+        let result = SymbolTable::parse_sym("//\tSTART\t30G0\n");
+
+        assert!(matches!(result, Err(VmError::InvalidSymbolTable(_))));
+    }
+
+    #[test]
+    fn parses_json_with_lines() {
+        let table = SymbolTable::parse_json(
+            r#"{"symbols": {"START": 12288}, "lines": [{"address": 12289, "file": "prog.asm", "line": 4}]}"#,
+        )
+        .unwrap();
+
+        assert_eq!(table.address_of("START"), Some(0x3000));
+        assert_eq!(
+            table.line_at(0x3001),
+            Some(&SourceLine {
+                file: "prog.asm".to_string(),
+                line: 4
+            })
+        );
+    }
+
+    #[test]
+    fn json_round_trip() {
+        let mut table = SymbolTable::new();
+        table.insert("START", 0x3000);
+        table.insert_line(0x3000, "prog.asm", 1);
+
+        assert_eq!(SymbolTable::parse_json(&table.to_json()).unwrap(), table);
+    }
+
+    #[test]
+    fn formats_addresses_with_symbols_and_lines() {
+        let mut table = SymbolTable::parse_sym(LC3AS_SYM).unwrap();
+        table.insert_line(0x3005, "prog.asm", 12);
+
+        assert_eq!(table.format_address(0x2FFF), "x2FFF");
+        assert_eq!(table.format_address(0x4000), "x4000");
+        assert_eq!(table.format_address(0x3000), "x3000 <START>");
+        assert_eq!(table.format_address(0x3005), "x3005 <LOOP+2> (prog.asm:12)");
+    }
+
+    #[test]
+    fn symbols_are_loaded_next_to_the_image() {
+        let dir = std::env::temp_dir().join("lc3_symbols_for_image");
+        std::fs::create_dir_all(&dir).unwrap();
+        let image = dir.join("prog.obj").to_string_lossy().to_string();
+        std::fs::write(&image, [0x30, 0x00, 0xF0, 0x25]).unwrap();
+        std::fs::write(dir.join("prog.sym"), LC3AS_SYM).unwrap();
+
+        let mut vm = crate::vm::Vm::new();
+        vm.load_image(&image, &Default::default()).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(vm.symbols.address_of("START"), Some(0x3000));
+    }
+}
@@ -0,0 +1,453 @@
+use std::collections::HashMap;
+use std::rc::Rc;
+
+use crate::{
+    constants::DEVICE_PAGE_START,
+    instruction::{Instruction, Operand},
+    permissions::Access,
+    vm::Vm,
+    vm_error::VmError,
+};
+
+/// The longest basic block that is translated at once.
+pub const MAX_BLOCK_LENGTH: u16 = 64;
+
+/// A translated instruction: the instruction with its fields already bound.
+type Op = Box<dyn Fn(&mut Vm, &mut bool) -> Result<(), VmError>>;
+
+/// A translated basic block: straight-line instructions starting at `start`, the last one
+/// being the only one that can change the PC (a branch, jump, call, trap or illegal instruction),
+/// unless the block was cut at `MAX_BLOCK_LENGTH` or at the device page.
+struct Block {
+    start: u16,
+    ops: Vec<Op>,
+}
+
+/// An execution backend that translates basic blocks into chains of closures and runs them.
+///
+/// Blocks are translated the first time they are reached and kept until memory inside them
+/// is written: `Memory::write` reports writes to translated addresses, and the blocks
+/// covering them are dropped before the next instruction runs, so self-modifying code
+/// behaves like under the interpreter. Coverage, the checkers, watchpoints and `last_fetch` are
+/// updated for every instruction, exactly as `Vm::step` does.
+///
+/// # Fields
+///
+/// * `blocks` - The translated blocks, by start address.
+/// * `cursor` - The block being stepped through and the index of its next instruction.
+/// * `translations` - How many blocks were translated, including retranslations.
+/// * `executed` - How many instructions were executed.
+///
+#[derive(Default)]
+pub struct BlockTranslator {
+    blocks: HashMap<u16, Rc<Block>>,
+    cursor: Option<(Rc<Block>, usize)>,
+    pub translations: usize,
+    pub executed: u64,
+}
+
+impl BlockTranslator {
+    /// Creates a translator with no translated blocks.
+    pub fn new() -> BlockTranslator {
+        BlockTranslator::default()
+    }
+
+    /// Runs the program until it halts or an error occurs.
+    ///
+    /// # Parameters
+    ///
+    /// - `vm`: The machine to run, starting at its PC.
+    /// - `running`: Boolean flag that indicates if the program is running, cleared when the program halts.
+    ///
+    /// # Returns
+    ///
+    /// Returns `Ok(())` when the program halted, otherwise the error that stopped it, including
+    /// `VmError::WatchpointHit` right after the instruction that triggered a watchpoint.
+    ///
+    pub fn run(&mut self, vm: &mut Vm, running: &mut bool) -> Result<(), VmError> {
+        while *running {
+            self.run_block(vm, running)?;
+        }
+        Ok(())
+    }
+
+    /// Runs the block at the PC, translating it first if needed.
+    ///
+    /// Execution leaves the block early when the program halts, when a watchpoint is hit or
+    /// when an instruction wrote into translated code.
+    ///
+    /// # Parameters
+    ///
+    /// - `vm`: The machine to run.
+    /// - `running`: Boolean flag that indicates if the program is running.
+    ///
+    /// # Returns
+    ///
+    /// Returns `Ok(())` if the instructions were executed successfully, otherwise returns a `VmError`.
+    ///
+    pub fn run_block(&mut self, vm: &mut Vm, running: &mut bool) -> Result<(), VmError> {
+        self.execute(vm, running, usize::MAX)
+    }
+
+    /// Executes a single instruction from its translated block, like `Vm::step`.
+    ///
+    /// The position in the block is remembered, so stepping through a block runs the same
+    /// translated code as `run_block` does.
+    ///
+    /// # Parameters
+    ///
+    /// - `vm`: The machine to run.
+    /// - `running`: Boolean flag that indicates if the program is running.
+    ///
+    /// # Returns
+    ///
+    /// Returns `Ok(())` if the instruction was executed successfully, otherwise returns a `VmError`.
+    ///
+    pub fn step(&mut self, vm: &mut Vm, running: &mut bool) -> Result<(), VmError> {
+        self.execute(vm, running, 1)
+    }
+
+    /// Executes up to `limit` instructions of the block at the PC.
+    fn execute(&mut self, vm: &mut Vm, running: &mut bool, limit: usize) -> Result<(), VmError> {
+        if !vm.memory.invalidated.is_empty() {
+            self.invalidate(vm);
+        }
+        let pc = vm.registers.pc;
+        if pc >= DEVICE_PAGE_START {
+            self.cursor = None;
+            return vm.step(running);
+        }
+        let (block, first) = match self.cursor.take() {
+            Some((block, index)) if block.start.wrapping_add(index as u16) == pc => (block, index),
+            _ => match self.blocks.get(&pc) {
+                Some(block) => (Rc::clone(block), 0),
+                None => (self.translate(vm, pc), 0),
+            },
+        };
+
+        let last = block.ops.len().min(first.saturating_add(limit));
+        for index in first..last {
+            let address = block.start.wrapping_add(index as u16);
+            vm.memory
+                .check_access(address, Access::Execute)
+                .map_err(VmError::AccessViolation)?;
+            vm.memory.watch_hit = None;
+            vm.memory.access_violation = None;
+            vm.memory.record_fetch(address);
+            vm.registers.pc = address.wrapping_add(1);
+            if let Some(coverage) = vm.coverage.as_mut() {
+                coverage.record_execution(address);
+            }
+            if vm.checks_instructions() {
+                let instruction = Instruction::decode(vm.memory.memory[address as usize]);
+                vm.check_instruction(address, instruction);
+            }
+
+            (block.ops[index])(vm, running)?;
+            self.executed += 1;
+
+            let modified = !vm.memory.invalidated.is_empty();
+            if modified {
+                self.invalidate(vm);
+            }
+            if let Some(violation) = vm.memory.access_violation.take() {
+                return Err(VmError::AccessViolation(violation));
+            }
+            if let Some(hit) = vm.memory.watch_hit.take() {
+                return Err(VmError::WatchpointHit(hit));
+            }
+            if modified || !*running {
+                return Ok(());
+            }
+        }
+        if last < block.ops.len() {
+            self.cursor = Some((block, last));
+        }
+        Ok(())
+    }
+
+    /// Translates the block starting at an address and remembers it.
+    fn translate(&mut self, vm: &mut Vm, start: u16) -> Rc<Block> {
+        let mut ops = Vec::new();
+        let mut address = start;
+        loop {
+            let instruction = Instruction::decode(vm.memory.memory[address as usize]);
+            vm.memory.translated[address as usize] = true;
+            ops.push(translate_instruction(instruction));
+            address = address.wrapping_add(1);
+            if ends_block(&instruction)
+                || ops.len() == MAX_BLOCK_LENGTH as usize
+                || address >= DEVICE_PAGE_START
+            {
+                break;
+            }
+        }
+
+        let block = Rc::new(Block { start, ops });
+        self.blocks.insert(start, Rc::clone(&block));
+        self.translations += 1;
+        block
+    }
+
+    /// Drops every block that covers an address written since the last call.
+    fn invalidate(&mut self, vm: &mut Vm) {
+        self.cursor = None;
+        for address in std::mem::take(&mut vm.memory.invalidated) {
+            let first = address.saturating_sub(MAX_BLOCK_LENGTH - 1);
+            for start in first..=address {
+                if self
+                    .blocks
+                    .get(&start)
+                    .is_some_and(|block| (address - start) < block.ops.len() as u16)
+                {
+                    self.blocks.remove(&start);
+                }
+            }
+        }
+    }
+}
+
+/// Whether an instruction can change the PC, so that nothing after it belongs to its block.
+fn ends_block(instruction: &Instruction) -> bool {
+    matches!(
+        instruction,
+        Instruction::Br { .. }
+            | Instruction::Jmp { .. }
+            | Instruction::Jsr { .. }
+            | Instruction::Jsrr { .. }
+            | Instruction::Trap { .. }
+            | Instruction::Rti
+            | Instruction::Illegal(_)
+    )
+}
+
+/// Binds the fields of an instruction into a closure that executes it.
+///
+/// The common register-only instructions are inlined; the others call the same `op_*`
+/// methods as the interpreter.
+///
+fn translate_instruction(instruction: Instruction) -> Op {
+    match instruction {
+        Instruction::Add {
+            dr,
+            sr1,
+            src2: Operand::Immediate(imm5),
+            ..
+        } => Box::new(move |vm, _| {
+            let result = vm.registers[sr1].wrapping_add(imm5 as u16);
+            vm.registers[dr] = result;
+            vm.registers.set_flags(result);
+            Ok(())
+        }),
+        Instruction::Add {
+            dr,
+            sr1,
+            src2: Operand::Register(sr2),
+            ..
+        } => Box::new(move |vm, _| {
+            let result = vm.registers[sr1].wrapping_add(vm.registers[sr2]);
+            vm.registers[dr] = result;
+            vm.registers.set_flags(result);
+            Ok(())
+        }),
+        Instruction::And {
+            dr,
+            sr1,
+            src2: Operand::Immediate(imm5),
+            ..
+        } => Box::new(move |vm, _| {
+            let result = vm.registers[sr1] & imm5 as u16;
+            vm.registers[dr] = result;
+            vm.registers.set_flags(result);
+            Ok(())
+        }),
+        Instruction::And {
+            dr,
+            sr1,
+            src2: Operand::Register(sr2),
+            ..
+        } => Box::new(move |vm, _| {
+            let result = vm.registers[sr1] & vm.registers[sr2];
+            vm.registers[dr] = result;
+            vm.registers.set_flags(result);
+            Ok(())
+        }),
+        Instruction::Not { dr, sr, .. } => Box::new(move |vm, _| {
+            let result = !vm.registers[sr];
+            vm.registers[dr] = result;
+            vm.registers.set_flags(result);
+            Ok(())
+        }),
+        Instruction::Br { conditions, offset } => Box::new(move |vm, _| {
+            vm.op_br(conditions, offset);
+            Ok(())
+        }),
+        Instruction::Ld { dr, offset } => Box::new(move |vm, _| vm.op_ld(dr, offset)),
+        Instruction::Ldi { dr, offset } => Box::new(move |vm, _| vm.op_ldi(dr, offset)),
+        Instruction::Ldr { dr, base, offset } => Box::new(move |vm, _| vm.op_ldr(dr, base, offset)),
+        Instruction::Lea { dr, offset } => Box::new(move |vm, _| vm.op_lea(dr, offset)),
+        Instruction::St { sr, offset } => Box::new(move |vm, _| vm.op_st(sr, offset)),
+        Instruction::Sti { sr, offset } => Box::new(move |vm, _| vm.op_sti(sr, offset)),
+        Instruction::Str { sr, base, offset } => Box::new(move |vm, _| vm.op_str(sr, base, offset)),
+        Instruction::Jmp { base, .. } => Box::new(move |vm, _| vm.op_jmp(base)),
+        Instruction::Jsr { offset } => Box::new(move |vm, _| vm.op_jsr(offset)),
+        Instruction::Jsrr { base, .. } => Box::new(move |vm, _| vm.op_jsrr(base)),
+        Instruction::Trap { vector, .. } => {
+            Box::new(move |vm, running| vm.handle_trap(vector, running))
+        }
+        Instruction::Rti | Instruction::Illegal(_) => {
+            Box::new(move |vm, running| vm.execute(instruction, running))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        assembler::{vm_on_backend, BACKENDS},
+        coverage::Coverage,
+        differential::{Lockstep, Outcome},
+        vm::Backend,
+        watchpoint::{WatchMode, Watchpoint},
+    };
+
+    const SUM: &str = "
+        .ORIG x3000
+        LD R6, STACK
+        LEA R1, DATA
+        AND R2, R2, #0
+        ADD R2, R2, #5
+        AND R0, R0, #0
+LOOP    LDR R3, R1, #0
+        JSR ADDIT
+        ADD R1, R1, #1
+        ADD R2, R2, #-1
+        BRp LOOP
+        ST R0, RESULT
+        LDI R4, PTR
+        NOT R4, R4
+        HALT
+ADDIT   ADD R6, R6, #-1
+        STR R7, R6, #0
+        ADD R0, R0, R3
+        LDR R7, R6, #0
+        ADD R6, R6, #1
+        RET
+STACK   .FILL x4000
+PTR     .FILL RESULT
+RESULT  .FILL 0
+DATA    .FILL 3
+        .FILL -1
+        .FILL 10
+        .FILL 7
+        .FILL x8000
+        .END
+";
+
+    // Rewrites the instruction at TARGET, later in the same block, on every iteration.
+    const SELF_MODIFYING: &str = "
+        .ORIG x3000
+        AND R0, R0, #0
+        AND R1, R1, #0
+        ADD R1, R1, #3
+LOOP    LD R2, PATCH
+        ST R2, TARGET
+TARGET  ADD R0, R0, #1
+        ADD R2, R2, #1
+        ST R2, PATCH
+        ADD R1, R1, #-1
+        BRp LOOP
+        HALT
+PATCH   ADD R0, R0, #2
+        .END
+";
+
+    /// Runs a program with both backends and checks that they end in the same state.
+    fn run_both(source: &str) -> Vm {
+        let mut reference = vm_on_backend("prog.asm", source, Backend::Interpreter);
+        let mut translated = vm_on_backend("prog.asm", source, Backend::Blocks);
+        reference.run_until_halt().unwrap();
+        translated.run_until_halt().unwrap();
+
+        assert_eq!(translated.registers.gpr, reference.registers.gpr);
+        assert_eq!(translated.registers.pc, reference.registers.pc);
+        assert_eq!(translated.registers.cond, reference.registers.cond);
+        assert!(translated.memory.memory == reference.memory.memory);
+        translated
+    }
+
+    #[test]
+    fn matches_the_interpreter() {
+        let vm = run_both(SUM);
+
+        assert_eq!(vm.registers[0], 0x8013);
+        assert_eq!(vm.registers[4], !0x8013);
+    }
+
+    #[test]
+    fn self_modifying_code_is_retranslated() {
+        let vm = run_both(SELF_MODIFYING);
+
+        assert_eq!(vm.registers[0], 2 + 3 + 4);
+    }
+
+    #[test]
+    fn stepping_agrees_with_the_interpreter_at_every_step() {
+        for source in [SUM, SELF_MODIFYING] {
+            let mut lockstep = Lockstep::new(
+                vm_on_backend("prog.asm", source, Backend::Interpreter),
+                vm_on_backend("prog.asm", source, Backend::Blocks),
+            );
+
+            match lockstep.run(10_000) {
+                Outcome::Halted { .. } => {}
+                other => panic!("expected both programs to halt, got {:?}", other),
+            }
+        }
+    }
+
+    #[test]
+    fn blocks_are_reused() {
+        let mut vm = vm_on_backend("prog.asm", SUM, Backend::Blocks);
+        let mut translator = BlockTranslator::new();
+
+        translator.run(&mut vm, &mut true).unwrap();
+
+        // The entry, the loop, the subroutine, the code after the call and the code after the loop.
+        assert_eq!(translator.translations, 5);
+    }
+
+    #[test]
+    fn watchpoints_stop_at_the_same_instruction() {
+        let mut stops = Vec::new();
+        for backend in BACKENDS {
+            let mut vm = vm_on_backend("prog.asm", SUM, backend);
+            let result = vm.symbols.address_of("RESULT").unwrap();
+            vm.memory
+                .watchpoints
+                .push(Watchpoint::new(result, result, WatchMode::Write));
+
+            match vm.run_until_halt() {
+                Err(VmError::WatchpointHit(hit)) => stops.push((hit.pc, vm.registers.pc)),
+                other => panic!("expected a watchpoint hit, got {:?}", other),
+            }
+        }
+
+        assert_eq!(stops[0], stops[1]);
+    }
+
+    #[test]
+    fn coverage_is_recorded_per_instruction() {
+        let mut coverages = Vec::new();
+        for backend in BACKENDS {
+            let mut vm = vm_on_backend("prog.asm", SUM, backend);
+            vm.coverage = Some(Coverage::new());
+            vm.run_until_halt().unwrap();
+            let coverage = vm.coverage.unwrap();
+            coverages.push((coverage.hits, coverage.branches));
+        }
+
+        assert_eq!(coverages[0], coverages[1]);
+    }
+}
@@ -1,37 +1,36 @@
-use byteorder::{BigEndian, ReadBytesExt};
-use std::fs::File;
-use std::io::{BufReader, Write};
+use std::io::Write;
 
+use crate::image_format::read_sections;
+use crate::load_map::LoadedImage;
 use crate::memory::Memory;
 use crate::vm_error::VmError;
 
-/// Reads an image file into memory. The image file is expected to start with
-/// a 16-bit address indicating where in memory the data should be loaded, followed by
+/// Reads an image file into memory. The format (`.obj`, `.hex`, `.bin` or an lc3tools
+/// object) is detected from the extension and content of the file. Every section starts
+/// with a 16-bit address indicating where in memory the data should be loaded, followed by
 /// 16-bit instructions to be stored sequentially in memory.
 ///
 /// # Returns
 ///
-/// An `Ok` result if the image file reading was successful, otherwise a `VmError`.
-pub fn read_image_file(path: &str, memory: &mut Memory) -> Result<(), VmError> {
-    let file = File::open(path).map_err(|e| VmError::FailedToOpenFile(e.to_string()))?;
-    let mut reader = BufReader::new(file);
-
-    let mut address = reader
-        .read_u16::<BigEndian>()
-        .map_err(|e| VmError::FailedToReadBigEndian(e.to_string()))?;
-    while let Ok(instr) = reader.read_u16::<BigEndian>() {
-        memory.write(address, instr);
-        address = match address.checked_add(1) {
-            Some(a) => a,
-            None => {
-                return Err(VmError::FailedToReadBigEndian(
-                    "Address overflow while reading image file".to_string(),
-                ));
-            }
-        };
+/// One `LoadedImage` per section describing where the file was placed if the image file reading was successful, otherwise a `VmError`.
+pub fn read_image_file(path: &str, memory: &mut Memory) -> Result<Vec<LoadedImage>, VmError> {
+    let mut images = Vec::new();
+    for section in read_sections(path)? {
+        if section.origin as usize + section.words.len() > u16::MAX as usize + 1 {
+            return Err(VmError::InvalidImage(
+                "Address overflow while reading image file".to_string(),
+            ));
+        }
+        for (address, word) in (section.origin..=u16::MAX).zip(&section.words) {
+            memory.write(address, *word);
+        }
+        images.push(LoadedImage {
+            path: path.to_string(),
+            origin: section.origin,
+            length: section.words.len(),
+        });
     }
-
-    Ok(())
+    Ok(images)
 }
 
 /// Flushes the stdout buffer
@@ -46,6 +45,59 @@ pub fn flush_stdout() -> Result<(), VmError> {
         .map_err(|e| VmError::FailedToFlush(e.to_string()))
 }
 
+/// Writes `contents` to the file at `path`, replacing it if it already exists.
+///
+/// # Returns
+///
+/// An `Ok` result if the file was written, otherwise a `VmError`.
+///
+pub fn write_file(path: &str, contents: impl AsRef<[u8]>) -> Result<(), VmError> {
+    std::fs::write(path, contents).map_err(|e| VmError::FailedToWriteFile(e.to_string()))
+}
+
+/// Parses a number written in LC-3 notation.
+///
+/// Accepts hexadecimal (`x3000`, `0x3000`), decimal (`#-5`, `12`) and binary (`b1010`) literals.
+///
+/// # Returns
+///
+/// The parsed value, or `None` if the text is not a valid number.
+///
+pub fn parse_number(text: &str) -> Option<i32> {
+    let (negative, digits) = match text.strip_prefix('-') {
+        Some(rest) => (true, rest),
+        None => (false, text),
+    };
+    let value = if let Some(hex) = digits
+        .strip_prefix("0x")
+        .or_else(|| digits.strip_prefix(['x', 'X']))
+    {
+        i32::from_str_radix(hex, 16).ok()?
+    } else if let Some(dec) = digits.strip_prefix('#') {
+        dec.parse::<i32>().ok()?
+    } else if let Some(bin) = digits.strip_prefix(['b', 'B']) {
+        i32::from_str_radix(bin, 2).ok()?
+    } else {
+        digits.parse::<i32>().ok()?
+    };
+    Some(if negative { -value } else { value })
+}
+
+/// Parses an LC-3 number that must fit in 16 bits, either as an unsigned or a signed value.
+///
+/// # Returns
+///
+/// The value as a `u16`, or `None` if the text is not a valid 16-bit number.
+///
+pub fn parse_word(text: &str) -> Option<u16> {
+    let value = parse_number(text)?;
+    if (-0x8000..=0xFFFF).contains(&value) {
+        Some(value as u16)
+    } else {
+        None
+    }
+}
+
 /// Sign-extends a value based on a given bit count.
 pub fn sign_extend(x: u16, bit_count: u16) -> i16 {
     let y = if (x >> (bit_count - 1)) & 1 != 0 {
@@ -55,3 +107,26 @@ pub fn sign_extend(x: u16, bit_count: u16) -> i16 {
     };
     y as i16
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_number_formats() {
+        assert_eq!(parse_number("x3000"), Some(0x3000));
+        assert_eq!(parse_number("0x3000"), Some(0x3000));
+        assert_eq!(parse_number("#-5"), Some(-5));
+        assert_eq!(parse_number("42"), Some(42));
+        assert_eq!(parse_number("b101"), Some(5));
+        assert_eq!(parse_number("-x10"), Some(-16));
+        assert_eq!(parse_number("label"), None);
+    }
+
+    #[test]
+    fn parse_word_range() {
+        assert_eq!(parse_word("xFFFF"), Some(0xFFFF));
+        assert_eq!(parse_word("#-1"), Some(0xFFFF));
+        assert_eq!(parse_word("x10000"), None);
+    }
+}
@@ -0,0 +1,418 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+use std::fs;
+
+use crate::{
+    constants::{
+        DEVICE_PAGE_START, INTERRUPT_VECTOR_TABLE_END, INTERRUPT_VECTOR_TABLE_START, OP_RES,
+        TRAP_HALT, TRAP_VECTOR_TABLE_END,
+    },
+    image_format::{parse_image, ImageFormat, Section},
+    instruction::Instruction,
+    vm_error::VmError,
+};
+
+/// How serious a problem found in an image is.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Severity {
+    /// Suspicious, but the image can still run.
+    Warning,
+    /// The image cannot be loaded, or will misbehave when it runs.
+    Error,
+}
+
+/// A problem found in an image.
+///
+/// # Fields
+///
+/// * `severity` - How serious the problem is.
+/// * `path` - The image the problem was found in.
+/// * `address` - The address the problem is about, if any.
+/// * `message` - A description of the problem.
+///
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub path: String,
+    pub address: Option<u16>,
+    pub message: String,
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let severity = match self.severity {
+            Severity::Warning => "warning",
+            Severity::Error => "error",
+        };
+        match self.address {
+            Some(address) => write!(
+                f,
+                "{}: x{:04X}: {}: {}",
+                self.path, address, severity, self.message
+            ),
+            None => write!(f, "{}: {}: {}", self.path, severity, self.message),
+        }
+    }
+}
+
+/// Regions of memory that programs are not expected to be loaded into.
+const RESERVED_REGIONS: [(&str, u16, u16, Severity); 3] = [
+    (
+        "the trap vector table",
+        0x0000,
+        TRAP_VECTOR_TABLE_END,
+        Severity::Warning,
+    ),
+    (
+        "the interrupt vector table",
+        INTERRUPT_VECTOR_TABLE_START,
+        INTERRUPT_VECTOR_TABLE_END,
+        Severity::Warning,
+    ),
+    (
+        "the device register page",
+        DEVICE_PAGE_START,
+        0xFFFF,
+        Severity::Error,
+    ),
+];
+
+/// Checks a set of image files without running them.
+///
+/// Images that cannot be read or parsed (odd length `.obj` files, empty files, malformed
+/// text images) are reported as errors. The others are checked together with
+/// `check_image`, so a branch into another of the images is not reported.
+///
+/// # Arguments
+///
+/// * `paths` - The image files, in load order.
+///
+/// # Returns
+///
+/// Every problem found, image by image.
+///
+pub fn check_images(paths: &[String]) -> Vec<Diagnostic> {
+    let mut diagnostics = Vec::new();
+    let mut images = Vec::new();
+    for path in paths {
+        let sections = fs::read(path)
+            .map_err(|e| VmError::FailedToOpenFile(e.to_string()))
+            .and_then(|bytes| parse_image(ImageFormat::detect(path, &bytes), &bytes));
+        match sections {
+            Ok(sections) => images.push((path, sections)),
+            Err(error) => diagnostics.push(Diagnostic {
+                severity: Severity::Error,
+                path: path.clone(),
+                address: None,
+                message: error_message(error),
+            }),
+        }
+    }
+
+    let loaded: Vec<Section> = images
+        .iter()
+        .flat_map(|(_, sections)| sections.iter().cloned())
+        .collect();
+    for (path, sections) in &images {
+        diagnostics.extend(check_image(path, sections, &loaded));
+    }
+    diagnostics
+}
+
+/// Checks the sections of one image.
+///
+/// Reports empty sections and sections loaded into the trap vector table, the interrupt
+/// vector table or the device register page. The code of the image is then found by
+/// following the control flow from the start of every section (and from the vectors, for
+/// sections that fill a vector table), so data words are never mistaken for instructions.
+/// Reserved opcodes in the code are errors, and branches or subroutine calls to addresses
+/// outside `loaded` are warnings.
+///
+/// # Arguments
+///
+/// * `path` - The image file, used in the diagnostics.
+/// * `sections` - The sections of the image.
+/// * `loaded` - Every section that will be in memory along with this image, including its own.
+///
+/// # Returns
+///
+/// Every problem found, ordered by address.
+///
+pub fn check_image(path: &str, sections: &[Section], loaded: &[Section]) -> Vec<Diagnostic> {
+    let mut diagnostics = Vec::new();
+    let diagnostic = |severity, address, message| Diagnostic {
+        severity,
+        path: path.to_string(),
+        address: Some(address),
+        message,
+    };
+
+    for section in sections {
+        let Some(end) = end_of(section) else {
+            diagnostics.push(diagnostic(
+                Severity::Warning,
+                section.origin,
+                "the section contains no words".to_string(),
+            ));
+            continue;
+        };
+        for (name, start, stop, severity) in RESERVED_REGIONS {
+            if section.origin <= stop && end >= start {
+                diagnostics.push(diagnostic(
+                    severity,
+                    section.origin.max(start),
+                    format!("loads into {} (x{:04X}-x{:04X})", name, start, stop),
+                ));
+            }
+        }
+    }
+
+    let is_loaded = |address: u16| {
+        loaded
+            .iter()
+            .any(|s| end_of(s).is_some_and(|end| address >= s.origin && address <= end))
+    };
+    let words = words_of(sections);
+    for address in find_code(sections) {
+        let instr = words[&address];
+        let instruction = Instruction::decode(instr);
+        let kind = match instruction {
+            Instruction::Illegal(_) if instr >> 12 == OP_RES => {
+                diagnostics.push(diagnostic(
+                    Severity::Error,
+                    address,
+                    format!("reserved opcode in code (x{:04X})", instr),
+                ));
+                continue;
+            }
+            Instruction::Illegal(_) => {
+                diagnostics.push(diagnostic(
+                    Severity::Warning,
+                    address,
+                    format!("illegal instruction in code (x{:04X})", instr),
+                ));
+                continue;
+            }
+            _ if instruction.reserved() != 0 => {
+                diagnostics.push(diagnostic(
+                    Severity::Warning,
+                    address,
+                    format!(
+                        "instruction with reserved bits set in code (x{:04X})",
+                        instr
+                    ),
+                ));
+                continue;
+            }
+            Instruction::Br { .. } => "branch",
+            Instruction::Jsr { .. } => "subroutine",
+            _ => continue,
+        };
+        if let Some(target) = instruction.target(address).filter(|t| !is_loaded(*t)) {
+            diagnostics.push(diagnostic(
+                Severity::Warning,
+                address,
+                format!(
+                    "{} target x{:04X} is outside the loaded images",
+                    kind, target
+                ),
+            ));
+        }
+    }
+
+    diagnostics.sort_by_key(|d| d.address);
+    diagnostics
+}
+
+/// Finds the code of an image by following the control flow from the start of every
+/// section, and from the vectors for sections that fill a vector table.
+///
+/// Branches and calls are followed within the image only, and `JMP`, `JSRR`, `RTI` and
+/// `HALT` end a path, so code only reached through a register is not found.
+///
+/// # Arguments
+///
+/// * `sections` - The sections of the image.
+///
+/// # Returns
+///
+/// The addresses of the words reached as instructions, illegal ones included.
+///
+pub fn find_code(sections: &[Section]) -> BTreeSet<u16> {
+    let words = words_of(sections);
+    let mut entry_points = Vec::new();
+    for section in sections {
+        for (i, word) in section.words.iter().enumerate() {
+            if section.origin as usize + i <= INTERRUPT_VECTOR_TABLE_END as usize {
+                entry_points.push(*word);
+            }
+        }
+        if section.origin > INTERRUPT_VECTOR_TABLE_END && !section.words.is_empty() {
+            entry_points.push(section.origin);
+        }
+    }
+
+    let mut visited = BTreeSet::new();
+    while let Some(address) = entry_points.pop() {
+        let Some(&instr) = words.get(&address) else {
+            continue;
+        };
+        if !visited.insert(address) {
+            continue;
+        }
+        let next = address.wrapping_add(1);
+        let instruction = Instruction::decode(instr);
+        match instruction {
+            Instruction::Illegal(_) | Instruction::Jmp { .. } | Instruction::Rti => {}
+            Instruction::Trap { vector, .. } if vector as u16 == TRAP_HALT => {}
+            Instruction::Br { conditions, .. } => {
+                entry_points.extend(instruction.target(address));
+                if conditions != 0x7 {
+                    entry_points.push(next);
+                }
+            }
+            Instruction::Jsr { .. } => {
+                entry_points.extend(instruction.target(address));
+                entry_points.push(next);
+            }
+            _ => entry_points.push(next),
+        }
+    }
+    visited
+}
+
+/// Maps every address of the sections to its word.
+fn words_of(sections: &[Section]) -> BTreeMap<u16, u16> {
+    let mut words = BTreeMap::new();
+    for section in sections {
+        let Some(end) = end_of(section) else {
+            continue;
+        };
+        words.extend((section.origin..=end).zip(section.words.iter().copied()));
+    }
+    words
+}
+
+/// Returns the last address of a section, or `None` if it is empty.
+fn end_of(section: &Section) -> Option<u16> {
+    let length = u16::try_from(section.words.len()).ok()?;
+    let last = length.checked_sub(1)?;
+    section.origin.checked_add(last)
+}
+
+/// Extracts the description of an error that prevented an image from being read.
+fn error_message(error: VmError) -> String {
+    match error {
+        VmError::FailedToOpenFile(message) | VmError::InvalidImage(message) => message,
+        other => format!("{:?}", other),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn section(origin: u16, words: &[u16]) -> Section {
+        Section {
+            origin,
+            words: words.to_vec(),
+        }
+    }
+
+    fn check(sections: &[Section]) -> Vec<Diagnostic> {
+        check_image("prog.obj", sections, sections)
+    }
+
+    #[test]
+    fn clean_program_has_no_diagnostics() {
+        // LEA R0, MSG; PUTS; HALT; MSG .FILL xD048, a data word that looks like a reserved opcode
+        let sections = [section(0x3000, &[0xE002, 0xF022, 0xF025, 0xD048])];
+
+        assert!(check(&sections).is_empty());
+    }
+
+    #[test]
+    fn reserved_opcode_in_code_is_an_error() {
+        let diagnostics = check(&[section(0x3000, &[0x1021, 0xD000, 0xF025])]);
+
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].severity, Severity::Error);
+        assert_eq!(diagnostics[0].address, Some(0x3001));
+    }
+
+    #[test]
+    fn malformed_instructions_in_code_are_warnings() {
+        // NOT R0, R0 with the low bits cleared; RTI with a low bit set; HALT
+        let diagnostics = check(&[section(0x3000, &[0x9000, 0x8001, 0xF025])]);
+
+        assert_eq!(
+            diagnostics
+                .iter()
+                .map(|d| d.to_string())
+                .collect::<Vec<_>>(),
+            vec![
+                "prog.obj: x3000: warning: instruction with reserved bits set in code (x9000)",
+                "prog.obj: x3001: warning: illegal instruction in code (x8001)",
+            ]
+        );
+    }
+
+    #[test]
+    fn branch_outside_loaded_images_is_a_warning() {
+        // BRnzp #16; HALT
+        let diagnostics = check(&[section(0x3000, &[0x0E10, 0xF025])]);
+
+        assert_eq!(
+            diagnostics[0].to_string(),
+            "prog.obj: x3000: warning: branch target x3011 is outside the loaded images"
+        );
+    }
+
+    #[test]
+    fn branch_into_another_image_is_fine() {
+        // JSR #-2 calls x2000, which is loaded from another image.
+        let own = [section(0x2001, &[0x4FFE, 0xF025])];
+        let loaded = [own[0].clone(), section(0x2000, &[0xC1C0])];
+
+        assert!(check_image("prog.obj", &own, &loaded).is_empty());
+    }
+
+    #[test]
+    fn reserved_regions_are_reported() {
+        let diagnostics = check(&[section(0x00F0, &[0x0400; 0x20]), section(0xFE00, &[0])]);
+        let messages: Vec<_> = diagnostics
+            .iter()
+            .map(|d| (d.severity, d.address))
+            .collect();
+
+        assert_eq!(
+            messages,
+            vec![
+                (Severity::Warning, Some(0x00F0)),
+                (Severity::Warning, Some(0x0100)),
+                (Severity::Error, Some(0xFE00)),
+            ]
+        );
+    }
+
+    #[test]
+    fn empty_section_is_a_warning() {
+        let diagnostics = check(&[section(0x3000, &[])]);
+
+        assert_eq!(diagnostics[0].severity, Severity::Warning);
+    }
+
+    #[test]
+    fn unreadable_images_are_errors() {
+        let path = std::env::temp_dir().join("lc3_validation_odd.obj");
+        let path = path.to_string_lossy().to_string();
+        std::fs::write(&path, [0x30, 0x00, 0xF0]).unwrap();
+
+        let diagnostics = check_images(std::slice::from_ref(&path));
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].severity, Severity::Error);
+        assert!(diagnostics[0].message.starts_with("odd file length"));
+    }
+}
@@ -1,30 +1,109 @@
-use std::process;
-
 use crate::{
-    constants::{
-        OP_ADD, OP_AND, OP_BR, OP_JMP, OP_JSR, OP_LD, OP_LDI, OP_LDR, OP_LEA, OP_NOT, OP_ST,
-        OP_STI, OP_STR, OP_TRAP,
-    },
+    constants::DEVICE_PAGE_START,
+    convention::ConventionChecker,
+    coverage::Coverage,
+    image_format::Section,
     input_buffering::{disable_input_buffering, restore_input_buffering},
+    instruction::Instruction,
+    load_map::{LoadMap, LoadedImage, OverlapPolicy},
     memory::Memory,
     registers::Registers,
+    rng::Rng,
+    shadow::{Location, Shadow},
+    stack::StackMonitor,
+    symbols::SymbolTable,
+    translation::BlockTranslator,
     utils::{flush_stdout, read_image_file},
     vm_error::VmError,
 };
 
+/// Options that control how image files are loaded.
+///
+/// # Fields
+///
+/// * `overlap_policy` - What to do when an image overwrites memory loaded by a previous one.
+///
+#[derive(Debug, Clone, Default)]
+pub struct LoadOptions {
+    pub overlap_policy: OverlapPolicy,
+}
+
+/// How `Vm::run` executes the program.
+///
+/// * `Interpreter` - Fetches, decodes and executes one instruction at a time with `Vm::step`.
+/// * `Blocks` - Translates basic blocks into closures with a `BlockTranslator` and runs them.
+///
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Backend {
+    #[default]
+    Interpreter,
+    Blocks,
+}
+
+impl Backend {
+    /// Parses a backend name as given on the command line: `interp` or `blocks`.
+    pub fn from_name(name: &str) -> Option<Backend> {
+        match name {
+            "interp" | "interpreter" => Some(Backend::Interpreter),
+            "blocks" => Some(Backend::Blocks),
+            _ => None,
+        }
+    }
+}
+
 /// Represents the virtual machine (VM) that emulates the LC-3 computer.
 ///
 /// # Fields
 /// * `registers` - Holds the state of the LC-3 registers.
 /// * `memory` - Manages the memory of the LC-3 machine.
-/// * `termios` - Stores the terminal I/O settings to handle input buffering.
+/// * `load_map` - The image files loaded into memory, in load order.
+/// * `symbols` - Symbols and source lines of the loaded images, used whenever an address is shown.
+/// * `coverage` - Coverage collector, only present when coverage tracking is enabled.
+/// * `shadow` - Tracks initialized registers and memory, only present when uninitialized reads
+///   are checked.
+/// * `convention` - Shadow call stack checking `JSR`/`RET` discipline, only present when enabled.
+/// * `stack` - Bounds of the `R6` stack, only present when the stack is monitored.
+/// * `backend` - The execution backend used by `run`.
 ///
 pub struct Vm {
     pub registers: Registers,
     pub memory: Memory,
+    pub load_map: LoadMap,
+    pub symbols: SymbolTable,
+    pub coverage: Option<Coverage>,
+    pub shadow: Option<Shadow>,
+    pub convention: Option<ConventionChecker>,
+    pub stack: Option<StackMonitor>,
+    pub backend: Backend,
+}
+
+impl Default for Vm {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl Vm {
+    /// Creates a new `Vm` instance with zeroed memory, default registers and no images loaded.
+    ///
+    /// # Returns
+    ///
+    /// A `Vm` instance ready to have a program written into its memory.
+    ///
+    pub fn new() -> Vm {
+        Vm {
+            registers: Registers::new(),
+            memory: Memory::new(),
+            load_map: LoadMap::new(),
+            symbols: SymbolTable::new(),
+            coverage: None,
+            shadow: None,
+            convention: None,
+            stack: None,
+            backend: Backend::Interpreter,
+        }
+    }
+
     /// Creates a new `Vm` instance from a set of image files.
     ///
     /// This method initializes the memory and registers of the LC-3 machine,
@@ -40,16 +119,115 @@ impl Vm {
     /// A Result with a fully initialized `Vm` instance ready to run the loaded program or an error if something went wrong.
     ///
     pub fn new_from_images(args: Vec<String>) -> Result<Vm, VmError> {
-        let mut memory = Memory::new();
-        let registers = Registers::new();
+        Vm::new_from_images_with_options(args, &LoadOptions::default())
+    }
+
+    /// Creates a new `Vm` instance from a set of image files, using the given loading options.
+    ///
+    /// # Arguments
+    ///
+    /// * `args` - A vector of strings representing the paths to the image files.
+    /// * `options` - How the images should be loaded.
+    ///
+    /// # Returns
+    ///
+    /// A Result with a fully initialized `Vm` instance or an error if an image could not be loaded.
+    ///
+    pub fn new_from_images_with_options(
+        args: Vec<String>,
+        options: &LoadOptions,
+    ) -> Result<Vm, VmError> {
+        let mut vm = Vm::new();
 
         for path in &args[1..] {
             println!("Loading image file: {}", path);
             flush_stdout()?;
-            read_image_file(path, &mut memory)?;
+            vm.load_image(path, options)?;
         }
 
-        Ok(Vm { registers, memory })
+        Ok(vm)
+    }
+
+    /// Loads an image file into memory and records it in the load map.
+    ///
+    /// If the image overlaps a previously loaded one, the overlap is either reported as
+    /// a warning or returned as an error, depending on `options.overlap_policy`. A symbol
+    /// table next to the image (`prog.sym` or `prog.sym.json` for `prog.obj`) is loaded too.
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - The path to the image file.
+    /// * `options` - How the image should be loaded.
+    ///
+    /// # Returns
+    ///
+    /// An `Ok` result if the image was loaded, otherwise a `VmError`.
+    ///
+    pub fn load_image(&mut self, path: &str, options: &LoadOptions) -> Result<(), VmError> {
+        let mut memory = Memory::new();
+        let images = read_image_file(path, &mut memory)?;
+        let symbols = SymbolTable::for_image(path)?;
+        let overlaps = self
+            .load_map
+            .add_all(images.clone(), options.overlap_policy)?;
+        for overlap in overlaps {
+            println!("Warning: {}", overlap);
+        }
+        if let Some(symbols) = symbols {
+            self.symbols.merge(symbols);
+        }
+        for address in images.iter().flat_map(LoadedImage::addresses) {
+            self.memory.write(address, memory.memory[address as usize]);
+            if let Some(shadow) = self.shadow.as_mut() {
+                shadow.initialize(Location::Memory(address));
+            }
+        }
+        flush_stdout()
+    }
+
+    /// Writes sections, such as the output of the assembler, into memory.
+    ///
+    /// Unlike `load_image`, nothing is added to the load map and no symbols are loaded.
+    ///
+    /// # Arguments
+    ///
+    /// * `sections` - The sections to write, in order.
+    ///
+    pub fn load_sections(&mut self, sections: &[Section]) {
+        for section in sections {
+            for (i, word) in section.words.iter().enumerate() {
+                let address = section.origin.wrapping_add(i as u16);
+                self.memory.write(address, *word);
+                if let Some(shadow) = self.shadow.as_mut() {
+                    shadow.initialize(Location::Memory(address));
+                }
+            }
+        }
+    }
+
+    /// Fills the general-purpose registers and every memory cell that no image was loaded
+    /// into with random values, instead of the zeros of `Registers::new` and `Memory::new`.
+    ///
+    /// Programs that rely on zeroed state then misbehave, and the same seed always gives the
+    /// same state, so a failing run can be replayed. The PC, the condition flags and the
+    /// device page are left as they are. Cells loaded with `load_sections` are not in the load
+    /// map, so the sections should be loaded after the state is randomized.
+    ///
+    /// # Arguments
+    ///
+    /// * `seed` - The seed of the random values.
+    ///
+    pub fn randomize(&mut self, seed: u64) {
+        let mut rng = Rng::new(seed);
+        for r in 0..8 {
+            self.registers[r] = rng.next_u16();
+        }
+        for address in 0..DEVICE_PAGE_START {
+            let value = rng.next_u16();
+            if self.load_map.image_for(address).is_none() {
+                self.memory.write(address, value);
+            }
+        }
     }
 
     /// Runs the loaded program.
@@ -60,7 +238,7 @@ impl Vm {
     ///
     /// # Errors
     ///
-    /// If the execution encounters a critical error (which causes to halt), the terminal settings will be restored and the program will exit with an error code.
+    /// If the execution encounters a critical error, the terminal settings are restored before the error is returned.
     ///
     /// # Returns
     ///
@@ -68,60 +246,233 @@ impl Vm {
     ///
     pub fn run(&mut self) -> Result<(), VmError> {
         let termios = disable_input_buffering()?;
+        let result = self.run_until_halt();
+        restore_input_buffering(&termios)?;
+        result
+    }
+
+    /// Runs the program with the selected backend until it halts, without touching the terminal.
+    ///
+    /// # Returns
+    ///
+    /// Returns `Ok(())` when the program halted, otherwise the error that stopped it.
+    ///
+    pub fn run_until_halt(&mut self) -> Result<(), VmError> {
         let mut running = true;
-        while running {
-            let pc = self.registers.pc;
-            let instr = self.memory.read(pc)?;
-            self.registers.pc = self.registers.pc.wrapping_add(1);
-            let op = instr >> 12;
+        match self.backend {
+            Backend::Interpreter => {
+                while running {
+                    self.step(&mut running)?;
+                }
+                Ok(())
+            }
+            Backend::Blocks => BlockTranslator::new().run(self, &mut running),
+        }
+    }
+
+    /// Fetches, decodes and executes a single instruction.
+    ///
+    /// # Parameters
+    ///
+    /// - `running`: Boolean flag that indicates if the program is running, cleared when the program halts.
+    ///
+    /// # Returns
+    ///
+    /// Returns `Ok(())` if the instruction was executed successfully, otherwise returns a `VmError`.
+    /// When the instruction triggered a watchpoint it still completes, and `VmError::WatchpointHit`
+    /// is returned so the caller can stop. An access the memory regions do not allow stops the
+    /// instruction where it happens and returns `VmError::AccessViolation`; the VM has no
+    /// supervisor mode, so no ACV exception is raised.
+    ///
+    pub fn step(&mut self, running: &mut bool) -> Result<(), VmError> {
+        let pc = self.registers.pc;
+        self.memory.watch_hit = None;
+        self.memory.access_violation = None;
+        let instruction = self.memory.fetch_instruction(pc)?;
+        self.registers.pc = self.registers.pc.wrapping_add(1);
 
-            self.handle_operations(instr, op, &mut running)?;
+        if let Some(coverage) = self.coverage.as_mut() {
+            coverage.record_execution(pc);
+        }
+        if self.checks_instructions() {
+            self.check_instruction(pc, instruction);
+        }
+
+        self.execute(instruction, running)?;
+
+        if let Some(violation) = self.memory.access_violation.take() {
+            return Err(VmError::AccessViolation(violation));
+        }
+        match self.memory.watch_hit.take() {
+            Some(hit) => Err(VmError::WatchpointHit(hit)),
+            None => Ok(()),
         }
-        restore_input_buffering(&termios)?;
-        process::exit(1);
     }
 
-    /// Handles the execution of operations based on the provided opcode.
+    /// Returns whether any checker needs to see instructions before they are executed.
+    pub(crate) fn checks_instructions(&self) -> bool {
+        self.shadow.is_some() || self.convention.is_some() || self.stack.is_some()
+    }
+
+    /// Runs the enabled checkers on an instruction about to be executed.
+    ///
+    /// # Parameters
+    ///
+    /// - `pc`: The address of the instruction.
+    /// - `instruction`: The instruction about to be executed.
+    ///
+    pub(crate) fn check_instruction(&mut self, pc: u16, instruction: Instruction) {
+        self.check_shadow(pc, instruction);
+        self.check_convention(pc, instruction);
+        self.check_stack(pc, instruction);
+    }
+
+    /// Executes a decoded instruction.
+    ///
+    /// `RTI` is not supported, since the VM has no supervisor mode, so it is reported like the
+    /// reserved opcode and halts the program. Bits an instruction ignores are ignored here too.
     ///
     /// # Parameters
     ///
-    /// - `instr`: The instruction to be executed.
-    /// - `op`: The operation code extracted from the instruction.
+    /// - `instruction`: The instruction to be executed.
     /// - `running`: Boolean flag that indicates if the program is running.
     ///
     /// # Returns
     ///
-    /// Returns `Ok(())` if the handling was successful, otherwise returns a `VmError`.
-    ///
-    pub fn handle_operations(
-        &mut self,
-        instr: u16,
-        op: u16,
-        running: &mut bool,
-    ) -> Result<(), VmError> {
-        match op {
-            OP_ADD => self.op_add(instr),
-            OP_AND => self.op_and(instr),
-            OP_NOT => self.op_not(instr),
-            OP_BR => {
-                self.op_br(instr);
+    /// Returns `Ok(())` if the execution was successful, otherwise returns a `VmError`.
+    ///
+    pub fn execute(&mut self, instruction: Instruction, running: &mut bool) -> Result<(), VmError> {
+        match instruction {
+            Instruction::Add { dr, sr1, src2, .. } => self.op_add(dr, sr1, src2),
+            Instruction::And { dr, sr1, src2, .. } => self.op_and(dr, sr1, src2),
+            Instruction::Not { dr, sr, .. } => self.op_not(dr, sr),
+            Instruction::Br { conditions, offset } => {
+                self.op_br(conditions, offset);
                 Ok(())
             }
-            OP_JMP => self.op_jmp(instr),
-            OP_JSR => self.op_jsr(instr),
-            OP_LD => self.op_ld(instr),
-            OP_LDI => self.op_ldi(instr),
-            OP_LDR => self.op_ldr(instr),
-            OP_LEA => self.op_lea(instr),
-            OP_ST => self.op_st(instr),
-            OP_STI => self.op_sti(instr),
-            OP_STR => self.op_str(instr),
-            OP_TRAP => self.handle_trap(instr, running),
-            _ => {
-                println!("Bad opcode: {}", op);
-                flush_stdout()?;
+            Instruction::Jmp { base, .. } => self.op_jmp(base),
+            Instruction::Jsr { offset } => self.op_jsr(offset),
+            Instruction::Jsrr { base, .. } => self.op_jsrr(base),
+            Instruction::Ld { dr, offset } => self.op_ld(dr, offset),
+            Instruction::Ldi { dr, offset } => self.op_ldi(dr, offset),
+            Instruction::Ldr { dr, base, offset } => self.op_ldr(dr, base, offset),
+            Instruction::Lea { dr, offset } => self.op_lea(dr, offset),
+            Instruction::St { sr, offset } => self.op_st(sr, offset),
+            Instruction::Sti { sr, offset } => self.op_sti(sr, offset),
+            Instruction::Str { sr, base, offset } => self.op_str(sr, base, offset),
+            Instruction::Trap { vector, .. } => self.handle_trap(vector, running),
+            Instruction::Rti | Instruction::Illegal(_) => {
+                let message = format!(
+                    "Bad opcode {} at {}\n",
+                    instruction.encode() >> 12,
+                    self.symbols.format_address(self.memory.last_fetch)
+                );
+                self.memory.console.write(&message)?;
                 self.trap_halt(running)
             }
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn writes_invalidate_predecoded_instructions() {
+        let mut vm = Vm::new();
+        vm.memory.write(0x3000, 0x1021); // ADD R0, R0, #1
+        vm.memory.write(0x3001, 0x3FFE); // ST R7, #-2: overwrites x3000 with R7
+        vm.memory.write(0x3002, 0x0FFD); // BRnzp #-3
+        vm.registers[7] = 0x1262; // ADD R1, R1, #2
+        let mut running = true;
+
+        for _ in 0..4 {
+            vm.step(&mut running).unwrap();
+        }
+
+        assert_eq!(vm.registers[0], 1);
+        assert_eq!(vm.registers[1], 2);
+    }
+
+    #[test]
+    fn randomize_is_reproducible_and_spares_loaded_images() {
+        let mut vm = Vm::new();
+        vm.load_map
+            .add(
+                LoadedImage {
+                    path: "test.obj".to_string(),
+                    origin: 0x3000,
+                    length: 2,
+                },
+                OverlapPolicy::Error,
+            )
+            .unwrap();
+        vm.memory.write(0x3000, 0x1234);
+        let mut other = Vm::new();
+
+        vm.randomize(42);
+        other.randomize(42);
+
+        assert_eq!(vm.registers, other.registers);
+        assert_eq!(vm.memory.memory[..0x3000], other.memory.memory[..0x3000]);
+        assert_ne!(vm.registers.gpr, [0; 8]);
+        assert_eq!(vm.memory.memory[0x3000], 0x1234);
+        assert_eq!(vm.memory.memory[0x3001], 0);
+        assert_eq!(vm.memory.memory[DEVICE_PAGE_START as usize], 0);
+        assert_eq!(vm.registers.pc, Registers::new().pc);
+    }
+
+    #[test]
+    fn an_image_with_an_overlapping_section_is_not_loaded_at_all() {
+        let path = std::env::temp_dir().join("lc3_vm_overlapping_section.obj");
+        let path = path.to_string_lossy().to_string();
+        let sections = [
+            Section {
+                origin: 0x4000,
+                words: vec![0x0041],
+            },
+            Section {
+                origin: 0x3001,
+                words: vec![0xF025],
+            },
+        ];
+        std::fs::write(
+            &path,
+            crate::image_format::write_image(crate::image_format::ImageFormat::Lc3Tools, &sections)
+                .unwrap(),
+        )
+        .unwrap();
+        let mut vm = Vm::new();
+        let earlier = LoadedImage {
+            path: "earlier.obj".to_string(),
+            origin: 0x3000,
+            length: 2,
+        };
+        vm.load_map
+            .add(earlier.clone(), OverlapPolicy::Error)
+            .unwrap();
+        let options = LoadOptions {
+            overlap_policy: OverlapPolicy::Error,
+        };
+
+        let result = vm.load_image(&path, &options);
+        std::fs::remove_file(&path).unwrap();
+
+        assert!(matches!(result, Err(VmError::OverlappingImages(_))));
+        assert_eq!(vm.load_map.images, vec![earlier]);
+        assert_eq!(vm.memory.memory[0x4000], 0);
+    }
+
+    #[test]
+    fn predecoding_can_be_disabled() {
+        let mut vm = Vm::new();
+        vm.memory.predecode = false;
+        vm.memory.write(0x3000, 0x1021); // ADD R0, R0, #1
+        let mut running = true;
+
+        vm.step(&mut running).unwrap();
+
+        assert_eq!(vm.registers[0], 1);
+    }
+}
@@ -1,3 +1,5 @@
+use crate::{permissions::AccessViolation, watchpoint::WatchHit};
+
 /// Custom error for the VM
 #[derive(Debug)]
 pub enum VmError {
@@ -5,8 +7,18 @@ pub enum VmError {
     FailedToCreateTermios(String),
     FailedToSetAttrTermios(String),
     FailedToOpenFile(String),
-    FailedToReadBigEndian(String),
+    InvalidImage(String),
+    OverlappingImages(String),
+    InvalidSymbolTable(String),
+    AssemblyError(String),
+    LinkError(String),
+    FailedToWriteFile(String),
     FailedToFlush(String),
     FailedToReadStdin(String),
     InvalidRegister(String),
+    InvalidExpression(String),
+    InvalidSpec(String),
+    CallFailed(String),
+    WatchpointHit(WatchHit),
+    AccessViolation(AccessViolation),
 }
@@ -0,0 +1,257 @@
+use std::fmt;
+
+use crate::symbols::{Describe, SymbolTable};
+
+/// The kind of memory access that was performed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AccessKind {
+    Read,
+    Write,
+}
+
+/// Which accesses a watchpoint reacts to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WatchMode {
+    Read,
+    Write,
+    ReadWrite,
+}
+
+/// Extra condition on the value involved in the access.
+///
+/// For writes the condition is checked against the value being written, for
+/// reads against the value that was read.
+///
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ValueCondition {
+    Equals(u16),
+    NotEquals(u16),
+    /// Only triggers when a write changes the stored value.
+    Changed,
+}
+
+/// A watchpoint on a single address or an inclusive range of addresses.
+///
+/// # Fields
+///
+/// * `start`, `end` - Inclusive bounds of the watched range.
+/// * `mode` - The accesses that trigger the watchpoint.
+/// * `condition` - Optional condition on the accessed value.
+///
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Watchpoint {
+    pub start: u16,
+    pub end: u16,
+    pub mode: WatchMode,
+    pub condition: Option<ValueCondition>,
+}
+
+impl Watchpoint {
+    /// Creates an unconditional watchpoint on the inclusive range `start..=end`.
+    pub fn new(start: u16, end: u16, mode: WatchMode) -> Watchpoint {
+        Watchpoint {
+            start,
+            end,
+            mode,
+            condition: None,
+        }
+    }
+
+    /// Returns the watchpoint with a condition on the accessed value.
+    pub fn with_condition(mut self, condition: ValueCondition) -> Watchpoint {
+        self.condition = Some(condition);
+        self
+    }
+
+    /// Checks whether an access triggers this watchpoint.
+    ///
+    /// # Arguments
+    ///
+    /// * `address` - The accessed address.
+    /// * `kind` - Whether the access was a read or a write.
+    /// * `old_value` - The value stored before the access.
+    /// * `new_value` - The value stored after the access.
+    ///
+    /// # Returns
+    ///
+    /// `true` if the watchpoint should stop the VM.
+    ///
+    pub fn matches(&self, address: u16, kind: AccessKind, old_value: u16, new_value: u16) -> bool {
+        if address < self.start || address > self.end {
+            return false;
+        }
+        let mode_matches = matches!(
+            (self.mode, kind),
+            (WatchMode::ReadWrite, _)
+                | (WatchMode::Read, AccessKind::Read)
+                | (WatchMode::Write, AccessKind::Write)
+        );
+        if !mode_matches {
+            return false;
+        }
+        match self.condition {
+            None => true,
+            Some(ValueCondition::Equals(v)) => new_value == v,
+            Some(ValueCondition::NotEquals(v)) => new_value != v,
+            Some(ValueCondition::Changed) => old_value != new_value,
+        }
+    }
+}
+
+impl fmt::Display for Watchpoint {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.start == self.end {
+            write!(f, "x{:04X}", self.start)?;
+        } else {
+            write!(f, "x{:04X}-x{:04X}", self.start, self.end)?;
+        }
+        let mode = match self.mode {
+            WatchMode::Read => "r",
+            WatchMode::Write => "w",
+            WatchMode::ReadWrite => "rw",
+        };
+        write!(f, " {}", mode)?;
+        match self.condition {
+            None => Ok(()),
+            Some(ValueCondition::Equals(v)) => write!(f, " ==x{:04X}", v),
+            Some(ValueCondition::NotEquals(v)) => write!(f, " !=x{:04X}", v),
+            Some(ValueCondition::Changed) => write!(f, " changed"),
+        }
+    }
+}
+
+/// Details of the access that triggered a watchpoint.
+///
+/// # Fields
+///
+/// * `pc` - Address of the instruction that performed the access.
+/// * `address` - The accessed address.
+/// * `kind` - Whether the access was a read or a write.
+/// * `old_value`, `new_value` - The stored value before and after the access.
+///
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WatchHit {
+    pub pc: u16,
+    pub address: u16,
+    pub kind: AccessKind,
+    pub old_value: u16,
+    pub new_value: u16,
+}
+
+impl Describe for WatchHit {
+    fn describe(&self, symbols: &SymbolTable) -> String {
+        match self.kind {
+            AccessKind::Read => format!(
+                "Watchpoint: instruction at {} read x{:04X} from {}",
+                symbols.format_address(self.pc),
+                self.new_value,
+                symbols.format_address(self.address)
+            ),
+            AccessKind::Write => format!(
+                "Watchpoint: instruction at {} wrote x{:04X} to {} (was x{:04X})",
+                symbols.format_address(self.pc),
+                self.new_value,
+                symbols.format_address(self.address),
+                self.old_value
+            ),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vm::Vm;
+    use crate::vm_error::VmError;
+
+    fn create_vm() -> Vm {
+        Vm::new()
+    }
+
+    #[test]
+    fn range_and_mode_are_respected() {
+        let watch = Watchpoint::new(0x4000, 0x400F, WatchMode::Write);
+
+        assert!(watch.matches(0x4000, AccessKind::Write, 0, 1));
+        assert!(watch.matches(0x400F, AccessKind::Write, 0, 1));
+        assert!(!watch.matches(0x4010, AccessKind::Write, 0, 1));
+        assert!(!watch.matches(0x4000, AccessKind::Read, 0, 0));
+    }
+
+    #[test]
+    fn value_conditions() {
+        let equals = Watchpoint::new(0x4000, 0x4000, WatchMode::Write)
+            .with_condition(ValueCondition::Equals(0xBEEF));
+        let changed = Watchpoint::new(0x4000, 0x4000, WatchMode::Write)
+            .with_condition(ValueCondition::Changed);
+
+        assert!(equals.matches(0x4000, AccessKind::Write, 0, 0xBEEF));
+        assert!(!equals.matches(0x4000, AccessKind::Write, 0, 0xBEEE));
+        assert!(changed.matches(0x4000, AccessKind::Write, 1, 2));
+        assert!(!changed.matches(0x4000, AccessKind::Write, 2, 2));
+    }
+
+    #[test]
+    fn str_into_watched_address_stops_the_vm() {
+        let mut vm = create_vm();
+        vm.memory.write(0x4000, 0x1111);
+        vm.registers.set(0, 0x2222).unwrap();
+        vm.registers.set(1, 0x4000).unwrap();
+        vm.memory.write(0x3000, 0b0111_0000_0100_0000); // STR R0, R1, #0
+        vm.memory
+            .watchpoints
+            .push(Watchpoint::new(0x4000, 0x4000, WatchMode::Write));
+
+        let mut running = true;
+        let hit = match vm.step(&mut running) {
+            Err(VmError::WatchpointHit(hit)) => hit,
+            other => panic!("expected a watchpoint hit, got {:?}", other),
+        };
+
+        assert_eq!(
+            hit,
+            WatchHit {
+                pc: 0x3000,
+                address: 0x4000,
+                kind: AccessKind::Write,
+                old_value: 0x1111,
+                new_value: 0x2222,
+            }
+        );
+        assert_eq!(vm.memory.read(0x4000).unwrap(), 0x2222);
+        assert_eq!(vm.registers.pc, 0x3001);
+    }
+
+    #[test]
+    fn hit_description_uses_symbols() {
+        let mut symbols = SymbolTable::new();
+        symbols.insert("MAIN", 0x3000);
+        symbols.insert("COUNT", 0x4000);
+        let hit = WatchHit {
+            pc: 0x3002,
+            address: 0x4000,
+            kind: AccessKind::Read,
+            old_value: 7,
+            new_value: 7,
+        };
+
+        assert_eq!(
+            hit.describe(&symbols),
+            "Watchpoint: instruction at x3002 <MAIN+2> read x0007 from x4000 <COUNT>"
+        );
+    }
+
+    #[test]
+    fn instruction_fetch_does_not_trigger_read_watchpoints() {
+        let mut vm = create_vm();
+        vm.memory
+            .watchpoints
+            .push(Watchpoint::new(0x3000, 0x3000, WatchMode::Read));
+        vm.memory.write(0x3000, 0b0001_0000_0010_0001); // ADD R0, R0, #1
+
+        let mut running = true;
+        vm.step(&mut running).unwrap();
+
+        assert_eq!(vm.registers[0], 1);
+    }
+}
@@ -0,0 +1,185 @@
+//! ISA conformance suite.
+//!
+//! Every program in `tests/conformance/` is assembled, loaded with the public `Vm` API and run
+//! on each backend with a scripted console. Its leading comments give the expected outcome:
+//!
+//! * `; expect NAME=VALUE ..` - `NAME` is `R0`-`R7`, `PC`, `COND` (`n`, `z` or `p`), or a label
+//!   or address whose memory cell is checked. Values are LC-3 numbers, such as `x3000` or `#-1`.
+//! * `; input "TEXT"` - The keys typed on the keyboard, with the escapes of `.STRINGZ`.
+//! * `; output "TEXT"` - Everything the program prints, including the `HALT` message.
+//! * `; steps N` - The most instructions the program may execute (10,000 by default).
+//!
+//! The program starts at the origin of its first section and must halt.
+
+use std::fs;
+
+use lc_3_vm::{
+    assembler::{assemble, preprocessor::unquote, Assembly},
+    console::ScriptedConsole,
+    constants::{FL_NEG, FL_POS, FL_ZRO},
+    translation::BlockTranslator,
+    utils::parse_word,
+    vm::{Backend, Vm},
+};
+
+const PROGRAMS: &str = "tests/conformance";
+const DEFAULT_STEPS: u64 = 10_000;
+
+/// What a program is expected to do.
+#[derive(Default)]
+struct Expectations {
+    checks: Vec<(String, u16)>,
+    input: Vec<u8>,
+    output: Option<String>,
+    steps: Option<u64>,
+}
+
+fn parse_expectations(text: &str) -> Expectations {
+    let mut expectations = Expectations::default();
+    for line in text.lines() {
+        let Some(comment) = line.trim().strip_prefix(';') else {
+            continue;
+        };
+        let comment = comment.trim();
+        let (directive, rest) = comment.split_once(' ').unwrap_or((comment, ""));
+        match directive {
+            "expect" => {
+                for check in rest.split_whitespace() {
+                    let (name, value) = check
+                        .split_once('=')
+                        .unwrap_or_else(|| panic!("expected NAME=VALUE, found {}", check));
+                    let value = match value {
+                        "n" => FL_NEG,
+                        "z" => FL_ZRO,
+                        "p" => FL_POS,
+                        _ => parse_word(value)
+                            .unwrap_or_else(|| panic!("invalid value in {}", check)),
+                    };
+                    expectations.checks.push((name.to_string(), value));
+                }
+            }
+            "input" => expectations.input = unquote(rest.trim()).unwrap().into_bytes(),
+            "output" => expectations.output = Some(unquote(rest.trim()).unwrap()),
+            "steps" => expectations.steps = Some(rest.trim().parse().unwrap()),
+            _ => {}
+        }
+    }
+    expectations
+}
+
+/// Runs the program on a fresh machine until it halts, returning the machine and its output.
+fn run(
+    assembly: &Assembly,
+    expectations: &Expectations,
+    backend: Backend,
+) -> Result<(Vm, String), String> {
+    let mut vm = Vm::new();
+    let console = ScriptedConsole::new(&expectations.input);
+    let output = console.output();
+    vm.memory.console = Box::new(console);
+    vm.load_sections(&assembly.sections);
+    vm.registers.pc = assembly.sections[0].origin;
+    vm.backend = backend;
+
+    let max_steps = expectations.steps.unwrap_or(DEFAULT_STEPS);
+    let mut running = true;
+    let mut translator = BlockTranslator::new();
+    let mut steps = 0;
+    while running {
+        if steps >= max_steps {
+            return Err(format!("did not halt within {} steps", max_steps));
+        }
+        let result = match backend {
+            Backend::Interpreter => {
+                steps += 1;
+                vm.step(&mut running)
+            }
+            Backend::Blocks => {
+                let result = translator.run_block(&mut vm, &mut running);
+                steps = translator.executed;
+                result
+            }
+        };
+        result.map_err(|e| format!("stopped with {:?}", e))?;
+    }
+    let output = output.borrow().clone();
+    Ok((vm, output))
+}
+
+/// Checks the final state of the machine, returning a description of every mismatch.
+fn check(vm: &Vm, output: &str, assembly: &Assembly, expectations: &Expectations) -> Vec<String> {
+    let mut failures = Vec::new();
+    for (name, expected) in &expectations.checks {
+        let actual = match name.as_str() {
+            "PC" => vm.registers.pc,
+            "COND" => vm.registers.cond,
+            _ => match name.strip_prefix('R').and_then(|n| n.parse::<u16>().ok()) {
+                Some(register) if register < 8 => vm.registers[register],
+                _ => {
+                    let address = assembly
+                        .symbols
+                        .address_of(name)
+                        .or_else(|| parse_word(name))
+                        .unwrap_or_else(|| panic!("unknown label or address {}", name));
+                    vm.memory.memory[address as usize]
+                }
+            },
+        };
+        if actual != *expected {
+            failures.push(format!(
+                "{}: expected x{:04X}, found x{:04X}",
+                name, expected, actual
+            ));
+        }
+    }
+    if let Some(expected) = &expectations.output {
+        if output != expected {
+            failures.push(format!(
+                "output: expected {:?}, found {:?}",
+                expected, output
+            ));
+        }
+    }
+    failures
+}
+
+#[test]
+fn conformance_programs() {
+    let mut paths: Vec<_> = fs::read_dir(PROGRAMS)
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension().is_some_and(|e| e == "asm"))
+        .collect();
+    paths.sort();
+    assert!(!paths.is_empty(), "no programs in {}", PROGRAMS);
+
+    let mut failures = Vec::new();
+    for path in &paths {
+        let path = path.to_str().unwrap();
+        let text = fs::read_to_string(path).unwrap();
+        let expectations = parse_expectations(&text);
+        assert!(
+            expectations.output.is_some(),
+            "{} has no expected output",
+            path
+        );
+        let assembly = match assemble(path, &text) {
+            Ok(assembly) => assembly,
+            Err(error) => {
+                failures.push(format!("{}: {:?}", path, error));
+                continue;
+            }
+        };
+        for backend in [Backend::Interpreter, Backend::Blocks] {
+            match run(&assembly, &expectations, backend) {
+                Ok((vm, output)) => {
+                    for failure in check(&vm, &output, &assembly, &expectations) {
+                        failures.push(format!("{} ({:?}): {}", path, backend, failure));
+                    }
+                }
+                Err(error) => failures.push(format!("{} ({:?}): {}", path, backend, error)),
+            }
+        }
+    }
+    assert!(failures.is_empty(), "\n{}", failures.join("\n"));
+}
@@ -0,0 +1,19 @@
+; ADD with register and immediate operands, the flags it sets and 16-bit overflow.
+;
+; expect R0=#5 R1=#-3 R2=#2 R3=#0 R4=x8000 R5=x0000 R6=#-16 COND=n
+; output "HALT\n"
+
+        .ORIG x3000
+        ADD R0, R0, #5          ; R0 = 5
+        ADD R1, R1, #-3         ; R1 = -3
+        ADD R2, R0, R1          ; R2 = 2
+        ADD R3, R2, #-2         ; R3 = 0, z
+        LD R4, MAXPOS
+        ADD R4, R4, #1          ; x7FFF + 1 overflows to x8000
+        LD R5, ALLONES
+        ADD R5, R5, #1          ; xFFFF + 1 wraps to x0000
+        ADD R6, R6, #-16        ; the smallest immediate
+        HALT
+MAXPOS  .FILL x7FFF
+ALLONES .FILL xFFFF
+        .END
@@ -0,0 +1,33 @@
+; ADD sets exactly one condition flag, stored where the program can see it.
+;
+; expect NEG=x0004 ZERO=x0002 POS=x0001 COND=p
+; output "HALT\n"
+
+        .ORIG x3000
+        ADD R0, R0, #-1
+        JSR SAVE
+        ST R1, NEG
+        ADD R0, R0, #1
+        JSR SAVE
+        ST R1, ZERO
+        ADD R0, R0, #1
+        JSR SAVE
+        ST R1, POS
+        HALT
+
+; Stores the flags of the last result in R1 as n=4, z=2, p=1.
+SAVE    AND R1, R1, #0
+        ADD R0, R0, #0
+        BRn ISNEG
+        BRz ISZERO
+        ADD R1, R1, #1
+        RET
+ISNEG   ADD R1, R1, #4
+        RET
+ISZERO  ADD R1, R1, #2
+        RET
+
+NEG     .FILL #0
+ZERO    .FILL #0
+POS     .FILL #0
+        .END
@@ -0,0 +1,16 @@
+; AND with register and immediate operands.
+;
+; expect R0=x0F0F R1=x00FF R2=x000F R3=x0000 R4=x000F R5=x0F0F COND=p
+; output "HALT\n"
+
+        .ORIG x3000
+        LD R0, MASK
+        LD R1, LOW
+        AND R2, R0, R1          ; x0F0F & x00FF
+        AND R3, R0, #0          ; clears, z
+        AND R4, R0, #15
+        AND R5, R0, #-1         ; -1 sign-extends to xFFFF
+        HALT
+MASK    .FILL x0F0F
+LOW     .FILL x00FF
+        .END
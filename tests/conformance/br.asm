@@ -0,0 +1,60 @@
+; Every combination of BR conditions, taken and not taken, against each flag.
+; R1 counts taken branches and R2 counts fall-throughs; BR without conditions never branches.
+;
+; expect R1=#12 R2=#12
+; output "HALT\n"
+
+        .ORIG x3000
+        ADD R0, R0, #-1         ; n
+        JSR TRY
+        ADD R0, R0, #1          ; z
+        JSR TRY
+        ADD R0, R0, #1          ; p
+        JSR TRY
+        HALT
+
+; Tries the eight condition masks against the current flags. Each BR is followed by a
+; fall-through increment of R2 which a taken branch skips, landing on an increment of R1.
+TRY     ADD R6, R7, #0
+        ADD R0, R0, #0
+        .FILL x0001             ; BR (no conditions) #1
+        ADD R2, R2, #1
+        BRnzp #1
+        ADD R1, R1, #1
+        ADD R0, R0, #0
+        BRn #2
+        ADD R2, R2, #1
+        BRnzp #1
+        ADD R1, R1, #1
+        ADD R0, R0, #0
+        BRz #2
+        ADD R2, R2, #1
+        BRnzp #1
+        ADD R1, R1, #1
+        ADD R0, R0, #0
+        BRp #2
+        ADD R2, R2, #1
+        BRnzp #1
+        ADD R1, R1, #1
+        ADD R0, R0, #0
+        BRnz #2
+        ADD R2, R2, #1
+        BRnzp #1
+        ADD R1, R1, #1
+        ADD R0, R0, #0
+        BRnp #2
+        ADD R2, R2, #1
+        BRnzp #1
+        ADD R1, R1, #1
+        ADD R0, R0, #0
+        BRzp #2
+        ADD R2, R2, #1
+        BRnzp #1
+        ADD R1, R1, #1
+        ADD R0, R0, #0
+        BRnzp #2
+        ADD R2, R2, #1
+        BRnzp #1
+        ADD R1, R1, #1
+        JMP R6
+        .END
@@ -0,0 +1,13 @@
+; RTI and the reserved opcode stop the program with a message. Bits that an instruction
+; ignores do not make it illegal: it runs as if they had their usual value.
+;
+; expect R0=xFFFF R2=xFFFE PC=x3004
+; output "Bad opcode 8 at x3003\nHALT\n"
+
+        .ORIG x3000
+        ADD R0, R0, #1
+        .FILL x907E             ; NOT R0, R1 with bits [5:0] = 111110
+        .FILL x1408             ; ADD R2, R0, R0 with bit 3 set
+        RTI
+        .FILL xD000
+        .END
@@ -0,0 +1,25 @@
+; JMP, RET, JSR and JSRR, including the return address in R7 and JSRR through R7 itself,
+; which jumps to the old value of R7.
+;
+; expect R1=#3 R3=x3005 R7=x3009 PC=x3009
+; output "HALT\n"
+
+        .ORIG x3000
+        JSR ONE                 ; R7 = x3001
+        LEA R5, TWO
+        JSRR R5                 ; R7 = x3003
+        LEA R7, THREE
+        JSRR R7                 ; jumps to THREE, R7 = x3005
+        LEA R6, DONE
+        JMP R6
+        ADD R1, R1, #8          ; skipped
+DONE    HALT                    ; R7 = x3009
+
+ONE     ADD R1, R1, #1
+        RET
+TWO     ADD R1, R1, #1
+        RET
+THREE   ADD R1, R1, #1
+        ADD R3, R7, #0
+        RET
+        .END
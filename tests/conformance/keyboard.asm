@@ -0,0 +1,19 @@
+; Polls the keyboard status register and reads keys from the keyboard data register,
+; echoing each one until a newline arrives.
+;
+; input "hi\n"
+; expect R1=#3 R0=#10 xFE02=#10
+; output "hi\nHALT\n"
+
+        .ORIG x3000
+POLL    LDI R2, KBSRPTR
+        BRzp POLL               ; bit 15 is set when a key is ready
+        LDI R0, KBDRPTR
+        ADD R1, R1, #1
+        OUT
+        ADD R3, R0, #-10        ; stop after the newline
+        BRnp POLL
+        HALT
+KBSRPTR .FILL xFE00
+KBDRPTR .FILL xFE02
+        .END
@@ -0,0 +1,16 @@
+; LD and ST with PC-relative offsets in both directions. LD sets the flags, ST does not.
+;
+; expect R0=x8001 R1=#0 BEFORE=x8001 AFTER=x8001 COND=n
+; output "HALT\n"
+
+        .ORIG x3000
+        BRnzp START
+BEFORE  .FILL #0
+START   LD R1, BEFORE           ; z
+        LD R0, VALUE            ; n
+        ST R0, BEFORE
+        ST R0, AFTER
+        HALT
+VALUE   .FILL x8001
+AFTER   .FILL #0
+        .END
@@ -0,0 +1,20 @@
+; LDI and STI go through a pointer stored next to the code.
+;
+; expect R0=#42 R1=#-7 TARGET=#-7 SOURCE=#42 COND=n
+; output "HALT\n"
+
+        .ORIG x3000
+        LDI R0, SRCPTR          ; R0 = SOURCE
+        LD R1, MINUS7
+        STI R1, DSTPTR          ; TARGET = -7
+        LDI R1, DSTPTR          ; reads it back, n
+        HALT
+SRCPTR  .FILL SOURCE
+DSTPTR  .FILL TARGET
+MINUS7  .FILL #-7
+        .END
+
+        .ORIG x4000
+SOURCE  .FILL #42
+TARGET  .FILL #0
+        .END
@@ -0,0 +1,21 @@
+; LDR and STR with positive and negative offsets from a base register.
+;
+; expect R0=#1 R1=#3 R2=#9 ARRAY=#1 x4001=#9 x4002=#3 x4005=#1 COND=p
+; output "HALT\n"
+
+        .ORIG x3000
+        LD R6, BASE             ; x4002
+        LDR R0, R6, #-2         ; ARRAY[0] = 1
+        LDR R1, R6, #0          ; ARRAY[2] = 3
+        ADD R2, R2, #9
+        STR R2, R6, #-1         ; ARRAY[1] = 9
+        STR R0, R6, #3          ; x4005 = 1
+        HALT
+BASE    .FILL x4002
+        .END
+
+        .ORIG x4000
+ARRAY   .FILL #1
+        .FILL #2
+        .FILL #3
+        .END
@@ -0,0 +1,12 @@
+; LEA loads an address without reading memory, and sets the flags from it.
+;
+; expect R0=x3004 R1=x2FFF R2=x3000 COND=p
+; output "HALT\n"
+
+        .ORIG x3000
+HERE    LEA R0, THERE
+        LEA R1, #-3             ; x3002 - 3, the word before the program
+        LEA R2, HERE
+        HALT
+THERE   .FILL xFFFF
+        .END
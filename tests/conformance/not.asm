@@ -0,0 +1,12 @@
+; NOT complements every bit and sets the flags.
+;
+; expect R0=x0000 R1=xFFFF R2=x5555 R3=xAAAA COND=n
+; output "HALT\n"
+
+        .ORIG x3000
+        NOT R1, R0              ; xFFFF, n
+        LD R2, PATTERN
+        NOT R3, R2
+        HALT
+PATTERN .FILL x5555
+        .END
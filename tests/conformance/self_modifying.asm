@@ -0,0 +1,16 @@
+; A loop that rewrites one of its own instructions: on the first pass the patched word is
+; ADD R1, R1, #1, after that it is ADD R1, R1, #2. Both backends must see every write.
+;
+; expect R1=#7 R2=#0 PATCH=x1262
+; output "HALT\n"
+
+        .ORIG x3000
+        ADD R2, R2, #4
+PATCH   ADD R1, R1, #1
+        LD R3, NEWINSTR
+        ST R3, PATCH
+        ADD R2, R2, #-1
+        BRp PATCH
+        HALT
+NEWINSTR ADD R1, R1, #2
+        .END
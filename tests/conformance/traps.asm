@@ -0,0 +1,31 @@
+; The console service routines: GETC, OUT, PUTS, IN, PUTSP and HALT. GETC does not echo,
+; IN prompts and echoes. Every trap saves the return address in R7.
+;
+; input "ab"
+; expect R0=x301B R1=#97 R2=#98 R7=x300F
+; output "a!Hello\nEnter a character: bPacked!HALT\n"
+
+        .ORIG x3000
+        GETC                    ; 'a', not echoed
+        ADD R1, R0, #0
+        OUT                     ; echoes it
+        LD R0, BANG
+        OUT
+        LEA R0, HELLO
+        PUTS
+        IN                      ; 'b', prompted and echoed
+        ADD R2, R0, #0
+        LEA R0, PACKED
+        PUTSP
+        LD R0, BANG
+        OUT
+        LEA R0, LAST
+        HALT
+BANG    .FILL x0021
+HELLO   .STRINGZ "Hello\n"
+PACKED  .FILL x6150             ; "Pa"
+        .FILL x6B63             ; "ck"
+        .FILL x6465             ; "ed"
+        .FILL x0000
+LAST    .FILL #0
+        .END
@@ -0,0 +1,26 @@
+; The PC, PC-relative addresses and base + offset addresses all wrap around the top of
+; memory. The program starts at xFFFA, runs into x0000 and branches back up to its HALT.
+;
+; expect R0=x0002 R1=#11 R2=#22 R3=x0000 xFFFE=#11 R7=xFFFA PC=xFFFA COND=z
+; output "HALT\n"
+
+        .ORIG xFFFA
+        LEA R0, #7              ; xFFFB + 7 = x0002
+        LD R1, #5               ; xFFFC + 5 = x0001
+        LDR R2, R0, #0
+        ADD R3, R3, #-2
+        ADD R3, R3, #1
+        ADD R3, R3, #1          ; z, and the PC wraps from xFFFF to x0000
+        .END
+
+        .ORIG x0000
+        BRz #2
+        .FILL #11
+        .FILL #22
+        STR R1, R0, #-4         ; x0002 - 4 = xFFFE
+        BRnzp #-12              ; x0005 - 12 = xFFF9
+        .END
+
+        .ORIG xFFF9
+        HALT
+        .END